@@ -0,0 +1,85 @@
+//! Compares the cost of writing events at `TRACE` volume to a text file, to a JSON tee and to a
+//! binary log, in time per event and bytes written per event
+//!
+//! Run with `cargo bench --bench binary_log`. Each output is measured in a process of its own,
+//! as the subscriber can only be installed once, with the console output thrown away unformatted
+//! where it isn't the output measured.
+
+use pretty_tracing_subscriber::{Builder, Destination, Discard, Verbosity};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+use tracing::Level;
+
+const EVENTS: usize = 200_000;
+
+/// Names the output measured by the child processes
+const MODE: &str = "BINARY_LOG_BENCH_MODE";
+
+mod app {
+    pub mod db {
+        pub fn query(i: usize) {
+            tracing::trace!(
+                rows = i,
+                table = "users",
+                cached = i.is_multiple_of(3),
+                "fetched rows"
+            );
+        }
+    }
+}
+
+/// Logs `EVENTS` events in a span, after a warm-up, to the output named `mode` in `path`
+fn run(mode: &str, path: &Path) {
+    let verbosity = Verbosity::from_flags(0, 0, Some("trace".to_owned()));
+    let builder = Builder::new("binary_log", verbosity);
+    let builder = match mode {
+        "text file" => builder.route(Level::TRACE, vec![Destination::File(path.to_owned())]),
+        "JSON tee" => builder
+            .discard_output(Discard::BeforeFormatting)
+            .tee_json(Destination::File(path.to_owned()), "trace"),
+        "binary log" => builder
+            .discard_output(Discard::BeforeFormatting)
+            .binary_log(path, "trace"),
+        _ => unreachable!("unknown mode {}", mode),
+    };
+    let guard = builder.init_with_guard();
+    let span = tracing::trace_span!("request", id = 42);
+    let _entered = span.enter();
+    for i in 0..1_000 {
+        app::db::query(i);
+    }
+    let start = Instant::now();
+    for i in 0..EVENTS {
+        app::db::query(i);
+    }
+    drop(guard);
+    let elapsed = start.elapsed();
+    let bytes = fs::metadata(path).map_or(0, |metadata| metadata.len());
+    println!(
+        "{:<12} {:>8.0} ns/event {:>8.1} bytes/event",
+        mode,
+        elapsed.as_nanos() as f64 / EVENTS as f64,
+        bytes as f64 / (EVENTS + 1_000) as f64
+    );
+}
+
+fn main() {
+    let dir = env::temp_dir();
+    if let Ok(mode) = env::var(MODE) {
+        let path = dir.join(format!("binary_log_bench_{}", std::process::id()));
+        run(&mode, &path);
+        let _ = fs::remove_file(&path);
+        return;
+    }
+    let exe = env::current_exe().expect("cannot find the benchmark executable");
+    for mode in ["text file", "JSON tee", "binary log"] {
+        let status = Command::new(&exe)
+            .env(MODE, mode)
+            .status()
+            .expect("cannot run the benchmark");
+        assert!(status.success(), "the {} benchmark failed", mode);
+    }
+}
@@ -0,0 +1,77 @@
+//! Measures the cost of formatting events at `TRACE` volume, in time and allocations per event
+//!
+//! Run with `cargo bench --bench formatting`. The formatted output is thrown away, so only the
+//! work of the formatter is measured, not that of writing to a terminal.
+
+use pretty_tracing_subscriber::{Builder, Discard, Verbosity};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Counts the allocations made, to tell how many formatting an event takes
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const EVENTS: usize = 200_000;
+
+mod app {
+    pub mod db {
+        pub fn query(i: usize) {
+            tracing::trace!(rows = i, "fetched rows");
+        }
+    }
+
+    pub fn plain(i: usize) {
+        tracing::trace!("tick {}", i);
+    }
+}
+
+/// Formats `EVENTS` events logged by `log`, after a warm-up, and prints what each one cost
+fn measure(name: &str, log: impl Fn(usize)) {
+    for i in 0..1_000 {
+        log(i);
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 0..EVENTS {
+        log(i);
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{:<24} {:>8.0} ns/event {:>6.2} allocations/event",
+        name,
+        elapsed.as_nanos() as f64 / EVENTS as f64,
+        allocations as f64 / EVENTS as f64
+    );
+}
+
+fn main() {
+    Builder::new(
+        "formatting",
+        Verbosity::from_flags(0, 0, Some("trace".to_owned())),
+    )
+    .verbose_format(true, true)
+    .discard_output(Discard::AfterFormatting)
+    .init();
+    measure("message", app::plain);
+    measure("message and field", app::db::query);
+    let span = tracing::trace_span!("request", id = 42);
+    let _entered = span.enter();
+    measure("in a span", app::db::query);
+}
@@ -0,0 +1,501 @@
+//! Writes events to an append-only file where each line is chained to the previous one by a
+//! hash, with the `audit` feature
+
+#[cfg(feature = "audit")]
+pub(crate) use native::open;
+#[cfg(feature = "audit")]
+pub use native::{verify_audit_log, AuditError};
+
+#[cfg(not(feature = "audit"))]
+pub(crate) use unsupported::open;
+
+#[cfg(feature = "audit")]
+mod native {
+    use crate::health::SinkStatus;
+    use crate::route::{self, Router};
+    use crate::shutdown::Sink;
+    use crate::status::Stderr;
+    use hmac::{Hmac, Mac};
+    use sha2::{Digest, Sha256};
+    use std::fmt::{self, Write as _};
+    use std::fs::File;
+    use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::writer::BoxMakeWriter;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    /// The hash the first line is chained to
+    const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+    /// The record appended after a last line cut short, such as by a crash, which is ended
+    /// first so the chain carries on from the line before it
+    const RECOVERED: &str = r#"{"audit":"recovered","message":"the previous line was cut short"}"#;
+
+    /// How much of the file is read at once when looking for its last line
+    const CHUNK: u64 = 4096;
+
+    /// An audit log, see [`Builder::audit_log`](crate::Builder::audit_log)
+    #[derive(Debug)]
+    struct AuditLog {
+        /// The file, and the hash of its last line
+        file: Mutex<(File, String)>,
+        key: Option<Vec<u8>>,
+        health: Arc<SinkStatus>,
+    }
+
+    impl AuditLog {
+        /// Opens the audit log at `path`, continuing the chain of the lines already in it
+        ///
+        /// A last line without a newline is ended, and followed by a [`RECOVERED`] record,
+        /// rather than having the next record appended to it.
+        fn open(path: &Path, key: Option<Vec<u8>>) -> io::Result<Self> {
+            let tail = Tail::read(path)?;
+            let mut file = route::open_append(path)?;
+            if tail.partial {
+                file.write_all(b"\n")?;
+            }
+            let last = tail.hash.unwrap_or_else(|| GENESIS.to_owned());
+            let log = Self {
+                file: Mutex::new((file, last)),
+                key,
+                health: SinkStatus::register(format!("the audit log {}", path.display())),
+            };
+            if tail.partial {
+                log.append(RECOVERED.as_bytes())?;
+            }
+            Ok(log)
+        }
+
+        /// Appends each line of `buf`, preceded by its hash
+        fn append(&self, buf: &[u8]) -> io::Result<()> {
+            let mut state = self.file.lock().unwrap_or_else(|e| e.into_inner());
+            let (file, last) = &mut *state;
+            for record in buf.split(|&b| b == b'\n').filter(|line| !line.is_empty()) {
+                let hash = chain(self.key.as_deref(), last, record);
+                let mut line = Vec::with_capacity(hash.len() + record.len() + 2);
+                line.extend_from_slice(hash.as_bytes());
+                line.push(b' ');
+                line.extend_from_slice(record);
+                line.push(b'\n');
+                file.write_all(&line)?;
+                *last = hash;
+            }
+            Ok(())
+        }
+    }
+
+    impl Sink for AuditLog {
+        fn drain(&self) -> io::Result<()> {
+            let state = self.file.lock().unwrap_or_else(|e| e.into_inner());
+            state.0.sync_data()
+        }
+
+        fn close(&self, _marker: &str) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct AuditWriter(Arc<AuditLog>);
+
+    impl MakeWriter for AuditWriter {
+        type Writer = AuditWriter;
+
+        fn make_writer(&self) -> AuditWriter {
+            self.clone()
+        }
+    }
+
+    impl Write for AuditWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let appended = self.0.append(buf);
+            self.0.health.record(&appended);
+            appended.map(|()| buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Opens the audit log at `path`, continuing the chain of the lines already in it, and
+    /// returns the writer for its records and the sink syncing it to disk on shutdown
+    pub(crate) fn open(
+        path: &Path,
+        key: Option<Vec<u8>>,
+        stderr: &Stderr,
+    ) -> io::Result<(Router, Arc<dyn Sink>)> {
+        let log = Arc::new(AuditLog::open(path, key)?);
+        let mut router = Router::new(stderr.clone());
+        router.replace_stderr(Arc::new(BoxMakeWriter::new(AuditWriter(log.clone()))));
+        Ok((router, log))
+    }
+
+    /// The end of an audit log, read backwards
+    #[derive(Debug, Default)]
+    struct Tail {
+        /// The hash starting the last complete line, if there is one
+        hash: Option<String>,
+        /// Whether the file ends with a line cut short, without a newline
+        partial: bool,
+    }
+
+    impl Tail {
+        fn read(path: &Path) -> io::Result<Self> {
+            let mut file = match File::open(path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+                Err(e) => return Err(e),
+            };
+            let len = file.metadata()?.len();
+            if len == 0 {
+                return Ok(Self::default());
+            }
+            let mut last = [0];
+            file.seek(SeekFrom::Start(len - 1))?;
+            file.read_exact(&mut last)?;
+            let partial = last[0] != b'\n';
+            let end = if partial {
+                match rfind_newline(&mut file, len)? {
+                    Some(newline) => newline,
+                    None => {
+                        return Ok(Self {
+                            hash: None,
+                            partial,
+                        })
+                    }
+                }
+            } else {
+                len - 1
+            };
+            let start = rfind_newline(&mut file, end)?.map_or(0, |newline| newline + 1);
+            let mut line = Vec::new();
+            file.seek(SeekFrom::Start(start))?;
+            (&mut file).take(end - start).read_to_end(&mut line)?;
+            let hash = line.split(|&b| b == b' ').next().unwrap_or_default();
+            let hash = Some(String::from_utf8_lossy(hash).into_owned()).filter(|h| !h.is_empty());
+            Ok(Self { hash, partial })
+        }
+    }
+
+    /// The position of the last newline before `end` in `file`, searching a chunk at a time
+    fn rfind_newline(file: &mut File, mut end: u64) -> io::Result<Option<u64>> {
+        let mut chunk = vec![0; CHUNK as usize];
+        while end > 0 {
+            let start = end.saturating_sub(CHUNK);
+            let chunk = &mut chunk[..(end - start) as usize];
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(chunk)?;
+            if let Some(i) = chunk.iter().rposition(|&b| b == b'\n') {
+                return Ok(Some(start + i as u64));
+            }
+            end = start;
+        }
+        Ok(None)
+    }
+
+    /// The hash of `record` chained to `previous`, keyed with HMAC if there is a `key`
+    fn chain(key: Option<&[u8]>, previous: &str, record: &[u8]) -> String {
+        let digest: Vec<u8> = match key {
+            Some(key) => {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+                mac.update(previous.as_bytes());
+                mac.update(record);
+                mac.finalize().into_bytes().to_vec()
+            }
+            None => {
+                let mut hasher = Sha256::new();
+                hasher.update(previous.as_bytes());
+                hasher.update(record);
+                hasher.finalize().to_vec()
+            }
+        };
+        let mut hex = String::with_capacity(64);
+        for byte in digest {
+            let _ = write!(hex, "{:02x}", byte);
+        }
+        hex
+    }
+
+    /// Checks that no line of the audit log at `path` was changed, removed or inserted, returning
+    /// the number of lines
+    ///
+    /// Each line holds the SHA-256 hash of the one before it followed by its JSON record, hashed
+    /// together, or their HMAC-SHA256 when the log was written with a `key`, which must then be
+    /// given here. Truncating the end of the log can't be detected from the log alone, so the
+    /// number of lines, or the last hash, should be compared to what was recorded elsewhere.
+    /// A last line cut short, such as by a crash, is reported as [`AuditError::Truncated`] once
+    /// the log is written to again.
+    ///
+    /// ```no_run
+    /// match pretty_tracing_subscriber::verify_audit_log("audit.log", Some(b"secret")) {
+    ///     Ok(lines) => println!("{} lines verified", lines),
+    ///     Err(e) => eprintln!("{}", e),
+    /// }
+    /// ```
+    pub fn verify_audit_log(path: impl AsRef<Path>, key: Option<&[u8]>) -> Result<u64, AuditError> {
+        let file = File::open(path).map_err(AuditError::Io)?;
+        let mut previous = GENESIS.to_owned();
+        let mut lines = 0;
+        let mut split = BufReader::new(file).split(b'\n').peekable();
+        while let Some(line) = split.next() {
+            let line = line.map_err(AuditError::Io)?;
+            lines += 1;
+            let verified = match split_line(&line) {
+                Some((hash, record)) => {
+                    let expected = chain(key, &previous, record);
+                    Some(expected).filter(|expected| hash == expected.as_bytes())
+                }
+                None => None,
+            };
+            previous = match verified {
+                Some(hash) => hash,
+                None => {
+                    // A line cut short is followed by a record chained to the line before it
+                    let recovered = chain(key, &previous, RECOVERED.as_bytes());
+                    let next = split.peek().and_then(|next| next.as_ref().ok());
+                    if next.and_then(|next| split_line(next))
+                        == Some((recovered.as_bytes(), RECOVERED.as_bytes()))
+                    {
+                        return Err(AuditError::Truncated { line: lines });
+                    }
+                    return Err(match split_line(&line) {
+                        Some(_) => AuditError::Mismatch { line: lines },
+                        None => AuditError::Malformed { line: lines },
+                    });
+                }
+            };
+        }
+        Ok(lines)
+    }
+
+    /// Splits a line into its hash and its record
+    fn split_line(line: &[u8]) -> Option<(&[u8], &[u8])> {
+        let space = line.iter().position(|&b| b == b' ')?;
+        Some((&line[..space], &line[space + 1..]))
+    }
+
+    /// Why an audit log failed verification with [`verify_audit_log`]
+    #[derive(Debug)]
+    pub enum AuditError {
+        /// The log couldn't be read
+        Io(io::Error),
+        /// A line doesn't start with a hash
+        Malformed {
+            /// The number of the line, from 1
+            line: u64,
+        },
+        /// The hash of a line doesn't match its record and the line before it, so one of them
+        /// was changed, or lines were removed or inserted
+        Mismatch {
+            /// The number of the line, from 1
+            line: u64,
+        },
+        /// A line was cut short, such as by a crash while it was written, and the log was
+        /// written to again after it
+        Truncated {
+            /// The number of the line, from 1
+            line: u64,
+        },
+    }
+
+    impl fmt::Display for AuditError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                AuditError::Io(e) => write!(f, "failed to read the audit log: {}", e),
+                AuditError::Malformed { line } => {
+                    write!(f, "line {} of the audit log has no hash", line)
+                }
+                AuditError::Mismatch { line } => write!(
+                    f,
+                    "the audit log was tampered with at line {}, whose hash doesn't match",
+                    line
+                ),
+                AuditError::Truncated { line } => {
+                    write!(f, "line {} of the audit log was cut short", line)
+                }
+            }
+        }
+    }
+
+    impl std::error::Error for AuditError {}
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::fs;
+        use std::path::PathBuf;
+
+        const KEY: &[u8] = b"secret";
+
+        /// A fresh path in the temporary directory, removed when dropped
+        struct TempLog(PathBuf);
+
+        impl TempLog {
+            fn new(name: &str) -> Self {
+                let path =
+                    std::env::temp_dir().join(format!("audit_{}_{}.log", std::process::id(), name));
+                let _ = fs::remove_file(&path);
+                Self(path)
+            }
+
+            fn write(&self, key: Option<&[u8]>, records: &[&str]) {
+                let log = AuditLog::open(&self.0, key.map(<[u8]>::to_vec)).unwrap();
+                for record in records {
+                    log.append(format!("{}\n", record).as_bytes()).unwrap();
+                }
+            }
+
+            fn verify(&self, key: Option<&[u8]>) -> Result<u64, AuditError> {
+                verify_audit_log(&self.0, key)
+            }
+
+            fn edit(&self, edit: impl FnOnce(&mut Vec<String>)) {
+                let text = fs::read_to_string(&self.0).unwrap();
+                let mut lines: Vec<_> = text.lines().map(str::to_owned).collect();
+                edit(&mut lines);
+                fs::write(&self.0, lines.join("\n") + "\n").unwrap();
+            }
+        }
+
+        impl Drop for TempLog {
+            fn drop(&mut self) {
+                let _ = fs::remove_file(&self.0);
+            }
+        }
+
+        #[test]
+        fn clean_chain_verifies() {
+            let log = TempLog::new("clean");
+            log.write(None, &[r#"{"a":1}"#, r#"{"b":2}"#, r#"{"c":3}"#]);
+            assert_eq!(log.verify(None).unwrap(), 3);
+            let text = fs::read_to_string(&log.0).unwrap();
+            let first = text.lines().next().unwrap();
+            assert_eq!(
+                first,
+                format!("{} {}", chain(None, GENESIS, br#"{"a":1}"#), r#"{"a":1}"#)
+            );
+        }
+
+        #[test]
+        fn hmac_chain_needs_the_key() {
+            let log = TempLog::new("hmac");
+            log.write(Some(KEY), &[r#"{"a":1}"#, r#"{"b":2}"#]);
+            assert_eq!(log.verify(Some(KEY)).unwrap(), 2);
+            assert!(matches!(
+                log.verify(Some(b"wrong")),
+                Err(AuditError::Mismatch { line: 1 })
+            ));
+            assert!(matches!(
+                log.verify(None),
+                Err(AuditError::Mismatch { line: 1 })
+            ));
+        }
+
+        #[test]
+        fn edited_line_is_reported() {
+            let log = TempLog::new("edited");
+            log.write(None, &[r#"{"a":1}"#, r#"{"b":2}"#, r#"{"c":3}"#]);
+            log.edit(|lines| lines[1] = lines[1].replace('2', "3"));
+            assert!(matches!(
+                log.verify(None),
+                Err(AuditError::Mismatch { line: 2 })
+            ));
+        }
+
+        #[test]
+        fn deleted_line_is_reported() {
+            let log = TempLog::new("deleted");
+            log.write(None, &[r#"{"a":1}"#, r#"{"b":2}"#, r#"{"c":3}"#]);
+            log.edit(|lines| {
+                lines.remove(1);
+            });
+            assert!(matches!(
+                log.verify(None),
+                Err(AuditError::Mismatch { line: 2 })
+            ));
+        }
+
+        #[test]
+        fn line_without_hash_is_malformed() {
+            let log = TempLog::new("malformed");
+            log.write(None, &[r#"{"a":1}"#]);
+            log.edit(|lines| lines.push("garbage".to_owned()));
+            assert!(matches!(
+                log.verify(None),
+                Err(AuditError::Malformed { line: 2 })
+            ));
+        }
+
+        #[test]
+        fn reopening_continues_the_chain() {
+            let log = TempLog::new("reopen");
+            log.write(Some(KEY), &[r#"{"a":1}"#, r#"{"b":2}"#]);
+            log.write(Some(KEY), &[r#"{"c":3}"#]);
+            assert_eq!(log.verify(Some(KEY)).unwrap(), 3);
+        }
+
+        #[test]
+        fn long_lines_are_found_from_the_end() {
+            let log = TempLog::new("long");
+            let long = format!(r#"{{"a":"{}"}}"#, "x".repeat(3 * CHUNK as usize));
+            log.write(None, &[&long, &long]);
+            log.write(None, &[r#"{"b":2}"#]);
+            assert_eq!(log.verify(None).unwrap(), 3);
+        }
+
+        #[test]
+        fn line_cut_short_is_ended_and_reported() {
+            let log = TempLog::new("partial");
+            log.write(None, &[r#"{"a":1}"#, r#"{"b":2}"#]);
+            let mut text = fs::read_to_string(&log.0).unwrap();
+            text.truncate(text.len() - 4);
+            fs::write(&log.0, &text).unwrap();
+
+            log.write(None, &[r#"{"c":3}"#]);
+            let text = fs::read_to_string(&log.0).unwrap();
+            let lines: Vec<_> = text.lines().collect();
+            assert_eq!(lines.len(), 4);
+            assert!(lines[2].ends_with(RECOVERED));
+            assert!(lines[3].ends_with(r#"{"c":3}"#));
+            assert!(matches!(
+                log.verify(None),
+                Err(AuditError::Truncated { line: 2 })
+            ));
+        }
+
+        #[test]
+        fn first_line_cut_short_is_reported() {
+            let log = TempLog::new("partial_first");
+            fs::write(&log.0, "0123").unwrap();
+            log.write(Some(KEY), &[r#"{"a":1}"#]);
+            assert!(matches!(
+                log.verify(Some(KEY)),
+                Err(AuditError::Truncated { line: 1 })
+            ));
+        }
+    }
+}
+
+#[cfg(not(feature = "audit"))]
+mod unsupported {
+    use crate::route::Router;
+    use crate::shutdown::Sink;
+    use crate::status::Stderr;
+    use std::io;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    pub(crate) fn open(
+        _path: &Path,
+        _key: Option<Vec<u8>>,
+        _stderr: &Stderr,
+    ) -> io::Result<(Router, Arc<dyn Sink>)> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the audit feature is not enabled",
+        ))
+    }
+}
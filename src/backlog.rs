@@ -0,0 +1,187 @@
+use crate::levels;
+use ansi_term::Color;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::Level;
+
+/// How many lines can wait for the writer thread before events start being held up
+const CAPACITY: usize = 1024;
+
+/// How often a full queue is checked for a stalled write
+const POLL: Duration = Duration::from_millis(1);
+
+/// How often dropped events are reported while the consumer is slow
+const NOTICE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long shutting down waits for queued lines to be written
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Lines queued for a writer thread, so that a stalled consumer of the output doesn't stall the
+/// application, see [`Builder::drop_when_slow`](crate::Builder::drop_when_slow)
+///
+/// Lines are only dropped once the queue is full and the write in progress has been blocked for
+/// longer than the threshold. Until then, events wait for room in the queue as they would for a
+/// direct write, so a briefly busy consumer loses nothing. With a threshold of zero, lines are
+/// dropped as soon as the queue is full, see
+/// [`Builder::drop_when_full`](crate::Builder::drop_when_full).
+#[derive(Debug)]
+pub(crate) struct Backlog {
+    sender: SyncSender<Message>,
+    state: Arc<State>,
+}
+
+#[derive(Debug)]
+enum Message {
+    Line(Vec<u8>),
+    /// Acknowledged once every line queued before it has been written
+    Drain(SyncSender<()>),
+}
+
+#[derive(Debug)]
+struct State {
+    threshold: Duration,
+    epoch: Instant,
+    /// Nanoseconds after `epoch`, plus one, at which the write in progress started, or 0
+    writing_since: AtomicU64,
+    /// Lines dropped at each level, in the order of [`levels::index`], and other lines last
+    dropped: [AtomicU64; 6],
+}
+
+impl Backlog {
+    /// Starts the writer thread, which writes each line with `write`
+    pub(crate) fn start<W>(threshold: Duration, write: W) -> Self
+    where
+        W: Fn(&[u8]) -> io::Result<()> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel(CAPACITY);
+        let state = Arc::new(State {
+            threshold,
+            epoch: Instant::now(),
+            writing_since: AtomicU64::new(0),
+            dropped: Default::default(),
+        });
+        let writer_state = state.clone();
+        thread::Builder::new()
+            .name("log writer".to_owned())
+            .spawn(move || writer_state.run(&receiver, write))
+            .expect("failed to spawn the log writer thread");
+        Self { sender, state }
+    }
+
+    /// Queues a line of an event at `level`, or another line without one, or drops it if the
+    /// consumer is stalled and the queue is full
+    pub(crate) fn push(&self, level: Option<Level>, line: &[u8]) {
+        let mut message = Message::Line(line.to_vec());
+        loop {
+            message = match self.sender.try_send(message) {
+                Err(TrySendError::Full(message)) if !self.state.stalled() => message,
+                Err(TrySendError::Full(_)) => {
+                    let index = level.map_or(5, levels::index);
+                    self.state.dropped[index].fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+            };
+            thread::sleep(POLL);
+        }
+    }
+
+    /// Waits for the queued lines to be written, giving up after a while if the consumer is
+    /// stalled
+    pub(crate) fn drain(&self) {
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        let (done, written) = mpsc::sync_channel(1);
+        let mut message = Message::Drain(done);
+        loop {
+            message = match self.sender.try_send(message) {
+                Err(TrySendError::Full(message)) if Instant::now() < deadline => message,
+                Ok(()) => break,
+                Err(_) => return,
+            };
+            thread::sleep(POLL);
+        }
+        let _ = written.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+    }
+}
+
+impl State {
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_nanos() as u64 + 1
+    }
+
+    /// Whether the write in progress has been blocked for longer than the threshold
+    fn stalled(&self) -> bool {
+        if self.threshold.is_zero() {
+            return true;
+        }
+        match self.writing_since.load(Ordering::Relaxed) {
+            0 => false,
+            since => Duration::from_nanos(self.now() - since) > self.threshold,
+        }
+    }
+
+    fn run<W>(&self, receiver: &Receiver<Message>, write: W)
+    where
+        W: Fn(&[u8]) -> io::Result<()>,
+    {
+        let mut last_notice = None;
+        loop {
+            match receiver.recv_timeout(NOTICE_INTERVAL) {
+                Ok(Message::Line(line)) => {
+                    self.writing_since.store(self.now(), Ordering::Relaxed);
+                    let _ = write(&line);
+                    self.writing_since.store(0, Ordering::Relaxed);
+                }
+                Ok(Message::Drain(done)) => {
+                    self.notice(&write);
+                    let _ = done.send(());
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            let due = last_notice.is_none_or(|last: Instant| last.elapsed() >= NOTICE_INTERVAL);
+            if due && self.notice(&write) {
+                last_notice = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Reports the events dropped since the last notice, returning whether there were any
+    fn notice<W>(&self, write: &W) -> bool
+    where
+        W: Fn(&[u8]) -> io::Result<()>,
+    {
+        let counts: Vec<String> = self
+            .dropped
+            .iter()
+            .zip([
+                "error events",
+                "warning events",
+                "info events",
+                "debug events",
+                "trace events",
+                "other lines",
+            ])
+            .filter_map(|(dropped, what)| match dropped.swap(0, Ordering::Relaxed) {
+                0 => None,
+                dropped => Some(format!("{} {}", dropped, what)),
+            })
+            .collect();
+        let counts = match counts.split_last() {
+            None => return false,
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+        };
+        let notice = format!(
+            "{} dropped {} due to slow consumer\n",
+            Color::Yellow.bold().paint("warning:"),
+            counts
+        );
+        let _ = write(notice.as_bytes());
+        true
+    }
+}
@@ -0,0 +1,91 @@
+use ansi_term::Style;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::fmt::{self, Write};
+
+/// Paths of the frames that capture the backtrace and dispatch the event to the formatter,
+/// which are left out
+const INTERNAL: &[&str] = &[
+    "std::",
+    "core::",
+    "alloc::",
+    "tracing::",
+    "tracing_core::",
+    "tracing_subscriber::",
+    "pretty_tracing_subscriber::",
+];
+
+/// Captures the backtrace of an `ERROR` event, when enabled with `RUST_BACKTRACE` or `forced`
+pub(crate) fn capture(forced: bool) -> Option<Backtrace> {
+    let backtrace = if forced {
+        Backtrace::force_capture()
+    } else {
+        Backtrace::capture()
+    };
+    (backtrace.status() == BacktraceStatus::Captured).then_some(backtrace)
+}
+
+/// Writes the frames of `backtrace` from where the event was logged, indented and dimmed, with
+/// the frames in the modules under `roots` standing out
+pub(crate) fn write(f: &mut dyn Write, backtrace: &Backtrace, roots: &[String]) -> fmt::Result {
+    let dimmed = Style::new().dimmed();
+    let rendered = backtrace.to_string();
+    let frames = frames(&rendered);
+    let internal = |(symbol, locations): &(&str, Vec<&str>)| {
+        INTERNAL.iter().any(|path| in_module(symbol, path))
+            // The closures of the event macros are named after the function logging the event
+            || locations.iter().any(|location| location.contains("/tracing-"))
+    };
+    let start = frames
+        .iter()
+        .position(|frame| !internal(frame))
+        .unwrap_or(0);
+    // Like the short backtraces of panics, stop before the frames starting the program
+    let end = frames
+        .iter()
+        .position(|(symbol, _)| symbol.contains("__rust_begin_short_backtrace"))
+        .unwrap_or(frames.len());
+    let end = frames[..end]
+        .iter()
+        .rposition(|frame| !internal(frame))
+        .map_or(end, |last| last + 1);
+
+    for (i, (symbol, locations)) in frames[start..end.max(start)].iter().enumerate() {
+        let root = roots
+            .iter()
+            .any(|root| in_module(symbol, &format!("{}::", root)));
+        let style = if root { Style::new().bold() } else { dimmed };
+        writeln!(
+            f,
+            "    {} {}",
+            dimmed.paint(format!("{:>2}:", i)),
+            style.paint(*symbol)
+        )?;
+        for location in locations {
+            writeln!(f, "        {}", dimmed.paint(format!("at {}", location)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Splits the rendering of a backtrace into the symbol of each frame and its locations
+fn frames(rendered: &str) -> Vec<(&str, Vec<&str>)> {
+    let mut frames: Vec<(&str, Vec<&str>)> = Vec::new();
+    for line in rendered.lines().map(str::trim) {
+        if let Some(location) = line.strip_prefix("at ") {
+            if let Some((_, locations)) = frames.last_mut() {
+                locations.push(location);
+            }
+        } else if let Some((index, symbol)) = line.split_once(": ") {
+            if index.bytes().all(|b| b.is_ascii_digit()) {
+                frames.push((symbol, Vec::new()));
+            }
+        }
+    }
+    frames
+}
+
+/// Whether `symbol` is a function under `path`, including the methods of trait implementations
+/// for its types
+fn in_module(symbol: &str, path: &str) -> bool {
+    symbol.trim_start_matches('<').starts_with(path)
+}
@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The target of the startup banner, which passes whatever the filters
+pub(crate) const BANNER_TARGET: &str = "pretty_tracing_subscriber::startup";
+
+/// What the running binary was built from, shown by the startup banner, see
+/// [`Builder::startup_banner`](crate::Builder::startup_banner)
+///
+/// [`build_info!`](crate::build_info) fills it in from the package of the crate it is used in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    package: String,
+    version: String,
+    git_sha: Option<String>,
+}
+
+impl BuildInfo {
+    /// The name and version of the package the binary belongs to
+    pub fn new(package: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            package: package.into(),
+            version: version.into(),
+            git_sha: None,
+        }
+    }
+
+    /// The git commit the binary was built from
+    pub fn git_sha(mut self, sha: impl Into<String>) -> Self {
+        self.git_sha = Some(sha.into());
+        self
+    }
+
+    /// The package, version and git commit, as in `my_app 1.2.0 (3f9c0a1)`
+    pub(crate) fn describe(&self) -> String {
+        match &self.git_sha {
+            Some(sha) => format!("{} {} ({})", self.package, self.version, sha),
+            None => format!("{} {}", self.package, self.version),
+        }
+    }
+}
+
+/// The [`BuildInfo`] of the package of the calling crate, with the git commit that
+/// [`emit_git_sha`](crate::emit_git_sha) recorded in its build script, if any
+///
+/// ```no_run
+/// # #[cfg(feature = "cli")] {
+/// # use pretty_tracing_subscriber::{Builder, Verbosity};
+/// # use structopt::StructOpt;
+/// Builder::new("my_app", Verbosity::from_args())
+///     .startup_banner(pretty_tracing_subscriber::build_info!())
+///     .init();
+/// # }
+/// ```
+#[macro_export]
+macro_rules! build_info {
+    () => {{
+        let info = $crate::BuildInfo::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        match option_env!("PRETTY_TRACING_GIT_SHA") {
+            Some(sha) => info.git_sha(sha),
+            None => info,
+        }
+    }};
+}
+
+/// Records the git commit being built for [`build_info!`](crate::build_info), to be called from
+/// a build script, with this crate among the build dependencies
+///
+/// The commit is read again after every commit or checkout. Nothing is recorded outside of a
+/// git checkout, or when git isn't installed.
+///
+/// ```ignore
+/// // build.rs
+/// fn main() {
+///     pretty_tracing_subscriber::emit_git_sha();
+/// }
+/// ```
+pub fn emit_git_sha() {
+    let git = |args: &[&str]| {
+        let output = Command::new("git").args(args).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(String::from_utf8(output.stdout).ok()?.trim().to_owned())
+    };
+    if let Some(sha) = git(&["rev-parse", "--short=12", "HEAD"]) {
+        println!("cargo:rustc-env=PRETTY_TRACING_GIT_SHA={}", sha);
+    }
+    if let Some(dir) = git(&["rev-parse", "--git-dir"]) {
+        // Every commit and checkout appends to the log of `HEAD`
+        let log = PathBuf::from(dir).join("logs").join("HEAD");
+        println!("cargo:rerun-if-changed={}", log.display());
+    }
+}
+
+/// Logs the startup banner, with what built the binary, how it was invoked and the filter in
+/// effect
+pub(crate) fn log(info: &BuildInfo, filter: &str) {
+    let args: Vec<String> = std::env::args().collect();
+    let binary = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| info.package.clone());
+    tracing::info!(
+        target: BANNER_TARGET,
+        binary = %binary,
+        package = %info.package,
+        version = %info.version,
+        git_sha = info.git_sha.as_deref().unwrap_or("unknown"),
+        filter,
+        argv = ?args,
+        "starting {} {}",
+        binary,
+        info.version
+    );
+}
@@ -0,0 +1,402 @@
+//! Pushes events to log collectors in batches from a background thread, or a task on the Tokio
+//! runtime with the `tokio` feature, so the application isn't held up by the network
+//!
+//! Loki, OpenTelemetry and GELF over TCP go through [`start`]. GELF over UDP and syslog don't, as
+//! sending a datagram doesn't wait for the server, and Sentry sends each envelope from a thread of
+//! its own rather than in batches.
+
+use crate::health::{self, SinkStatus};
+use crate::http::{self, Endpoint};
+use crate::shutdown::Sink;
+use std::fmt;
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How many events wait to be pushed at most, beyond which new events are dropped
+const BUFFER: usize = 8192;
+
+/// How many events are pushed in one request at most
+const BATCH: usize = 1024;
+
+/// How long events wait for more to be pushed with them
+const LINGER: Duration = Duration::from_secs(1);
+
+/// How many times a failed push is retried, waiting twice as long each time
+const RETRIES: u32 = 3;
+
+/// How long the first retry waits
+const BACKOFF: Duration = Duration::from_millis(500);
+
+/// Called once every entry received before it has been pushed
+type Flushed = Box<dyn FnOnce() + Send>;
+
+/// Where the batches are pushed
+pub(crate) trait Push: Send + Sync + 'static {
+    /// Pushes an encoded batch, returning the HTTP status of the response, or 200 once it is
+    /// written for transports without responses
+    fn push(&self, body: &str) -> io::Result<u16>;
+}
+
+impl Push for Endpoint {
+    fn push(&self, body: &str) -> io::Result<u16> {
+        self.post(&[("Content-Type", "application/json")], body)
+    }
+}
+
+/// Keeps the bodies pushed, for the tests of the collectors
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Recorded(pub(crate) std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+#[cfg(test)]
+impl Push for Recorded {
+    fn push(&self, body: &str) -> io::Result<u16> {
+        self.0.lock().unwrap().push(body.to_owned());
+        Ok(200)
+    }
+}
+
+pub(crate) enum Message<T> {
+    Entry(T),
+    /// Asks for every entry received so far to be pushed
+    Flush(Flushed),
+}
+
+/// What pushes the batches, see
+/// [`Builder::tokio_batching`](crate::Builder::tokio_batching)
+#[derive(Debug, Clone, Default)]
+pub(crate) enum Driver {
+    /// A thread of its own for each collector
+    #[default]
+    Thread,
+    /// A task on the Tokio runtime for each collector, posting from its blocking pool
+    #[cfg(feature = "tokio")]
+    Tokio(tokio::runtime::Handle),
+}
+
+/// Hands entries to the thread or task pushing them
+pub(crate) enum Sender<T> {
+    Thread(SyncSender<Message<T>>),
+    #[cfg(feature = "tokio")]
+    Tokio(tokio::sync::mpsc::Sender<Message<T>>),
+}
+
+impl<T> Sender<T> {
+    /// Queues `entry`, dropping it rather than holding up the application when the collector
+    /// can't keep up
+    pub(crate) fn send(&self, entry: T) {
+        let _ = self.try_send(Message::Entry(entry));
+    }
+
+    fn try_send(&self, message: Message<T>) -> Result<(), ()> {
+        match self {
+            Sender::Thread(sender) => sender.try_send(message).map_err(|_| ()),
+            #[cfg(feature = "tokio")]
+            Sender::Tokio(sender) => sender.try_send(message).map_err(|_| ()),
+        }
+    }
+
+    /// Asks for the entries queued so far to be pushed, waiting for room in the queue
+    fn flush(&self, flushed: Flushed) -> Result<(), ()> {
+        match self {
+            Sender::Thread(sender) => sender.send(Message::Flush(flushed)).map_err(|_| ()),
+            #[cfg(feature = "tokio")]
+            Sender::Tokio(sender) => sender
+                .blocking_send(Message::Flush(flushed))
+                .map_err(|_| ()),
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Sender::Thread(sender) => Sender::Thread(sender.clone()),
+            #[cfg(feature = "tokio")]
+            Sender::Tokio(sender) => Sender::Tokio(sender.clone()),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Sender")
+    }
+}
+
+/// Starts the thread or task pushing batches of up to [`BATCH`] entries gathered for up to
+/// [`LINGER`] to `endpoint`, returning the sender handing entries to it, and the sink that waits
+/// for it to push them all on shutdown
+///
+/// `encode` empties the batch into the body pushed, such as the JSON body of a request.
+/// `collector` names the thread and appears in errors, and in the warnings logged when pushes
+/// fail.
+pub(crate) fn start<T: Send + 'static>(
+    collector: &'static str,
+    driver: &Driver,
+    endpoint: impl Push,
+    mut encode: impl FnMut(&mut Vec<T>) -> String + Send + 'static,
+) -> io::Result<(Sender<T>, BatchSink<T>)> {
+    let health = SinkStatus::register(collector);
+    let sender = match driver {
+        Driver::Thread => {
+            let (sender, receiver) = mpsc::sync_channel(BUFFER);
+            thread::Builder::new()
+                .name(collector.to_ascii_lowercase())
+                .spawn(move || push_batches(receiver, &endpoint, &mut encode, &health))?;
+            Sender::Thread(sender)
+        }
+        #[cfg(feature = "tokio")]
+        Driver::Tokio(runtime) => {
+            let (sender, receiver) = tokio::sync::mpsc::channel(BUFFER);
+            runtime.spawn(tokio_driver::push_batches(
+                receiver, endpoint, encode, health,
+            ));
+            Sender::Tokio(sender)
+        }
+    };
+    let sink = BatchSink {
+        collector,
+        sender: sender.clone(),
+    };
+    Ok((sender, sink))
+}
+
+/// The entries waiting to be pushed, and the flushes waiting for them
+struct Batching<T> {
+    batch: Vec<T>,
+    deadline: Option<Instant>,
+    flushed: Vec<Flushed>,
+}
+
+impl<T> Batching<T> {
+    fn new() -> Self {
+        Self {
+            batch: Vec::new(),
+            deadline: None,
+            flushed: Vec::new(),
+        }
+    }
+
+    /// Takes in `message`, or `None` once the deadline has passed, returning whether the batch
+    /// is due to be pushed
+    fn receive(&mut self, message: Option<Message<T>>) -> bool {
+        match message {
+            Some(Message::Entry(entry)) => {
+                self.batch.push(entry);
+                self.deadline.get_or_insert_with(|| Instant::now() + LINGER);
+                self.batch.len() >= BATCH
+            }
+            Some(Message::Flush(flushed)) => {
+                self.flushed.push(flushed);
+                true
+            }
+            None => true,
+        }
+    }
+
+    /// Encodes the batch, if it isn't empty, starting the next one
+    fn take(&mut self, encode: &mut impl FnMut(&mut Vec<T>) -> String) -> Option<String> {
+        self.deadline = None;
+        if self.batch.is_empty() {
+            return None;
+        }
+        Some(encode(&mut self.batch))
+    }
+
+    /// Tells the flushes waiting that the batch was pushed
+    fn pushed(&mut self) {
+        for flushed in self.flushed.drain(..) {
+            flushed();
+        }
+    }
+}
+
+fn push_batches<T>(
+    receiver: Receiver<Message<T>>,
+    endpoint: &impl Push,
+    encode: &mut impl FnMut(&mut Vec<T>) -> String,
+    health: &SinkStatus,
+) {
+    let mut batching = Batching::new();
+    loop {
+        let message = match batching.deadline {
+            Some(deadline) => {
+                match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => match receiver.recv() {
+                Ok(message) => Some(message),
+                Err(_) => break,
+            },
+        };
+        if batching.receive(message) {
+            if let Some(body) = batching.take(encode) {
+                post(endpoint, &body, health);
+            }
+            batching.pushed();
+        }
+    }
+    if let Some(body) = batching.take(encode) {
+        post(endpoint, &body, health);
+    }
+}
+
+/// Pushes `body`, retrying with backoff while the collector is unavailable
+///
+/// Other client errors reject the batch for good, such as when entries are too old.
+fn post(endpoint: &impl Push, body: &str, health: &SinkStatus) {
+    let mut backoff = BACKOFF;
+    for attempt in 0..=RETRIES {
+        let response = endpoint.push(body);
+        if posted(&response) || attempt == RETRIES {
+            health.record_post(&response);
+            // Nothing is locked on this thread, so the failure can be logged right away
+            health::report();
+            break;
+        }
+        thread::sleep(backoff);
+        backoff *= 2;
+    }
+}
+
+/// Whether a post is done with, rather than to be retried
+fn posted(response: &io::Result<u16>) -> bool {
+    matches!(response, Ok(status) if *status < 500 && *status != 429)
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) use tokio_driver::off_runtime;
+
+#[cfg(feature = "tokio")]
+mod tokio_driver {
+    use super::{posted, Batching, Message, Push, BACKOFF, RETRIES};
+    use crate::health::{self, SinkStatus};
+    use std::cell::Cell;
+    use std::sync::Arc;
+    use tokio::sync::mpsc::Receiver;
+    use tokio::time;
+
+    thread_local! {
+        /// Set on the thread that shuts logging down for `LogGuard::shutdown_async`
+        static OFF_RUNTIME: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Runs `f` on a thread of the blocking pool, where waiting doesn't hold up the tasks
+    /// pushing batches
+    pub(crate) fn off_runtime<R>(f: impl FnOnce() -> R) -> R {
+        /// Puts the flag back as it was, even if `f` panics
+        struct Restore(bool);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                OFF_RUNTIME.with(|off| off.set(self.0));
+            }
+        }
+
+        let _restore = Restore(OFF_RUNTIME.with(|off| off.replace(true)));
+        f()
+    }
+
+    /// Whether this thread can wait for the tasks pushing batches, which it can unless it
+    /// drives the runtime they run on
+    pub(super) fn may_block() -> bool {
+        tokio::runtime::Handle::try_current().is_err() || OFF_RUNTIME.with(Cell::get)
+    }
+
+    pub(super) async fn push_batches<T>(
+        mut receiver: Receiver<Message<T>>,
+        endpoint: impl Push,
+        mut encode: impl FnMut(&mut Vec<T>) -> String,
+        health: Arc<SinkStatus>,
+    ) {
+        let endpoint = Arc::new(endpoint);
+        let mut batching = Batching::new();
+        loop {
+            let message = match batching.deadline {
+                Some(deadline) => match time::timeout_at(deadline.into(), receiver.recv()).await {
+                    Ok(Some(message)) => Some(message),
+                    Ok(None) => break,
+                    Err(_) => None,
+                },
+                None => match receiver.recv().await {
+                    Some(message) => Some(message),
+                    None => break,
+                },
+            };
+            if batching.receive(message) {
+                if let Some(body) = batching.take(&mut encode) {
+                    post(&endpoint, body, &health).await;
+                }
+                batching.pushed();
+            }
+        }
+        if let Some(body) = batching.take(&mut encode) {
+            post(&endpoint, body, &health).await;
+        }
+    }
+
+    /// Pushes `body` from the blocking pool, waiting on the runtime between retries
+    async fn post(endpoint: &Arc<impl Push>, body: String, health: &SinkStatus) {
+        let body = Arc::new(body);
+        let mut backoff = BACKOFF;
+        for attempt in 0..=RETRIES {
+            let (endpoint, body) = (endpoint.clone(), body.clone());
+            let response = tokio::task::spawn_blocking(move || endpoint.push(&body)).await;
+            let response = match response {
+                Ok(response) => response,
+                Err(_) => break,
+            };
+            if posted(&response) || attempt == RETRIES {
+                health.record_post(&response);
+                health::report();
+                break;
+            }
+            time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+}
+
+/// Waits for the entries handed to the thread or task to be pushed when logging shuts down
+pub(crate) struct BatchSink<T> {
+    collector: &'static str,
+    sender: Sender<T>,
+}
+
+impl<T: Send> Sink for BatchSink<T> {
+    fn drain(&self) -> io::Result<()> {
+        #[cfg(feature = "tokio")]
+        if let Sender::Tokio(_) = &self.sender {
+            if !tokio_driver::may_block() {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "cannot wait for pushes on the Tokio runtime, see `LogGuard::shutdown_async`",
+                ));
+            }
+        }
+        let (done, flushed) = mpsc::sync_channel(1);
+        let flushed_now: Flushed = Box::new(move || {
+            let _ = done.send(());
+        });
+        self.sender.flush(flushed_now).map_err(|()| {
+            let message = format!("{} thread stopped", self.collector);
+            io::Error::new(io::ErrorKind::BrokenPipe, message)
+        })?;
+        flushed
+            .recv_timeout(http::TIMEOUT * (RETRIES + 2))
+            .map_err(|_| {
+                let message = format!("pushing to {} timed out", self.collector);
+                io::Error::new(io::ErrorKind::TimedOut, message)
+            })
+    }
+
+    fn close(&self, _marker: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
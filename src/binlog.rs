@@ -0,0 +1,1417 @@
+use crate::fields::Redaction;
+use crate::health::SinkStatus;
+use crate::levels;
+use crate::route;
+use crate::shutdown::Sink;
+use crate::timestamp;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::callsite::{Callsite, Identifier};
+use tracing::field::{self, Field, FieldSet, Value, ValueSet, Visit};
+use tracing::metadata::Kind;
+use tracing::span::{Attributes, Record};
+use tracing::subscriber::Interest;
+use tracing::{dispatcher, Event, Id, Level, Metadata, Span, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Follows the kind of the record starting each run, telling binary logs from other files
+const MAGIC: &[u8; 4] = b"PTSB";
+
+/// Version of the format, which the decoder refuses logs of other versions with
+const VERSION: u8 = 1;
+
+/// Size of the buffer records are written through
+const BUFFER: usize = 64 * 1024;
+
+/// Largest record the decoder accepts, beyond which the log is taken for corrupt
+const MAX_RECORD: u64 = 16 * 1024 * 1024;
+
+/// Most fields a replayed event or span has, as many as the macros of `tracing` accept
+const MAX_FIELDS: usize = 32;
+
+// The kinds of records, which follow their length
+const RUN: u8 = 0;
+const CALLSITE: u8 = 1;
+const EVENT: u8 = 2;
+const NEW_SPAN: u8 = 3;
+const RECORD: u8 = 4;
+const CLOSE: u8 = 5;
+
+// The types of field values, which follow the index of the field
+const I64: u8 = 0;
+const U64: u8 = 1;
+const F64: u8 = 2;
+const BOOL: u8 = 3;
+const STR: u8 = 4;
+const DEBUG: u8 = 5;
+
+/// Numbers the spans written to any binary log, from 1 so that 0 stands for no span
+static NEXT_SPAN: AtomicU64 = AtomicU64::new(1);
+
+/// The callsites replayed so far, see [`replay_binary_log`]
+static REPLAYED: Mutex<Vec<&'static ReplayedCallsite>> = Mutex::new(Vec::new());
+
+/// The number of a span in binary logs, stored in the span's extensions
+struct SpanNumber(u64);
+
+/// Writes events, and the spans they are logged in, as compact binary records, see
+/// [`Builder::binary_log`](crate::Builder::binary_log)
+///
+/// Each record is its length, as a LEB128 varint, followed by its kind and contents. A run
+/// starts with a record holding [`MAGIC`], the [`VERSION`] and the time in nanoseconds since the
+/// Unix epoch. The level, names, location and field names of a callsite are written once per
+/// run, in a record numbering it, before its first event or span. Events and spans then refer
+/// to it by number, with their time as a zigzag varint of nanoseconds since the record before,
+/// and their fields as the index of the field, the type of the value and the value, up to the
+/// end of the record.
+#[derive(Clone)]
+pub(crate) struct BinaryLog(Arc<Writer>);
+
+struct Writer {
+    state: Mutex<State>,
+    redaction: Redaction,
+    health: Arc<SinkStatus>,
+}
+
+struct State {
+    file: BufWriter<File>,
+    /// The numbers of the callsites written so far in this run
+    callsites: HashMap<Identifier, u64>,
+    /// The time of the last record, in nanoseconds since the Unix epoch
+    time: u64,
+    /// The record being encoded, kept to reuse its allocation
+    record: Vec<u8>,
+}
+
+impl BinaryLog {
+    /// Appends a run to the binary log at `path`
+    pub(crate) fn open(path: &Path, redaction: Redaction) -> io::Result<Self> {
+        let file = route::open_append(path)?;
+        let time = nanos(SystemTime::now());
+        let mut state = State {
+            file: BufWriter::with_capacity(BUFFER, file),
+            callsites: HashMap::new(),
+            time,
+            record: vec![RUN],
+        };
+        state.record.extend_from_slice(MAGIC);
+        state.record.push(VERSION);
+        put_varint(&mut state.record, time);
+        state.write()?;
+        Ok(Self(Arc::new(Writer {
+            state: Mutex::new(state),
+            redaction,
+            health: SinkStatus::register(format!("the binary log {}", path.display())),
+        })))
+    }
+
+    /// The sink flushing the buffered records on shutdown
+    pub(crate) fn sink(&self) -> Arc<dyn Sink> {
+        self.0.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.0.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn encoder<'a>(&'a self, record: &'a mut Vec<u8>, fields: &'a FieldSet) -> Encoder<'a> {
+        Encoder {
+            record,
+            fields,
+            redaction: &self.0.redaction,
+        }
+    }
+}
+
+impl State {
+    /// The number of the callsite of `metadata` in this run, writing its record first if it
+    /// has none yet
+    fn callsite(&mut self, metadata: &'static Metadata<'static>) -> io::Result<u64> {
+        let id = metadata.callsite();
+        if let Some(&number) = self.callsites.get(&id) {
+            return Ok(number);
+        }
+        let number = self.callsites.len() as u64;
+        let record = &mut self.record;
+        record.clear();
+        record.push(CALLSITE);
+        put_varint(record, number);
+        record.push(levels::index(*metadata.level()) as u8);
+        record.push(u8::from(metadata.is_span()));
+        put_str(record, metadata.name());
+        put_str(record, metadata.target());
+        put_optional_str(record, metadata.module_path());
+        put_optional_str(record, metadata.file());
+        put_varint(
+            record,
+            metadata.line().map_or(0, |line| u64::from(line) + 1),
+        );
+        for field in metadata.fields() {
+            put_str(record, field.name());
+        }
+        self.write()?;
+        self.callsites.insert(id, number);
+        Ok(number)
+    }
+
+    /// Starts a record of `kind` about the callsite `callsite`
+    fn start(&mut self, kind: u8, callsite: u64) {
+        self.record.clear();
+        self.record.push(kind);
+        put_varint(&mut self.record, callsite);
+    }
+
+    /// Adds the time of the record, relative to that of the record before
+    fn put_time(&mut self) {
+        let time = nanos(SystemTime::now());
+        put_zigzag(&mut self.record, time as i64 - self.time as i64);
+        self.time = time;
+    }
+
+    /// Writes the record encoded, after its length
+    fn write(&mut self) -> io::Result<()> {
+        let (length, size) = varint(self.record.len() as u64);
+        self.file.write_all(&length[..size])?;
+        self.file.write_all(&self.record)
+    }
+}
+
+impl<S> Layer<S> for BinaryLog
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let parent = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<SpanNumber>()
+                .map(|number| number.0)
+        });
+        // Spans written to several binary logs have the same number in each
+        let number = {
+            let mut extensions = span.extensions_mut();
+            match extensions.get_mut::<SpanNumber>() {
+                Some(number) => number.0,
+                None => {
+                    let number = NEXT_SPAN.fetch_add(1, Ordering::Relaxed);
+                    extensions.insert(SpanNumber(number));
+                    number
+                }
+            }
+        };
+        let metadata = attrs.metadata();
+        let mut state = self.state();
+        let written = state.callsite(metadata).and_then(|callsite| {
+            state.start(NEW_SPAN, callsite);
+            put_varint(&mut state.record, number);
+            put_varint(&mut state.record, parent.unwrap_or_default());
+            state.put_time();
+            attrs.record(&mut self.encoder(&mut state.record, metadata.fields()));
+            state.write()
+        });
+        self.0.health.record(&written);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let number = match span.extensions().get::<SpanNumber>() {
+            Some(number) => number.0,
+            None => return,
+        };
+        let mut state = self.state();
+        state.start(RECORD, number);
+        values.record(&mut self.encoder(&mut state.record, span.metadata().fields()));
+        let written = state.write();
+        self.0.health.record(&written);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let span = ctx
+            .event_span(event)
+            .and_then(|span| span.extensions().get::<SpanNumber>().map(|number| number.0));
+        let metadata = event.metadata();
+        let mut state = self.state();
+        let written = state.callsite(metadata).and_then(|callsite| {
+            state.start(EVENT, callsite);
+            put_varint(&mut state.record, span.unwrap_or_default());
+            state.put_time();
+            event.record(&mut self.encoder(&mut state.record, metadata.fields()));
+            state.write()?;
+            // Errors are rare and may come before a crash, so they don't wait in the buffer
+            match *metadata.level() {
+                Level::ERROR => state.file.flush(),
+                _ => Ok(()),
+            }
+        });
+        self.0.health.record(&written);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let number = match ctx.span(&id) {
+            Some(span) => span.extensions().get::<SpanNumber>().map(|number| number.0),
+            None => None,
+        };
+        if let Some(number) = number {
+            let mut state = self.state();
+            state.start(CLOSE, number);
+            state.put_time();
+            let written = state.write();
+            self.0.health.record(&written);
+        }
+    }
+}
+
+impl Sink for Writer {
+    fn drain(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.file.flush()?;
+        state.file.get_ref().sync_data()
+    }
+
+    fn close(&self, _marker: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes the values of fields after their index in the field set and their type, without
+/// formatting any but those only known by their `Debug` rendering
+struct Encoder<'a> {
+    record: &'a mut Vec<u8>,
+    fields: &'a FieldSet,
+    redaction: &'a Redaction,
+}
+
+impl Encoder<'_> {
+    /// Starts the value of `field`, of type `kind`, returning `false` if it is redacted, in
+    /// which case the value is written already
+    fn field(&mut self, field: &Field, kind: u8) -> bool {
+        let index = self.fields.iter().position(|known| known == *field);
+        put_varint(self.record, index.unwrap_or_default() as u64);
+        if self.redaction.matches(field.name()) {
+            self.record.push(STR);
+            put_str(self.record, Redaction::REDACTED);
+            return false;
+        }
+        self.record.push(kind);
+        true
+    }
+}
+
+impl Visit for Encoder<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if self.field(field, I64) {
+            put_zigzag(self.record, value);
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if self.field(field, U64) {
+            put_varint(self.record, value);
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if self.field(field, F64) {
+            self.record.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if self.field(field, BOOL) {
+            self.record.push(u8::from(value));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if self.field(field, STR) {
+            put_str(self.record, value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if self.field(field, DEBUG) {
+            // The length of the rendering is only known once it is written
+            let start = self.record.len();
+            let _ = write!(self.record, "{:?}", value);
+            let (length, size) = varint((self.record.len() - start) as u64);
+            self.record
+                .splice(start..start, length[..size].iter().copied());
+        }
+    }
+}
+
+fn nanos(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_nanos() as u64
+}
+
+/// `n` as a LEB128 varint, in the first bytes of the array, and their number
+fn varint(mut n: u64) -> ([u8; 10], usize) {
+    let mut bytes = [0; 10];
+    let mut size = 0;
+    while n >= 0x80 {
+        bytes[size] = n as u8 | 0x80;
+        n >>= 7;
+        size += 1;
+    }
+    bytes[size] = n as u8;
+    (bytes, size + 1)
+}
+
+fn put_varint(record: &mut Vec<u8>, n: u64) {
+    let (bytes, size) = varint(n);
+    record.extend_from_slice(&bytes[..size]);
+}
+
+fn put_zigzag(record: &mut Vec<u8>, n: i64) {
+    put_varint(record, ((n << 1) ^ (n >> 63)) as u64);
+}
+
+fn put_str(record: &mut Vec<u8>, s: &str) {
+    put_varint(record, s.len() as u64);
+    record.extend_from_slice(s.as_bytes());
+}
+
+/// Writes `s` with its length plus one, or 0 for `None`
+fn put_optional_str(record: &mut Vec<u8>, s: Option<&str>) {
+    match s {
+        Some(s) => {
+            put_varint(record, s.len() as u64 + 1);
+            record.extend_from_slice(s.as_bytes());
+        }
+        None => record.push(0),
+    }
+}
+
+/// The callsite of events or spans in a binary log: their level, where they were logged and
+/// the names of their fields
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BinaryCallsite {
+    level: Level,
+    span: bool,
+    name: String,
+    target: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    fields: Vec<String>,
+}
+
+impl BinaryCallsite {
+    /// The level of the events or spans
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Whether the callsite is that of spans rather than events
+    pub fn is_span(&self) -> bool {
+        self.span
+    }
+
+    /// The name of the spans, or the name `tracing` gives events, such as
+    /// `event src/main.rs:12`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The target, such as `my_app::db`
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The module path
+    pub fn module_path(&self) -> Option<&str> {
+        self.module_path.as_deref()
+    }
+
+    /// The source file, as recorded by the compiler
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// The line in the source file
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// The names of the fields, which the fields of records refer to by index
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+}
+
+/// The value of a field in a binary log
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinaryValue {
+    /// A signed integer
+    I64(i64),
+    /// An unsigned integer
+    U64(u64),
+    /// A floating-point number
+    F64(f64),
+    /// A boolean
+    Bool(bool),
+    /// A string
+    Str(String),
+    /// The `Debug` rendering of a value of another type, or of a message
+    Debug(String),
+}
+
+impl fmt::Display for BinaryValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::I64(value) => write!(f, "{}", value),
+            Self::U64(value) => write!(f, "{}", value),
+            Self::F64(value) => write!(f, "{}", value),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Str(value) | Self::Debug(value) => f.write_str(value),
+        }
+    }
+}
+
+/// A record of a binary log, as read by [`decode_binary_log`]
+///
+/// Fields are the index of their name in [`BinaryCallsite::fields`] and their value. Spans are
+/// numbered in the order they were opened, and the numbers start over with each run appended to
+/// the log.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinaryRecord {
+    /// The start of a run of the application
+    Run {
+        /// When logging was set up
+        time: SystemTime,
+    },
+    /// An event
+    Event {
+        /// When the event was logged
+        time: SystemTime,
+        /// Where the event was logged
+        callsite: Arc<BinaryCallsite>,
+        /// The span the event was logged in
+        span: Option<u64>,
+        /// The fields of the event
+        fields: Vec<(usize, BinaryValue)>,
+    },
+    /// A span that opened
+    NewSpan {
+        /// The number of the span
+        id: u64,
+        /// When the span opened
+        time: SystemTime,
+        /// Where the span was opened
+        callsite: Arc<BinaryCallsite>,
+        /// The span the span was opened in
+        parent: Option<u64>,
+        /// The fields of the span given when it opened
+        fields: Vec<(usize, BinaryValue)>,
+    },
+    /// Values recorded in a span after it opened
+    Record {
+        /// The number of the span
+        id: u64,
+        /// The fields recorded
+        fields: Vec<(usize, BinaryValue)>,
+    },
+    /// A span that closed
+    Close {
+        /// The number of the span
+        id: u64,
+        /// When the span closed
+        time: SystemTime,
+    },
+}
+
+/// Reads the records of a binary log written by
+/// [`Builder::binary_log`](crate::Builder::binary_log)
+///
+/// The records come in the order they were written. A record cut short at the end of the log,
+/// as by a crash, is reported as an error of the kind [`io::ErrorKind::UnexpectedEof`], after
+/// which nothing more is read, as after any error.
+///
+/// ```no_run
+/// # use pretty_tracing_subscriber::{decode_binary_log, BinaryRecord};
+/// # use std::fs::File;
+/// let mut errors = 0;
+/// for record in decode_binary_log(File::open("events.bin")?) {
+///     if let BinaryRecord::Event { callsite, .. } = record? {
+///         if callsite.level() == tracing::Level::ERROR {
+///             errors += 1;
+///         }
+///     }
+/// }
+/// println!("{} errors", errors);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn decode_binary_log<R: Read>(reader: R) -> BinaryDecoder<R> {
+    BinaryDecoder {
+        reader: BufReader::with_capacity(BUFFER, reader),
+        callsites: Vec::new(),
+        time: 0,
+        record: Vec::new(),
+        started: false,
+        failed: false,
+    }
+}
+
+/// Iterator over the records of a binary log, see [`decode_binary_log`]
+#[derive(Debug)]
+pub struct BinaryDecoder<R> {
+    reader: BufReader<R>,
+    /// The callsites of the current run, by number
+    callsites: Vec<Arc<BinaryCallsite>>,
+    /// The time of the last record, in nanoseconds since the Unix epoch
+    time: u64,
+    record: Vec<u8>,
+    /// Whether the record starting the first run was read
+    started: bool,
+    failed: bool,
+}
+
+impl<R: Read> BinaryDecoder<R> {
+    /// Reads the next record into `self.record`, returning `false` at the end of the log
+    fn read_record(&mut self) -> io::Result<bool> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(false);
+        }
+        let mut length = 0u64;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0];
+            self.reader.read_exact(&mut byte)?;
+            length |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        if length > MAX_RECORD {
+            return Err(corrupt("record too long"));
+        }
+        self.record.resize(length as usize, 0);
+        self.reader.read_exact(&mut self.record).map(|()| true)
+    }
+
+    fn next_record(&mut self) -> io::Result<Option<BinaryRecord>> {
+        loop {
+            if !self.read_record()? {
+                return Ok(None);
+            }
+            let mut bytes = Bytes(&self.record);
+            let kind = bytes.u8()?;
+            if !self.started && kind != RUN {
+                return Err(corrupt("not a binary log"));
+            }
+            let record = match kind {
+                RUN => {
+                    if bytes.take(MAGIC.len())? != MAGIC {
+                        return Err(corrupt("not a binary log"));
+                    }
+                    let version = bytes.u8()?;
+                    if version != VERSION {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("unsupported binary log version {}", version),
+                        ));
+                    }
+                    self.started = true;
+                    self.time = bytes.varint()?;
+                    self.callsites.clear();
+                    BinaryRecord::Run {
+                        time: UNIX_EPOCH + Duration::from_nanos(self.time),
+                    }
+                }
+                CALLSITE => {
+                    if bytes.varint()? != self.callsites.len() as u64 {
+                        return Err(corrupt("callsites out of order"));
+                    }
+                    let callsite = bytes.callsite()?;
+                    self.callsites.push(Arc::new(callsite));
+                    continue;
+                }
+                EVENT => BinaryRecord::Event {
+                    callsite: self.callsite(bytes.varint()?)?,
+                    span: bytes.span()?,
+                    time: bytes.time(&mut self.time)?,
+                    fields: bytes.fields()?,
+                },
+                NEW_SPAN => BinaryRecord::NewSpan {
+                    callsite: self.callsite(bytes.varint()?)?,
+                    id: bytes.varint()?,
+                    parent: bytes.span()?,
+                    time: bytes.time(&mut self.time)?,
+                    fields: bytes.fields()?,
+                },
+                RECORD => BinaryRecord::Record {
+                    id: bytes.varint()?,
+                    fields: bytes.fields()?,
+                },
+                CLOSE => BinaryRecord::Close {
+                    id: bytes.varint()?,
+                    time: bytes.time(&mut self.time)?,
+                },
+                _ => return Err(corrupt("unknown record")),
+            };
+            return Ok(Some(record));
+        }
+    }
+
+    fn callsite(&self, number: u64) -> io::Result<Arc<BinaryCallsite>> {
+        let callsite = self.callsites.get(number as usize);
+        callsite.cloned().ok_or_else(|| corrupt("unknown callsite"))
+    }
+}
+
+impl<R: Read> Iterator for BinaryDecoder<R> {
+    type Item = io::Result<BinaryRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.next_record() {
+            Ok(record) => record.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+fn corrupt(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("corrupt binary log: {}", what),
+    )
+}
+
+/// The contents of a record being decoded
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(corrupt("record cut short"));
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(corrupt("number too long"))
+    }
+
+    fn zigzag(&mut self) -> io::Result<i64> {
+        let n = self.varint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    fn str(&mut self) -> io::Result<String> {
+        let length = self.varint()?;
+        self.string(length)
+    }
+
+    fn optional_str(&mut self) -> io::Result<Option<String>> {
+        match self.varint()? {
+            0 => Ok(None),
+            length => self.string(length - 1).map(Some),
+        }
+    }
+
+    fn string(&mut self, length: u64) -> io::Result<String> {
+        let bytes = self.take(length as usize)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| corrupt("invalid UTF-8"))
+    }
+
+    /// The number of a span, or `None` for 0
+    fn span(&mut self) -> io::Result<Option<u64>> {
+        Ok(Some(self.varint()?).filter(|&number| number != 0))
+    }
+
+    /// The time of a record, relative to `last`, which it then replaces
+    fn time(&mut self, last: &mut u64) -> io::Result<SystemTime> {
+        *last = last.wrapping_add(self.zigzag()? as u64);
+        Ok(UNIX_EPOCH + Duration::from_nanos(*last))
+    }
+
+    fn callsite(&mut self) -> io::Result<BinaryCallsite> {
+        let level = match self.u8()? {
+            0 => Level::ERROR,
+            1 => Level::WARN,
+            2 => Level::INFO,
+            3 => Level::DEBUG,
+            4 => Level::TRACE,
+            _ => return Err(corrupt("unknown level")),
+        };
+        let span = self.u8()? != 0;
+        let name = self.str()?;
+        let target = self.str()?;
+        let module_path = self.optional_str()?;
+        let file = self.optional_str()?;
+        let line = self.varint()?.checked_sub(1).map(|line| line as u32);
+        let mut fields = Vec::new();
+        while !self.0.is_empty() {
+            fields.push(self.str()?);
+        }
+        Ok(BinaryCallsite {
+            level,
+            span,
+            name,
+            target,
+            module_path,
+            file,
+            line,
+            fields,
+        })
+    }
+
+    /// The fields making up the rest of the record
+    fn fields(&mut self) -> io::Result<Vec<(usize, BinaryValue)>> {
+        let mut fields = Vec::new();
+        while !self.0.is_empty() {
+            let index = self.varint()? as usize;
+            let value = match self.u8()? {
+                I64 => BinaryValue::I64(self.zigzag()?),
+                U64 => BinaryValue::U64(self.varint()?),
+                F64 => {
+                    let mut bytes = [0; 8];
+                    bytes.copy_from_slice(self.take(8)?);
+                    BinaryValue::F64(f64::from_le_bytes(bytes))
+                }
+                BOOL => BinaryValue::Bool(self.u8()? != 0),
+                STR => BinaryValue::Str(self.str()?),
+                DEBUG => BinaryValue::Debug(self.str()?),
+                _ => return Err(corrupt("unknown type of value")),
+            };
+            fields.push((index, value));
+        }
+        Ok(fields)
+    }
+}
+
+/// Replays the records of a binary log into the default subscriber, such as the one installed
+/// by [`Builder::init`](crate::Builder::init), returning the number of events replayed
+///
+/// This shows a binary log the way the formatted output would have shown it: each event and
+/// span is logged again, with the time it was written at, and goes through the filters as
+/// usual. The durations of spans, and anything else measured as the records are replayed, are
+/// those of the replay. Replaying stops at the first record that can't be decoded, with its
+/// error.
+///
+/// `tracing` needs the callsites of events to live until the process exits, so those of the
+/// log are leaked, once for each distinct callsite however many logs are replayed.
+///
+/// ```no_run
+/// # #[cfg(feature = "cli")] {
+/// # use pretty_tracing_subscriber::{replay_binary_log, Builder, Verbosity};
+/// # use std::fs::File;
+/// # use structopt::StructOpt;
+/// Builder::new("my_app", Verbosity::from_args()).init();
+/// replay_binary_log(File::open("events.bin")?)?;
+/// # }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn replay_binary_log(reader: impl Read) -> io::Result<u64> {
+    // The callsites of the run, by the address the decoder keeps them at, and the spans
+    // replayed with the callsites of those shown, or the closest span shown above those that
+    // aren't
+    let mut callsites = HashMap::new();
+    let mut spans: HashMap<u64, (Span, Option<&'static ReplayedCallsite>)> = HashMap::new();
+    let mut events = 0;
+    for record in decode_binary_log(reader) {
+        let mut replayed = |callsite: &Arc<BinaryCallsite>| -> &'static ReplayedCallsite {
+            let address = Arc::as_ptr(callsite) as usize;
+            callsites
+                .entry(address)
+                .or_insert_with(|| ReplayedCallsite::of(callsite))
+        };
+        match record? {
+            BinaryRecord::Run { .. } => {
+                callsites.clear();
+                spans.clear();
+            }
+            BinaryRecord::Event {
+                time,
+                callsite,
+                span,
+                fields,
+            } => {
+                let callsite = replayed(&callsite);
+                if !callsite.enabled() {
+                    continue;
+                }
+                let parent = span.and_then(|span| spans.get(&span)?.0.id());
+                timestamp::set_replayed(Some(time));
+                callsite.with_values(&fields, |values| {
+                    Event::child_of(parent, callsite.metadata(), values)
+                });
+                timestamp::set_replayed(None);
+                events += 1;
+            }
+            BinaryRecord::NewSpan {
+                id,
+                time,
+                callsite,
+                parent,
+                fields,
+            } => {
+                let parent = parent.and_then(|parent| spans.get(&parent));
+                let parent = parent.map_or_else(Span::none, |(span, _)| span.clone());
+                let callsite = replayed(&callsite);
+                if !callsite.enabled() {
+                    spans.insert(id, (parent, None));
+                    continue;
+                }
+                timestamp::set_replayed(Some(time));
+                let span = callsite.with_values(&fields, |values| {
+                    Span::child_of(parent.id(), callsite.metadata(), values)
+                });
+                timestamp::set_replayed(None);
+                spans.insert(id, (span, Some(callsite)));
+            }
+            BinaryRecord::Record { id, fields } => {
+                if let Some((span, Some(callsite))) = spans.get(&id) {
+                    callsite.with_values(&fields, |values| span.record_all(values));
+                }
+            }
+            BinaryRecord::Close { id, time } => {
+                timestamp::set_replayed(Some(time));
+                spans.remove(&id);
+                timestamp::set_replayed(None);
+            }
+        }
+    }
+    Ok(events)
+}
+
+/// A callsite of a binary log as replayed, with metadata that lives until the process exits
+struct ReplayedCallsite {
+    recorded: BinaryCallsite,
+    metadata: OnceLock<Metadata<'static>>,
+    fields: OnceLock<Vec<Field>>,
+}
+
+impl ReplayedCallsite {
+    /// The replayed callsite of `recorded`, leaked and registered with `tracing` unless it
+    /// was replayed before
+    fn of(recorded: &BinaryCallsite) -> &'static Self {
+        let mut replayed = REPLAYED.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(callsite) = replayed.iter().find(|known| known.recorded == *recorded) {
+            return callsite;
+        }
+        let callsite: &'static Self = Box::leak(Box::new(Self {
+            recorded: recorded.clone(),
+            metadata: OnceLock::new(),
+            fields: OnceLock::new(),
+        }));
+        let leak = |s: &str| -> &'static str { Box::leak(s.to_owned().into_boxed_str()) };
+        let names: Vec<&'static str> = recorded.fields.iter().map(|name| leak(name)).collect();
+        let metadata = Metadata::new(
+            leak(&recorded.name),
+            leak(&recorded.target),
+            recorded.level,
+            recorded.file.as_deref().map(leak),
+            recorded.line,
+            recorded.module_path.as_deref().map(leak),
+            FieldSet::new(Box::leak(names.into_boxed_slice()), Identifier(callsite)),
+            if recorded.span {
+                Kind::SPAN
+            } else {
+                Kind::EVENT
+            },
+        );
+        let fields = metadata.fields().iter().collect();
+        let _ = callsite.metadata.set(metadata);
+        let _ = callsite.fields.set(fields);
+        tracing::callsite::register(callsite);
+        replayed.push(callsite);
+        callsite
+    }
+
+    fn metadata(&'static self) -> &'static Metadata<'static> {
+        self.metadata
+            .get()
+            .expect("the metadata is set before leaking")
+    }
+
+    /// Whether the default subscriber shows the events or spans of the callsite
+    fn enabled(&'static self) -> bool {
+        dispatcher::get_default(|dispatch| dispatch.enabled(self.metadata()))
+    }
+
+    /// Calls `f` with `fields` as a value set of the callsite
+    fn with_values<T>(
+        &'static self,
+        fields: &[(usize, BinaryValue)],
+        f: impl FnOnce(&ValueSet<'_>) -> T,
+    ) -> T {
+        let field_set = self.metadata().fields();
+        let known = self
+            .fields
+            .get()
+            .expect("the fields are set before leaking");
+        let first = match known.first() {
+            Some(first) => first,
+            None => {
+                let none: [(&Field, Option<&dyn Value>); 0] = [];
+                return f(&field_set.value_set(&none));
+            }
+        };
+        // Messages and other values only known by their rendering are shown as they were
+        let rendered: Vec<_> = fields
+            .iter()
+            .map(|(_, value)| match value {
+                BinaryValue::Debug(rendering) => Some(field::display(rendering)),
+                _ => None,
+            })
+            .collect();
+        // Value sets are arrays, whose unused slots name the first field without a value
+        let mut values: [(&Field, Option<&dyn Value>); MAX_FIELDS] = [(first, None); MAX_FIELDS];
+        let fields = fields.iter().zip(&rendered);
+        for (slot, ((index, value), rendered)) in values.iter_mut().zip(fields) {
+            let field = match known.get(*index) {
+                Some(field) => field,
+                None => continue,
+            };
+            let value: &dyn Value = match (value, rendered) {
+                (_, Some(rendered)) => rendered,
+                (BinaryValue::I64(value), _) => value,
+                (BinaryValue::U64(value), _) => value,
+                (BinaryValue::F64(value), _) => value,
+                (BinaryValue::Bool(value), _) => value,
+                (BinaryValue::Str(value) | BinaryValue::Debug(value), _) => value,
+            };
+            *slot = (field, Some(value));
+        }
+        f(&field_set.value_set(&values))
+    }
+}
+
+impl Callsite for ReplayedCallsite {
+    fn set_interest(&self, _interest: Interest) {}
+
+    fn metadata(&self) -> &Metadata<'_> {
+        self.metadata
+            .get()
+            .expect("the metadata is set before leaking")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use tracing::level_filters::STATIC_MAX_LEVEL;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    /// A fresh path in the temporary directory, removed when dropped
+    struct TempLog(PathBuf);
+
+    impl TempLog {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("binlog_{}_{}.bin", std::process::id(), name));
+            let _ = fs::remove_file(&path);
+            Self(path)
+        }
+
+        /// Appends a run to the log, with the records `log` writes in it
+        fn run(&self, log: impl FnOnce()) {
+            let binary = BinaryLog::open(&self.0, Redaction::default()).unwrap();
+            let subscriber = Registry::default().with(binary.clone());
+            tracing::subscriber::with_default(subscriber, log);
+            binary.sink().drain().unwrap();
+        }
+
+        fn bytes(&self) -> Vec<u8> {
+            fs::read(&self.0).unwrap()
+        }
+    }
+
+    impl Drop for TempLog {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Whether the events logged at `INFO` are compiled in, which the `max_level_*` features
+    /// of tracing may prevent
+    fn logging() -> bool {
+        STATIC_MAX_LEVEL >= Level::INFO
+    }
+
+    fn decode(bytes: &[u8]) -> Vec<io::Result<BinaryRecord>> {
+        decode_binary_log(bytes).collect()
+    }
+
+    fn records(bytes: &[u8]) -> Vec<BinaryRecord> {
+        decode(bytes).into_iter().map(Result::unwrap).collect()
+    }
+
+    /// The values of the fields of `record` by name
+    fn fields(record: &BinaryRecord) -> Vec<(String, BinaryValue)> {
+        let (callsite, fields) = match record {
+            BinaryRecord::Event {
+                callsite, fields, ..
+            }
+            | BinaryRecord::NewSpan {
+                callsite, fields, ..
+            } => (callsite, fields),
+            _ => panic!("{:?} has no callsite", record),
+        };
+        fields
+            .iter()
+            .map(|(index, value)| (callsite.fields()[*index].clone(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn varints_round_trip_at_the_bounds() {
+        for &n in &[
+            0,
+            1,
+            127,
+            128,
+            16_383,
+            16_384,
+            u64::from(u32::MAX),
+            u64::MAX,
+        ] {
+            let mut record = Vec::new();
+            put_varint(&mut record, n);
+            assert_eq!(record.len(), varint(n).1);
+            let mut bytes = Bytes(&record);
+            assert_eq!(bytes.varint().unwrap(), n);
+            assert!(bytes.0.is_empty());
+        }
+        assert_eq!(varint(0).1, 1);
+        assert_eq!(varint(u64::MAX).1, 10);
+    }
+
+    #[test]
+    fn zigzag_round_trips_at_the_bounds() {
+        for &n in &[0, 1, -1, 63, -64, 64, i64::MAX, i64::MIN] {
+            let mut record = Vec::new();
+            put_zigzag(&mut record, n);
+            assert_eq!(Bytes(&record).zigzag().unwrap(), n);
+        }
+        let mut record = Vec::new();
+        put_zigzag(&mut record, -1);
+        assert_eq!(record, [1]);
+    }
+
+    #[test]
+    fn overlong_varint_is_corrupt() {
+        let error = Bytes(&[0xff; 11]).varint().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = Bytes(&[0x80]).varint().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn every_value_round_trips() {
+        if !logging() {
+            return;
+        }
+        let log = TempLog::new("values");
+        log.run(|| {
+            tracing::info!(
+                small = i64::MIN,
+                large = i64::MAX,
+                unsigned = u64::MAX,
+                ratio = -0.25,
+                flag = true,
+                name = "é\"\n",
+                list = ?[1, 2],
+                "hello {}",
+                "world"
+            );
+        });
+        let records = records(&log.bytes());
+        assert!(matches!(records[0], BinaryRecord::Run { .. }));
+        assert_eq!(records.len(), 2);
+        let fields = fields(&records[1]);
+        let expected = [
+            ("message", BinaryValue::Debug("hello world".to_owned())),
+            ("small", BinaryValue::I64(i64::MIN)),
+            ("large", BinaryValue::I64(i64::MAX)),
+            ("unsigned", BinaryValue::U64(u64::MAX)),
+            ("ratio", BinaryValue::F64(-0.25)),
+            ("flag", BinaryValue::Bool(true)),
+            ("name", BinaryValue::Str("é\"\n".to_owned())),
+            ("list", BinaryValue::Debug("[1, 2]".to_owned())),
+        ];
+        for (name, value) in &expected {
+            assert!(
+                fields.contains(&((*name).to_owned(), value.clone())),
+                "{} is missing from {:?}",
+                name,
+                fields
+            );
+        }
+        assert_eq!(fields.len(), expected.len());
+        match &records[1] {
+            BinaryRecord::Event { callsite, span, .. } => {
+                assert_eq!(callsite.level(), Level::INFO);
+                assert!(!callsite.is_span());
+                assert_eq!(callsite.target(), module_path!());
+                assert_eq!(callsite.file(), Some(file!()));
+                assert!(callsite.line().is_some());
+                assert_eq!(*span, None);
+            }
+            record => panic!("expected an event, got {:?}", record),
+        }
+    }
+
+    #[test]
+    fn callsites_are_written_once_per_run() {
+        if !logging() {
+            return;
+        }
+        let log = TempLog::new("interning");
+        log.run(|| {
+            for i in 0..3 {
+                tracing::info!(i, "repeated");
+            }
+        });
+        let bytes = log.bytes();
+        let mut decoder = decode_binary_log(&bytes[..]);
+        let mut kinds = Vec::new();
+        while decoder.read_record().unwrap() {
+            kinds.push(decoder.record[0]);
+        }
+        assert_eq!(kinds, [RUN, CALLSITE, EVENT, EVENT, EVENT]);
+
+        let records = records(&bytes);
+        let callsites: Vec<_> = records
+            .iter()
+            .filter_map(|record| match record {
+                BinaryRecord::Event { callsite, .. } => Some(callsite),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(callsites.len(), 3);
+        assert!(Arc::ptr_eq(callsites[0], callsites[2]));
+        assert_eq!(
+            fields(&records[2])[1],
+            ("i".to_owned(), BinaryValue::I64(1))
+        );
+    }
+
+    #[test]
+    fn nested_spans_keep_their_parents() {
+        if !logging() {
+            return;
+        }
+        let log = TempLog::new("spans");
+        log.run(|| {
+            let outer = tracing::info_span!("outer", id = 1u64);
+            let _outer = outer.enter();
+            let inner = tracing::info_span!("inner", step = tracing::field::Empty);
+            let _inner = inner.enter();
+            inner.record("step", "load");
+            tracing::info!("inside");
+        });
+        let records = records(&log.bytes());
+        let (outer, inner) = match (&records[1], &records[2]) {
+            (
+                BinaryRecord::NewSpan {
+                    id: outer,
+                    parent: None,
+                    callsite,
+                    ..
+                },
+                BinaryRecord::NewSpan {
+                    id: inner,
+                    parent: Some(parent),
+                    ..
+                },
+            ) => {
+                assert_eq!(callsite.name(), "outer");
+                assert!(callsite.is_span());
+                assert_eq!(parent, outer);
+                (*outer, *inner)
+            }
+            records => panic!("expected two spans, got {:?}", records),
+        };
+        assert_eq!(
+            fields(&records[1]),
+            [("id".to_owned(), BinaryValue::U64(1))]
+        );
+        assert_eq!(
+            records[3],
+            BinaryRecord::Record {
+                id: inner,
+                fields: vec![(0, BinaryValue::Str("load".to_owned()))],
+            }
+        );
+        assert!(matches!(
+            records[4],
+            BinaryRecord::Event { span: Some(span), .. } if span == inner
+        ));
+        // The inner span closes first
+        let closed: Vec<_> = records[5..]
+            .iter()
+            .map(|record| match record {
+                BinaryRecord::Close { id, .. } => *id,
+                record => panic!("expected a close, got {:?}", record),
+            })
+            .collect();
+        assert_eq!(closed, [inner, outer]);
+    }
+
+    #[test]
+    fn runs_appended_start_over() {
+        if !logging() {
+            return;
+        }
+        let log = TempLog::new("runs");
+        log.run(|| tracing::info!("first"));
+        log.run(|| {
+            tracing::warn!("second");
+            tracing::info!("first");
+        });
+        let records = records(&log.bytes());
+        let kinds: Vec<_> = records
+            .iter()
+            .map(|record| match record {
+                BinaryRecord::Run { .. } => "run",
+                BinaryRecord::Event { callsite, .. } => callsite.level().as_str(),
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(kinds, ["run", "INFO", "run", "WARN", "INFO"]);
+
+        // The second run numbers its callsites from 0 again
+        let bytes = log.bytes();
+        let mut decoder = decode_binary_log(&bytes[..]);
+        let mut numbers = Vec::new();
+        while decoder.read_record().unwrap() {
+            if decoder.record[0] == CALLSITE {
+                numbers.push(Bytes(&decoder.record[1..]).varint().unwrap());
+            }
+        }
+        assert_eq!(numbers, [0, 0, 1]);
+
+        let times: Vec<_> = records
+            .iter()
+            .map(|record| match record {
+                BinaryRecord::Run { time } | BinaryRecord::Event { time, .. } => *time,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn truncated_tail_is_an_error() {
+        if !logging() {
+            return;
+        }
+        let log = TempLog::new("truncated");
+        log.run(|| {
+            let span = tracing::info_span!("work", n = 3);
+            let _entered = span.enter();
+            tracing::info!(text = "some text", "done");
+        });
+        let bytes = log.bytes();
+        let complete = records(&bytes).len();
+        // Every cut decodes the records before it, then fails rather than panicking
+        for end in 1..bytes.len() {
+            let decoded = decode(&bytes[..end]);
+            let (last, before) = decoded.split_last().unwrap();
+            assert!(before.iter().all(Result::is_ok), "cut at {}", end);
+            if let Err(e) = last {
+                assert!(
+                    matches!(
+                        e.kind(),
+                        io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData
+                    ),
+                    "cut at {}: {}",
+                    end,
+                    e
+                );
+            } else {
+                assert!(decoded.len() < complete, "cut at {}", end);
+            }
+        }
+        let cut = decode(&bytes[..bytes.len() - 1]);
+        let e = cut.last().unwrap().as_ref().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn corrupt_tail_is_an_error() {
+        if !logging() {
+            return;
+        }
+        let log = TempLog::new("corrupt");
+        log.run(|| tracing::info!("fine"));
+        let bytes = log.bytes();
+        let tails: [&[u8]; 4] = [
+            // A record of an unknown kind
+            &[2, 9, 0],
+            // An event at a callsite never written
+            &[4, EVENT, 7, 0, 0],
+            // A record longer than any written
+            &[0xff, 0xff, 0xff, 0xff, 0x7f],
+            // A value of an unknown type
+            &[5, EVENT, 0, 0, 0, 0, 9],
+        ];
+        for tail in &tails {
+            let mut corrupt = bytes.clone();
+            corrupt.extend_from_slice(tail);
+            let decoded = decode(&corrupt);
+            let (last, before) = decoded.split_last().unwrap();
+            assert_eq!(before.len(), 2, "{:?}", tail);
+            assert!(before.iter().all(Result::is_ok));
+            let e = last.as_ref().unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{:?}: {}", tail, e);
+        }
+    }
+
+    #[test]
+    fn other_files_are_refused() {
+        let decoded = decode(b"\x05hello");
+        assert_eq!(decoded.len(), 1);
+        let e = decoded[0].as_ref().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(decode(b"").is_empty());
+
+        let mut run = vec![RUN];
+        run.extend_from_slice(MAGIC);
+        run.push(VERSION + 1);
+        run.push(0);
+        let mut bytes = vec![run.len() as u8];
+        bytes.extend_from_slice(&run);
+        let e = decode(&bytes).remove(0).unwrap_err();
+        assert!(e.to_string().contains("version"), "{}", e);
+    }
+}
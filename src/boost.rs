@@ -0,0 +1,212 @@
+use crate::fields::SpanFields;
+use std::cell::Cell;
+use std::fmt;
+use std::str::FromStr;
+use tracing::field::FieldSet;
+use tracing::metadata::Kind;
+use tracing::{Event, Level, Metadata};
+
+thread_local! {
+    /// The least severe level that the filters show the event being dispatched on this thread
+    /// at, when they only let it through for the rules that may raise its level
+    static PENDING: Cell<Option<Level>> = const { Cell::new(None) };
+    /// The level the event being formatted on this thread was raised to
+    static RAISED: Cell<Option<Level>> = const { Cell::new(None) };
+}
+
+/// Raises the level of the events whose fields meet a condition, see
+/// [`Builder::boost_level`](crate::Builder::boost_level)
+///
+/// Parsed from a condition on a field of the event and the level to raise it to, separated by a
+/// colon, such as `retry_count>3:warn` or `error=true:warn`. The condition is the name of the
+/// field, to raise events where it is present, or the name followed by `=` or `!=` and a value,
+/// or by `>`, `>=`, `<` or `<=` and a number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoostRule {
+    field: String,
+    condition: Condition,
+    level: Level,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Present,
+    Is(String),
+    IsNot(String),
+    Above(f64),
+    AtLeast(f64),
+    Below(f64),
+    AtMost(f64),
+}
+
+impl BoostRule {
+    /// Raises events meeting `condition`, such as `retry_count>3`, to `level`
+    pub fn new(condition: &str, level: Level) -> Result<Self, ParseBoostRuleError> {
+        let error = || ParseBoostRuleError(condition.to_owned());
+        let operator = condition.find(['=', '!', '>', '<']);
+        let (field, condition) = match operator {
+            None => (condition.trim(), Condition::Present),
+            Some(at) => {
+                let (field, rest) = condition.split_at(at);
+                let number = |value: &str| value.trim().parse::<f64>().map_err(|_| error());
+                let condition = if let Some(value) = rest.strip_prefix("!=") {
+                    Condition::IsNot(unquote(value).to_owned())
+                } else if let Some(value) = rest.strip_prefix(">=") {
+                    Condition::AtLeast(number(value)?)
+                } else if let Some(value) = rest.strip_prefix("<=") {
+                    Condition::AtMost(number(value)?)
+                } else if let Some(value) = rest.strip_prefix('>') {
+                    Condition::Above(number(value)?)
+                } else if let Some(value) = rest.strip_prefix('<') {
+                    Condition::Below(number(value)?)
+                } else if let Some(value) = rest.strip_prefix('=') {
+                    Condition::Is(unquote(value).to_owned())
+                } else {
+                    return Err(error());
+                };
+                (field.trim(), condition)
+            }
+        };
+        if field.is_empty() {
+            return Err(error());
+        }
+        Ok(Self {
+            field: field.to_owned(),
+            condition,
+            level,
+        })
+    }
+
+    fn matches(&self, fields: &SpanFields) -> bool {
+        let value = match fields.get(&self.field) {
+            Some(value) => value,
+            None => return false,
+        };
+        let number = || value.parse::<f64>().ok();
+        match &self.condition {
+            Condition::Present => true,
+            Condition::Is(expected) => value == expected,
+            Condition::IsNot(unexpected) => value != unexpected,
+            Condition::Above(limit) => number().is_some_and(|n| n > *limit),
+            Condition::AtLeast(limit) => number().is_some_and(|n| n >= *limit),
+            Condition::Below(limit) => number().is_some_and(|n| n < *limit),
+            Condition::AtMost(limit) => number().is_some_and(|n| n <= *limit),
+        }
+    }
+}
+
+/// Accepts the quotes that the `Debug` field style prints around strings
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+impl fmt::Display for BoostRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.field)?;
+        match &self.condition {
+            Condition::Present => {}
+            Condition::Is(value) => write!(f, "={}", value)?,
+            Condition::IsNot(value) => write!(f, "!={}", value)?,
+            Condition::Above(limit) => write!(f, ">{}", limit)?,
+            Condition::AtLeast(limit) => write!(f, ">={}", limit)?,
+            Condition::Below(limit) => write!(f, "<{}", limit)?,
+            Condition::AtMost(limit) => write!(f, "<={}", limit)?,
+        }
+        write!(f, ":{}", self.level.as_str().to_ascii_lowercase())
+    }
+}
+
+/// Error returned when a [`BoostRule`] can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBoostRuleError(String);
+
+impl fmt::Display for ParseBoostRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid boost rule `{}`, expected a condition such as `retry_count>3` and a level, \
+             as in `retry_count>3:warn`",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseBoostRuleError {}
+
+impl FromStr for BoostRule {
+    type Err = ParseBoostRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseBoostRuleError(s.to_owned());
+        let (condition, level) = s.rsplit_once(':').ok_or_else(error)?;
+        let level = level.trim().parse::<Level>().map_err(|_| error())?;
+        Self::new(condition, level).map_err(|_| error())
+    }
+}
+
+/// The levels, more severe than its own, that the rules may raise events of `metadata` to,
+/// judging by the fields its callsite declares
+pub(crate) fn candidates<'a>(
+    rules: &'a [BoostRule],
+    metadata: &'a Metadata<'_>,
+) -> impl Iterator<Item = Level> + 'a {
+    rules
+        .iter()
+        .filter(move |rule| {
+            rule.level < *metadata.level() && metadata.fields().field(&rule.field).is_some()
+        })
+        .map(|rule| rule.level)
+}
+
+/// `metadata` with its level replaced by `level`, to ask the filters about it
+pub(crate) fn at_level<'a>(metadata: &Metadata<'a>, level: Level) -> Metadata<'a> {
+    Metadata::new(
+        metadata.name(),
+        metadata.target(),
+        level,
+        metadata.file(),
+        metadata.line(),
+        metadata.module_path(),
+        FieldSet::new(&[], metadata.callsite()),
+        Kind::EVENT,
+    )
+}
+
+/// The most severe level, above its own, that the rules raise `event` to
+pub(crate) fn raised(rules: &[BoostRule], event: &Event<'_>) -> Option<Level> {
+    let metadata = event.metadata();
+    // Only the fields the rules look at are rendered, and only if the callsite declares one
+    candidates(rules, metadata).next()?;
+    let fields =
+        SpanFields::record_selected(event, |name| rules.iter().any(|rule| rule.field == name));
+    rules
+        .iter()
+        .filter(|rule| rule.level < *metadata.level() && rule.matches(&fields))
+        .map(|rule| rule.level)
+        .min()
+}
+
+/// Notes the level the event being dispatched has to be raised to for the filters to show it,
+/// or `None` if they show it anyway
+pub(crate) fn set_pending(level: Option<Level>) {
+    PENDING.with(|pending| pending.set(level));
+}
+
+/// Takes the level noted by [`set_pending`] for the event being dispatched
+pub(crate) fn take_pending() -> Option<Level> {
+    PENDING.with(Cell::take)
+}
+
+/// Notes the level the event being formatted is shown at, or `None` once it is formatted
+pub(crate) fn set_raised(level: Option<Level>) {
+    RAISED.with(|raised| raised.set(level));
+}
+
+/// The level to show the event being formatted at, `level` unless a rule raised it
+pub(crate) fn shown(level: Level) -> Level {
+    RAISED.with(Cell::get).unwrap_or(level)
+}
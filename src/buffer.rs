@@ -0,0 +1,111 @@
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+/// How many bytes are held at most before they are written without waiting for the interval
+const CAPACITY: usize = 64 * 1024;
+
+/// Buffers flushed when the process exits without shutting logging down, such as with
+/// `std::process::exit`
+#[cfg(unix)]
+static AT_EXIT: Mutex<Vec<Weak<Buffer>>> = Mutex::new(Vec::new());
+
+type WriteFn = Box<dyn Fn(&[u8]) -> io::Result<usize> + Send + Sync>;
+
+/// Output held in memory and written in chunks, so that programs logging a lot don't pay for a
+/// write per event, see [`Builder::buffer_output`](crate::Builder::buffer_output)
+pub(crate) struct Buffer {
+    pending: Mutex<Vec<u8>>,
+    write: WriteFn,
+}
+
+impl Buffer {
+    /// Starts the thread writing out the buffer every `interval` with `write`
+    pub(crate) fn start<W>(interval: Duration, write: W) -> Arc<Self>
+    where
+        W: Fn(&[u8]) -> io::Result<usize> + Send + Sync + 'static,
+    {
+        let buffer = Arc::new(Self {
+            pending: Mutex::new(Vec::with_capacity(CAPACITY)),
+            write: Box::new(write),
+        });
+        let weak = Arc::downgrade(&buffer);
+        thread::Builder::new()
+            .name("log flusher".to_owned())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                // Stops once logging is shut down and the buffer dropped
+                match weak.upgrade() {
+                    Some(buffer) => {
+                        let _ = buffer.flush();
+                    }
+                    None => return,
+                }
+            })
+            .expect("failed to spawn the log flusher thread");
+        #[cfg(unix)]
+        {
+            let mut buffers = AT_EXIT.lock().unwrap_or_else(|e| e.into_inner());
+            if buffers.is_empty() {
+                // SAFETY: `flush_at_exit` is an `extern "C"` function that doesn't unwind
+                unsafe { libc::atexit(flush_at_exit) };
+            }
+            buffers.push(Arc::downgrade(&buffer));
+        }
+        buffer
+    }
+
+    /// Appends `buf`, writing out the buffer if it is full
+    pub(crate) fn push(&self, buf: &[u8]) -> io::Result<()> {
+        let mut pending = self.lock();
+        pending.extend_from_slice(buf);
+        if pending.len() < CAPACITY {
+            return Ok(());
+        }
+        self.write_out(&mut pending)
+    }
+
+    /// Writes out everything held so far
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        self.write_out(&mut self.lock())
+    }
+
+    /// Writes while the buffer is locked, so chunks are written in order
+    fn write_out(&self, pending: &mut Vec<u8>) -> io::Result<()> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let result = (self.write)(pending);
+        pending.clear();
+        result.map(drop)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<u8>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Buffer")
+            .field("pending", &self.lock().len())
+            .finish()
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn flush_at_exit() {
+    // Another thread may hold the locks when the process exits, in which case waiting for it
+    // would never end
+    let buffers = match AT_EXIT.try_lock() {
+        Ok(buffers) => buffers,
+        Err(_) => return,
+    };
+    for buffer in buffers.iter().filter_map(Weak::upgrade) {
+        if let Ok(mut pending) = buffer.pending.try_lock() {
+            let _ = buffer.write_out(&mut pending);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::wrap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::subscriber::DefaultGuard;
+use tracing::Level;
+
+/// An event written by the subscriber installed with [`capture`](crate::capture), as it would
+/// have been formatted, without colors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedEvent {
+    level: Level,
+    target: String,
+    text: String,
+}
+
+impl CapturedEvent {
+    /// The level of the event, or of the span for the lines of closed spans
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// The target of the event, usually the module it was logged from
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The formatted event, without the final newline
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl fmt::Display for CapturedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// The buffer the formatted output goes to instead of stderr
+#[derive(Debug, Default)]
+pub(crate) struct Memory(Mutex<Vec<CapturedEvent>>);
+
+impl Memory {
+    pub(crate) fn write(&self, level: Level, target: &str, buf: &[u8]) {
+        let text = wrap::strip_escapes(&String::from_utf8_lossy(buf));
+        let event = CapturedEvent {
+            level,
+            target: target.to_owned(),
+            text: text.trim_end_matches('\n').to_owned(),
+        };
+        self.lock().push(event);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<CapturedEvent>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Keeps the events logged on the current thread in memory until dropped, see
+/// [`capture`](crate::capture)
+///
+/// ```
+/// use pretty_tracing_subscriber::capture;
+/// use tracing::Level;
+/// # // The `max_level_*` features of tracing may compile the event out
+/// # if tracing::level_filters::STATIC_MAX_LEVEL < Level::WARN {
+/// #     return;
+/// # }
+///
+/// let logs = capture();
+/// tracing::warn!(path = "config.toml", "falling back to the defaults");
+/// logs.assert_logged(Level::WARN, "falling back");
+/// assert_eq!(logs.at_level(Level::ERROR).len(), 0);
+/// ```
+#[must_use = "events are only captured while the handle is alive"]
+pub struct Capture {
+    memory: Arc<Memory>,
+    _guard: DefaultGuard,
+}
+
+impl Capture {
+    pub(crate) fn new(memory: Arc<Memory>, guard: DefaultGuard) -> Self {
+        Self {
+            memory,
+            _guard: guard,
+        }
+    }
+
+    /// The events captured so far, in the order they were logged
+    pub fn events(&self) -> Vec<CapturedEvent> {
+        self.memory.lock().clone()
+    }
+
+    /// The events captured so far at `level`
+    pub fn at_level(&self, level: Level) -> Vec<CapturedEvent> {
+        self.filtered(|event| event.level == level)
+    }
+
+    /// The events captured so far with the target `target` or one in a module under it
+    pub fn with_target(&self, target: &str) -> Vec<CapturedEvent> {
+        self.filtered(|event| {
+            event
+                .target
+                .strip_prefix(target)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        })
+    }
+
+    /// The whole formatted output so far, one event per line
+    pub fn text(&self) -> String {
+        let events = self.memory.lock();
+        events.iter().map(|event| format!("{}\n", event)).collect()
+    }
+
+    /// Forgets the events captured so far
+    pub fn clear(&self) {
+        self.memory.lock().clear();
+    }
+
+    /// Asserts that an event was logged at `level` with `text` in its formatted output, such as
+    /// part of its message or a field like `user=alice`
+    ///
+    /// # Panics
+    ///
+    /// Panics with the events captured so far if there is none.
+    #[track_caller]
+    pub fn assert_logged(&self, level: Level, text: &str) {
+        if self
+            .at_level(level)
+            .iter()
+            .any(|event| event.text.contains(text))
+        {
+            return;
+        }
+        let events = self.text();
+        if events.is_empty() {
+            panic!(
+                "expected an event at {} with `{}`, but nothing was logged",
+                level, text
+            );
+        }
+        panic!(
+            "expected an event at {} with `{}`, but the events were:\n{}",
+            level, text, events
+        );
+    }
+
+    fn filtered(&self, keep: impl Fn(&CapturedEvent) -> bool) -> Vec<CapturedEvent> {
+        let events = self.memory.lock();
+        events.iter().filter(|event| keep(event)).cloned().collect()
+    }
+}
+
+impl fmt::Debug for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Capture")
+            .field("events", &self.memory.lock().len())
+            .finish()
+    }
+}
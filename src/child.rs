@@ -0,0 +1,139 @@
+use crate::wrap;
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use tracing::{Level, Span};
+
+/// Target of the events emitted by [`LoggedChild`]
+const TARGET: &str = "pretty_tracing_subscriber::child";
+
+/// A child process whose output is logged, each line of its stdout and stderr becoming an event
+///
+/// The events have the name and process ID of the child in the `child` and `pid` fields, and
+/// the stream the line was written to in the `stream` field. Their level is guessed from the
+/// first words of the line, so `error: disk full`, `[WARN] retrying` and `level=debug msg=...`
+/// are logged at the level they name, and other lines as `info` events. They are emitted in the
+/// span current when the child was spawned.
+///
+/// ```no_run
+/// # use pretty_tracing_subscriber::LoggedChild;
+/// # use std::process::Command;
+/// let child = LoggedChild::spawn("make", Command::new("make").arg("all"))?;
+/// let status = child.wait()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct LoggedChild {
+    child: Child,
+    readers: Vec<JoinHandle<()>>,
+}
+
+impl LoggedChild {
+    /// Spawns `command` with its stdout and stderr captured, logging their lines under `name`
+    pub fn spawn(name: impl Into<String>, command: &mut Command) -> io::Result<Self> {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let name = name.into();
+        let pid = child.id();
+        let mut readers = Vec::with_capacity(2);
+        if let Some(stdout) = child.stdout.take() {
+            readers.push(forward(stdout, &name, pid, "stdout")?);
+        }
+        if let Some(stderr) = child.stderr.take() {
+            readers.push(forward(stderr, &name, pid, "stderr")?);
+        }
+        Ok(Self { child, readers })
+    }
+
+    /// The process ID of the child
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// The child, to write to its stdin or check whether it has exited
+    pub fn child_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+
+    /// Kills the child, whose last lines are still logged by [`wait`](Self::wait)
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.child.kill()
+    }
+
+    /// Waits for the child to exit and for every line of its output to be logged
+    pub fn wait(mut self) -> io::Result<ExitStatus> {
+        let status = self.child.wait()?;
+        for reader in self.readers {
+            let _ = reader.join();
+        }
+        Ok(status)
+    }
+}
+
+/// Logs each line read from `output` on a thread of its own
+fn forward(
+    output: impl Read + Send + 'static,
+    name: &str,
+    pid: u32,
+    stream: &'static str,
+) -> io::Result<JoinHandle<()>> {
+    let name = name.to_owned();
+    let span = Span::current();
+    thread::Builder::new()
+        .name(format!("{} {}", name, stream))
+        .spawn(move || {
+            let _entered = span.enter();
+            let mut output = BufReader::new(output);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match output.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                let text = wrap::strip_escapes(&String::from_utf8_lossy(&line));
+                let text = text.trim_end();
+                if !text.trim_start().is_empty() {
+                    emit(guess_level(text), &name, pid, stream, text);
+                }
+            }
+        })
+}
+
+fn emit(level: Level, name: &str, pid: u32, stream: &str, line: &str) {
+    macro_rules! emit {
+        ($level:expr) => {
+            tracing::event!(target: TARGET, $level, child = name, pid, stream, "{}", line)
+        };
+    }
+    match level {
+        Level::ERROR => emit!(Level::ERROR),
+        Level::WARN => emit!(Level::WARN),
+        Level::INFO => emit!(Level::INFO),
+        Level::DEBUG => emit!(Level::DEBUG),
+        Level::TRACE => emit!(Level::TRACE),
+    }
+}
+
+/// The level named by one of the first three words of `line`, ignoring case and the brackets,
+/// colons and `level=` around it, or `info`
+fn guess_level(line: &str) -> Level {
+    line.split_whitespace()
+        .take(3)
+        .find_map(|word| {
+            let word = word.trim_matches(|c: char| !c.is_alphanumeric() && c != '=');
+            let word = word.strip_prefix("level=").unwrap_or(word);
+            let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+            match word.to_ascii_lowercase().as_str() {
+                "error" | "err" | "fatal" | "critical" | "crit" | "panic" => Some(Level::ERROR),
+                "warn" | "warning" => Some(Level::WARN),
+                "info" | "notice" => Some(Level::INFO),
+                "debug" => Some(Level::DEBUG),
+                "trace" => Some(Level::TRACE),
+                _ => None,
+            }
+        })
+        .unwrap_or(Level::INFO)
+}
@@ -0,0 +1,180 @@
+use std::fmt;
+use tracing_subscriber::filter::LevelFilter;
+
+/// Level and target filters from one source of configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogSettings {
+    /// Level of events from targets that no directive matches
+    pub level: Option<LevelFilter>,
+    /// Filter directives in `env_logger` format, such as `my_app::db=trace`
+    ///
+    /// Directives can also select spans by name, and by the value of one of their fields, as
+    /// `EnvFilter` does: `[request]=debug` shows `DEBUG` events inside `request` spans, and
+    /// `my_app[request{method=POST}]=trace` shows every event of `my_app` inside `request`
+    /// spans whose `method` is `POST`, so one operation can be looked into without the noise of
+    /// all others. Directives selecting several fields are ignored, as `EnvFilter` can't match
+    /// them, while [`Builder::filter_field`](crate::Builder::filter_field) can narrow the events
+    /// down further.
+    ///
+    /// `!my_app::poller` hides every event of `my_app::poller` and the modules under it,
+    /// whatever the level and the other directives of any source, such as to mute a chatty
+    /// module while everything else is shown at `TRACE`.
+    pub directives: Vec<String>,
+}
+
+impl LogSettings {
+    /// Settings made of the comma-separated directives in `filters`, such as the value of an
+    /// environment variable
+    pub fn from_directives(filters: &str) -> Self {
+        Self {
+            level: None,
+            directives: split_directives(filters),
+        }
+    }
+
+    /// Whether the settings leave the level to sources of lower precedence, as they do when
+    /// they only mute targets
+    fn is_empty(&self) -> bool {
+        let muting = |directive: &String| muted_target(directive).is_some();
+        self.level.is_none() && self.directives.iter().all(muting)
+    }
+}
+
+/// Displays the settings as filter directives, such as `info,my_app::db=trace`
+impl fmt::Display for LogSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = self.level.map(|level| level.to_string());
+        let directives: Vec<_> = level.iter().chain(&self.directives).cloned().collect();
+        if directives.is_empty() {
+            f.write_str("(none)")
+        } else {
+            f.write_str(&directives.join(","))
+        }
+    }
+}
+
+/// The filter settings from every source, combined by [`resolve`](Self::resolve)
+///
+/// From lowest to highest precedence, the sources are the defaults in code, the application's
+/// configuration file, the filter presets enabled by name, the log environment variable and the
+/// command line:
+///
+/// - The level comes from the source of highest precedence that sets a level or any directives.
+///   Directives on the command line therefore replace the default level rather than add to it,
+///   so `--log my_app::db=trace` only shows that module. Presets only decide the level when
+///   they set one, so `--log-preset quiet-deps` keeps the level of the other sources.
+/// - The directives of every source apply, and those of higher precedence win for the same
+///   target.
+/// - Targets muted by any source, such as with `--log-mute`, are hidden whatever the other
+///   directives.
+///
+/// Returned by [`Builder::log_config`](crate::Builder::log_config), and shown by the
+/// `--log-print-config` option.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogConfig {
+    /// The default level of the [`VerbosityMapping`](crate::VerbosityMapping) and the caps of
+    /// [`Builder::quiet_dependencies`](crate::Builder::quiet_dependencies)
+    pub defaults: LogSettings,
+    /// Settings read by the application, see
+    /// [`Builder::file_settings`](crate::Builder::file_settings)
+    pub file: LogSettings,
+    /// The filter presets enabled with `--log-preset`, see
+    /// [`Builder::filter_preset`](crate::Builder::filter_preset)
+    pub presets: LogSettings,
+    /// The log environment variable
+    pub env: LogSettings,
+    /// `--verbose`, `--quiet` and `--log`
+    pub cli: LogSettings,
+    /// `--silent`, which turns every other source off
+    pub silent: bool,
+}
+
+impl LogConfig {
+    /// Combines the sources into the settings in effect
+    pub fn resolve(&self) -> LogSettings {
+        if self.silent {
+            return LogSettings {
+                level: Some(LevelFilter::OFF),
+                directives: Vec::new(),
+            };
+        }
+        // Presets add to the level of the other sources unless they set one
+        let presets_level = LogSettings {
+            level: self.presets.level,
+            directives: Vec::new(),
+        };
+        let levels = [
+            &self.defaults,
+            &self.file,
+            &presets_level,
+            &self.env,
+            &self.cli,
+        ];
+        let level = levels
+            .iter()
+            .rev()
+            .find(|source| !source.is_empty())
+            .and_then(|source| source.level);
+        let sources = [
+            &self.defaults,
+            &self.file,
+            &self.presets,
+            &self.env,
+            &self.cli,
+        ];
+        let directives = sources
+            .iter()
+            .flat_map(|source| source.directives.iter().cloned())
+            .collect();
+        LogSettings { level, directives }
+    }
+}
+
+impl fmt::Display for LogConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "defaults:    {}", self.defaults)?;
+        writeln!(f, "file:        {}", self.file)?;
+        writeln!(f, "presets:     {}", self.presets)?;
+        writeln!(f, "environment: {}", self.env)?;
+        if self.silent {
+            writeln!(f, "command:     --silent")?;
+        } else {
+            writeln!(f, "command:     {}", self.cli)?;
+        }
+        write!(f, "effective:   {}", self.resolve())
+    }
+}
+
+/// The target that `directive` mutes, if it is one like `!my_app::poller`
+pub(crate) fn muted_target(directive: &str) -> Option<&str> {
+    directive.strip_prefix('!').map(str::trim)
+}
+
+/// Splits comma-separated filter directives, skipping empty ones
+///
+/// Commas between the brackets of span directives, such as `[request{method=POST,path=/}]`,
+/// separate the fields of the span rather than directives, so those are kept whole and
+/// reported as such.
+pub(crate) fn split_directives(filters: &str) -> Vec<String> {
+    let mut directives = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in filters.char_indices() {
+        match c {
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                directives.push(&filters[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    directives.push(&filters[start..]);
+    directives
+        .into_iter()
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
@@ -0,0 +1,359 @@
+use crate::config::LogSettings;
+use crate::levels::LevelStyle;
+use crate::{Builder, Locale, LogFormat, LogGuard, Output, TimePrecision, TimeSource, Verbosity};
+use serde::de::{Deserializer, Error as _};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+use tracing_subscriber::filter::LevelFilter;
+
+/// Logging options read from a configuration file, with the `config-file` feature
+///
+/// Every option is optional, and those of the command line take precedence, as described by
+/// [`LogConfig`](crate::LogConfig). Files are read as TOML by [`read`](Self::read), and the
+/// struct can be deserialized from any other format serde supports, such as YAML.
+///
+/// ```toml
+/// level = "info"
+/// format = "pretty"
+/// level-style = "short"
+/// tree = true
+/// presets = ["http", "db"]
+/// redact = ["password", "*_token"]
+/// file = "/var/log/my_app.log"
+/// time-format = "%Y-%m-%d %H:%M:%S%.3f"
+///
+/// [targets]
+/// "my_app::db" = "debug"
+/// hyper = "warn"
+///
+/// [outputs]
+/// log-file = "debug"
+/// ```
+///
+/// Applications with a configuration of their own can embed the options in it, with the
+/// `#[serde(flatten)]` attribute or under a key of their choice, and set up logging with
+/// [`init`](Self::init). Unknown keys are ignored, so that they don't clash with the
+/// application's.
+///
+/// ```no_run
+/// # #[cfg(feature = "cli")] {
+/// # use pretty_tracing_subscriber::{FileConfig, Verbosity};
+/// # use structopt::StructOpt;
+/// #[derive(serde::Deserialize)]
+/// struct Settings {
+///     listen: String,
+///     #[serde(flatten)]
+///     log: FileConfig,
+/// }
+///
+/// # let text = "";
+/// let settings: Settings = toml::from_str(text).expect("invalid settings");
+/// let _guard = settings.log.init("my_app", Verbosity::from_args());
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct FileConfig {
+    /// Level of events from targets that `targets` doesn't list
+    #[serde(
+        deserialize_with = "level",
+        serialize_with = "serialize_level",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub level: Option<LevelFilter>,
+    /// Levels of targets and the modules under them
+    #[serde(
+        deserialize_with = "target_levels",
+        serialize_with = "serialize_target_levels",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub targets: BTreeMap<String, LevelFilter>,
+    /// How events are written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<LogFormat>,
+    /// How levels are labelled, see [`Builder::level_style`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level_style: Option<LevelStyle>,
+    /// Shows spans as a tree, see [`Builder::tree`]
+    pub tree: bool,
+    /// Aligns columns, see [`Builder::aligned`]
+    pub aligned: bool,
+    /// Names of the spans whose events are written as one block, see [`Builder::group_spans`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub group_spans: Vec<String>,
+    /// Shows the spans of events in headers when they change, see [`Builder::span_headers`]
+    pub span_headers: bool,
+    /// Presets to enable, of formatting such as `http` or of filters such as `quiet-deps`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub presets: Vec<String>,
+    /// Patterns of field names whose values are redacted, see [`Builder::redact`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub redact: Vec<String>,
+    /// Caps the levels of noisy dependencies, see [`Builder::quiet_dependencies`]
+    pub quiet_dependencies: bool,
+    /// Also writes the output to this file, like `--log-file`, which takes precedence
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Also sends events to the syslog daemon at this address, see [`Builder::syslog`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub syslog: Option<String>,
+    /// Also sends events to Graylog at this address, see [`Builder::gelf`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gelf: Option<String>,
+    /// Also serves events to log viewers connecting to this address, see
+    /// [`Builder::event_stream`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_stream: Option<String>,
+    /// Filters of outputs that get events whether or not they are shown, such as
+    /// `log-file = "debug"`, see [`Builder::output_filter`]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub outputs: BTreeMap<Output, String>,
+    /// Where the times shown come from, see [`Builder::time_source`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_source: Option<TimeSource>,
+    /// How finely times are shown, see [`Builder::time_precision`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_precision: Option<TimePrecision>,
+    /// Shows the time since the previous event, see [`Builder::event_deltas`]
+    pub event_deltas: bool,
+    /// Shows how long the span of each event has been open, see [`Builder::span_elapsed`]
+    pub span_elapsed: bool,
+    /// The strftime format of times, see [`Builder::time_format`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_format: Option<String>,
+    /// Shows times at this many minutes east of UTC, see [`Builder::utc_offset`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utc_offset: Option<i32>,
+    /// The locale of times and durations, such as `de_DE`, or `auto` for that of the process,
+    /// see [`Builder::locale`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// Prefixes of source paths and those to show instead, see [`Builder::path_prefix`]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub path_prefixes: BTreeMap<String, String>,
+}
+
+impl FileConfig {
+    /// Reads the options from the TOML file at `path`
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, ConfigFileError> {
+        let path = path.as_ref();
+        let error = |kind| ConfigFileError {
+            path: path.to_owned(),
+            kind,
+        };
+        let text = fs::read_to_string(path).map_err(|e| error(ErrorKind::Io(e)))?;
+        toml::from_str(&text).map_err(|e| error(ErrorKind::Parse(e)))
+    }
+
+    /// The level and target filters, for [`Builder::file_settings`]
+    pub fn settings(&self) -> LogSettings {
+        LogSettings {
+            level: self.level,
+            directives: self
+                .targets
+                .iter()
+                .map(|(target, level)| format!("{}={}", target, level))
+                .collect(),
+        }
+    }
+
+    /// Initialises [`tracing_subscriber`] like [`init_with_guard`](crate::init_with_guard), with
+    /// these options
+    ///
+    /// # Panics
+    ///
+    /// Panics if the time format or UTC offset is invalid.
+    pub fn init(self, root_module: impl Into<String>, verbosity: Verbosity) -> LogGuard {
+        Builder::new(root_module, verbosity)
+            .config_file(self)
+            .init_with_guard()
+    }
+}
+
+impl Builder {
+    /// Applies the options read from a configuration file, see [`FileConfig`]
+    ///
+    /// # Panics
+    ///
+    /// Panics if the time format or UTC offset is invalid.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, FileConfig, Verbosity};
+    /// # use structopt::StructOpt;
+    /// let config = FileConfig::read("/etc/my_app/logging.toml").unwrap_or_default();
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .config_file(config)
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn config_file(mut self, config: FileConfig) -> Self {
+        self = self.file_settings(config.settings());
+        if let Some(format) = config.format {
+            self = self.log_format(format);
+        }
+        if let Some(style) = config.level_style {
+            self = self.level_style(style);
+        }
+        if config.tree {
+            self = self.tree();
+        }
+        if config.aligned {
+            self = self.aligned();
+        }
+        for name in &config.group_spans {
+            self = self.group_spans(name);
+        }
+        if config.span_headers {
+            self = self.span_headers();
+        }
+        for preset in &config.presets {
+            self = self.enable_preset(preset);
+        }
+        for pattern in &config.redact {
+            self = self.redact(pattern);
+        }
+        if config.quiet_dependencies {
+            self = self.quiet_dependencies();
+        }
+        if self.verbosity.log_file.is_none() {
+            self.verbosity.log_file = config.file;
+        }
+        if let Some(address) = config.syslog {
+            self = self.syslog(address);
+        }
+        if let Some(address) = config.gelf {
+            self = self.gelf(address);
+        }
+        if let Some(address) = config.event_stream {
+            self = self.event_stream(address);
+        }
+        for (output, filter) in config.outputs {
+            self = self.output_filter(output, filter);
+        }
+        if let Some(source) = config.time_source {
+            self = self.time_source(source);
+        }
+        if let Some(precision) = config.time_precision {
+            self = self.time_precision(precision);
+        }
+        if config.event_deltas {
+            self = self.event_deltas();
+        }
+        if config.span_elapsed {
+            self = self.span_elapsed();
+        }
+        match config.locale.as_deref() {
+            Some("auto") => self = self.locale(Locale::from_env()),
+            Some(tag) => self = self.locale(Locale::new(tag)),
+            None => {}
+        }
+        for (from, to) in &config.path_prefixes {
+            self = self.path_prefix(from, to);
+        }
+        if let Some(format) = &config.time_format {
+            self = self.time_format(format);
+        }
+        if let Some(minutes) = config.utc_offset {
+            self = self.utc_offset(minutes);
+        }
+        self
+    }
+}
+
+/// Initialises [`tracing_subscriber`] like [`init`](crate::init), with the options of the TOML
+/// configuration file at `path`, see [`FileConfig`]
+///
+/// ```no_run
+/// # #[cfg(feature = "cli")] {
+/// # use pretty_tracing_subscriber::Verbosity;
+/// # use structopt::StructOpt;
+/// let _guard = pretty_tracing_subscriber::init_from_config(
+///     "my_app",
+///     Verbosity::from_args(),
+///     "/etc/my_app/logging.toml",
+/// )
+/// .expect("invalid logging configuration");
+/// # }
+/// ```
+pub fn init_from_config(
+    root_module: impl Into<String>,
+    verbosity: Verbosity,
+    path: impl AsRef<Path>,
+) -> Result<LogGuard, ConfigFileError> {
+    Ok(FileConfig::read(path)?.init(root_module, verbosity))
+}
+
+/// Why a configuration file couldn't be read by [`FileConfig::read`]
+#[derive(Debug)]
+pub struct ConfigFileError {
+    path: PathBuf,
+    kind: ErrorKind,
+}
+
+#[derive(Debug)]
+enum ErrorKind {
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for ConfigFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ErrorKind::Io(e) => write!(f, "failed to read `{}`: {}", self.path.display(), e),
+            ErrorKind::Parse(e) => write!(
+                f,
+                "invalid logging configuration in `{}`: {}",
+                self.path.display(),
+                e
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigFileError {}
+
+fn level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<LevelFilter>, D::Error> {
+    let level = Option::<String>::deserialize(deserializer)?;
+    level
+        .map(|level| level.parse().map_err(D::Error::custom))
+        .transpose()
+}
+
+fn target_levels<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, LevelFilter>, D::Error> {
+    let targets = BTreeMap::<String, String>::deserialize(deserializer)?;
+    targets
+        .into_iter()
+        .map(|(target, level)| Ok((target, level.parse().map_err(D::Error::custom)?)))
+        .collect()
+}
+
+fn serialize_level<S: Serializer>(
+    level: &Option<LevelFilter>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match level {
+        Some(level) => serializer.serialize_some(&level_name(*level)),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn serialize_target_levels<S: Serializer>(
+    targets: &BTreeMap<String, LevelFilter>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(
+        targets
+            .iter()
+            .map(|(target, level)| (target, level_name(*level))),
+    )
+}
+
+/// The lowercase name of a level, as written in configuration files
+fn level_name(level: LevelFilter) -> String {
+    level.to_string().to_lowercase()
+}
@@ -0,0 +1,217 @@
+//! Writes the formatted output to the browser console on `wasm32-unknown-unknown`, with the
+//! `web-console` feature
+//!
+//! There is no stderr in the browser, so each event is passed to a function the page imports
+//! into the module as `pretty_tracing_subscriber.console_write`, which gets the method to call
+//! on `console`, and the address and length of its arguments, separated by nul characters:
+//!
+//! ```js
+//! const imports = {
+//!     pretty_tracing_subscriber: {
+//!         console_write(method, ptr, len) {
+//!             const bytes = new Uint8Array(wasm.exports.memory.buffer, ptr, len);
+//!             const args = new TextDecoder().decode(bytes).split("\0");
+//!             console[["log", "warn", "error", "debug"][method]](...args);
+//!         },
+//!     },
+//! };
+//! ```
+//!
+//! The first argument is the text with `%c` where the style changes, and the others are the
+//! CSS the colors of the terminal output are translated to.
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown", feature = "web-console"))]
+pub(crate) use native::{write, ENABLED};
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown", feature = "web-console")))]
+pub(crate) use unsupported::{write, ENABLED};
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown", feature = "web-console"))]
+mod native {
+    use std::fmt::Write as _;
+    use tracing::Level;
+
+    /// Whether the formatted output goes to the console instead of stderr
+    pub(crate) const ENABLED: bool = true;
+
+    #[link(wasm_import_module = "pretty_tracing_subscriber")]
+    extern "C" {
+        fn console_write(method: u32, ptr: *const u8, len: usize);
+    }
+
+    /// Writes `buf`, an event formatted with ANSI escapes, with `console.error` for errors,
+    /// `console.warn` for warnings, `console.debug` below `INFO`, and `console.log` otherwise
+    pub(crate) fn write(level: Level, buf: &[u8]) {
+        let method = match level {
+            Level::ERROR => 2,
+            Level::WARN => 1,
+            Level::INFO => 0,
+            Level::DEBUG | Level::TRACE => 3,
+        };
+        let text = String::from_utf8_lossy(buf);
+        let (format, styles) = styled(text.trim_end_matches('\n'));
+        let mut args = format;
+        for style in styles {
+            args.push('\0');
+            args.push_str(&style);
+        }
+        // SAFETY: the import only reads the `len` bytes at `ptr` during the call
+        unsafe { console_write(method, args.as_ptr(), args.len()) }
+    }
+
+    /// The style set by the escapes seen so far
+    #[derive(Default)]
+    struct Style {
+        bold: bool,
+        dimmed: bool,
+        italic: bool,
+        underline: bool,
+        color: Option<String>,
+        background: Option<String>,
+    }
+
+    impl Style {
+        fn css(&self) -> String {
+            let mut css = String::new();
+            if self.bold {
+                css.push_str("font-weight:bold;");
+            }
+            if self.italic {
+                css.push_str("font-style:italic;");
+            }
+            if self.underline {
+                css.push_str("text-decoration:underline;");
+            }
+            match &self.color {
+                Some(color) => {
+                    let _ = write!(css, "color:{};", color);
+                }
+                // The console supports too little CSS to fade other colors
+                None if self.dimmed => css.push_str("color:gray;"),
+                None => {}
+            }
+            if let Some(background) = &self.background {
+                let _ = write!(css, "background:{};", background);
+            }
+            css
+        }
+
+        /// Applies the parameters of a Select Graphic Rendition sequence
+        fn apply(&mut self, params: &str) {
+            let mut params = params.split(';').map(|param| param.parse().unwrap_or(0));
+            while let Some(param) = params.next() {
+                match param {
+                    0 => *self = Style::default(),
+                    1 => self.bold = true,
+                    2 => self.dimmed = true,
+                    3 => self.italic = true,
+                    4 => self.underline = true,
+                    22 => {
+                        self.bold = false;
+                        self.dimmed = false;
+                    }
+                    23 => self.italic = false,
+                    24 => self.underline = false,
+                    30..=37 => self.color = Some(PALETTE[param as usize - 30].to_owned()),
+                    90..=97 => self.color = Some(PALETTE[param as usize - 90 + 8].to_owned()),
+                    39 => self.color = None,
+                    40..=47 => self.background = Some(PALETTE[param as usize - 40].to_owned()),
+                    100..=107 => {
+                        self.background = Some(PALETTE[param as usize - 100 + 8].to_owned())
+                    }
+                    49 => self.background = None,
+                    38 => self.color = extended(&mut params),
+                    48 => self.background = extended(&mut params),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// The 16 colors of the terminal, readable on the light and dark themes of the console
+    const PALETTE: [&str; 16] = [
+        "#000000", "#cd3131", "#0dbc79", "#b58900", "#2472c8", "#bc3fbc", "#11a8cd", "#a0a0a0",
+        "#666666", "#f14c4c", "#23d18b", "#d7ba3d", "#3b8eea", "#d670d6", "#29b8db", "#e5e5e5",
+    ];
+
+    /// Reads a color of the 256-color palette, `5;n`, or an RGB color, `2;r;g;b`
+    fn extended(params: &mut impl Iterator<Item = u32>) -> Option<String> {
+        match params.next()? {
+            5 => match params.next()? {
+                n @ 0..=15 => Some(PALETTE[n as usize].to_owned()),
+                n @ 16..=231 => {
+                    let level = |c: u32| if c == 0 { 0 } else { 55 + c * 40 };
+                    let n = n - 16;
+                    Some(format!(
+                        "rgb({},{},{})",
+                        level(n / 36),
+                        level(n / 6 % 6),
+                        level(n % 6)
+                    ))
+                }
+                n => {
+                    let gray = 8 + (n.min(255) - 232) * 10;
+                    Some(format!("rgb({0},{0},{0})", gray))
+                }
+            },
+            2 => {
+                let (r, g, b) = (params.next()?, params.next()?, params.next()?);
+                Some(format!("rgb({},{},{})", r, g, b))
+            }
+            _ => None,
+        }
+    }
+
+    /// Translates the ANSI escapes in `text` to `%c` directives and the CSS they apply,
+    /// escaping other `%` characters
+    ///
+    /// Escapes other than colors, such as hyperlinks, are dropped.
+    fn styled(text: &str) -> (String, Vec<String>) {
+        let mut format = String::with_capacity(text.len());
+        let mut styles = Vec::new();
+        let mut style = Style::default();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '%' => format.push_str("%%"),
+                '\x1b' => match chars.next() {
+                    Some('[') => {
+                        let mut params = String::new();
+                        let end = loop {
+                            match chars.next() {
+                                Some(c @ ('0'..='9' | ';' | '?')) => params.push(c),
+                                end => break end,
+                            }
+                        };
+                        if end == Some('m') {
+                            style.apply(&params);
+                            format.push_str("%c");
+                            styles.push(style.css());
+                        }
+                    }
+                    // Operating system commands end with a bell or a string terminator
+                    Some(']') => {
+                        while let Some(c) = chars.next() {
+                            if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                                break;
+                            }
+                        }
+                    }
+                    _ => {}
+                },
+                c => format.push(c),
+            }
+        }
+        (format, styles)
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown", feature = "web-console")))]
+mod unsupported {
+    use tracing::Level;
+
+    /// Whether the formatted output goes to the console instead of stderr
+    pub(crate) const ENABLED: bool = false;
+
+    pub(crate) fn write(_level: Level, _buf: &[u8]) {}
+}
@@ -0,0 +1,228 @@
+//! Writes a report file when the application crashes, see
+//! [`Builder::crash_reports`](crate::Builder::crash_reports)
+
+use crate::banner::BuildInfo;
+use crate::fields::SpanFields;
+use crate::panic::RecentEvents;
+use crate::timestamp::{self, TimePrecision};
+use crate::{panic, run, wrap};
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::SystemTime;
+use std::{env, process, thread};
+use tracing::Span;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Registry;
+
+/// Target of the events logging where reports were written
+const TARGET: &str = "pretty_tracing_subscriber::crash";
+
+/// How many of the last events shown are kept for the report
+const RECENT: usize = 100;
+
+/// Keeps the last events shown, formatted, and writes them to a report file along with the
+/// details of a crash
+#[derive(Debug)]
+pub(crate) struct CrashReporter {
+    dir: PathBuf,
+    app: String,
+    build: Option<BuildInfo>,
+    recent: Mutex<VecDeque<String>>,
+}
+
+impl CrashReporter {
+    /// Writes reports for `app` to `dir`, which is created if needed
+    pub(crate) fn new(dir: &Path, app: &str, build: Option<BuildInfo>) -> io::Result<Arc<Self>> {
+        fs::create_dir_all(dir)?;
+        let reporter = Arc::new(Self {
+            dir: dir.to_owned(),
+            app: app.to_owned(),
+            build,
+            recent: Mutex::new(VecDeque::with_capacity(RECENT)),
+        });
+        let weak: Weak<Self> = Arc::downgrade(&reporter);
+        panic::register_recent(weak);
+        Ok(reporter)
+    }
+
+    /// Writes a report whenever a thread panics, before running the previously installed panic
+    /// hook, and logs its path once that hook has shown the panic
+    pub(crate) fn install_hook(self: &Arc<Self>) {
+        let weak = Arc::downgrade(self);
+        let previous = panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let reporter = match Weak::upgrade(&weak) {
+                Some(reporter) => reporter,
+                None => return previous(info),
+            };
+            let location = info.location().map(|location| {
+                format!(
+                    "{}:{}:{}",
+                    location.file(),
+                    location.line(),
+                    location.column()
+                )
+            });
+            let message = panic::message(info.payload());
+            let written = reporter.write("panic", message, location.as_deref());
+            previous(info);
+            log(&written);
+        }));
+    }
+
+    /// Writes a report of a fatal error described by `message`, returning the path of the file
+    pub(crate) fn report(&self, message: &str) -> io::Result<PathBuf> {
+        let written = self.write("fatal error", message, None);
+        log(&written);
+        written
+    }
+
+    fn write(&self, what: &str, message: &str, location: Option<&str>) -> io::Result<PathBuf> {
+        let now = SystemTime::now();
+        let mut time = String::new();
+        let _ = timestamp::write_rfc3339(&mut time, now, TimePrecision::Millis);
+        let mut stamp = String::new();
+        let _ = timestamp::write_rfc3339(&mut stamp, now, TimePrecision::Seconds);
+        stamp.retain(|c| c != '-' && c != ':');
+        let path = self.dir.join(format!(
+            "{}-crash-{}-{}.txt",
+            self.app,
+            stamp,
+            process::id()
+        ));
+
+        let thread = thread::current();
+        let mut report = format!("{} {}: {}\n\n", self.app, what, message);
+        let mut detail = |name: &str, value: &str| {
+            let _ = writeln!(report, "{:<10}{}", format!("{}:", name), value);
+        };
+        detail("time", &time);
+        if let Some(build) = &self.build {
+            detail("version", &build.describe());
+        }
+        detail("run", run::run_id());
+        detail("pid", &process::id().to_string());
+        detail("thread", thread.name().unwrap_or("<unnamed>"));
+        if let Some(location) = location {
+            detail("location", location);
+        }
+        detail(
+            "platform",
+            &format!("{} {}", env::consts::OS, env::consts::ARCH),
+        );
+        let args: Vec<String> = env::args().collect();
+        detail("argv", &format!("{:?}", args));
+
+        let spans = current_spans();
+        if !spans.is_empty() {
+            report.push_str("\nspans, from the root:\n");
+            for span in spans {
+                let _ = writeln!(report, "  {}", span);
+            }
+        }
+        let _ = write!(report, "\nbacktrace:\n{}", Backtrace::force_capture());
+        if !report.ends_with('\n') {
+            report.push('\n');
+        }
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        match recent.len() {
+            0 => report.push_str("\nno events were logged before this\n"),
+            1 => report.push_str("\nthe event logged before this:\n"),
+            n => {
+                let _ = writeln!(report, "\nthe last {} events logged before this:", n);
+            }
+        }
+        recent.iter().for_each(|line| report.push_str(line));
+        drop(recent);
+
+        File::create(&path)?.write_all(report.as_bytes())?;
+        Ok(path)
+    }
+
+    fn push(&self, line: &[u8]) {
+        let line = wrap::strip_escapes(&String::from_utf8_lossy(line));
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() == RECENT {
+            recent.pop_front();
+        }
+        recent.push_back(line);
+    }
+}
+
+impl RecentEvents for CrashReporter {
+    fn recent_events(&self) -> usize {
+        self.recent.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+/// The names and fields of the spans the current thread is in, from the root inwards
+fn current_spans() -> Vec<String> {
+    let id = match Span::current().id() {
+        Some(id) => id,
+        None => return Vec::new(),
+    };
+    tracing::dispatcher::get_default(|dispatch| {
+        let registry = match dispatch.downcast_ref::<Registry>() {
+            Some(registry) => registry,
+            None => return Vec::new(),
+        };
+        let span = match registry.span(&id) {
+            Some(span) => span,
+            None => return Vec::new(),
+        };
+        span.scope()
+            .from_root()
+            .map(|span| {
+                let mut line = span.name().to_owned();
+                if let Some(fields) = span.extensions().get::<SpanFields>() {
+                    for (name, value) in fields.iter() {
+                        let _ = write!(line, " {}={}", name, value);
+                    }
+                }
+                line
+            })
+            .collect()
+    })
+}
+
+/// Logs where the report went, or why it couldn't be written
+fn log(written: &io::Result<PathBuf>) {
+    match written {
+        Ok(path) => tracing::error!(
+            target: TARGET,
+            report = %path.display(),
+            "crash report written to {}",
+            path.display()
+        ),
+        Err(e) => tracing::error!(target: TARGET, "cannot write crash report: {}", e),
+    }
+}
+
+/// Keeps each formatted event for the next report
+#[derive(Debug, Clone)]
+pub(crate) struct CrashWriter(pub(crate) Arc<CrashReporter>);
+
+impl MakeWriter for CrashWriter {
+    type Writer = CrashWriter;
+
+    fn make_writer(&self) -> CrashWriter {
+        self.clone()
+    }
+}
+
+impl Write for CrashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.push(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+//! Mirrors the formatted output to the debugger on Windows
+
+#[cfg(windows)]
+pub(crate) use native::{write, SUPPORTED};
+
+#[cfg(not(windows))]
+pub(crate) use unsupported::{write, SUPPORTED};
+
+#[cfg(windows)]
+mod native {
+    use std::iter;
+
+    /// Whether there is a debugger output to mirror events to
+    pub(crate) const SUPPORTED: bool = true;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn OutputDebugStringW(text: *const u16);
+    }
+
+    /// Passes `text` to the attached debugger, such as Visual Studio or DebugView, which does
+    /// nothing when there is none
+    pub(crate) fn write(text: &str) {
+        let text: Vec<u16> = text
+            .encode_utf16()
+            .map(|c| if c == 0 { u16::from(b' ') } else { c })
+            .chain(iter::once(0))
+            .collect();
+        // SAFETY: the string is nul-terminated and outlives the call
+        unsafe { OutputDebugStringW(text.as_ptr()) }
+    }
+}
+
+#[cfg(not(windows))]
+mod unsupported {
+    /// Whether there is a debugger output to mirror events to
+    pub(crate) const SUPPORTED: bool = false;
+
+    pub(crate) fn write(_text: &str) {}
+}
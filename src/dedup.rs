@@ -0,0 +1,585 @@
+use crate::fields::SpanFields;
+use crate::shutdown::Sink;
+use crate::timing::DisplayDuration;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::callsite::Identifier;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+/// How many span contexts are kept as examples of where suppressed errors came from
+const SAMPLES: usize = 3;
+
+/// Above this many tracked messages, those without suppressed repeats are forgotten
+const MAX_TRACKED: usize = 256;
+
+/// Name of the field marking the events of [`log_once!`](crate::log_once)
+const ONCE_FIELD: &str = "log.once";
+
+/// Target of the events reporting how many times the previous event was repeated, which pass
+/// whatever the filters
+pub(crate) const REPEAT_TARGET: &str = "pretty_tracing_subscriber::repeat";
+
+thread_local! {
+    /// Summary of the repeats suppressed before the event being formatted on this thread
+    static NOTE: RefCell<Option<StormNote>> = const { RefCell::new(None) };
+}
+
+/// Coalesces identical `ERROR` events into one line per window
+///
+/// The first occurrence of an error in a window is shown, and repeats of it until the window
+/// ends are counted instead. The next occurrence after that is shown with a note saying how
+/// many repeats were suppressed, and in which spans. If the error doesn't occur again, the
+/// repeats are reported by the next event of any kind after the window, or on shutdown.
+#[derive(Debug)]
+pub(crate) struct StormFilter {
+    window: Duration,
+    storms: Mutex<HashMap<(Identifier, String), Storm>>,
+    /// Whether any storm has suppressed repeats not reported yet
+    pending: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Storm {
+    started: Instant,
+    suppressed: usize,
+    samples: Vec<String>,
+}
+
+/// How many repeats of an error were suppressed, attached to the next occurrence that is shown
+#[derive(Debug, Clone)]
+pub(crate) struct StormNote {
+    suppressed: usize,
+    window: Duration,
+    samples: Vec<String>,
+}
+
+impl StormFilter {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            storms: Mutex::default(),
+            pending: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the event should be shown, noting any suppressed repeats for the formatter
+    pub(crate) fn allows_event<S>(&self, event: &Event<'_>, ctx: &Context<'_, S>) -> bool
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let metadata = event.metadata();
+        let now = Instant::now();
+        if *metadata.level() != Level::ERROR || metadata.is_span() {
+            if metadata.target() != REPEAT_TARGET {
+                self.flush(Some(now), None);
+            }
+            return true;
+        }
+        let fields = SpanFields::record_selected(event, |name| name == "message");
+        let key = (
+            metadata.callsite(),
+            fields.get("message").unwrap_or_default().to_owned(),
+        );
+        // The repeats of this error are noted on it instead
+        self.flush(Some(now), Some(&key));
+
+        let mut storms = self.storms.lock().unwrap_or_else(|e| e.into_inner());
+        if storms.len() > MAX_TRACKED {
+            let window = self.window;
+            storms.retain(|_, storm| storm.suppressed > 0 || now - storm.started < window);
+        }
+        let storm = match storms.get_mut(&key) {
+            Some(storm) => storm,
+            None => {
+                storms.insert(
+                    key,
+                    Storm {
+                        started: now,
+                        suppressed: 0,
+                        samples: Vec::new(),
+                    },
+                );
+                return true;
+            }
+        };
+
+        if now - storm.started < self.window {
+            storm.suppressed += 1;
+            self.pending.store(true, Ordering::Relaxed);
+            if storm.samples.len() < SAMPLES {
+                if let Some(sample) = Self::span_context(event, ctx) {
+                    if !storm.samples.contains(&sample) {
+                        storm.samples.push(sample);
+                    }
+                }
+            }
+            return false;
+        }
+
+        if storm.suppressed > 0 {
+            let note = StormNote {
+                suppressed: storm.suppressed,
+                window: self.window,
+                samples: storm.samples.split_off(0),
+            };
+            NOTE.with(|cell| *cell.borrow_mut() = Some(note));
+        }
+        storm.started = now;
+        storm.suppressed = 0;
+        true
+    }
+
+    /// Reports the suppressed repeats of the storms whose window ended before `now`, or of every
+    /// storm without `now`, other than that of `except`, and forgets those storms
+    fn flush(&self, now: Option<Instant>, except: Option<&(Identifier, String)>) {
+        if !self.pending.load(Ordering::Relaxed) {
+            return;
+        }
+        // The reports go through this filter again, so the lock is released by now
+        for (message, note) in self.take_ended(now, except) {
+            tracing::info!(
+                target: REPEAT_TARGET,
+                repeated = note.suppressed,
+                "error \"{}\" {}",
+                message,
+                note
+            );
+        }
+    }
+
+    /// Forgets the storms [`flush`](Self::flush) reports, returning their messages and notes
+    fn take_ended(
+        &self,
+        now: Option<Instant>,
+        except: Option<&(Identifier, String)>,
+    ) -> Vec<(String, StormNote)> {
+        let mut storms = self.storms.lock().unwrap_or_else(|e| e.into_inner());
+        let mut ended = Vec::new();
+        storms.retain(|key, storm| {
+            let over = now.is_none_or(|now| now - storm.started >= self.window);
+            if storm.suppressed == 0 || !over || Some(key) == except {
+                return true;
+            }
+            let note = StormNote {
+                suppressed: storm.suppressed,
+                window: self.window,
+                samples: storm.samples.split_off(0),
+            };
+            ended.push((key.1.clone(), note));
+            false
+        });
+        let pending = storms.values().any(|storm| storm.suppressed > 0);
+        self.pending.store(pending, Ordering::Relaxed);
+        ended
+    }
+
+    /// Describes the innermost span of the event, such as `request{id=42}`
+    fn span_context<S>(event: &Event<'_>, ctx: &Context<'_, S>) -> Option<String>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let span = ctx.event_span(event)?;
+        let mut context = span.name().to_owned();
+        if let Some(fields) = span.extensions().get::<SpanFields>() {
+            let mut seen = false;
+            for (name, value) in fields.iter() {
+                context.push(if seen { ' ' } else { '{' });
+                let _ = write!(context, "{}={}", name, value);
+                seen = true;
+            }
+            if seen {
+                context.push('}');
+            }
+        }
+        Some(context)
+    }
+}
+
+impl StormNote {
+    /// Takes the note left for the event being formatted on this thread, if any
+    pub(crate) fn take() -> Option<Self> {
+        NOTE.with(|cell| cell.borrow_mut().take())
+    }
+
+    /// Leaves the note for the event being formatted on this thread again, for another rendering
+    /// of it
+    pub(crate) fn restore(self) {
+        NOTE.with(|cell| *cell.borrow_mut() = Some(self));
+    }
+
+    /// How many repeats were suppressed
+    pub(crate) fn suppressed(&self) -> usize {
+        self.suppressed
+    }
+}
+
+impl fmt::Display for StormNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "(repeated {} more times within {}",
+            self.suppressed,
+            DisplayDuration(self.window)
+        )?;
+        if !self.samples.is_empty() {
+            write!(f, ", in {}", self.samples.join(", "))?;
+        }
+        f.write_char(')')
+    }
+}
+
+/// Reports the repeats of the storms still going on shutdown, as no other event may come
+impl Sink for StormFilter {
+    fn drain(&self) -> io::Result<()> {
+        self.flush(None, None);
+        Ok(())
+    }
+
+    fn close(&self, _marker: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Collapses consecutive identical events into the first of them, followed by a line saying how
+/// many times it was repeated once another event comes, see
+/// [`Builder::collapse_repeats`](crate::Builder::collapse_repeats)
+///
+/// Events are identical when they come from the same call site with the same fields.
+#[derive(Debug, Default)]
+pub(crate) struct RepeatFilter {
+    /// The call site and fields of the last event shown, and how many times it was repeated
+    last: Mutex<Option<((Identifier, String), usize)>>,
+}
+
+impl RepeatFilter {
+    /// Whether the event should be shown, reporting the repeats of the previous one first if it
+    /// is a different event
+    pub(crate) fn allows_event(&self, event: &Event<'_>) -> bool {
+        if event.metadata().target() == REPEAT_TARGET || event.metadata().is_span() {
+            return true;
+        }
+        let fields = SpanFields::record_selected(event, |_| true);
+        let mut rendered = String::new();
+        for (name, value) in fields.iter() {
+            let _ = write!(rendered, "{}={}\0", name, value);
+        }
+        let key = (event.metadata().callsite(), rendered);
+
+        let mut last = self.lock();
+        let repeated = match &mut *last {
+            Some((last_key, repeated)) if *last_key == key => {
+                *repeated += 1;
+                return false;
+            }
+            Some((_, repeated)) => *repeated,
+            None => 0,
+        };
+        *last = Some((key, 0));
+        drop(last);
+        report(repeated);
+        true
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<((Identifier, String), usize)>> {
+        self.last.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Reports the repeats of the last event on shutdown, as no other event will
+impl Sink for RepeatFilter {
+    fn drain(&self) -> io::Result<()> {
+        let repeated = self.lock().take().map_or(0, |(_, repeated)| repeated);
+        report(repeated);
+        Ok(())
+    }
+
+    fn close(&self, _marker: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn report(repeated: usize) {
+    if repeated > 0 {
+        tracing::info!(
+            target: REPEAT_TARGET,
+            repeated,
+            "last message repeated {} times",
+            repeated
+        );
+    }
+}
+
+/// The messages of the events of [`log_once!`](crate::log_once) shown so far
+///
+/// Repeats are recognized by their message alone, so a message logged from several places is
+/// shown once in all.
+#[derive(Debug, Default)]
+pub(crate) struct ShownOnce(Mutex<HashSet<String>>);
+
+impl ShownOnce {
+    /// Whether the event should be shown, remembering its message if it is logged once
+    pub(crate) fn allows_event(&self, event: &Event<'_>) -> bool {
+        if event.metadata().fields().field(ONCE_FIELD).is_none() {
+            return true;
+        }
+        let fields = SpanFields::record_selected(event, |name| name == "message");
+        let message = fields.get("message").unwrap_or_default().to_owned();
+        let mut shown = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        shown.insert(message)
+    }
+}
+
+/// Logs an event at `level` the first time its message is logged in the process, and ignores it
+/// after that
+///
+/// It takes the same arguments as [`tracing::event!`] after the level, which has to be a
+/// constant. The subscriber remembers the messages shown, so the event is shown once however
+/// many call sites log the same message, without the application keeping flags of its own. Only
+/// events that pass the filters count, and JSON tees still get every occurrence.
+///
+/// ```no_run
+/// use pretty_tracing_subscriber::log_once;
+/// use pretty_tracing_subscriber::tracing::Level;
+///
+/// # let flag = "--color";
+/// log_once!(Level::INFO, flag, "using the legacy config format");
+/// ```
+#[macro_export]
+macro_rules! log_once {
+    ($level:expr, $($arg:tt)+) => {
+        $crate::tracing::event!($level, log.once = true, $($arg)+)
+    };
+}
+
+/// Logs a `WARN` event the first time its message is logged in the process, see
+/// [`log_once!`](crate::log_once)
+///
+/// ```no_run
+/// use pretty_tracing_subscriber::warn_once;
+///
+/// # let flag = "--color";
+/// warn_once!(flag, "this flag is deprecated and will be removed in the next release");
+/// ```
+#[macro_export]
+macro_rules! warn_once {
+    ($($arg:tt)+) => {
+        $crate::log_once!($crate::tracing::Level::WARN, $($arg)+)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tracing::level_filters::STATIC_MAX_LEVEL;
+    use tracing_subscriber::layer::{Layer, SubscriberExt};
+    use tracing_subscriber::Registry;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    /// Records the messages of the events the filter shows, with the note left for each
+    #[derive(Default)]
+    struct Shown(Mutex<Vec<(String, Option<usize>)>>);
+
+    struct Filtered {
+        filter: Arc<StormFilter>,
+        shown: Arc<Shown>,
+    }
+
+    impl<S> Layer<S> for Filtered
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            if self.filter.allows_event(event, &ctx) {
+                let fields = SpanFields::record_selected(event, |name| name == "message");
+                let message = fields.get("message").unwrap_or_default().to_owned();
+                let note = StormNote::take().map(|note| note.suppressed());
+                self.shown.0.lock().unwrap().push((message, note));
+            }
+        }
+    }
+
+    /// Runs `log` through a fresh filter, returning it and what it showed
+    fn filter(log: impl FnOnce(&StormFilter)) -> (Arc<StormFilter>, Vec<(String, Option<usize>)>) {
+        let filter = Arc::new(StormFilter::new(WINDOW));
+        let shown = Arc::new(Shown::default());
+        let layer = Filtered {
+            filter: filter.clone(),
+            shown: shown.clone(),
+        };
+        tracing::subscriber::with_default(Registry::default().with(layer), || log(&filter));
+        let shown = shown.0.lock().unwrap().clone();
+        (filter, shown)
+    }
+
+    /// Moves the start of every storm a window back, ending them
+    fn end_windows(filter: &StormFilter) {
+        for storm in filter.storms.lock().unwrap().values_mut() {
+            storm.started -= filter.window;
+        }
+    }
+
+    fn error(message: &str) {
+        tracing::error!("{}", message);
+    }
+
+    /// Whether the events logged at `ERROR` are compiled in, which the `max_level_*` features
+    /// of tracing may prevent
+    fn logging() -> bool {
+        STATIC_MAX_LEVEL >= Level::ERROR
+    }
+
+    #[test]
+    fn repeats_within_the_window_are_noted_on_the_next_occurrence() {
+        if !logging() {
+            return;
+        }
+        let (_, shown) = filter(|filter| {
+            for _ in 0..3 {
+                error("boom");
+            }
+            end_windows(filter);
+            error("boom");
+            error("boom");
+        });
+        assert_eq!(
+            shown,
+            [("boom".to_owned(), None), ("boom".to_owned(), Some(2))]
+        );
+    }
+
+    #[test]
+    fn errors_with_other_messages_are_separate_storms() {
+        if !logging() {
+            return;
+        }
+        let (_, shown) = filter(|_| {
+            for message in &["a", "b", "a", "b", "c"] {
+                error(message);
+            }
+        });
+        let messages: Vec<_> = shown.iter().map(|(message, _)| message.as_str()).collect();
+        assert_eq!(messages, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn other_levels_are_never_suppressed() {
+        if STATIC_MAX_LEVEL < Level::WARN {
+            return;
+        }
+        let (_, shown) = filter(|_| {
+            for _ in 0..3 {
+                tracing::warn!("slow");
+            }
+        });
+        assert_eq!(shown.len(), 3);
+    }
+
+    #[test]
+    fn samples_name_the_first_spans_repeats_came_from() {
+        if !logging() {
+            return;
+        }
+        let (filter, _) = filter(|_| {
+            error("boom");
+            for name in &["a", "b", "a", "c", "d"] {
+                let span = tracing::error_span!("span", name = *name);
+                let _entered = span.enter();
+                error("boom");
+            }
+        });
+        let ended = filter.take_ended(None, None);
+        assert_eq!(ended.len(), 1);
+        let (message, note) = &ended[0];
+        assert_eq!(message, "boom");
+        assert_eq!(note.suppressed(), 5);
+        // Without the formatter recording span fields, contexts are just span names
+        assert_eq!(note.samples, ["span"]);
+        assert_eq!(
+            note.to_string(),
+            format!(
+                "(repeated 5 more times within {}, in span)",
+                DisplayDuration(WINDOW)
+            )
+        );
+    }
+
+    #[test]
+    fn flushing_reports_ended_storms_and_forgets_them() {
+        if !logging() {
+            return;
+        }
+        let (filter, _) = filter(|_| {
+            error("a");
+            error("a");
+            error("b");
+            error("c");
+            error("c");
+        });
+        assert!(filter.pending.load(Ordering::Relaxed));
+        assert!(filter.take_ended(Some(Instant::now()), None).is_empty());
+
+        end_windows(&filter);
+        let except = {
+            let storms = filter.storms.lock().unwrap();
+            storms.keys().find(|key| key.1 == "c").unwrap().clone()
+        };
+        let ended = filter.take_ended(Some(Instant::now()), Some(&except));
+        let ended: Vec<_> = ended
+            .iter()
+            .map(|(message, note)| (message.as_str(), note.suppressed()))
+            .collect();
+        assert_eq!(ended, [("a", 1)]);
+        // Storms without repeats are kept, as are those excepted
+        assert_eq!(filter.storms.lock().unwrap().len(), 2);
+        assert!(filter.pending.load(Ordering::Relaxed));
+
+        let ended = filter.take_ended(None, None);
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0].0, "c");
+        assert!(!filter.pending.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn ended_storms_without_repeats_are_evicted_past_the_limit() {
+        if !logging() {
+            return;
+        }
+        let (filter, shown) = filter(|filter| {
+            error("repeated");
+            error("repeated");
+            for i in 0..=MAX_TRACKED {
+                error(&i.to_string());
+            }
+            assert_eq!(filter.storms.lock().unwrap().len(), MAX_TRACKED + 2);
+            end_windows(filter);
+            error("new");
+        });
+        assert_eq!(shown.len(), MAX_TRACKED + 3);
+        // The storm with a repeat is flushed first, and the others are evicted
+        let storms = filter.storms.lock().unwrap();
+        let messages: Vec<_> = storms.keys().map(|key| key.1.as_str()).collect();
+        assert_eq!(messages, ["new"]);
+    }
+
+    #[test]
+    fn storms_still_going_are_kept_past_the_limit() {
+        if !logging() {
+            return;
+        }
+        let (filter, _) = filter(|_| {
+            for i in 0..=MAX_TRACKED + 1 {
+                error(&i.to_string());
+            }
+        });
+        assert_eq!(filter.storms.lock().unwrap().len(), MAX_TRACKED + 2);
+    }
+}
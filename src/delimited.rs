@@ -0,0 +1,168 @@
+use crate::fields::Redaction;
+use crate::json::{self, MessageVisitor};
+use crate::timestamp::{self, TimePrecision};
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// The formats of [`Builder::tee_delimited`](crate::Builder::tee_delimited), which write a row
+/// per event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimited {
+    /// Comma-separated values, where values holding commas, quotes or line breaks are quoted as
+    /// in RFC 4180
+    Csv,
+    /// Tab-separated values, where tabs, line breaks and backslashes in values are escaped as
+    /// `\t`, `\n`, `\r` and `\\`
+    Tsv,
+}
+
+/// The names of the columns, in the header row
+const COLUMNS: [&str; 8] = [
+    "timestamp",
+    "level",
+    "target",
+    "file",
+    "line",
+    "spans",
+    "message",
+    "fields",
+];
+
+/// Writes each event as a row of delimited values, after a header row naming the columns
+///
+/// The columns are the timestamp, level, target, file, line, the names of the event's spans
+/// from the root, separated by `:`, the message, and the other fields as a JSON object:
+///
+/// ```text
+/// timestamp,level,target,file,line,spans,message,fields
+/// 2021-06-02T09:14:03.210114Z,INFO,app::db,src/db.rs,42,request:query,fetched rows,"{""rows"":3}"
+/// ```
+#[derive(Debug)]
+pub(crate) struct DelimitedFormatter {
+    format: Delimited,
+    redaction: Redaction,
+    precision: TimePrecision,
+    /// Whether the header row is still to be written, before the first row
+    header: AtomicBool,
+}
+
+impl DelimitedFormatter {
+    /// Writes the header row first if `header` is set, which it needn't be when appending to a
+    /// file that has one already
+    pub(crate) fn new(
+        format: Delimited,
+        redaction: Redaction,
+        precision: TimePrecision,
+        header: bool,
+    ) -> Self {
+        Self {
+            format,
+            redaction,
+            precision,
+            header: AtomicBool::new(header),
+        }
+    }
+
+    fn separator(&self) -> char {
+        match self.format {
+            Delimited::Csv => ',',
+            Delimited::Tsv => '\t',
+        }
+    }
+
+    /// Writes `value` as a cell, escaped for the format
+    fn write_cell(&self, f: &mut dyn Write, value: &str) -> fmt::Result {
+        match self.format {
+            Delimited::Csv if value.contains([',', '"', '\n', '\r']) => {
+                f.write_char('"')?;
+                for (i, part) in value.split('"').enumerate() {
+                    if i > 0 {
+                        f.write_str("\"\"")?;
+                    }
+                    f.write_str(part)?;
+                }
+                f.write_char('"')
+            }
+            Delimited::Csv => f.write_str(value),
+            Delimited::Tsv => {
+                for c in value.chars() {
+                    match c {
+                        '\t' => f.write_str("\\t")?,
+                        '\n' => f.write_str("\\n")?,
+                        '\r' => f.write_str("\\r")?,
+                        '\\' => f.write_str("\\\\")?,
+                        c => f.write_char(c)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes the cells of a row, ending it
+    fn write_row<'a>(
+        &self,
+        f: &mut dyn Write,
+        cells: impl IntoIterator<Item = &'a str>,
+    ) -> fmt::Result {
+        for (i, cell) in cells.into_iter().enumerate() {
+            if i > 0 {
+                f.write_char(self.separator())?;
+            }
+            self.write_cell(f, cell)?;
+        }
+        f.write_char('\n')
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for DelimitedFormatter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        f: &mut dyn Write,
+        e: &Event<'_>,
+    ) -> fmt::Result {
+        if self.header.swap(false, Ordering::Relaxed) {
+            self.write_row(f, COLUMNS)?;
+        }
+        let metadata = e.metadata();
+        let mut timestamp = String::new();
+        timestamp::write_rfc3339(&mut timestamp, SystemTime::now(), self.precision)?;
+        let line = metadata.line().map(|line| line.to_string());
+        let span = e.parent().and_then(|id| ctx.span(id));
+        let mut spans = String::new();
+        if let Some(scope) = span.or_else(|| ctx.lookup_current()) {
+            for (i, span) in scope.scope().from_root().enumerate() {
+                if i > 0 {
+                    spans.push(':');
+                }
+                spans.push_str(span.name());
+            }
+        }
+        let mut message = MessageVisitor(None);
+        e.record(&mut message);
+        let mut fields = String::new();
+        json::write_fields(&mut fields, e, &self.redaction)?;
+        self.write_row(
+            f,
+            [
+                timestamp.as_str(),
+                metadata.level().as_str(),
+                metadata.target(),
+                metadata.file().unwrap_or_default(),
+                line.as_deref().unwrap_or_default(),
+                &spans,
+                message.0.as_deref().unwrap_or_default(),
+                &fields,
+            ],
+        )
+    }
+}
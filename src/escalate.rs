@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::{Span, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Registry;
+
+/// Number of [`VerboseScope`]s alive, which spares the filters from looking at the spans of
+/// events while there are none
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Marks a span whose subtree shows every event, with the number of scopes opened on it
+#[derive(Debug)]
+struct Escalated(usize);
+
+/// Shows every event emitted within the current span, and the spans below it, whatever the
+/// filters, until the returned guard is dropped
+///
+/// This lets one suspicious code path be traced in full in production, without turning on
+/// `TRACE` events everywhere. Events of other threads are shown too when they are emitted in a
+/// span below the current one, such as in tasks instrumented with a child span. The filters of
+/// outputs set up with [`Builder::output_filter`](crate::Builder::output_filter) and of JSON
+/// tees are unaffected.
+///
+/// The current span has to pass the filters itself, as hidden spans aren't tracked, and
+/// nothing happens outside of any span. Opening or closing the first scope makes `tracing`
+/// recheck every callsite, so scopes are meant to cover a whole request or job rather than a
+/// tight loop.
+///
+/// ```no_run
+/// # use pretty_tracing_subscriber::verbose_scope;
+/// # fn is_suspicious(_: u64) -> bool { true }
+/// # fn handle(_: u64) {}
+/// # let id = 7;
+/// let span = tracing::info_span!("request", id);
+/// let _entered = span.enter();
+/// let _verbose = is_suspicious(id).then(verbose_scope);
+/// handle(id);
+/// ```
+pub fn verbose_scope() -> VerboseScope {
+    let span = Span::current();
+    if !adjust(&span, |count| *count += 1) {
+        return VerboseScope { span: None };
+    }
+    if ACTIVE.fetch_add(1, Ordering::SeqCst) == 0 {
+        tracing::callsite::rebuild_interest_cache();
+    }
+    VerboseScope { span: Some(span) }
+}
+
+/// Shows every event within a span until dropped, see [`verbose_scope`]
+#[derive(Debug)]
+#[must_use = "the span is only shown in full until the scope is dropped"]
+pub struct VerboseScope {
+    span: Option<Span>,
+}
+
+impl Drop for VerboseScope {
+    fn drop(&mut self) {
+        if let Some(span) = self.span.take() {
+            adjust(&span, |count| *count -= 1);
+            if ACTIVE.fetch_sub(1, Ordering::SeqCst) == 1 {
+                tracing::callsite::rebuild_interest_cache();
+            }
+        }
+    }
+}
+
+/// Changes the number of scopes opened on `span`, returning whether it is tracked
+fn adjust(span: &Span, change: impl FnOnce(&mut usize)) -> bool {
+    let adjusted = span.with_subscriber(|(id, dispatch)| {
+        let span = dispatch.downcast_ref::<Registry>()?.span(id)?;
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<Escalated>() {
+            Some(escalated) => {
+                change(&mut escalated.0);
+                if escalated.0 == 0 {
+                    extensions.remove::<Escalated>();
+                }
+            }
+            None => {
+                let mut count = 0;
+                change(&mut count);
+                extensions.insert(Escalated(count));
+            }
+        }
+        Some(())
+    });
+    adjusted.flatten().is_some()
+}
+
+/// Whether any [`VerboseScope`] is alive
+pub(crate) fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed) != 0
+}
+
+/// Whether the current span, or one above it, is shown in full
+pub(crate) fn escalated<S>(ctx: &Context<'_, S>) -> bool
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    active()
+        && ctx.lookup_current().is_some_and(|span| {
+            span.scope()
+                .any(|span| span.extensions().get::<Escalated>().is_some())
+        })
+}
@@ -0,0 +1,140 @@
+use crate::levels;
+use ansi_term::Color;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// The number of events that passed the filters, by level, and by target when enabled with
+/// [`Builder::count_targets`](crate::Builder::count_targets)
+///
+/// Returned by [`LogGuard::event_counts`](crate::LogGuard::event_counts). Displays as a summary
+/// such as `3 errors, 12 warnings`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventCounts {
+    levels: [u64; 5],
+    targets: BTreeMap<String, [u64; 5]>,
+}
+
+impl EventCounts {
+    /// The number of `ERROR` events
+    pub fn errors(&self) -> u64 {
+        self.at_level(Level::ERROR)
+    }
+
+    /// The number of `WARN` events
+    pub fn warnings(&self) -> u64 {
+        self.at_level(Level::WARN)
+    }
+
+    /// The number of events at `level`
+    pub fn at_level(&self, level: Level) -> u64 {
+        self.levels[levels::index(level)]
+    }
+
+    /// The number of events at `level` with the target `target` or one in a module under it,
+    /// which is always 0 unless targets are counted
+    pub fn in_target(&self, target: &str, level: Level) -> u64 {
+        self.targets
+            .iter()
+            .filter(|(name, _)| {
+                name.strip_prefix(target)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map(|(_, counts)| counts[levels::index(level)])
+            .sum()
+    }
+
+    /// The targets events were logged with, and the number of events at each level, from
+    /// `ERROR` to `TRACE`
+    pub fn targets(&self) -> impl Iterator<Item = (&str, [u64; 5])> {
+        self.targets
+            .iter()
+            .map(|(target, counts)| (target.as_str(), *counts))
+    }
+
+    /// The summary written at shutdown, with the counts in red and yellow
+    pub(crate) fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if self.errors() > 0 {
+            let errors = plural(self.errors(), "error");
+            parts.push(Color::Red.bold().paint(errors).to_string());
+        }
+        if self.warnings() > 0 {
+            let warnings = plural(self.warnings(), "warning");
+            parts.push(Color::Yellow.bold().paint(warnings).to_string());
+        }
+        parts.join(", ")
+    }
+}
+
+impl fmt::Display for EventCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&plural(self.errors(), "error"))?;
+        write!(f, ", {}", plural(self.warnings(), "warning"))
+    }
+}
+
+fn plural(count: u64, noun: &str) -> String {
+    match count {
+        1 => format!("1 {}", noun),
+        n => format!("{} {}s", n, noun),
+    }
+}
+
+/// The counts kept as events are logged
+#[derive(Debug)]
+pub(crate) struct Tally {
+    levels: [AtomicU64; 5],
+    targets: Option<Mutex<HashMap<String, [u64; 5]>>>,
+}
+
+impl Tally {
+    pub(crate) fn new(count_targets: bool) -> Arc<Self> {
+        Arc::new(Self {
+            levels: Default::default(),
+            targets: count_targets.then(Mutex::default),
+        })
+    }
+
+    /// The layer that counts the events
+    pub(crate) fn counter(self: &Arc<Self>) -> Counter {
+        Counter(self.clone())
+    }
+
+    pub(crate) fn counts(&self) -> EventCounts {
+        let levels = [0, 1, 2, 3, 4].map(|i| self.levels[i].load(Ordering::Relaxed));
+        let targets = match &self.targets {
+            Some(targets) => {
+                let targets = targets.lock().unwrap_or_else(|e| e.into_inner());
+                targets.iter().map(|(k, v)| (k.clone(), *v)).collect()
+            }
+            None => BTreeMap::new(),
+        };
+        EventCounts { levels, targets }
+    }
+}
+
+/// Counts the events that pass the filters
+pub(crate) struct Counter(Arc<Tally>);
+
+impl<S: Subscriber> Layer<S> for Counter {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let index = levels::index(*metadata.level());
+        self.0.levels[index].fetch_add(1, Ordering::Relaxed);
+        if let Some(targets) = &self.0.targets {
+            let mut targets = targets.lock().unwrap_or_else(|e| e.into_inner());
+            match targets.get_mut(metadata.target()) {
+                Some(counts) => counts[index] += 1,
+                None => {
+                    let mut counts = [0; 5];
+                    counts[index] = 1;
+                    targets.insert(metadata.target().to_owned(), counts);
+                }
+            }
+        }
+    }
+}
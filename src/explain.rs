@@ -0,0 +1,165 @@
+use crate::status::Stderr;
+use ansi_term::Color;
+use std::any::TypeId;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::callsite::Identifier;
+use tracing::span::{Attributes, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Id, Metadata, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context, Layer};
+
+/// How many filter decisions are explained at most, so a busy program isn't flooded
+const MAX_EXPLAINED: usize = 100;
+
+/// Explains why events were filtered out, for a while after the subscriber is installed, see
+/// [`Builder::explain_filters`](crate::Builder::explain_filters)
+///
+/// Each call site is explained once, the first time one of its events is filtered out.
+#[derive(Debug)]
+pub(crate) struct Explainer {
+    until: Instant,
+    writer: Stderr,
+    explained: Mutex<HashSet<Identifier>>,
+    remaining: AtomicUsize,
+}
+
+impl Explainer {
+    pub(crate) fn new(window: Duration, writer: Stderr) -> Self {
+        Self {
+            until: Instant::now() + window,
+            writer,
+            explained: Mutex::default(),
+            remaining: AtomicUsize::new(MAX_EXPLAINED),
+        }
+    }
+
+    /// Writes why an event or span from the call site described by `metadata` was filtered out,
+    /// unless that call site was explained before
+    pub(crate) fn explain(&self, metadata: &Metadata<'_>, reason: impl fmt::Display) {
+        if self.remaining.load(Ordering::Relaxed) == 0 || Instant::now() > self.until {
+            return;
+        }
+        let mut explained = self.explained.lock().unwrap_or_else(|e| e.into_inner());
+        if !explained.insert(metadata.callsite()) {
+            return;
+        }
+        drop(explained);
+        if self.remaining.fetch_sub(1, Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let mut line = format!(
+            "{} hid {} ",
+            Color::Cyan.bold().paint("filter:"),
+            metadata.level()
+        );
+        if metadata.is_span() {
+            line.push_str(&format!("span `{}`", metadata.name()));
+        } else {
+            line.push_str("event");
+        }
+        if let (Some(file), Some(number)) = (metadata.file(), metadata.line()) {
+            line.push_str(&format!(" at {}:{}", file, number));
+        }
+        line.push_str(&format!(" in {}: {}\n", metadata.target(), reason));
+        let _ = self.writer.clone().write_all(line.as_bytes());
+    }
+}
+
+/// Wraps a level or target filter to explain the call sites it disables
+pub(crate) struct Explained<L> {
+    pub(crate) inner: L,
+    pub(crate) explainer: Option<Arc<Explainer>>,
+    /// Describes the filter, such as ``the verbosity level `info` ``
+    pub(crate) rule: String,
+}
+
+impl<L> Explained<L> {
+    fn explain(&self, metadata: &Metadata<'_>) {
+        if let Some(explainer) = &self.explainer {
+            explainer.explain(metadata, format_args!("disabled by {}", self.rule));
+        }
+    }
+}
+
+impl<S, L> Layer<S> for Explained<L>
+where
+    S: Subscriber,
+    L: Layer<S>,
+{
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber)
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        let interest = self.inner.register_callsite(metadata);
+        if interest.is_never() {
+            self.explain(metadata);
+        }
+        interest
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        let enabled = self.inner.enabled(metadata, ctx);
+        if !enabled {
+            self.explain(metadata);
+        }
+        enabled
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        self.inner.new_span(attrs, id, ctx)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        // Events below the hint are skipped before their call site is registered, where they
+        // would be explained
+        match self.explainer {
+            Some(_) => Some(LevelFilter::TRACE),
+            None => self.inner.max_level_hint(),
+        }
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        self.inner.on_record(span, values, ctx)
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.inner.on_follows_from(span, follows, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        self.inner.on_event(event, ctx)
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.inner.on_close(id, ctx)
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.inner.on_id_change(old, new, ctx)
+    }
+
+    #[doc(hidden)]
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const _ as *const ())
+        } else {
+            self.inner.downcast_raw(id)
+        }
+    }
+}
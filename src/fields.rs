@@ -0,0 +1,740 @@
+use crate::timing::DisplayDuration;
+use crate::wrap::Continued;
+use ansi_term::Style;
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::iter;
+use std::time::Duration;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Record};
+use tracing::{Event, Id, Subscriber};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// The field values of a span, rendered to text, stored in the span's extensions
+///
+/// Strings are stored as they are, other values in their `Debug` representation.
+#[derive(Debug, Default)]
+pub struct SpanFields(Vec<(&'static str, String)>);
+
+impl SpanFields {
+    /// Returns the rendered value of the field called `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Iterates over the names and rendered values of the fields, in recording order
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        self.0.iter().map(|(name, value)| (*name, value.as_str()))
+    }
+
+    fn set(&mut self, field: &Field, value: String) {
+        match self.0.iter_mut().find(|(name, _)| *name == field.name()) {
+            Some((_, old)) => *old = value,
+            None => self.0.push((field.name(), value)),
+        }
+    }
+}
+
+impl SpanFields {
+    /// Records only the fields of `event` that `wanted` selects, leaving the others, which may
+    /// be expensive to render, untouched
+    pub(crate) fn record_selected(event: &Event<'_>, wanted: impl Fn(&str) -> bool) -> Self {
+        let mut selected = Selected {
+            fields: Self::default(),
+            wanted,
+        };
+        event.record(&mut selected);
+        selected.fields
+    }
+}
+
+struct Selected<P> {
+    fields: SpanFields,
+    wanted: P,
+}
+
+impl<P: Fn(&str) -> bool> Visit for Selected<P> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if (self.wanted)(field.name()) {
+            self.fields.record_str(field, value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if (self.wanted)(field.name()) {
+            self.fields.record_debug(field, value);
+        }
+    }
+}
+
+impl Visit for SpanFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set(field, value.to_owned());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.set(field, format!("{:?}", value));
+    }
+}
+
+/// Field names whose values must never be printed
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Redaction {
+    patterns: Vec<String>,
+}
+
+impl Redaction {
+    /// What redacted values are replaced with
+    pub(crate) const REDACTED: &'static str = "[REDACTED]";
+
+    pub(crate) fn add(&mut self, pattern: &str) {
+        self.patterns.push(pattern.to_owned());
+    }
+
+    /// Whether `name` matches any of the patterns
+    pub(crate) fn matches(&self, name: &str) -> bool {
+        self.patterns.iter().any(|pattern| glob(pattern, name))
+    }
+}
+
+/// Field names left out of the formatted output, see
+/// [`Builder::hide_fields`](crate::Builder::hide_fields)
+#[derive(Debug, Clone)]
+pub(crate) struct Visibility {
+    hidden: Vec<String>,
+    shown: Vec<String>,
+}
+
+impl Default for Visibility {
+    /// Hides the `log.target`, `log.module_path`, `log.file` and `log.line` fields of records
+    /// bridged from the `log` crate
+    fn default() -> Self {
+        Self {
+            hidden: vec!["log.*".to_owned()],
+            shown: Vec::new(),
+        }
+    }
+}
+
+impl Visibility {
+    pub(crate) fn hide(&mut self, pattern: &str) {
+        self.hidden.push(pattern.to_owned());
+    }
+
+    pub(crate) fn show(&mut self, pattern: &str) {
+        self.shown.push(pattern.to_owned());
+    }
+
+    /// Whether `name` matches a hidden pattern and no shown one
+    pub(crate) fn hides(&self, name: &str) -> bool {
+        let matches = |patterns: &[String]| patterns.iter().any(|pattern| glob(pattern, name));
+        matches(&self.hidden) && !matches(&self.shown)
+    }
+}
+
+/// Field names whose values are shown in friendlier units, see
+/// [`Builder::duration_fields`](crate::Builder::duration_fields) and
+/// [`Builder::size_fields`](crate::Builder::size_fields)
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Units {
+    pub(crate) durations: Vec<String>,
+    pub(crate) sizes: Vec<String>,
+}
+
+impl Units {
+    fn is_empty(&self) -> bool {
+        self.durations.is_empty() && self.sizes.is_empty()
+    }
+
+    /// Renders the value of the field `name` in friendlier units, if the field is one of them
+    /// and its value is a number or a `Duration`
+    fn humanize(&self, name: &str, value: &str) -> Option<String> {
+        let matches = |patterns: &[String]| patterns.iter().any(|pattern| glob(pattern, name));
+        if matches(&self.durations) {
+            Some(DisplayDuration(parse_duration(value)?).to_string())
+        } else if matches(&self.sizes) {
+            Some(display_size(value.parse().ok()?))
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads a number of nanoseconds, or the `Debug` representation of a `Duration`, such as
+/// `1.523ms`
+fn parse_duration(value: &str) -> Option<Duration> {
+    if let Ok(nanos) = value.parse() {
+        return Some(Duration::from_nanos(nanos));
+    }
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let seconds = match unit {
+        "ns" => number / 1e9,
+        "µs" | "us" => number / 1e6,
+        "ms" => number / 1e3,
+        "s" => number,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Shows a number of bytes in binary units, such as `1.0 MiB`
+fn display_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Matches `text` against a pattern in which `*` stands for any number of characters
+fn glob(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let mut parts = parts.peekable();
+    if parts.peek().is_none() {
+        return rest.is_empty();
+    }
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Captures the fields of each span so that presets can inspect them, keeping them up to date
+/// as values are recorded later
+#[derive(Default)]
+pub(crate) struct FieldLayer {
+    pub(crate) redaction: Redaction,
+}
+
+impl<S> Layer<S> for FieldLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        // Each output writing JSON records the fields too, the first one to see the span keeps
+        // them
+        if let Some(span) = ctx.span(id) {
+            let mut extensions = span.extensions_mut();
+            if extensions.get_mut::<SpanFields>().is_none() {
+                let mut fields = SpanFields::default();
+                attrs.record(&mut fields);
+                self.redact(&mut fields);
+                extensions.insert(fields);
+            }
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut extensions = span.extensions_mut();
+            if let Some(fields) = extensions.get_mut::<SpanFields>() {
+                values.record(fields);
+                self.redact(fields);
+            }
+        }
+    }
+}
+
+impl FieldLayer {
+    fn redact(&self, fields: &mut SpanFields) {
+        for (name, value) in &mut fields.0 {
+            if self.redaction.matches(name) {
+                *value = Redaction::REDACTED.to_owned();
+            }
+        }
+    }
+}
+
+/// The kinds of synthetic events that `fmt::Layer` emits for span lifecycle changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SpanEvent {
+    New,
+    Close,
+}
+
+impl SpanEvent {
+    /// Recognises span lifecycle events, which carry the metadata of the span itself
+    pub(crate) fn of(event: &Event<'_>) -> Option<Self> {
+        if !event.metadata().is_span() {
+            return None;
+        }
+        let mut visitor = SpanEventVisitor(None);
+        event.record(&mut visitor);
+        visitor.0
+    }
+}
+
+struct SpanEventVisitor(Option<SpanEvent>);
+
+impl Visit for SpanEventVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.0 = match value {
+                "new" => Some(SpanEvent::New),
+                "close" => Some(SpanEvent::Close),
+                _ => None,
+            };
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.record_str(field, &format!("{:?}", value))
+        }
+    }
+}
+
+/// How the values of fields other than the message are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FieldStyle {
+    /// `key=value` with values in their `Debug` representation, like `tracing_subscriber` does
+    #[default]
+    Debug,
+    /// `key=value` with values only quoted when needed, and newlines escaped, like logfmt
+    ///
+    /// Every field stays on one line and is separated by a single space, so the output can be
+    /// reliably split with grep and awk.
+    KeyValue,
+}
+
+impl FieldStyle {
+    /// Whether a logfmt value has to be quoted to be read back unambiguously
+    fn needs_quotes(value: &str) -> bool {
+        value.is_empty()
+            || value
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control() || c == '"' || c == '=')
+    }
+
+    fn quote(value: &str) -> String {
+        if !Self::needs_quotes(value) {
+            return value.to_owned();
+        }
+        let mut quoted = String::with_capacity(value.len() + 2);
+        quoted.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+}
+
+/// The order in which fields other than the message are printed
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum FieldOrder {
+    /// The order in which the fields were recorded, which is usually the order at the call site
+    #[default]
+    Recorded,
+    /// Sorted by field name
+    Alphabetical,
+    /// The named fields first, in the given order, followed by the rest in recorded order
+    Priority(Vec<String>),
+}
+
+impl FieldOrder {
+    fn sort(&self, fields: &mut [(&'static str, String)]) {
+        match self {
+            FieldOrder::Recorded => {}
+            FieldOrder::Alphabetical => fields.sort_by_key(|(name, _)| *name),
+            FieldOrder::Priority(names) => fields.sort_by_key(|(name, _)| {
+                names
+                    .iter()
+                    .position(|priority| priority == name)
+                    .unwrap_or(names.len())
+            }),
+        }
+    }
+}
+
+/// The fields of an event, rendered to text, with the message kept apart from the rest
+#[derive(Debug, Default)]
+pub(crate) struct EventFields {
+    pub(crate) message: Option<String>,
+    pub(crate) fields: Vec<(&'static str, String)>,
+}
+
+impl EventFields {
+    pub(crate) fn new<R: RecordFields>(formatter: &FieldFormatter, record: R) -> Self {
+        let mut visitor = EventFieldsVisitor {
+            formatter,
+            fields: Self::default(),
+        };
+        record.record(&mut visitor);
+        formatter.order.sort(&mut visitor.fields.fields);
+        visitor.fields
+    }
+
+    /// Writes the message followed by the other fields on a single line
+    pub(crate) fn write(&self, f: &mut dyn fmt::Write) -> fmt::Result {
+        self.write_styled(f, Style::new())
+    }
+
+    /// Writes the message followed by the other fields on a single line, painting the other
+    /// fields with `style`
+    pub(crate) fn write_styled(&self, f: &mut dyn fmt::Write, style: Style) -> fmt::Result {
+        let mut seen = false;
+        if let Some(ref message) = self.message {
+            f.write_str(message)?;
+            seen = true;
+        }
+        for (name, value) in &self.fields {
+            if seen {
+                f.write_char(' ')?;
+            }
+            write!(f, "{}", style.paint(format!("{}={}", name, value)))?;
+            seen = true;
+        }
+        Ok(())
+    }
+}
+
+struct EventFieldsVisitor<'a> {
+    formatter: &'a FieldFormatter,
+    fields: EventFields,
+}
+
+impl EventFieldsVisitor<'_> {
+    fn push(&mut self, field: &Field, value: &dyn fmt::Debug, raw: Option<&str>) {
+        let name = field.name();
+        if name == "message" {
+            self.fields.message = Some(format!("{:?}", value));
+        } else if !self.formatter.visibility.hides(name) {
+            let name = name.strip_prefix("r#").unwrap_or(name);
+            let value = if self.formatter.redaction.matches(name) {
+                Redaction::REDACTED.to_owned()
+            } else {
+                self.formatter.render(name, value, raw)
+            };
+            self.fields.fields.push((name, value));
+        }
+    }
+}
+
+impl Visit for EventFieldsVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.push(field, &format_args!("{}", value), Some(value))
+        } else {
+            self.push(field, &value, Some(value))
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(field, value, None)
+    }
+}
+
+/// Writes the chain of sources of each error among the fields of `event`, one cause per line below
+/// the event line, which only shows the error itself
+pub(crate) fn write_causes(
+    f: &mut dyn fmt::Write,
+    event: &Event<'_>,
+    redaction: &Redaction,
+) -> fmt::Result {
+    let mut visitor = CauseVisitor {
+        redaction,
+        causes: String::new(),
+    };
+    event.record(&mut visitor);
+    f.write_str(&visitor.causes)
+}
+
+struct CauseVisitor<'a> {
+    redaction: &'a Redaction,
+    causes: String,
+}
+
+impl Visit for CauseVisitor<'_> {
+    fn record_error(&mut self, field: &Field, value: &(dyn Error + 'static)) {
+        let name = field.name();
+        let name = name.strip_prefix("r#").unwrap_or(name);
+        if self.redaction.matches(name) {
+            return;
+        }
+        let mut causes = iter::successors(value.source(), |&error| error.source()).peekable();
+        if causes.peek().is_none() {
+            return;
+        }
+        let dimmed = Style::new().dimmed();
+        let heading = format!("`{}` was caused by:", name);
+        let _ = writeln!(self.causes, "    {}", dimmed.paint(heading));
+        for (i, cause) in causes.enumerate() {
+            let number = format!("{:>2}:", i);
+            let _ = writeln!(self.causes, "    {} {}", dimmed.paint(number), cause);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+/// Renders the fields of events and spans in the configured [`FieldStyle`] and [`FieldOrder`]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FieldFormatter {
+    pub(crate) style: FieldStyle,
+    pub(crate) order: FieldOrder,
+    pub(crate) max_length: Option<usize>,
+    pub(crate) redaction: Redaction,
+    pub(crate) visibility: Visibility,
+    pub(crate) units: Units,
+}
+
+impl FieldFormatter {
+    /// Renders the value of the field `name` as it appears after the `=`
+    fn render(&self, name: &str, value: &dyn fmt::Debug, raw: Option<&str>) -> String {
+        let mut text = match (self.style, raw) {
+            (FieldStyle::KeyValue, Some(raw)) => raw.to_owned(),
+            _ => format!("{:?}", value),
+        };
+        if let Some(humanized) = self.units.humanize(name, &text) {
+            text = humanized;
+        } else if let Some(max_length) = self.max_length {
+            // The length of the value itself, not of its quotes and escapes
+            let original = raw.map_or(text.len(), str::len);
+            Self::truncate(&mut text, max_length, original);
+        }
+        match self.style {
+            FieldStyle::Debug => text,
+            FieldStyle::KeyValue => FieldStyle::quote(&text),
+        }
+    }
+
+    /// Cuts the text down to at most `max_length` bytes, noting the `original` length of the
+    /// value
+    ///
+    /// Strings formatted with `Debug` keep their closing quote, cut inside the quotes and not
+    /// in the middle of an escape, such as `\n` or `\u{200b}`.
+    fn truncate(text: &mut String, max_length: usize, original: usize) {
+        if text.len() <= max_length {
+            return;
+        }
+        let quoted = text.len() >= 2 && text.starts_with('"') && text.ends_with('"');
+        let mut cut = if quoted {
+            max_length.saturating_sub(1).max(1)
+        } else {
+            max_length
+        };
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        if quoted {
+            // Back up to the start of the escape the cut lands in, if any
+            let bytes = text.as_bytes();
+            let mut i = 1;
+            while i < cut {
+                if bytes[i] != b'\\' {
+                    i += 1;
+                    continue;
+                }
+                let end = match bytes.get(i + 1) {
+                    Some(b'u') => text[i..]
+                        .find('}')
+                        .map_or(text.len(), |close| i + close + 1),
+                    _ => i + 2,
+                };
+                if end > cut {
+                    cut = i;
+                    break;
+                }
+                i = end;
+            }
+        }
+        text.truncate(cut);
+        if quoted {
+            text.push('"');
+        }
+        write!(text, "…({} bytes)", original).expect("writing to a String can't fail");
+    }
+
+    /// Writes the message of `event` followed by its other fields as they are recorded, without
+    /// collecting them first, unless they have to be collected to be reordered or rendered
+    ///
+    /// The lines of the message after the first start with `continuation`. Returns `None`,
+    /// having written nothing, when the fields have to be collected.
+    pub(crate) fn write_recorded(
+        &self,
+        f: &mut dyn fmt::Write,
+        event: &Event<'_>,
+        continuation: &str,
+    ) -> Option<fmt::Result> {
+        let rendered = self.style != FieldStyle::Debug
+            || self.order != FieldOrder::Recorded
+            || self.max_length.is_some()
+            || !self.units.is_empty();
+        // The message goes first wherever it is recorded
+        let fields = event.metadata().fields();
+        let message_first = match fields.field("message") {
+            Some(message) => fields.iter().next() == Some(message),
+            None => true,
+        };
+        if rendered || !message_first {
+            return None;
+        }
+        let mut visitor = RecordedVisitor {
+            formatter: self,
+            f,
+            continuation,
+            seen: false,
+            result: Ok(()),
+        };
+        event.record(&mut visitor);
+        Some(visitor.result)
+    }
+}
+
+/// Writes fields as they are recorded, the way [`EventFields::write`] writes them once collected
+struct RecordedVisitor<'a> {
+    formatter: &'a FieldFormatter,
+    f: &'a mut dyn fmt::Write,
+    continuation: &'a str,
+    seen: bool,
+    result: fmt::Result,
+}
+
+impl RecordedVisitor<'_> {
+    fn write(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let name = field.name();
+        if self.result.is_err() || (name != "message" && self.formatter.visibility.hides(name)) {
+            return;
+        }
+        let separator = if self.seen { " " } else { "" };
+        self.seen = true;
+        self.result = if name == "message" {
+            let mut continued = Continued {
+                inner: &mut *self.f,
+                continuation: self.continuation,
+            };
+            write!(continued, "{:?}", value)
+        } else {
+            let name = name.strip_prefix("r#").unwrap_or(name);
+            if self.formatter.redaction.matches(name) {
+                write!(self.f, "{}{}={}", separator, name, Redaction::REDACTED)
+            } else {
+                write!(self.f, "{}{}={:?}", separator, name, value)
+            }
+        };
+    }
+}
+
+impl Visit for RecordedVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.write(field, &format_args!("{}", value))
+        } else {
+            self.write(field, &value)
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.write(field, value)
+    }
+}
+
+impl<'writer> FormatFields<'writer> for FieldFormatter {
+    fn format_fields<R: RecordFields>(
+        &self,
+        writer: &'writer mut dyn fmt::Write,
+        fields: R,
+    ) -> fmt::Result {
+        EventFields::new(self, fields).write(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn truncated(text: &str, max_length: usize) -> String {
+        let (mut text, original) = (text.to_owned(), text.len());
+        FieldFormatter::truncate(&mut text, max_length, original);
+        text
+    }
+
+    fn rendered(value: &str, max_length: usize) -> String {
+        let formatter = FieldFormatter {
+            max_length: Some(max_length),
+            ..FieldFormatter::default()
+        };
+        formatter.render("field", &value, Some(value))
+    }
+
+    #[test]
+    fn short_values_are_left_alone() {
+        assert_eq!(truncated("\"abc\"", 5), "\"abc\"");
+        assert_eq!(truncated("12345", 5), "12345");
+    }
+
+    #[test]
+    fn unquoted_values_are_cut_at_the_length() {
+        assert_eq!(truncated("1234567890", 4), "1234…(10 bytes)");
+    }
+
+    #[test]
+    fn quoted_values_keep_their_closing_quote() {
+        assert_eq!(truncated("\"abcdefgh\"", 5), "\"abc\"…(10 bytes)");
+    }
+
+    #[test]
+    fn escapes_are_not_split() {
+        assert_eq!(truncated("\"ab\\ncd\"", 5), "\"ab\"…(8 bytes)");
+        assert_eq!(truncated("\"a\\\\bcd\"", 5), "\"a\\\\\"…(8 bytes)");
+    }
+
+    #[test]
+    fn unicode_escapes_are_not_split() {
+        assert_eq!(truncated("\"ab\\u{200b}cd\"", 6), "\"ab\"…(14 bytes)");
+        assert_eq!(
+            truncated("\"ab\\u{200b}cd\"", 12),
+            "\"ab\\u{200b}\"…(14 bytes)"
+        );
+        assert_eq!(
+            truncated("\"\\u{200b}\\u{200b}\"", 10),
+            "\"\\u{200b}\"…(18 bytes)"
+        );
+    }
+
+    #[test]
+    fn original_length_is_that_of_the_value() {
+        assert_eq!(rendered("say \"hi\" now", 6), "\"say \"…(12 bytes)");
+        assert_eq!(rendered("a\nb\nc\nd", 5), "\"a\\n\"…(7 bytes)");
+        assert_eq!(rendered("\u{200b}abcdef", 6), "\"\"…(9 bytes)");
+    }
+
+    #[test]
+    fn multibyte_characters_are_not_split() {
+        assert_eq!(truncated("\"aé\"", 3), "\"a\"…(5 bytes)");
+        assert_eq!(truncated("aéb", 2), "a…(4 bytes)");
+    }
+}
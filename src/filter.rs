@@ -0,0 +1,440 @@
+use crate::boost::{self, BoostRule};
+use crate::dedup::{self, RepeatFilter, ShownOnce, StormFilter, StormNote};
+use crate::explain::Explainer;
+use crate::fields::SpanFields;
+use crate::overhead::{self, Stage};
+use crate::sample::{self, SampleRule};
+#[cfg(feature = "regex")]
+pub(crate) use regex::Regex;
+use std::any::TypeId;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::span::{Attributes, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Id, Metadata, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context, Filter, Layer};
+use tracing_subscriber::registry::{LookupSpan, Scope};
+
+/// Stands in for the message patterns without the `regex` feature, where none can be given
+#[cfg(not(feature = "regex"))]
+#[derive(Debug, Clone)]
+pub(crate) enum Regex {}
+
+#[cfg(not(feature = "regex"))]
+impl Regex {
+    pub(crate) fn is_match(&self, _text: &str) -> bool {
+        match *self {}
+    }
+}
+
+#[cfg(not(feature = "regex"))]
+impl fmt::Display for Regex {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {}
+    }
+}
+
+/// Selects events by the value of one of their fields, or of a field of an enclosing span
+///
+/// Parsed from `name=value`, or just `name` to select events where the field is present.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDirective {
+    name: String,
+    value: Option<String>,
+}
+
+impl FieldDirective {
+    /// Selects events where the field `name` is `value`, or where it is present if `value` is
+    /// `None`
+    pub fn new(name: &str, value: Option<&str>) -> Self {
+        Self {
+            name: name.to_owned(),
+            value: value.map(str::to_owned),
+        }
+    }
+
+    fn matches(&self, fields: &SpanFields) -> bool {
+        match (fields.get(&self.name), &self.value) {
+            (Some(actual), Some(expected)) => actual == expected,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    fn matches_scope<S>(&self, scope: Option<Scope<'_, S>>) -> bool
+    where
+        S: for<'a> LookupSpan<'a>,
+    {
+        scope.into_iter().flatten().any(|span| {
+            span.extensions()
+                .get::<SpanFields>()
+                .is_some_and(|fields| self.matches(fields))
+        })
+    }
+}
+
+impl fmt::Display for FieldDirective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={}", self.name, value),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+/// Error returned when a [`FieldDirective`] can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFieldDirectiveError(String);
+
+impl fmt::Display for ParseFieldDirectiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid field filter `{}`, expected `name=value`",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseFieldDirectiveError {}
+
+impl FromStr for FieldDirective {
+    type Err = ParseFieldDirectiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (s.trim(), None),
+        };
+        if name.is_empty() {
+            return Err(ParseFieldDirectiveError(s.to_owned()));
+        }
+        // Accept the quotes that the `Debug` field style prints around strings
+        let value = value.map(|value| {
+            value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value)
+        });
+        Ok(Self::new(name, value))
+    }
+}
+
+/// Why an event didn't pass the checks of an [`EventFilter`]
+#[derive(Debug)]
+enum Rejection<'a> {
+    Field(&'a FieldDirective),
+    NoMatch,
+    Excluded(&'a Regex),
+    Repeated,
+    Once,
+    Collapsed,
+    Sampled(&'a SampleRule),
+    NotRaised,
+}
+
+impl fmt::Display for Rejection<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Field(directive) => write!(f, "doesn't match field filter `{}`", directive),
+            Rejection::NoMatch => f.write_str("message matches none of the message filters"),
+            Rejection::Excluded(regex) => write!(f, "message matches exclusion `{}`", regex),
+            Rejection::Repeated => f.write_str("repeats an error shown shortly before"),
+            Rejection::Once => f.write_str("was logged once already"),
+            Rejection::Collapsed => f.write_str("repeats the previous event"),
+            Rejection::Sampled(rule) => write!(f, "not among the {} kept", rule),
+            Rejection::NotRaised => f.write_str("no boost rule raises it to a level shown"),
+        }
+    }
+}
+
+/// Names of the fields declared by span callsites, as a bloom filter
+///
+/// An event can only pass a field directive if its own callsite declares the field, or if some
+/// span might have it. Checking that against the callsite metadata rejects most events without
+/// recording any of their fields, which keeps field filters affordable at `TRACE` volumes.
+#[derive(Debug, Default)]
+pub(crate) struct SpanFieldNames(AtomicU64);
+
+impl SpanFieldNames {
+    fn bits(name: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        let hash = hasher.finish();
+        1 << (hash & 63) | 1 << ((hash >> 6) & 63)
+    }
+
+    fn insert(&self, name: &str) {
+        self.0.fetch_or(Self::bits(name), Ordering::Relaxed);
+    }
+
+    fn may_contain(&self, name: &str) -> bool {
+        let bits = Self::bits(name);
+        self.0.load(Ordering::Relaxed) & bits == bits
+    }
+}
+
+/// Skips the events whose callsite can't pass the field directives and message patterns of an
+/// [`EventFilter`], as a per-layer filter checked along with the level and target filters,
+/// before the events are even built
+///
+/// Callsites without a `message` field are never enabled when no message pattern matches an
+/// empty message. Those without a field filtered on are checked against the fields of the spans
+/// registered so far on each event, as more spans may declare the field later.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Precheck {
+    /// The names of the fields filtered on
+    fields: Arc<[String]>,
+    /// Whether events without a message are hidden by the message patterns
+    needs_message: bool,
+    span_fields: Arc<SpanFieldNames>,
+}
+
+impl Precheck {
+    /// Checks callsites against `fields` and `matches`, or only records the fields of spans if
+    /// `explained`, so that every rejection reaches the explainer
+    pub(crate) fn new(fields: &[FieldDirective], matches: &[Regex], explained: bool) -> Self {
+        if explained {
+            return Self::default();
+        }
+        Self {
+            fields: fields
+                .iter()
+                .map(|directive| directive.name.clone())
+                .collect(),
+            needs_message: !matches.is_empty() && !matches.iter().any(|re| re.is_match("")),
+            span_fields: Arc::default(),
+        }
+    }
+
+    /// Whether the events of `metadata` can't pass the message patterns, whatever their message
+    fn lacks_message(&self, metadata: &Metadata<'_>) -> bool {
+        self.needs_message && metadata.fields().field("message").is_none()
+    }
+
+    /// Whether the events of `metadata` might pass the field directives, judging by the field
+    /// names alone
+    fn may_have_fields(&self, metadata: &Metadata<'_>) -> bool {
+        self.fields.iter().all(|name| {
+            metadata.fields().field(name).is_some() || self.span_fields.may_contain(name)
+        })
+    }
+}
+
+impl<S> Filter<S> for Precheck {
+    fn enabled(&self, metadata: &Metadata<'_>, _ctx: &Context<'_, S>) -> bool {
+        !metadata.is_event() || self.may_have_fields(metadata)
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if metadata.is_span() {
+            for field in metadata.fields() {
+                self.span_fields.insert(field.name());
+            }
+            Interest::always()
+        } else if self.lacks_message(metadata) {
+            Interest::never()
+        } else if self.fields.is_empty() {
+            Interest::always()
+        } else {
+            Interest::sometimes()
+        }
+    }
+}
+
+/// Wraps a layer so that it only sees the events that pass the configured checks
+///
+/// Event fields are only available once an event is dispatched, so unlike level and target
+/// filters these checks can't use `enabled`. Span close events, which `fmt::Layer` emits from
+/// `on_close`, are checked against the fields of the span that is closing but not against the
+/// message patterns.
+pub(crate) struct EventFilter<L> {
+    pub(crate) inner: L,
+    pub(crate) fields: Vec<FieldDirective>,
+    /// Raise the level of events, which the level filters may only let through for them
+    pub(crate) boosts: Arc<[BoostRule]>,
+    /// Events are only shown if their message matches one of these, when there are any
+    pub(crate) matches: Vec<Regex>,
+    /// Events are hidden if their message matches any of these
+    pub(crate) excludes: Vec<Regex>,
+    pub(crate) storms: Option<Arc<StormFilter>>,
+    shown_once: ShownOnce,
+    pub(crate) repeats: Option<Arc<RepeatFilter>>,
+    pub(crate) samples: Vec<SampleRule>,
+    pub(crate) explainer: Option<Arc<Explainer>>,
+    /// Shared with the per-layer filter skipping the events that can't pass, and records the
+    /// fields of spans
+    pub(crate) precheck: Precheck,
+}
+
+impl<L> EventFilter<L> {
+    pub(crate) fn new(inner: L) -> Self {
+        Self {
+            inner,
+            fields: Vec::new(),
+            boosts: Arc::new([]),
+            matches: Vec::new(),
+            excludes: Vec::new(),
+            storms: None,
+            shown_once: ShownOnce::default(),
+            repeats: None,
+            samples: Vec::new(),
+            explainer: None,
+            precheck: Precheck::default(),
+        }
+    }
+
+    /// Finds a field directive the event can't pass, judging by the field names alone
+    fn precheck(&self, metadata: &Metadata<'_>) -> Option<&FieldDirective> {
+        self.fields.iter().find(|directive| {
+            metadata.fields().field(&directive.name).is_none()
+                && !self.precheck.span_fields.may_contain(&directive.name)
+        })
+    }
+
+    fn rejection<S>(&self, event: &Event<'_>, ctx: &Context<'_, S>) -> Option<Rejection<'_>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        if self.fields.is_empty() && self.matches.is_empty() && self.excludes.is_empty() {
+            return None;
+        }
+        if event.metadata().target() == dedup::REPEAT_TARGET {
+            return None;
+        }
+        if let Some(directive) = self.precheck(event.metadata()) {
+            return Some(Rejection::Field(directive));
+        }
+        if self.precheck.lacks_message(event.metadata()) {
+            return Some(Rejection::NoMatch);
+        }
+        // Only the fields filtered on are rendered, see `lazy`
+        let fields = SpanFields::record_selected(event, |name| {
+            name == "message" || self.fields.iter().any(|directive| directive.name == name)
+        });
+
+        let message = fields.get("message").unwrap_or_default();
+        if !self.matches.is_empty() && !self.matches.iter().any(|re| re.is_match(message)) {
+            return Some(Rejection::NoMatch);
+        }
+        if let Some(regex) = self.excludes.iter().find(|re| re.is_match(message)) {
+            return Some(Rejection::Excluded(regex));
+        }
+
+        let directive = self.fields.iter().find(|directive| {
+            !directive.matches(&fields) && !directive.matches_scope(ctx.event_scope(event))
+        });
+        directive.map(Rejection::Field)
+    }
+
+    fn allows_close<S>(&self, id: &Id, ctx: &Context<'_, S>) -> bool
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        self.fields
+            .iter()
+            .all(|directive| directive.matches_scope(ctx.span_scope(id)))
+    }
+}
+
+impl<S, L> Layer<S> for EventFilter<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber)
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, ctx)
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        self.inner.new_span(attrs, id, ctx)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        self.inner.on_record(span, values, ctx)
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.inner.on_follows_from(span, follows, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let pending = boost::take_pending();
+        let mut raised = None;
+        let rejection = overhead::measure(Stage::Filtering, || {
+            if !self.boosts.is_empty() {
+                raised = boost::raised(&self.boosts, event);
+            }
+            // Events let through for the rules have to be raised to a level that is shown
+            let not_raised = pending.filter(|&shown| raised.is_none_or(|level| level > shown));
+            // Sampling goes first, as it is the cheapest
+            not_raised
+                .map(|_| Rejection::NotRaised)
+                .or_else(|| sample::drops(&self.samples, event.metadata()).map(Rejection::Sampled))
+                .or_else(|| self.rejection(event, &ctx))
+                .or_else(|| {
+                    let storms = self.storms.as_ref()?;
+                    Some(Rejection::Repeated).filter(|_| !storms.allows_event(event, &ctx))
+                })
+                .or_else(|| Some(Rejection::Once).filter(|_| !self.shown_once.allows_event(event)))
+                .or_else(|| {
+                    let repeats = self.repeats.as_ref()?;
+                    Some(Rejection::Collapsed).filter(|_| !repeats.allows_event(event))
+                })
+        });
+        if let (Some(rejection), Some(explainer)) = (&rejection, &self.explainer) {
+            explainer.explain(event.metadata(), rejection);
+        }
+        if rejection.is_none() {
+            boost::set_raised(raised);
+            self.inner.on_event(event, ctx);
+            boost::set_raised(None);
+            // Don't leave a note behind if the inner layer didn't format the event
+            StormNote::take();
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if self.allows_close(&id, &ctx) {
+            self.inner.on_close(id, ctx)
+        }
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.inner.on_id_change(old, new, ctx)
+    }
+
+    #[doc(hidden)]
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const _ as *const ())
+        } else {
+            self.inner.downcast_raw(id)
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use crate::batch::{self, BatchSink, Driver, Push, Sender};
+use crate::dedup::StormNote;
+use crate::fields::{Redaction, SpanFields};
+use crate::health::SinkStatus;
+use crate::http;
+use crate::json;
+use crate::run;
+use crate::syslog;
+use std::fmt::{self, Write as _};
+use std::io::{self, Write as _};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Largest UDP datagram sent, which Graylog accepts by default
+const MAX_DATAGRAM: usize = 8192;
+
+/// Size of the header of each chunk: magic bytes, message ID, sequence number and count
+const CHUNK_HEADER: usize = 12;
+
+/// Most chunks a message can be split into, beyond which Graylog drops it
+const MAX_CHUNKS: usize = 128;
+
+#[derive(Debug)]
+enum Transport {
+    /// Sent directly, as sending a datagram doesn't wait for the server
+    Udp {
+        socket: UdpSocket,
+        health: Arc<SinkStatus>,
+    },
+    /// Written in batches from the background, see [`batch`]
+    Tcp(Sender<String>),
+}
+
+/// Writes batches of null-terminated messages to Graylog over TCP, reconnecting when a write
+/// fails, as the server may have restarted
+#[derive(Debug)]
+struct TcpPush {
+    address: String,
+    stream: Mutex<Option<TcpStream>>,
+}
+
+impl TcpPush {
+    fn connect(address: &str) -> io::Result<TcpStream> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for host"))?;
+        let stream = TcpStream::connect_timeout(&address, http::TIMEOUT)?;
+        stream.set_write_timeout(Some(http::TIMEOUT))?;
+        Ok(stream)
+    }
+}
+
+impl Push for TcpPush {
+    fn push(&self, body: &str) -> io::Result<u16> {
+        let mut stream = self.stream.lock().unwrap_or_else(|e| e.into_inner());
+        let mut sent = Ok(());
+        for _ in 0..2 {
+            if stream.is_none() {
+                *stream = Some(Self::connect(&self.address)?);
+            }
+            if let Some(tcp) = stream.as_mut() {
+                sent = tcp.write_all(body.as_bytes());
+                if sent.is_ok() {
+                    return Ok(200);
+                }
+            }
+            *stream = None;
+        }
+        sent.map(|()| 200)
+    }
+}
+
+/// Sends each event to Graylog as a GELF message, see [`Builder::gelf`](crate::Builder::gelf)
+///
+/// The message goes in `short_message`, the level in `level` as a syslog severity, and the
+/// run ID, target, call site and fields in additional fields, such as `_run_id`, `_line` and
+/// `_user_id`. Fields of enclosing spans are prefixed with the span name, such as
+/// `_request.id`, and fields named like a fixed one get an underscore after it, such as
+/// `_line_`. Messages are chunked over UDP, and terminated by a null byte over TCP.
+#[derive(Debug)]
+pub(crate) struct Gelf {
+    transport: Transport,
+    host: String,
+    redaction: Redaction,
+    /// Counts the messages sent, to give chunks of different messages different IDs
+    sent: AtomicU64,
+}
+
+impl Gelf {
+    /// Connects to `address`, a `host:port` to send UDP datagrams to, optionally prefixed with
+    /// `udp://`, or prefixed with `tcp://` to send over TCP from the background, returning the
+    /// sink writing the messages left on shutdown along with it
+    pub(crate) fn connect(
+        address: &str,
+        redaction: Redaction,
+        driver: &Driver,
+    ) -> io::Result<(Self, Option<BatchSink<String>>)> {
+        let (transport, sink) = match address.strip_prefix("tcp://") {
+            Some(address) => {
+                let push = TcpPush {
+                    address: address.to_owned(),
+                    stream: Mutex::new(Some(TcpPush::connect(address)?)),
+                };
+                let (sender, sink) =
+                    batch::start("Graylog", driver, push, |messages: &mut Vec<String>| {
+                        let mut body = String::new();
+                        for message in messages.drain(..) {
+                            body.push_str(&message);
+                            body.push('\0');
+                        }
+                        body
+                    })?;
+                (Transport::Tcp(sender), Some(sink))
+            }
+            None => {
+                let address = address.strip_prefix("udp://").unwrap_or(address);
+                let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+                socket.connect(address)?;
+                let health = SinkStatus::register("Graylog");
+                (Transport::Udp { socket, health }, None)
+            }
+        };
+        let gelf = Self {
+            transport,
+            host: syslog::hostname().unwrap_or_else(|| "-".to_owned()),
+            redaction,
+            sent: AtomicU64::new(0),
+        };
+        Ok((gelf, sink))
+    }
+
+    fn send(&self, message: String) {
+        match &self.transport {
+            Transport::Udp { socket, health } => {
+                health.record(&self.send_datagrams(socket, message.as_bytes()))
+            }
+            Transport::Tcp(sender) => sender.send(message),
+        }
+    }
+
+    fn send_datagrams(&self, socket: &UdpSocket, message: &[u8]) -> io::Result<()> {
+        if message.len() <= MAX_DATAGRAM {
+            return socket.send(message).map(drop);
+        }
+        let chunks = message.chunks(MAX_DATAGRAM - CHUNK_HEADER);
+        if chunks.len() > MAX_CHUNKS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("a message of {} bytes is too large to chunk", message.len()),
+            ));
+        }
+        let id = self.message_id();
+        let count = chunks.len() as u8;
+        let mut datagram = Vec::with_capacity(MAX_DATAGRAM);
+        for (sequence, chunk) in chunks.enumerate() {
+            datagram.clear();
+            datagram.extend_from_slice(&[0x1e, 0x0f]);
+            datagram.extend_from_slice(&id);
+            datagram.extend_from_slice(&[sequence as u8, count]);
+            datagram.extend_from_slice(chunk);
+            socket.send(&datagram)?;
+        }
+        Ok(())
+    }
+
+    /// Returns an ID for the chunks of a message, unique among the messages sent by this
+    /// process, and unlikely to be used by other processes
+    fn message_id(&self) -> [u8; 8] {
+        let sent = self.sent.fetch_add(1, Ordering::Relaxed);
+        let seed = (u64::from(std::process::id()) << 32)
+            ^ SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_nanos() as u64);
+        (seed ^ sent).to_be_bytes()
+    }
+}
+
+impl<S> Layer<S> for Gelf
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut message = String::from("{\"version\":\"1.1\",\"host\":");
+        let _ = json::write_str(&mut message, &self.host);
+        let _ = write!(
+            message,
+            ",\"timestamp\":{}.{:06},\"level\":{},\"_run_id\":\"{}\",\"_target\":",
+            timestamp.as_secs(),
+            timestamp.subsec_micros(),
+            severity(*metadata.level()),
+            run::run_id()
+        );
+        let _ = json::write_str(&mut message, metadata.target());
+        if let Some(file) = metadata.file() {
+            message.push_str(",\"_file\":");
+            let _ = json::write_str(&mut message, file);
+        }
+        if let Some(line) = metadata.line() {
+            let _ = write!(message, ",\"_line\":{}", line);
+        }
+
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(fields) = span.extensions().get::<SpanFields>() {
+                    for (name, value) in fields.iter() {
+                        put_name(&mut message, &format!("{}.{}", span.name(), name));
+                        let _ = json::write_str(&mut message, value);
+                    }
+                }
+            }
+        }
+
+        let mut visitor = FieldVisitor {
+            message: &mut message,
+            redaction: &self.redaction,
+            short_message: String::new(),
+        };
+        event.record(&mut visitor);
+        let mut short_message = visitor.short_message;
+        // The formatted output comes next and shows the note too
+        if let Some(note) = StormNote::take() {
+            let _ = write!(message, ",\"_repeated\":{}", note.suppressed());
+            let _ = write!(short_message, " {}", note);
+            note.restore();
+        }
+        message.push_str(",\"short_message\":");
+        let _ = json::write_str(&mut message, &short_message);
+        message.push('}');
+        self.send(message);
+    }
+}
+
+/// The syslog severity of a level, which GELF uses
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Additional fields written for every message, which fields of the same name would repeat
+const FIXED_FIELDS: [&str; 5] = ["run_id", "target", "file", "line", "repeated"];
+
+/// Writes the name of an additional field, made of ASCII word characters, dots and dashes, and
+/// prefixed with an underscore, avoiding `_id`, which GELF reserves, and the fixed fields, by
+/// appending an underscore
+fn put_name(message: &mut String, name: &str) {
+    let name = name.strip_prefix("r#").unwrap_or(name);
+    message.push_str(",\"_");
+    message.extend(name.chars().map(|c| match c {
+        c if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') => c,
+        _ => '_',
+    }));
+    if name == "id" || FIXED_FIELDS.contains(&name) {
+        message.push('_');
+    }
+    message.push_str("\":");
+}
+
+struct FieldVisitor<'a> {
+    message: &'a mut String,
+    redaction: &'a Redaction,
+    short_message: String,
+}
+
+impl FieldVisitor<'_> {
+    /// Writes the name of the field, returning whether its value should be written after it
+    fn name(&mut self, field: &Field) -> bool {
+        if field.name().starts_with("log.") {
+            return false;
+        }
+        put_name(self.message, field.name());
+        if self.redaction.matches(field.name()) {
+            let _ = json::write_str(self.message, Redaction::REDACTED);
+            return false;
+        }
+        true
+    }
+
+    fn raw(&mut self, field: &Field, value: impl fmt::Display) {
+        if self.name(field) {
+            let _ = write!(self.message, "{}", value);
+        }
+    }
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.raw(field, value)
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.raw(field, value)
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if value.is_finite() {
+            self.raw(field, value)
+        } else {
+            self.record_debug(field, &value)
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.short_message = value.to_owned();
+        } else if self.name(field) {
+            let _ = json::write_str(self.message, value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tracing::level_filters::STATIC_MAX_LEVEL;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    /// A sink sending over UDP to a socket of the test, along with that socket
+    fn connected() -> (Gelf, UdpSocket) {
+        let server = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let (gelf, sink) = Gelf::connect(&address, Redaction::default(), &Driver::Thread).unwrap();
+        assert!(sink.is_none());
+        (gelf, server)
+    }
+
+    fn receive(server: &UdpSocket) -> Vec<u8> {
+        let mut datagram = vec![0; MAX_DATAGRAM + 1];
+        let len = server.recv(&mut datagram).unwrap();
+        datagram.truncate(len);
+        datagram
+    }
+
+    fn gelf_socket(gelf: &Gelf) -> UdpSocket {
+        match &gelf.transport {
+            Transport::Udp { socket, .. } => socket.try_clone().unwrap(),
+            Transport::Tcp(_) => unreachable!(),
+        }
+    }
+
+    fn quoted(value: &str) -> String {
+        let mut quoted = String::new();
+        json::write_str(&mut quoted, value).unwrap();
+        quoted
+    }
+
+    fn name(name: &str) -> String {
+        let mut message = String::new();
+        put_name(&mut message, name);
+        message
+    }
+
+    #[test]
+    fn names_are_ascii_and_avoid_reserved_ones() {
+        assert_eq!(name("user_id"), ",\"_user_id\":");
+        assert_eq!(name("r#type"), ",\"_type\":");
+        assert_eq!(name("a b/c.d-e"), ",\"_a_b_c.d-e\":");
+        assert_eq!(name("café"), ",\"_caf_\":");
+        assert_eq!(name("id"), ",\"_id_\":");
+        for fixed in &FIXED_FIELDS {
+            assert_eq!(name(fixed), format!(",\"_{}_\":", fixed));
+        }
+        assert_eq!(name("request.line"), ",\"_request.line\":");
+    }
+
+    #[test]
+    fn events_are_sent_as_gelf_messages() {
+        if STATIC_MAX_LEVEL < Level::WARN {
+            return;
+        }
+        let (gelf, server) = connected();
+        let subscriber = Registry::default().with(gelf);
+        let mut line = 0;
+        tracing::subscriber::with_default(subscriber, || {
+            line = line!() + 1;
+            tracing::warn!(
+                target: "app::db",
+                line = 3,
+                count = 2u64,
+                ratio = 0.5,
+                nan = f64::NAN,
+                query = "say \"hi\"\n",
+                "slow query"
+            );
+        });
+        let message = String::from_utf8(receive(&server)).unwrap();
+
+        let host = syslog::hostname().unwrap_or_else(|| "-".to_owned());
+        assert!(message.starts_with(&format!(
+            "{{\"version\":\"1.1\",\"host\":{},\"timestamp\":",
+            quoted(&host)
+        )));
+        assert!(message.ends_with(",\"short_message\":\"slow query\"}"));
+        let expected = [
+            ",\"level\":4,".to_owned(),
+            format!(",\"_run_id\":\"{}\",", run::run_id()),
+            ",\"_target\":\"app::db\",".to_owned(),
+            format!(",\"_file\":{},", quoted(file!())),
+            format!(",\"_line\":{},", line),
+            ",\"_line_\":3,".to_owned(),
+            ",\"_count\":2,".to_owned(),
+            ",\"_ratio\":0.5,".to_owned(),
+            ",\"_nan\":\"NaN\",".to_owned(),
+            ",\"_query\":\"say \\\"hi\\\"\\n\",".to_owned(),
+        ];
+        for field in &expected {
+            assert!(message.contains(field.as_str()), "{} in {}", field, message);
+        }
+        assert!(!message.contains("\"_message\""));
+    }
+
+    #[test]
+    fn small_messages_are_sent_whole() {
+        let (gelf, server) = connected();
+        let message = vec![b'x'; MAX_DATAGRAM];
+        gelf.send_datagrams(&gelf_socket(&gelf), &message).unwrap();
+        assert_eq!(receive(&server), message);
+    }
+
+    #[test]
+    fn large_messages_are_chunked() {
+        let (gelf, server) = connected();
+        let payload = MAX_DATAGRAM - CHUNK_HEADER;
+        let message: Vec<u8> = (0..payload * 2 + 5).map(|i| i as u8).collect();
+        for _ in 0..2 {
+            gelf.send_datagrams(&gelf_socket(&gelf), &message).unwrap();
+        }
+
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let mut reassembled = Vec::new();
+            let mut id = None;
+            for sequence in 0..3u8 {
+                let datagram = receive(&server);
+                assert_eq!(datagram[..2], [0x1e, 0x0f]);
+                let chunk_id = &datagram[2..10];
+                assert_eq!(*id.get_or_insert_with(|| chunk_id.to_vec()), chunk_id);
+                assert_eq!(datagram[10..12], [sequence, 3]);
+                let expected = if sequence < 2 { payload } else { 5 };
+                assert_eq!(datagram.len(), CHUNK_HEADER + expected);
+                reassembled.extend_from_slice(&datagram[CHUNK_HEADER..]);
+            }
+            assert_eq!(reassembled, message);
+            ids.extend(id);
+        }
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    #[test]
+    fn messages_needing_too_many_chunks_are_refused() {
+        let (gelf, _server) = connected();
+        let message = vec![b'x'; (MAX_DATAGRAM - CHUNK_HEADER) * MAX_CHUNKS + 1];
+        let error = gelf
+            .send_datagrams(&gelf_socket(&gelf), &message)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
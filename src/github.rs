@@ -0,0 +1,59 @@
+//! Workflow commands that make GitHub Actions show events as annotations
+
+use std::fmt::{self, Write};
+use tracing::{Level, Metadata};
+
+/// Whether the program runs in a GitHub Actions job
+pub(crate) fn detected() -> bool {
+    std::env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true")
+}
+
+/// Writes a `::warning` or `::error` command for an event of `metadata` with `message`, or
+/// nothing for other levels
+///
+/// The call site is given as the annotation's file and line when it is relative to the
+/// repository, unlike that of dependencies, and the target as its title.
+pub(crate) fn write_annotation(
+    f: &mut dyn Write,
+    metadata: &Metadata<'_>,
+    message: &str,
+) -> fmt::Result {
+    let command = match *metadata.level() {
+        Level::ERROR => "error",
+        Level::WARN => "warning",
+        _ => return Ok(()),
+    };
+    write!(f, "::{} ", command)?;
+    if let Some(file) = metadata.file().filter(|file| is_relative(file)) {
+        f.write_str("file=")?;
+        escape(f, file, true)?;
+        if let Some(line) = metadata.line() {
+            write!(f, ",line={}", line)?;
+        }
+        f.write_char(',')?;
+    }
+    f.write_str("title=")?;
+    escape(f, metadata.target(), true)?;
+    f.write_str("::")?;
+    escape(f, message, false)?;
+    writeln!(f)
+}
+
+fn is_relative(file: &str) -> bool {
+    !file.starts_with('/') && !file.starts_with('\\') && file.get(1..2) != Some(":")
+}
+
+/// Escapes `text` as workflow commands expect, including the separators of properties in them
+fn escape(f: &mut dyn Write, text: &str, property: bool) -> fmt::Result {
+    for c in text.chars() {
+        match c {
+            '%' => f.write_str("%25")?,
+            '\r' => f.write_str("%0D")?,
+            '\n' => f.write_str("%0A")?,
+            ':' if property => f.write_str("%3A")?,
+            ',' if property => f.write_str("%2C")?,
+            c => f.write_char(c)?,
+        }
+    }
+    Ok(())
+}
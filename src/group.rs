@@ -0,0 +1,47 @@
+use crate::status::Stderr;
+use std::fmt;
+use std::io::Write;
+use std::mem;
+use tracing::Subscriber;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+/// The formatted events held back until a span closes, stored in the span's extensions, see
+/// [`Builder::group_spans`](crate::Builder::group_spans)
+///
+/// Events still held when the span goes away without its close event being formatted, as when
+/// field filters hide it, are written to stderr rather than lost.
+#[derive(Debug, Default)]
+struct Block(String);
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        if !self.0.is_empty() {
+            let _ = Stderr::new().write_all(self.0.as_bytes());
+        }
+    }
+}
+
+/// Adds the formatted `event` to the block of `span`, writing the whole block to `f` if
+/// `closing`, when it is the close event of the span
+pub(crate) fn hold<S>(
+    span: &SpanRef<'_, S>,
+    f: &mut dyn fmt::Write,
+    event: &str,
+    closing: bool,
+) -> fmt::Result
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let mut extensions = span.extensions_mut();
+    if !closing {
+        match extensions.get_mut::<Block>() {
+            Some(block) => block.0.push_str(event),
+            None => extensions.insert(Block(event.to_owned())),
+        }
+        return Ok(());
+    }
+    if let Some(mut block) = extensions.remove::<Block>() {
+        f.write_str(&mem::take(&mut block.0))?;
+    }
+    f.write_str(event)
+}
@@ -0,0 +1,278 @@
+use std::cell::Cell;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Target of the events reporting failing sinks, which pass whatever the filters
+pub(crate) const HEALTH_TARGET: &str = "pretty_tracing_subscriber::health";
+
+/// How often a sink that keeps failing is warned about at most
+const WARN_EVERY: Duration = Duration::from_secs(60);
+
+/// The sinks set up, which drop out of the list when they are dropped
+static SINKS: Mutex<Vec<Weak<SinkStatus>>> = Mutex::new(Vec::new());
+
+/// The reports waiting to be logged, see [`report`]
+static PENDING: Mutex<Vec<Report>> = Mutex::new(Vec::new());
+
+/// Whether [`PENDING`] may have reports, which spares the events from locking it
+static ANY_PENDING: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Set while this thread logs the reports, whose events could make a sink fail again
+    static REPORTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether the writes to a sink succeed, as returned by
+/// [`LogGuard::sink_health`](crate::LogGuard::sink_health)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinkHealth {
+    name: String,
+    healthy: bool,
+    failures: u64,
+    last_error: Option<String>,
+    last_failure: Option<SystemTime>,
+}
+
+impl SinkHealth {
+    /// The name of the sink, such as `the log file app.log` or `Loki`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the last write to the sink succeeded
+    pub fn healthy(&self) -> bool {
+        self.healthy
+    }
+
+    /// The number of writes to the sink that failed, each losing events
+    pub fn failures(&self) -> u64 {
+        self.failures
+    }
+
+    /// The error of the last write that failed
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// When the last write that failed was made
+    pub fn last_failure(&self) -> Option<SystemTime> {
+        self.last_failure
+    }
+}
+
+impl fmt::Display for SinkHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        match &self.last_error {
+            Some(error) if !self.healthy => write!(f, ": failing, {}", error)?,
+            _ => f.write_str(": ok")?,
+        }
+        if self.failures > 0 {
+            write!(f, " ({} failed writes)", self.failures)?;
+        }
+        Ok(())
+    }
+}
+
+/// Records how the writes to a sink go, queueing a warning when they start failing, at most
+/// every [`WARN_EVERY`] while they keep failing, and a note when they succeed again after a
+/// warning
+#[derive(Debug)]
+pub(crate) struct SinkStatus {
+    name: String,
+    healthy: AtomicBool,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    failures: u64,
+    last_error: Option<String>,
+    last_failure: Option<SystemTime>,
+    /// When the last warning was queued
+    warned: Option<Instant>,
+    /// Whether no write succeeded since the last warning, which a note then follows
+    recovering: bool,
+    /// The failures since the last warning
+    unreported: u64,
+}
+
+/// A failure or recovery of a sink waiting to be logged
+struct Report {
+    sink: String,
+    failures: u64,
+    /// The error, or `None` when the sink recovered
+    error: Option<String>,
+    /// The failures not warned about before this one
+    unreported: u64,
+}
+
+impl SinkStatus {
+    /// Starts recording the writes to the sink called `name`
+    pub(crate) fn register(name: impl Into<String>) -> Arc<Self> {
+        let status = Arc::new(Self {
+            name: name.into(),
+            healthy: AtomicBool::new(true),
+            state: Mutex::new(State::default()),
+        });
+        let mut sinks = SINKS.lock().unwrap_or_else(|e| e.into_inner());
+        sinks.retain(|sink| sink.strong_count() > 0);
+        sinks.push(Arc::downgrade(&status));
+        status
+    }
+
+    /// Records the outcome of a write
+    pub(crate) fn record<T, E: fmt::Display>(&self, result: &Result<T, E>) {
+        match result {
+            Ok(_) => self.succeeded(),
+            Err(e) => self.failed(e),
+        }
+    }
+
+    /// Records the outcome of a post to a log collector, which fails unless it answers with a
+    /// success status
+    pub(crate) fn record_post(&self, response: &io::Result<u16>) {
+        match response {
+            Ok(status) if (200..300).contains(status) => self.succeeded(),
+            Ok(status) => self.failed(&format_args!("the collector answered {}", status)),
+            Err(e) => self.failed(e),
+        }
+    }
+
+    /// Records a write that succeeded, which is cheap unless the sink was failing
+    pub(crate) fn succeeded(&self) {
+        if self.healthy.load(Ordering::Relaxed) || self.healthy.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        // Sinks such as UDP sockets fail every other write, which is noted once per warning
+        if !std::mem::take(&mut state.recovering) {
+            return;
+        }
+        queue(Report {
+            sink: self.name.clone(),
+            failures: state.failures,
+            error: None,
+            unreported: 0,
+        });
+    }
+
+    /// Records a write that failed with `error`
+    pub(crate) fn failed(&self, error: &dyn fmt::Display) {
+        self.healthy.store(false, Ordering::Relaxed);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let error = error.to_string();
+        state.failures += 1;
+        state.last_failure = Some(SystemTime::now());
+        state.last_error = Some(error.clone());
+        let due = state
+            .warned
+            .is_none_or(|warned| warned.elapsed() >= WARN_EVERY);
+        if !due {
+            state.unreported += 1;
+            return;
+        }
+        state.warned = Some(Instant::now());
+        state.recovering = true;
+        queue(Report {
+            sink: self.name.clone(),
+            failures: state.failures,
+            error: Some(error),
+            unreported: std::mem::take(&mut state.unreported),
+        });
+    }
+
+    fn health(&self) -> SinkHealth {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        SinkHealth {
+            name: self.name.clone(),
+            healthy: self.healthy.load(Ordering::Relaxed),
+            failures: state.failures,
+            last_error: state.last_error.clone(),
+            last_failure: state.last_failure,
+        }
+    }
+}
+
+fn queue(report: Report) {
+    PENDING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(report);
+    ANY_PENDING.store(true, Ordering::Release);
+}
+
+/// The health of every sink set up
+pub(crate) fn sinks() -> Vec<SinkHealth> {
+    let sinks = SINKS.lock().unwrap_or_else(|e| e.into_inner());
+    sinks
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|sink| sink.health())
+        .collect()
+}
+
+/// Logs the failures and recoveries of sinks recorded so far, returning whether there were
+/// any
+///
+/// Sinks record them while they write, often holding locks the events logging them would need,
+/// so they are queued and logged once the event being written is done with, or by the
+/// background threads of log collectors, which hold none.
+pub(crate) fn report() -> bool {
+    if !ANY_PENDING.load(Ordering::Acquire) || REPORTING.with(Cell::get) {
+        return false;
+    }
+    let reports = {
+        let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+        ANY_PENDING.store(false, Ordering::Release);
+        std::mem::take(&mut *pending)
+    };
+    REPORTING.with(|reporting| reporting.set(true));
+    for report in &reports {
+        let sink = report.sink.as_str();
+        let failures = report.failures;
+        match &report.error {
+            Some(error) if report.unreported > 0 => tracing::warn!(
+                target: HEALTH_TARGET,
+                sink,
+                failures,
+                "cannot write to {}, events are lost: {} ({} more failures since the last warning)",
+                sink,
+                error,
+                report.unreported
+            ),
+            Some(error) => tracing::warn!(
+                target: HEALTH_TARGET,
+                sink,
+                failures,
+                "cannot write to {}, events are lost: {}",
+                sink,
+                error
+            ),
+            None => tracing::info!(
+                target: HEALTH_TARGET,
+                sink,
+                failures,
+                "writing to {} again",
+                sink
+            ),
+        }
+    }
+    REPORTING.with(|reporting| reporting.set(false));
+    !reports.is_empty()
+}
+
+/// Logs the failures of sinks once each event has been written everywhere, as the outermost
+/// layer
+pub(crate) struct Reporter;
+
+impl<S: Subscriber> Layer<S> for Reporter {
+    fn on_event(&self, _event: &Event<'_>, _ctx: Context<'_, S>) {
+        report();
+    }
+}
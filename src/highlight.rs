@@ -0,0 +1,45 @@
+use crate::filter::Regex;
+#[cfg(feature = "regex")]
+use ansi_term::Style;
+
+/// Paints the parts of `text` that any of `patterns` match in inverse bold, see
+/// [`Builder::highlight`](crate::Builder::highlight), or returns `None` if none match
+///
+/// Overlapping and adjacent matches of different patterns are painted as one.
+#[cfg(feature = "regex")]
+pub(crate) fn highlight(text: &str, patterns: &[Regex]) -> Option<String> {
+    let mut ranges: Vec<(usize, usize)> = patterns
+        .iter()
+        .flat_map(|pattern| pattern.find_iter(text))
+        .filter(|found| !found.as_str().is_empty())
+        .map(|found| (found.start(), found.end()))
+        .collect();
+    if ranges.is_empty() {
+        return None;
+    }
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    let style = Style::new().reverse().bold();
+    let mut highlighted = String::with_capacity(text.len() + merged.len() * 12);
+    let mut written = 0;
+    for (start, end) in merged {
+        highlighted.push_str(&text[written..start]);
+        highlighted.push_str(&style.paint(&text[start..end]).to_string());
+        written = end;
+    }
+    highlighted.push_str(&text[written..]);
+    Some(highlighted)
+}
+
+/// Without the `regex` feature there are no patterns to paint
+#[cfg(not(feature = "regex"))]
+pub(crate) fn highlight(_text: &str, patterns: &[Regex]) -> Option<String> {
+    patterns.first().map(|pattern| match *pattern {})
+}
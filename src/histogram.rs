@@ -0,0 +1,222 @@
+use crate::shutdown::Sink;
+use crate::timing::{self, DisplayDuration};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use tracing::{Id, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// The target of the summaries logged periodically, see
+/// [`Builder::span_summary`](crate::Builder::span_summary)
+pub(crate) const SUMMARY_TARGET: &str = "pretty_tracing_subscriber::spans";
+
+/// Each power of two is split in this many buckets, which bounds the error of quantiles to 25%
+const SUB_BUCKETS: u32 = 4;
+
+/// How long the spans with a given name lived, as a histogram with logarithmic buckets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: vec![0; (128 * SUB_BUCKETS) as usize],
+            count: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+        }
+    }
+
+    fn record(&mut self, duration: Duration) {
+        self.buckets[bucket(duration.as_nanos())] += 1;
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+
+    /// The number of spans closed
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The time all the spans lived, added up
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// The average time the spans lived
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => Duration::from_secs_f64(self.total.as_secs_f64() / count as f64),
+        }
+    }
+
+    /// The longest time a span lived
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// The time within which the fraction `q` of the spans closed, such as 0.99 for the 99th
+    /// percentile, rounded up to the bucket it falls in
+    pub fn quantile(&self, q: f64) -> Duration {
+        let rank = (q.clamp(0.0, 1.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return upper_bound(i).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// Shows the count, the 50th, 90th and 99th percentiles and the maximum
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} spans, p50 {}, p90 {}, p99 {}, max {}",
+            self.count,
+            DisplayDuration(self.quantile(0.5)),
+            DisplayDuration(self.quantile(0.9)),
+            DisplayDuration(self.quantile(0.99)),
+            DisplayDuration(self.max)
+        )
+    }
+}
+
+/// The bucket of a duration in nanoseconds: the power of two below it, then which of the
+/// `SUB_BUCKETS` slices of that power it falls in
+fn bucket(nanos: u128) -> usize {
+    if nanos < u128::from(SUB_BUCKETS) {
+        return nanos as usize;
+    }
+    let power = 127 - nanos.leading_zeros();
+    let slice = (nanos >> (power - SUB_BUCKETS.trailing_zeros())) as u32 - SUB_BUCKETS;
+    (power * SUB_BUCKETS + slice) as usize
+}
+
+fn upper_bound(bucket: usize) -> Duration {
+    let bucket = bucket as u32;
+    if bucket < SUB_BUCKETS {
+        return Duration::from_nanos(u64::from(bucket));
+    }
+    let power = bucket / SUB_BUCKETS;
+    let slice = u128::from(bucket % SUB_BUCKETS + SUB_BUCKETS + 1);
+    let nanos = (slice << (power - SUB_BUCKETS.trailing_zeros())) - 1;
+    Duration::from_nanos(nanos.min(u128::from(u64::MAX)) as u64)
+}
+
+/// The durations of the spans closed so far, by span name, see
+/// [`LogGuard::span_durations`](crate::LogGuard::span_durations)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpanDurations(BTreeMap<&'static str, Histogram>);
+
+impl SpanDurations {
+    /// The histogram of the spans called `name`, if any closed
+    pub fn get(&self, name: &str) -> Option<&Histogram> {
+        self.0.get(name)
+    }
+
+    /// The span names and their histograms, by name
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &Histogram)> {
+        self.0.iter().map(|(name, histogram)| (*name, histogram))
+    }
+}
+
+/// Records how long spans live, see [`Builder::span_histograms`](crate::Builder::span_histograms)
+#[derive(Debug, Default)]
+pub(crate) struct SpanHistograms {
+    /// Since logging started
+    total: Mutex<HashMap<&'static str, Histogram>>,
+    /// Since the last summary
+    recent: Mutex<HashMap<&'static str, Histogram>>,
+    stopped: AtomicBool,
+}
+
+impl SpanHistograms {
+    /// Starts the thread logging a summary of the spans closed in each `interval`
+    pub(crate) fn report_every(self: &Arc<Self>, interval: Duration) {
+        let histograms = self.clone();
+        thread::Builder::new()
+            .name("span summary".to_owned())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                if histograms.stopped.load(Ordering::Relaxed) {
+                    return;
+                }
+                histograms.report();
+            })
+            .expect("failed to spawn the span summary thread");
+    }
+
+    pub(crate) fn durations(&self) -> SpanDurations {
+        let total = lock(&self.total);
+        SpanDurations(total.iter().map(|(k, v)| (*k, v.clone())).collect())
+    }
+
+    /// Logs a line for each span name closed since the last summary
+    fn report(&self) {
+        let recent = std::mem::take(&mut *lock(&self.recent));
+        let recent: BTreeMap<_, _> = recent.into_iter().collect();
+        for (name, histogram) in recent {
+            tracing::info!(target: SUMMARY_TARGET, "{}: {}", name, histogram);
+        }
+    }
+}
+
+/// Logs the spans closed since the last summary on shutdown, if summaries are logged
+impl Sink for SpanHistograms {
+    fn drain(&self) -> io::Result<()> {
+        if !self.stopped.swap(true, Ordering::Relaxed) {
+            self.report();
+        }
+        Ok(())
+    }
+
+    fn close(&self, _marker: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Adds the lifetime of each span that passes the filters to the histograms, when it closes
+pub(crate) struct HistogramLayer(pub(crate) Arc<SpanHistograms>);
+
+impl<S> Layer<S> for HistogramLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let duration = match timing::elapsed(&span) {
+            Some(duration) => duration,
+            None => return,
+        };
+        let name = span.metadata().name();
+        for histograms in [&self.0.total, &self.0.recent] {
+            lock(histograms)
+                .entry(name)
+                .or_insert_with(Histogram::new)
+                .record(duration);
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use crate::fields::{Redaction, SpanFields};
+use crate::run;
+use crate::shutdown::Sink;
+use crate::timestamp::{self, TimePrecision};
+use crate::timing::DisplayDuration;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::span::{Attributes, Record};
+use tracing::{Event, Id, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Most spans and events kept for the report, beyond which events are only counted
+const MAX_NODES: usize = 100_000;
+
+/// Styles the report, coloring levels like the formatted output
+const STYLE: &str = "\
+body{font:13px/1.5 ui-monospace,Menlo,Consolas,monospace;margin:0;background:#fdfdfd;color:#222}
+header{position:sticky;top:0;background:#f0f0f0;border-bottom:1px solid #ccc;padding:6px 12px}
+header h1{font-size:15px;margin:0 0 4px}
+header label{margin-right:12px;cursor:pointer}
+main{padding:6px 12px}
+details{margin-left:4px;padding-left:12px;border-left:1px solid #ddd}
+summary{cursor:pointer}
+.event{white-space:pre-wrap;padding-left:16px}
+time,.took,.target,.fields{color:#777}
+.took{margin-left:8px}
+.level{font-weight:bold}
+.ERROR>.level,.ERROR>summary>.level{color:#c62828}
+.WARN>.level,.WARN>summary>.level{color:#b8860b}
+.INFO>.level,.INFO>summary>.level{color:#2e7d32}
+.DEBUG>.level,.DEBUG>summary>.level{color:#1565c0}
+.TRACE>.level,.TRACE>summary>.level{color:#7b1fa2}
+.hide-ERROR .event.ERROR,.hide-WARN .event.WARN,.hide-INFO .event.INFO,
+.hide-DEBUG .event.DEBUG,.hide-TRACE .event.TRACE{display:none}
+";
+
+/// Hides the events of the levels unchecked, and opens or closes every span
+const SCRIPT: &str = "\
+for (const box of document.querySelectorAll('input[data-level]')) {
+  box.onchange = () => document.body.classList.toggle('hide-' + box.dataset.level, !box.checked);
+}
+function expand(open) {
+  for (const span of document.querySelectorAll('details')) span.open = open;
+}
+";
+
+/// Keeps the spans and events of the run, and writes them out as a standalone HTML page on
+/// shutdown, see [`Builder::html_report`](crate::Builder::html_report)
+///
+/// Spans are collapsible sections holding their events and the spans they entered, in the
+/// order they happened. Those holding warnings or errors start open.
+#[derive(Debug, Clone)]
+pub(crate) struct HtmlReport(Arc<Report>);
+
+#[derive(Debug)]
+struct Report {
+    path: PathBuf,
+    title: String,
+    redaction: Redaction,
+    started: SystemTime,
+    tree: Mutex<Tree>,
+}
+
+#[derive(Debug, Default)]
+struct Tree {
+    nodes: Vec<Node>,
+    /// The nodes outside of any span
+    roots: Vec<usize>,
+    events: usize,
+    /// The events left out past [`MAX_NODES`]
+    dropped: usize,
+}
+
+#[derive(Debug)]
+struct Node {
+    level: Level,
+    target: &'static str,
+    fields: SpanFields,
+    kind: Kind,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Span {
+        name: &'static str,
+        opened: Instant,
+        lived: Option<Duration>,
+        children: Vec<usize>,
+    },
+    Event {
+        time: SystemTime,
+    },
+}
+
+/// The node of a span in the report, stored in the span's extensions
+struct ReportNode(usize);
+
+impl HtmlReport {
+    pub(crate) fn new(path: PathBuf, title: String, redaction: Redaction) -> Self {
+        Self(Arc::new(Report {
+            path,
+            title,
+            redaction,
+            started: SystemTime::now(),
+            tree: Mutex::new(Tree::default()),
+        }))
+    }
+
+    /// The sink writing the page on shutdown
+    pub(crate) fn sink(&self) -> Arc<dyn Sink> {
+        self.0.clone()
+    }
+
+    fn tree(&self) -> MutexGuard<'_, Tree> {
+        self.0.tree.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Tree {
+    /// Adds `node` under the span of the node `parent`, if any
+    fn push(&mut self, parent: Option<usize>, node: Node) -> usize {
+        let index = self.nodes.len();
+        self.nodes.push(node);
+        let siblings = match parent.map(|parent| &mut self.nodes[parent].kind) {
+            Some(Kind::Span { children, .. }) => children,
+            _ => &mut self.roots,
+        };
+        siblings.push(index);
+        index
+    }
+
+    /// The most severe level of the events under the node `index`
+    fn worst(&self, index: usize) -> Option<Level> {
+        let node = &self.nodes[index];
+        match &node.kind {
+            Kind::Event { .. } => Some(node.level),
+            Kind::Span { children, .. } => {
+                // `Level` orders `ERROR` as the lowest
+                children.iter().filter_map(|&child| self.worst(child)).min()
+            }
+        }
+    }
+}
+
+impl<S> Layer<S> for HtmlReport
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let parent = span
+            .parent()
+            .and_then(|parent| parent.extensions().get::<ReportNode>().map(|node| node.0));
+        let mut tree = self.tree();
+        if tree.nodes.len() == MAX_NODES {
+            return;
+        }
+        let mut fields = SpanFields::default();
+        attrs.record(&mut fields);
+        let metadata = attrs.metadata();
+        let node = Node {
+            level: *metadata.level(),
+            target: metadata.target(),
+            fields,
+            kind: Kind::Span {
+                name: metadata.name(),
+                opened: Instant::now(),
+                lived: None,
+                children: Vec::new(),
+            },
+        };
+        let index = tree.push(parent, node);
+        span.extensions_mut().insert(ReportNode(index));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let index = match ctx.span(id) {
+            Some(span) => span.extensions().get::<ReportNode>().map(|node| node.0),
+            None => None,
+        };
+        if let Some(index) = index {
+            values.record(&mut self.tree().nodes[index].fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let parent = ctx
+            .event_span(event)
+            .and_then(|span| span.extensions().get::<ReportNode>().map(|node| node.0));
+        let mut tree = self.tree();
+        if tree.nodes.len() == MAX_NODES {
+            tree.dropped += 1;
+            return;
+        }
+        tree.events += 1;
+        let mut fields = SpanFields::default();
+        event.record(&mut fields);
+        let metadata = event.metadata();
+        let node = Node {
+            level: *metadata.level(),
+            target: metadata.target(),
+            fields,
+            kind: Kind::Event {
+                time: SystemTime::now(),
+            },
+        };
+        tree.push(parent, node);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let index = match ctx.span(&id) {
+            Some(span) => span.extensions().get::<ReportNode>().map(|node| node.0),
+            None => None,
+        };
+        if let Some(index) = index {
+            if let Kind::Span { opened, lived, .. } = &mut self.tree().nodes[index].kind {
+                *lived = Some(opened.elapsed());
+            }
+        }
+    }
+}
+
+impl Sink for Report {
+    /// Writes the page, replacing any report of an earlier run
+    fn drain(&self) -> io::Result<()> {
+        let tree = self.tree.lock().unwrap_or_else(|e| e.into_inner());
+        let mut page = String::new();
+        self.write_page(&mut page, &tree)
+            .map_err(|_| io::Error::other("failed to format the HTML report"))?;
+        write_file(&self.path, &page)
+    }
+
+    fn close(&self, _marker: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Report {
+    fn write_page(&self, f: &mut String, tree: &Tree) -> fmt::Result {
+        f.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+        write!(
+            f,
+            "<title>{}</title>\n<style>\n{}</style>\n",
+            Escaped(&self.title),
+            STYLE
+        )?;
+        f.push_str("</head>\n<body>\n<header>\n");
+        write!(
+            f,
+            "<h1>{}</h1>\n<p>run {}, started ",
+            Escaped(&self.title),
+            run::run_id()
+        )?;
+        timestamp::write_rfc3339(f, self.started, TimePrecision::Seconds)?;
+        write!(f, ", {} events", tree.events)?;
+        if tree.dropped > 0 {
+            write!(f, ", {} more left out", tree.dropped)?;
+        }
+        f.push_str("</p>\n");
+        for level in [
+            Level::ERROR,
+            Level::WARN,
+            Level::INFO,
+            Level::DEBUG,
+            Level::TRACE,
+        ] {
+            write!(
+                f,
+                "<label><input type=\"checkbox\" data-level=\"{0}\" checked> {0}</label>",
+                level
+            )?;
+        }
+        f.push_str(
+            "<button onclick=\"expand(true)\">expand all</button> \
+             <button onclick=\"expand(false)\">collapse all</button>\n</header>\n<main>\n",
+        );
+        for &index in &tree.roots {
+            self.write_node(f, tree, index)?;
+        }
+        write!(
+            f,
+            "</main>\n<script>\n{}</script>\n</body>\n</html>\n",
+            SCRIPT
+        )
+    }
+
+    fn write_node(&self, f: &mut String, tree: &Tree, index: usize) -> fmt::Result {
+        let node = &tree.nodes[index];
+        match &node.kind {
+            Kind::Span {
+                name,
+                lived,
+                children,
+                ..
+            } => {
+                let open = tree.worst(index).is_some_and(|worst| worst <= Level::WARN);
+                write!(
+                    f,
+                    "<details class=\"{}\"{}><summary><span class=\"level\">{}</span> <b>{}</b>",
+                    node.level,
+                    if open { " open" } else { "" },
+                    node.level,
+                    Escaped(name)
+                )?;
+                self.write_fields(f, &node.fields)?;
+                match lived {
+                    Some(lived) => {
+                        write!(f, "<span class=\"took\">{}</span>", DisplayDuration(*lived))?
+                    }
+                    None => f.push_str("<span class=\"took\">still open</span>"),
+                }
+                f.push_str("</summary>\n");
+                for &child in children {
+                    self.write_node(f, tree, child)?;
+                }
+                f.push_str("</details>\n")
+            }
+            Kind::Event { time } => {
+                write!(f, "<div class=\"event {}\"><time>", node.level)?;
+                timestamp::write_rfc3339(f, *time, TimePrecision::Millis)?;
+                write!(
+                    f,
+                    "</time> <span class=\"level\">{}</span> <span class=\"target\">{}</span> {}",
+                    node.level,
+                    Escaped(node.target),
+                    Escaped(node.fields.get("message").unwrap_or_default())
+                )?;
+                self.write_fields(f, &node.fields)?;
+                f.push_str("</div>\n")
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the fields other than the message, redacted
+    fn write_fields(&self, f: &mut String, fields: &SpanFields) -> fmt::Result {
+        let mut fields = fields
+            .iter()
+            .filter(|(name, _)| *name != "message" && !name.starts_with("log."))
+            .peekable();
+        if fields.peek().is_none() {
+            return Ok(());
+        }
+        f.push_str(" <span class=\"fields\">");
+        for (i, (name, value)) in fields.enumerate() {
+            let value = if self.redaction.matches(name) {
+                Redaction::REDACTED
+            } else {
+                value
+            };
+            if i > 0 {
+                f.push(' ');
+            }
+            write!(f, "{}={}", Escaped(name), Escaped(value))?;
+        }
+        f.push_str("</span>");
+        Ok(())
+    }
+}
+
+/// Writes the page to a file next to `path` first, so that a report being replaced is never
+/// left half written
+fn write_file(path: &Path, page: &str) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    fs::write(&partial, page)?;
+    fs::rename(&partial, path)
+}
+
+/// Escapes text for HTML
+struct Escaped<'a>(&'a str);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rest = self.0;
+        while let Some(i) = rest.find(['<', '>', '&', '"']) {
+            f.write_str(&rest[..i])?;
+            f.write_str(match rest.as_bytes()[i] {
+                b'<' => "&lt;",
+                b'>' => "&gt;",
+                b'&' => "&amp;",
+                _ => "&quot;",
+            })?;
+            rest = &rest[i + 1..];
+        }
+        f.write_str(rest)
+    }
+}
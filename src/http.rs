@@ -0,0 +1,185 @@
+//! Just enough HTTP to post events to log collectors, over TLS with the `tls` feature
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How long a request waits for the server
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How requests reach the server, given by the scheme of the URL
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Scheme {
+    Http,
+    /// Only supported with the `tls` feature
+    Https,
+}
+
+impl Scheme {
+    fn default_port(self) -> u16 {
+        match self {
+            Scheme::Http => 80,
+            Scheme::Https => 443,
+        }
+    }
+}
+
+/// Where requests are posted
+#[derive(Debug)]
+pub(crate) struct Endpoint {
+    scheme: Scheme,
+    /// Host and port, as given in the URL
+    authority: String,
+    path: String,
+}
+
+impl Endpoint {
+    /// Parses an `http://` or `https://` URL, posting to `default_path` if it has no path
+    pub(crate) fn parse(url: &str, default_path: &str) -> io::Result<Self> {
+        let (scheme, authority, path) = split_url(url)?;
+        Ok(Self::new(
+            scheme,
+            authority,
+            if path.len() > 1 { path } else { default_path },
+        ))
+    }
+
+    pub(crate) fn new(scheme: Scheme, authority: &str, path: &str) -> Self {
+        let authority = if authority.contains(':') {
+            authority.to_owned()
+        } else {
+            format!("{}:{}", authority, scheme.default_port())
+        };
+        Self {
+            scheme,
+            authority,
+            path: path.to_owned(),
+        }
+    }
+
+    /// Sends `body` in a POST request along with `headers`, such as its `Content-Type`,
+    /// returning the status code of the response
+    pub(crate) fn post(&self, headers: &[(&str, &str)], body: &str) -> io::Result<u16> {
+        let address = self
+            .authority
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for host"))?;
+        let stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.path,
+            self.authority,
+            body.len()
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        match self.scheme {
+            Scheme::Http => exchange(stream, &request),
+            #[cfg(feature = "tls")]
+            Scheme::Https => exchange(tls::wrap(self.host(), stream)?, &request),
+            #[cfg(not(feature = "tls"))]
+            Scheme::Https => Err(tls_unsupported()),
+        }
+    }
+
+    /// The host the certificate of the server is checked against, without the port
+    #[cfg(feature = "tls")]
+    fn host(&self) -> &str {
+        let host = match self.authority.rsplit_once(':') {
+            Some((host, _)) => host,
+            None => &self.authority,
+        };
+        host.trim_start_matches('[').trim_end_matches(']')
+    }
+}
+
+/// Writes `request` and reads the status code of the response
+fn exchange(mut stream: impl Read + Write, request: &str) -> io::Result<u16> {
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+
+    let mut status = [0; 12];
+    stream.read_exact(&mut status)?;
+    std::str::from_utf8(&status[9..12])
+        .ok()
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP response"))
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "https:// URLs need the tls feature",
+    )
+}
+
+/// Splits an `http://` or `https://` URL into its scheme, its authority, which may hold
+/// credentials, and its path
+pub(crate) fn split_url(url: &str) -> io::Result<(Scheme, &str, &str)> {
+    let (scheme, rest) = match url.strip_prefix("https://") {
+        Some(rest) => (Scheme::Https, rest),
+        None => match url.strip_prefix("http://") {
+            Some(rest) => (Scheme::Http, rest),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "only http:// and https:// URLs are supported",
+                ))
+            }
+        },
+    };
+    #[cfg(not(feature = "tls"))]
+    if scheme == Scheme::Https {
+        return Err(tls_unsupported());
+    }
+    Ok(match rest.find('/') {
+        Some(slash) => (scheme, &rest[..slash], &rest[slash..]),
+        None => (scheme, rest, "/"),
+    })
+}
+
+#[cfg(feature = "tls")]
+mod tls {
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+    use std::convert::TryFrom;
+    use std::io;
+    use std::net::TcpStream;
+    use std::sync::{Arc, OnceLock};
+
+    /// Trusts the Mozilla root certificates, shared by every connection
+    fn config() -> io::Result<Arc<ClientConfig>> {
+        static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+        if let Some(config) = CONFIG.get() {
+            return Ok(config.clone());
+        }
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(CONFIG.get_or_init(|| Arc::new(config)).clone())
+    }
+
+    /// Starts a TLS session with `host` over `stream`, which completes on the first write
+    pub(super) fn wrap(
+        host: &str,
+        stream: TcpStream,
+    ) -> io::Result<StreamOwned<ClientConnection, TcpStream>> {
+        let name = ServerName::try_from(host.to_owned())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let connection = ClientConnection::new(config()?, name).map_err(io::Error::other)?;
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
@@ -0,0 +1,95 @@
+use crate::config::LogSettings;
+use crate::reload::FilterHandle;
+use std::io::{self, BufRead, IsTerminal};
+use std::sync::Arc;
+use std::thread;
+use tracing_subscriber::filter::LevelFilter;
+
+/// The levels `+` and `-` step through, from the most verbose
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::TRACE,
+    LevelFilter::DEBUG,
+    LevelFilter::INFO,
+    LevelFilter::WARN,
+    LevelFilter::ERROR,
+    LevelFilter::OFF,
+];
+
+const HELP: &str = "\
+log commands, followed by Enter:
+  +, ++       show more, one level per +
+  -, --       show less, one level per -
+  debug       show this level and above
+  my_app=trace,hyper=warn
+              replace the command line filters with these directives
+  reset       go back to the filters of the command line
+  verbose     show the time, module and spans of events
+  compact     hide them again";
+
+/// Reads commands changing the filters from stdin, in a thread of its own, when stdin is a
+/// terminal, see [`Builder::interactive_verbosity`](crate::Builder::interactive_verbosity)
+pub(crate) fn start(filters: Arc<FilterHandle>) {
+    if !io::stdin().is_terminal() {
+        return;
+    }
+    let initial = filters
+        .config
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .cli
+        .clone();
+    let _ = thread::Builder::new()
+        .name("log-commands".to_owned())
+        .spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                run(&filters, line.trim(), &initial);
+            }
+        });
+}
+
+/// Runs `command`, where `initial` holds the filters of the command line
+fn run(filters: &FilterHandle, command: &str, initial: &LogSettings) {
+    if let Some(steps) = repeated(command, '+') {
+        step(filters, -(steps as isize));
+    } else if let Some(steps) = repeated(command, '-') {
+        step(filters, steps as isize);
+    } else {
+        match command {
+            "" => {}
+            "help" | "?" => eprintln!("{}", HELP),
+            "reset" => filters.update(|config| config.cli = initial.clone(), "stdin"),
+            "verbose" => filters.set_verbose_format(true),
+            "compact" => filters.set_verbose_format(false),
+            directives => filters.update(
+                |config| config.cli = LogSettings::from_directives(directives),
+                "stdin",
+            ),
+        }
+    }
+}
+
+/// How many times `command` repeats `c`, if it is made of nothing else
+fn repeated(command: &str, c: char) -> Option<usize> {
+    let all = !command.is_empty() && command.chars().all(|d| d == c);
+    all.then_some(command.len())
+}
+
+/// Moves the level in effect `steps` places along [`LEVELS`], towards `OFF` when positive
+fn step(filters: &FilterHandle, steps: isize) {
+    filters.update(
+        |config| {
+            let current = config.resolve().level.unwrap_or(LevelFilter::OFF);
+            let index = LEVELS
+                .iter()
+                .position(|&level| level == current)
+                .unwrap_or(0);
+            let index = (index as isize + steps).clamp(0, LEVELS.len() as isize - 1);
+            config.cli.level = Some(LEVELS[index as usize]);
+        },
+        "stdin",
+    );
+}
@@ -0,0 +1,242 @@
+//! Writes events to the systemd journal, with the `journald` feature
+
+#[cfg(all(unix, feature = "journald"))]
+pub(crate) use native::Journald;
+
+#[cfg(not(all(unix, feature = "journald")))]
+pub(crate) use unsupported::Journald;
+
+#[cfg(all(unix, feature = "journald"))]
+mod native {
+    use crate::dedup::StormNote;
+    use crate::fields::{Redaction, SpanFields};
+    use crate::health::SinkStatus;
+    use crate::{levels, run};
+    use std::fmt;
+    use std::os::unix::net::UnixDatagram;
+    use std::sync::Arc;
+    use tracing::field::{Field, Visit};
+    use tracing::{Event, Level, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer};
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// Socket of the journal's native protocol
+    const SOCKET: &str = "/run/systemd/journal/socket";
+
+    /// Names of the journal fields with a meaning of their own, which the fields of events and
+    /// spans are prefixed with `F_` not to clash with
+    const RESERVED: &[&str] = &[
+        "MESSAGE",
+        "MESSAGE_ID",
+        "PRIORITY",
+        "CODE_FILE",
+        "CODE_LINE",
+        "CODE_FUNC",
+        "ERRNO",
+        "INVOCATION_ID",
+        "USER_INVOCATION_ID",
+        "SYSLOG_FACILITY",
+        "SYSLOG_IDENTIFIER",
+        "SYSLOG_PID",
+        "SYSLOG_TIMESTAMP",
+        "SYSLOG_RAW",
+        "DOCUMENTATION",
+        "TID",
+        "UNIT",
+        "USER_UNIT",
+        "RUN_ID",
+        "TARGET",
+    ];
+
+    /// The longest field name the journal accepts
+    const MAX_NAME: usize = 64;
+
+    /// Sends each event to the journal as a message with structured fields, in place of stderr
+    ///
+    /// The level maps to `PRIORITY`, the call site to `CODE_FILE`, `CODE_LINE` and `TARGET`,
+    /// the ID of the run to `RUN_ID`, and fields to journal fields with their names in upper
+    /// case, such as `USER_ID`. Fields of enclosing spans are prefixed with the span name, such as
+    /// `REQUEST_ID`.
+    #[derive(Debug)]
+    pub(crate) struct Journald {
+        socket: UnixDatagram,
+        identifier: String,
+        redaction: Redaction,
+        health: Arc<SinkStatus>,
+        /// The levels that would have been written to stderr, from `ERROR` to `TRACE`
+        levels: [bool; 5],
+    }
+
+    impl Journald {
+        /// Connects to the journal if stderr is attached to it, as it is for services started by
+        /// systemd, which then sets `JOURNAL_STREAM` to the device and inode of the stream
+        pub(crate) fn connect(identifier: &str, redaction: Redaction) -> Option<Self> {
+            let stream = std::env::var("JOURNAL_STREAM").ok()?;
+            let (device, inode) = stream.split_once(':')?;
+            // SAFETY: `fstat` only writes a `stat` to the pointer it is given
+            let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+            if unsafe { libc::fstat(libc::STDERR_FILENO, &mut stat) } != 0 {
+                return None;
+            }
+            if device.parse().ok() != Some(stat.st_dev as u64)
+                || inode.parse().ok() != Some(stat.st_ino as u64)
+            {
+                return None;
+            }
+            let socket = UnixDatagram::unbound().ok()?;
+            socket.connect(SOCKET).ok()?;
+            Some(Self {
+                socket,
+                identifier: identifier.to_owned(),
+                redaction,
+                health: SinkStatus::register("the systemd journal"),
+                levels: [true; 5],
+            })
+        }
+
+        /// Only sends the events of the `levels` set, from `ERROR` to `TRACE`
+        pub(crate) fn set_levels(&mut self, levels: [bool; 5]) {
+            self.levels = levels;
+        }
+    }
+
+    impl<S> Layer<S> for Journald
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let metadata = event.metadata();
+            if !self.levels[levels::index(*metadata.level())] {
+                return;
+            }
+            let mut entry = Vec::new();
+            put(&mut entry, "PRIORITY", priority(*metadata.level()));
+            put(&mut entry, "SYSLOG_IDENTIFIER", &self.identifier);
+            put(&mut entry, "RUN_ID", run::run_id());
+            put(&mut entry, "TARGET", metadata.target());
+            if let Some(file) = metadata.file() {
+                put(&mut entry, "CODE_FILE", file);
+            }
+            if let Some(line) = metadata.line() {
+                put(&mut entry, "CODE_LINE", &line.to_string());
+            }
+
+            if let Some(scope) = ctx.event_scope(event) {
+                for span in scope.from_root() {
+                    if let Some(fields) = span.extensions().get::<SpanFields>() {
+                        for (name, value) in fields.iter() {
+                            let key = format!("{}_{}", span.name(), name);
+                            put(&mut entry, &field_name(&key), value);
+                        }
+                    }
+                }
+            }
+
+            let mut visitor = FieldVisitor {
+                entry: &mut entry,
+                redaction: &self.redaction,
+                message: String::new(),
+            };
+            event.record(&mut visitor);
+            let mut message = visitor.message;
+            if let Some(note) = StormNote::take() {
+                message.push_str(&format!(" {}", note));
+            }
+            put(&mut entry, "MESSAGE", &message);
+
+            // Entries too large for a datagram would have to be passed in a memfd, which isn't
+            // worth it for log messages, so they are lost
+            self.health.record(&self.socket.send(&entry));
+        }
+    }
+
+    /// The syslog priority of a level
+    fn priority(level: Level) -> &'static str {
+        match level {
+            Level::ERROR => "3",
+            Level::WARN => "4",
+            Level::INFO => "6",
+            Level::DEBUG | Level::TRACE => "7",
+        }
+    }
+
+    /// Turns a field name into a valid journal field name, made of at most 64 upper case
+    /// letters, digits and underscores, not starting with an underscore or digit, which are
+    /// reserved, and not clashing with the fields the journal gives a meaning to
+    fn field_name(name: &str) -> String {
+        let mut key: String = name
+            .chars()
+            .map(|c| match c {
+                'a'..='z' => c.to_ascii_uppercase(),
+                'A'..='Z' | '0'..='9' => c,
+                _ => '_',
+            })
+            .collect();
+        if RESERVED.contains(&key.as_str()) {
+            key.insert_str(0, "F_");
+        } else if !key.starts_with(|c: char| c.is_ascii_uppercase()) {
+            key.insert(0, 'F');
+        }
+        key.truncate(MAX_NAME);
+        key
+    }
+
+    /// Appends a field in the native protocol, where values spanning several lines are
+    /// prefixed with their length instead of ending at a newline
+    fn put(entry: &mut Vec<u8>, key: &str, value: &str) {
+        entry.extend_from_slice(key.as_bytes());
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+
+    struct FieldVisitor<'a> {
+        entry: &'a mut Vec<u8>,
+        redaction: &'a Redaction,
+        message: String,
+    }
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "message" {
+                self.message = value.to_owned();
+            } else if self.redaction.matches(field.name()) {
+                put(self.entry, &field_name(field.name()), Redaction::REDACTED);
+            } else {
+                put(self.entry, &field_name(field.name()), value);
+            }
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.record_str(field, &format!("{:?}", value))
+        }
+    }
+}
+
+#[cfg(not(all(unix, feature = "journald")))]
+mod unsupported {
+    use crate::fields::Redaction;
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::Layer;
+
+    /// Never constructed without the `journald` feature
+    #[derive(Debug)]
+    pub(crate) enum Journald {}
+
+    impl Journald {
+        pub(crate) fn connect(_identifier: &str, _redaction: Redaction) -> Option<Self> {
+            None
+        }
+
+        pub(crate) fn set_levels(&mut self, _levels: [bool; 5]) {
+            match *self {}
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Journald {}
+}
@@ -0,0 +1,669 @@
+use crate::boost;
+use crate::dedup::StormNote;
+use crate::fields::{Redaction, SpanFields};
+use crate::run;
+use crate::syslog;
+use crate::timestamp::{self, TimePrecision};
+use std::fmt::{self, Write};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::{FmtContext, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Where the fields of spans go in JSON records, see
+/// [`Builder::json_span_fields`](crate::Builder::json_span_fields)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JsonSpanFields {
+    /// In a `spans` array holding an object with the name and fields of each span, from the
+    /// root span inwards
+    #[default]
+    Nested,
+    /// At the top level of the record, where the field of the innermost span wins when spans
+    /// have fields of the same name
+    Innermost,
+    /// At the top level of the record, where the field of the outermost span wins when spans
+    /// have fields of the same name
+    Outermost,
+    /// At the top level of the record, prefixed with the name of their span, such as
+    /// `request.id`
+    Prefixed,
+}
+
+/// Names of the members of JSON records, which span fields flattened into the record are
+/// renamed from with a trailing underscore
+const JSON_FIELDS: &[&str] = &[
+    "timestamp",
+    "run_id",
+    "level",
+    "target",
+    "file",
+    "line",
+    "trace_id",
+    "span_id",
+    "fields",
+    "repeated",
+    "spans",
+];
+
+/// Writes an event as a single line of JSON, for log collectors
+///
+/// The object is streamed straight into the formatting layer's buffer, which is reused between
+/// events, so no intermediate values are built and nothing is allocated for events without
+/// `Debug` fields. Numbers and booleans keep their type, other values are rendered as strings,
+/// and the fields of the event's spans are listed from the root span inwards, unless
+/// `span_fields` flattens them:
+///
+/// ```text
+/// {"timestamp":"2021-06-02T09:14:03.210114Z","run_id":"3f9c0a17b2e4","level":"INFO","target":"app::db","file":"src/db.rs","line":42,"fields":{"message":"fetched rows","rows":3},"spans":[{"name":"request","id":"42"}]}
+/// ```
+pub(crate) fn write_event<S, N>(
+    f: &mut dyn Write,
+    ctx: &FmtContext<'_, S, N>,
+    e: &Event<'_>,
+    redaction: &Redaction,
+    trace_ids: Option<(String, String)>,
+    span_fields: JsonSpanFields,
+    precision: TimePrecision,
+) -> fmt::Result
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    let metadata = e.metadata();
+    f.write_str("{\"timestamp\":")?;
+    write_timestamp(f, precision)?;
+    write!(
+        f,
+        ",\"run_id\":\"{}\",\"level\":\"{}\",\"target\":",
+        run::run_id(),
+        boost::shown(*metadata.level())
+    )?;
+    write_str(f, metadata.target())?;
+    if let Some(file) = metadata.file() {
+        f.write_str(",\"file\":")?;
+        write_str(f, file)?;
+    }
+    if let Some(line) = metadata.line() {
+        write!(f, ",\"line\":{}", line)?;
+    }
+    if let Some((trace_id, span_id)) = trace_ids {
+        f.write_str(",\"trace_id\":")?;
+        write_str(f, &trace_id)?;
+        f.write_str(",\"span_id\":")?;
+        write_str(f, &span_id)?;
+    }
+
+    f.write_str(",\"fields\":{")?;
+    let mut visitor = FieldVisitor {
+        f: &mut *f,
+        redaction,
+        seen: false,
+        message: true,
+        bunyan: false,
+        result: Ok(()),
+    };
+    e.record(&mut visitor);
+    visitor.result?;
+    f.write_char('}')?;
+
+    if let Some(note) = StormNote::take() {
+        write!(f, ",\"repeated\":{}", note.suppressed())?;
+    }
+
+    let span = e.parent().and_then(|id| ctx.span(id));
+    let scope = span.or_else(|| ctx.lookup_current());
+    if let (Some(scope), false) = (&scope, span_fields == JsonSpanFields::Nested) {
+        let mut flattened: Vec<(String, String)> = Vec::new();
+        for span in scope.scope().from_root() {
+            if let Some(fields) = span.extensions().get::<SpanFields>() {
+                for (name, value) in fields.iter() {
+                    let name = match span_fields {
+                        JsonSpanFields::Prefixed => format!("{}.{}", span.name(), name),
+                        _ => name.to_owned(),
+                    };
+                    match flattened.iter_mut().find(|(seen, _)| *seen == name) {
+                        Some(_) if span_fields == JsonSpanFields::Outermost => {}
+                        Some((_, seen)) => *seen = value.to_owned(),
+                        None => flattened.push((name, value.to_owned())),
+                    }
+                }
+            }
+        }
+        for (name, value) in flattened {
+            f.write_char(',')?;
+            match JSON_FIELDS.contains(&name.as_str()) {
+                true => write_str(f, &format!("{}_", name))?,
+                false => write_str(f, &name)?,
+            }
+            f.write_char(':')?;
+            write_str(f, &value)?;
+        }
+    } else if let Some(scope) = scope {
+        f.write_str(",\"spans\":[")?;
+        for (i, span) in scope.scope().from_root().enumerate() {
+            if i > 0 {
+                f.write_char(',')?;
+            }
+            f.write_str("{\"name\":")?;
+            write_str(f, span.name())?;
+            if let Some(fields) = span.extensions().get::<SpanFields>() {
+                for (name, value) in fields.iter() {
+                    f.write_char(',')?;
+                    write_str(f, name)?;
+                    f.write_char(':')?;
+                    write_str(f, value)?;
+                }
+            }
+            f.write_char('}')?;
+        }
+        f.write_char(']')?;
+    }
+
+    f.write_str("}\n")
+}
+
+/// Names of the core fields of Bunyan records, which event fields are renamed from with a
+/// trailing underscore
+const BUNYAN_FIELDS: &[&str] = &[
+    "v", "name", "hostname", "pid", "level", "time", "msg", "src",
+];
+
+/// Writes an event as a Bunyan record, for the `bunyan` CLI and Node tooling
+///
+/// The core fields come first, with the level as a Bunyan level number, followed by the run ID,
+/// target, call site as `src`, the fields of the event and those of its spans, prefixed with the
+/// span name:
+///
+/// ```text
+/// {"v":0,"name":"app","hostname":"web-1","pid":4211,"level":30,"time":"2021-06-02T09:14:03.210114Z","msg":"fetched rows","run_id":"3f9c0a17b2e4","target":"app::db","src":{"file":"src/db.rs","line":42},"rows":3,"request.id":"42"}
+/// ```
+pub(crate) fn write_bunyan<S, N>(
+    f: &mut dyn Write,
+    ctx: &FmtContext<'_, S, N>,
+    e: &Event<'_>,
+    name: &str,
+    redaction: &Redaction,
+    trace_ids: Option<(String, String)>,
+    precision: TimePrecision,
+) -> fmt::Result
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    static HOSTNAME: OnceLock<String> = OnceLock::new();
+    let hostname = HOSTNAME.get_or_init(|| syslog::hostname().unwrap_or_default());
+
+    let metadata = e.metadata();
+    let level = match boost::shown(*metadata.level()) {
+        Level::TRACE => 10,
+        Level::DEBUG => 20,
+        Level::INFO => 30,
+        Level::WARN => 40,
+        Level::ERROR => 50,
+    };
+    f.write_str("{\"v\":0,\"name\":")?;
+    write_str(f, name)?;
+    f.write_str(",\"hostname\":")?;
+    write_str(f, hostname)?;
+    write!(
+        f,
+        ",\"pid\":{},\"level\":{},\"time\":",
+        std::process::id(),
+        level
+    )?;
+    write_timestamp(f, precision)?;
+
+    let mut message = MessageVisitor(None);
+    e.record(&mut message);
+    f.write_str(",\"msg\":")?;
+    match StormNote::take() {
+        Some(note) => {
+            write_str(f, &format!("{} {}", message.0.unwrap_or_default(), note))?;
+            write!(f, ",\"repeated\":{}", note.suppressed())?;
+        }
+        None => write_str(f, message.0.as_deref().unwrap_or_default())?,
+    }
+
+    write!(f, ",\"run_id\":\"{}\",\"target\":", run::run_id())?;
+    write_str(f, metadata.target())?;
+    if let (Some(file), Some(line)) = (metadata.file(), metadata.line()) {
+        f.write_str(",\"src\":{\"file\":")?;
+        write_str(f, file)?;
+        write!(f, ",\"line\":{}}}", line)?;
+    }
+    if let Some((trace_id, span_id)) = trace_ids {
+        f.write_str(",\"trace_id\":")?;
+        write_str(f, &trace_id)?;
+        f.write_str(",\"span_id\":")?;
+        write_str(f, &span_id)?;
+    }
+
+    let mut visitor = FieldVisitor {
+        f: &mut *f,
+        redaction,
+        seen: true,
+        message: false,
+        bunyan: true,
+        result: Ok(()),
+    };
+    e.record(&mut visitor);
+    visitor.result?;
+
+    let span = e.parent().and_then(|id| ctx.span(id));
+    if let Some(scope) = span.or_else(|| ctx.lookup_current()) {
+        for span in scope.scope().from_root() {
+            if let Some(fields) = span.extensions().get::<SpanFields>() {
+                for (name, value) in fields.iter() {
+                    f.write_char(',')?;
+                    write_str(f, &format!("{}.{}", span.name(), name))?;
+                    f.write_char(':')?;
+                    write_str(f, value)?;
+                }
+            }
+        }
+    }
+
+    f.write_str("}\n")
+}
+
+/// Writes the fields of an event other than the message as a JSON object
+pub(crate) fn write_fields(f: &mut dyn Write, e: &Event<'_>, redaction: &Redaction) -> fmt::Result {
+    f.write_char('{')?;
+    let mut visitor = FieldVisitor {
+        f: &mut *f,
+        redaction,
+        seen: false,
+        message: false,
+        bunyan: false,
+        result: Ok(()),
+    };
+    e.record(&mut visitor);
+    visitor.result?;
+    f.write_char('}')
+}
+
+/// Finds the message of an event
+pub(crate) struct MessageVisitor(pub(crate) Option<String>);
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.0 = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// Writes the current time as a quoted RFC 3339 timestamp in UTC
+fn write_timestamp(f: &mut dyn Write, precision: TimePrecision) -> fmt::Result {
+    f.write_char('"')?;
+    timestamp::write_rfc3339(f, SystemTime::now(), precision)?;
+    f.write_char('"')
+}
+
+/// Writes `value` as a quoted JSON string
+pub(crate) fn write_str(f: &mut dyn Write, value: &str) -> fmt::Result {
+    f.write_char('"')?;
+    Escaped(&mut *f).write_str(value)?;
+    f.write_char('"')
+}
+
+/// Escapes text written through it for use inside a JSON string, in unescaped runs
+struct Escaped<'a>(&'a mut dyn Write);
+
+impl Write for Escaped<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut start = 0;
+        for (i, byte) in s.bytes().enumerate() {
+            let escape = match byte {
+                b'"' => "\\\"",
+                b'\\' => "\\\\",
+                b'\n' => "\\n",
+                b'\r' => "\\r",
+                b'\t' => "\\t",
+                0..=0x1f => "",
+                _ => continue,
+            };
+            self.0.write_str(&s[start..i])?;
+            match escape {
+                "" => write!(self.0, "\\u{:04x}", byte)?,
+                escape => self.0.write_str(escape)?,
+            }
+            start = i + 1;
+        }
+        self.0.write_str(&s[start..])
+    }
+}
+
+/// Writes the fields of an event as the members of a JSON object
+struct FieldVisitor<'a> {
+    f: &'a mut dyn Write,
+    redaction: &'a Redaction,
+    seen: bool,
+    /// Whether the message is written along with the other fields
+    message: bool,
+    /// Renames fields named after the core fields of Bunyan records
+    bunyan: bool,
+    result: fmt::Result,
+}
+
+impl FieldVisitor<'_> {
+    /// Writes the name of the field, returning whether its value should be written after it
+    fn name(&mut self, field: &Field) -> bool {
+        let name = field.name();
+        if self.result.is_err() || name.starts_with("log.") {
+            return false;
+        }
+        let name = name.strip_prefix("r#").unwrap_or(name);
+        if !self.message && name == "message" {
+            return false;
+        }
+        let separator = if self.seen { "," } else { "" };
+        self.seen = true;
+        let renamed = self.bunyan && BUNYAN_FIELDS.contains(&name);
+        self.result = self
+            .f
+            .write_str(separator)
+            .and_then(|()| match renamed {
+                true => write_str(self.f, &format!("{}_", name)),
+                false => write_str(self.f, name),
+            })
+            .and_then(|()| self.f.write_char(':'));
+        if self.result.is_ok() && self.redaction.matches(name) {
+            self.result = write_str(self.f, Redaction::REDACTED);
+            return false;
+        }
+        self.result.is_ok()
+    }
+
+    fn raw(&mut self, field: &Field, value: impl fmt::Display) {
+        if self.name(field) {
+            self.result = write!(self.f, "{}", value);
+        }
+    }
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.raw(field, value)
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.raw(field, value)
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if value.is_finite() {
+            self.raw(field, value)
+        } else {
+            self.record_debug(field, &value)
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.raw(field, value)
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if self.name(field) {
+            self.result = write_str(self.f, value);
+        }
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.record_debug(field, &format_args!("{}", value))
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if self.name(field) {
+            self.result = self
+                .f
+                .write_char('"')
+                .and_then(|()| write!(Escaped(&mut *self.f), "{:?}", value))
+                .and_then(|()| self.f.write_char('"'));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::FieldLayer;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing::level_filters::STATIC_MAX_LEVEL;
+    use tracing_subscriber::fmt::{FormatEvent, MakeWriter};
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    /// Collects what the formatting layer writes
+    #[derive(Clone, Default)]
+    struct Written(Arc<Mutex<Vec<u8>>>);
+
+    impl MakeWriter for Written {
+        type Writer = Written;
+
+        fn make_writer(&self) -> Written {
+            self.clone()
+        }
+    }
+
+    impl io::Write for Written {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    enum Format {
+        Json(JsonSpanFields),
+        Bunyan,
+        Fields,
+    }
+
+    impl<S, N> FormatEvent<S, N> for Format
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        N: for<'a> FormatFields<'a> + 'static,
+    {
+        fn format_event(
+            &self,
+            ctx: &FmtContext<'_, S, N>,
+            f: &mut dyn Write,
+            e: &Event<'_>,
+        ) -> fmt::Result {
+            let mut redaction = Redaction::default();
+            redaction.add("password");
+            let precision = TimePrecision::Micros;
+            match *self {
+                Format::Json(span_fields) => {
+                    write_event(f, ctx, e, &redaction, None, span_fields, precision)
+                }
+                Format::Bunyan => write_bunyan(f, ctx, e, "app", &redaction, None, precision),
+                Format::Fields => {
+                    write_fields(f, e, &redaction)?;
+                    f.write_char('\n')
+                }
+            }
+        }
+    }
+
+    /// Whether the events logged at `INFO` are compiled in, which the `max_level_*` features
+    /// of tracing may prevent
+    fn logging() -> bool {
+        STATIC_MAX_LEVEL >= Level::INFO
+    }
+
+    /// The lines written for the events `log` logs
+    fn lines(format: Format, log: impl FnOnce()) -> Vec<String> {
+        let written = Written::default();
+        let layer = tracing_subscriber::fmt::layer()
+            .event_format(format)
+            .with_writer(written.clone());
+        let subscriber = Registry::default().with(FieldLayer::default()).with(layer);
+        tracing::subscriber::with_default(subscriber, log);
+        let written = String::from_utf8(written.0.lock().unwrap().clone()).unwrap();
+        written.lines().map(str::to_owned).collect()
+    }
+
+    fn quoted(value: &str) -> String {
+        let mut quoted = String::new();
+        write_str(&mut quoted, value).unwrap();
+        quoted
+    }
+
+    /// Checks that `line` starts with a timestamp member, returning the rest of it
+    fn after_timestamp<'a>(line: &'a str, prefix: &str) -> &'a str {
+        let rest = line.strip_prefix(prefix).unwrap();
+        let end = rest.find('"').unwrap();
+        assert!(rest[..end].ends_with('Z'), "{}", line);
+        &rest[end + 1..]
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(
+            quoted("say \"hi\"\\ \n\r\t\u{1}\u{1f} é ✓"),
+            r#""say \"hi\"\\ \n\r\t\u0001\u001f é ✓""#
+        );
+        assert_eq!(quoted(""), r#""""#);
+    }
+
+    #[test]
+    fn events_are_written_with_their_fields_and_spans() {
+        if !logging() {
+            return;
+        }
+        let mut line = 0;
+        let written = lines(Format::Json(JsonSpanFields::Nested), || {
+            let outer = tracing::info_span!("request", id = 42);
+            let _outer = outer.enter();
+            let inner = tracing::info_span!("query", table = "users");
+            let _inner = inner.enter();
+            line = line!() + 1;
+            tracing::info!(
+                target: "app::db",
+                rows = 3,
+                ok = true,
+                ratio = 0.5,
+                nan = f64::NAN,
+                password = "hunter2",
+                name = ?"a\"b",
+                "fetched {}",
+                "rows"
+            );
+        });
+        assert_eq!(written.len(), 1);
+        let rest = after_timestamp(&written[0], "{\"timestamp\":\"");
+        assert_eq!(
+            rest,
+            format!(
+                ",\"run_id\":\"{}\",\"level\":\"INFO\",\"target\":\"app::db\",\"file\":{},\
+                 \"line\":{},\"fields\":{{\"message\":\"fetched rows\",\"rows\":3,\"ok\":true,\
+                 \"ratio\":0.5,\"nan\":\"NaN\",\"password\":\"[REDACTED]\",\
+                 \"name\":\"\\\"a\\\\\\\"b\\\"\"}},\
+                 \"spans\":[{{\"name\":\"request\",\"id\":\"42\"}},\
+                 {{\"name\":\"query\",\"table\":\"users\"}}]}}",
+                run::run_id(),
+                quoted(file!()),
+                line
+            )
+        );
+    }
+
+    #[test]
+    fn events_outside_spans_have_no_spans() {
+        if !logging() {
+            return;
+        }
+        let written = lines(Format::Json(JsonSpanFields::Nested), || {
+            tracing::info!("started")
+        });
+        assert!(written[0].ends_with(",\"fields\":{\"message\":\"started\"}}"));
+    }
+
+    #[test]
+    fn span_fields_can_be_flattened() {
+        if !logging() {
+            return;
+        }
+        let log = || {
+            let outer = tracing::info_span!("outer", id = 1, line = "outer");
+            let _outer = outer.enter();
+            let inner = tracing::info_span!("inner", id = 2);
+            let _inner = inner.enter();
+            tracing::info!("done");
+        };
+        let members = |span_fields| {
+            let written = lines(Format::Json(span_fields), log);
+            let end = written[0].find("\"done\"}").unwrap() + "\"done\"}".len();
+            written[0][end..].to_owned()
+        };
+        assert_eq!(
+            members(JsonSpanFields::Innermost),
+            ",\"id\":\"2\",\"line_\":\"outer\"}"
+        );
+        assert_eq!(
+            members(JsonSpanFields::Outermost),
+            ",\"id\":\"1\",\"line_\":\"outer\"}"
+        );
+        assert_eq!(
+            members(JsonSpanFields::Prefixed),
+            ",\"outer.id\":\"1\",\"outer.line\":\"outer\",\"inner.id\":\"2\"}"
+        );
+    }
+
+    #[test]
+    fn bunyan_records_have_the_core_fields_first() {
+        if STATIC_MAX_LEVEL < Level::WARN {
+            return;
+        }
+        let mut line = 0;
+        let written = lines(Format::Bunyan, || {
+            let span = tracing::warn_span!("request", id = 42);
+            let _entered = span.enter();
+            line = line!() + 1;
+            tracing::warn!(target: "app", name = "x", pid = 1, attempts = 2, "retrying");
+        });
+        let prefix = format!(
+            "{{\"v\":0,\"name\":\"app\",\"hostname\":{},\"pid\":{},\"level\":40,\"time\":\"",
+            quoted(&syslog::hostname().unwrap_or_default()),
+            std::process::id()
+        );
+        let rest = after_timestamp(&written[0], &prefix);
+        assert_eq!(
+            rest,
+            format!(
+                ",\"msg\":\"retrying\",\"run_id\":\"{}\",\"target\":\"app\",\
+                 \"src\":{{\"file\":{},\"line\":{}}},\"name_\":\"x\",\"pid_\":1,\"attempts\":2,\
+                 \"request.id\":\"42\"}}",
+                run::run_id(),
+                quoted(file!()),
+                line
+            )
+        );
+    }
+
+    #[test]
+    fn fields_leave_out_the_message() {
+        if !logging() {
+            return;
+        }
+        let written = lines(Format::Fields, || {
+            tracing::info!(r#type = "a\tb", log.target = "x", "hello");
+            tracing::info!("alone");
+        });
+        assert_eq!(written, ["{\"type\":\"a\\tb\"}", "{}"]);
+    }
+}
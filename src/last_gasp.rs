@@ -0,0 +1,126 @@
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::{ptr, slice};
+use tracing::Level;
+
+/// Buffer registered with [`set_last_gasp_buffer`], used to assemble the line before writing it
+static BUFFER: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+static BUFFER_LEN: AtomicUsize = AtomicUsize::new(0);
+/// Set while a line is being assembled in [`BUFFER`], so concurrent calls don't share it
+static BUSY: AtomicBool = AtomicBool::new(false);
+/// Whether labels are colored, set when the subscriber is installed
+static COLORS: AtomicBool = AtomicBool::new(false);
+
+/// Size of the stack buffer used when no buffer has been registered
+const STACK_BUFFER: usize = 256;
+
+/// Registers the buffer used by [`last_gasp`] to assemble its line
+///
+/// Without one, [`last_gasp`] uses a small stack buffer and cuts long messages short. Call this
+/// once at startup; later calls replace the buffer. The buffer is handed back when
+/// [`last_gasp`] is writing at that moment, rather than waiting for it, which could deadlock
+/// in a signal handler that interrupted it.
+pub fn set_last_gasp_buffer(buffer: &'static mut [u8]) -> Result<(), &'static mut [u8]> {
+    if BUSY.swap(true, Ordering::Acquire) {
+        return Err(buffer);
+    }
+    BUFFER.store(buffer.as_mut_ptr(), Ordering::SeqCst);
+    BUFFER_LEN.store(buffer.len(), Ordering::SeqCst);
+    BUSY.store(false, Ordering::Release);
+    Ok(())
+}
+
+/// Colors the labels of [`last_gasp`] only when stderr is written with colors
+pub(crate) fn use_colors(colors: bool) {
+    COLORS.store(colors, Ordering::Relaxed);
+}
+
+/// Writes a terse `level: message` line straight to stderr
+///
+/// On Unix and Windows, unlike the subscriber, this neither allocates nor takes any locks, so it
+/// can be called from a signal handler, an allocation failure hook or any other place where the
+/// process is about to die and the normal logging path can't be trusted. Elsewhere it goes
+/// through the locked [`std::io::stderr`]. The line is written with a single `write` call when
+/// it fits in the buffer registered with [`set_last_gasp_buffer`]. The label is colored when
+/// the subscriber colors stderr, and plain otherwise or before it is installed.
+pub fn last_gasp(level: Level, message: &str) {
+    let label: &[u8] = match (level, COLORS.load(Ordering::Relaxed)) {
+        (Level::ERROR, true) => b"\x1b[1;31merror:\x1b[0m ",
+        (Level::WARN, true) => b"\x1b[1;33mwarning:\x1b[0m ",
+        (Level::INFO, true) => b"\x1b[1;32minfo:\x1b[0m ",
+        (Level::DEBUG, true) => b"\x1b[1;34mdebug:\x1b[0m ",
+        (Level::TRACE, true) => b"\x1b[1;35mtrace:\x1b[0m ",
+        (Level::ERROR, false) => b"error: ",
+        (Level::WARN, false) => b"warning: ",
+        (Level::INFO, false) => b"info: ",
+        (Level::DEBUG, false) => b"debug: ",
+        (Level::TRACE, false) => b"trace: ",
+    };
+    let parts = [label, message.as_bytes(), b"\n"];
+
+    if BUSY.swap(true, Ordering::Acquire) {
+        // Another thread or an interrupted handler is using the buffer, so write piecewise
+        for part in &parts {
+            write_stderr(part);
+        }
+        return;
+    }
+
+    let registered = BUFFER.load(Ordering::SeqCst);
+    let len = BUFFER_LEN.load(Ordering::SeqCst);
+    let mut stack = [0u8; STACK_BUFFER];
+    let buffer = if registered.is_null() || len == 0 {
+        &mut stack[..]
+    } else {
+        // SAFETY: the buffer is `'static` and `BUSY` grants exclusive access to it
+        unsafe { slice::from_raw_parts_mut(registered, len) }
+    };
+
+    // Keep room for the newline so a truncated message still ends the line
+    let mut end = 0;
+    for part in &parts[..2] {
+        let n = part.len().min(buffer.len() - 1 - end);
+        buffer[end..end + n].copy_from_slice(&part[..n]);
+        end += n;
+    }
+    buffer[end] = b'\n';
+    write_stderr(&buffer[..=end]);
+
+    BUSY.store(false, Ordering::Release);
+}
+
+#[cfg(unix)]
+fn write_stderr(mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        // SAFETY: `write` is async-signal-safe and only reads `bytes.len()` bytes from `bytes`
+        let written =
+            unsafe { libc::write(libc::STDERR_FILENO, bytes.as_ptr().cast(), bytes.len()) };
+        if written <= 0 {
+            return;
+        }
+        bytes = &bytes[written as usize..];
+    }
+}
+
+#[cfg(windows)]
+fn write_stderr(bytes: &[u8]) {
+    use std::fs::File;
+    use std::io::Write;
+    use std::mem::ManuallyDrop;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle};
+
+    // Write to the raw handle, as `Stderr` itself locks; it is borrowed, so never close it
+    let handle = std::io::stderr().as_raw_handle();
+    if handle.is_null() {
+        return;
+    }
+    // SAFETY: the handle stays open for the life of the process and `ManuallyDrop` keeps the
+    // `File` from closing it
+    let mut file = ManuallyDrop::new(unsafe { File::from_raw_handle(handle) });
+    let _ = file.write_all(bytes);
+}
+
+#[cfg(not(any(unix, windows)))]
+fn write_stderr(bytes: &[u8]) {
+    use std::io::Write;
+    let _ = std::io::stderr().write_all(bytes);
+}
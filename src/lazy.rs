@@ -0,0 +1,50 @@
+use std::fmt;
+
+/// A field value computed only when an event is written, see [`lazy`]
+#[derive(Clone, Copy)]
+pub struct Lazy<F>(F);
+
+/// Wraps a closure producing a field value, so the value is only computed when the event is
+/// written, and not at all when it is filtered out
+///
+/// Recorded with `%` it is shown with `Display`, and with `?` with `Debug`:
+///
+/// ```no_run
+/// use pretty_tracing_subscriber::lazy;
+/// # fn expensive_summary() -> String { String::new() }
+///
+/// tracing::debug!(summary = %lazy(|| expensive_summary()), "request parsed");
+/// ```
+///
+/// `tracing` skips the arguments of events that its level and target filters disable, but an
+/// argument computed eagerly is still paid for when the field and message filters of this crate
+/// then hide the event. Those filters only render the message and the fields they select on, so
+/// a lazy value is never computed for an event they hide. It is computed again for each output
+/// that writes the event, such as the transcript and JSON outputs. The fields of spans are
+/// rendered when the span is created, so a lazy value in a span is computed then.
+pub fn lazy<F, T>(f: F) -> Lazy<F>
+where
+    F: Fn() -> T,
+{
+    Lazy(f)
+}
+
+impl<F, T> fmt::Display for Lazy<F>
+where
+    F: Fn() -> T,
+    T: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (self.0)().fmt(f)
+    }
+}
+
+impl<F, T> fmt::Debug for Lazy<F>
+where
+    F: Fn() -> T,
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (self.0)().fmt(f)
+    }
+}
@@ -0,0 +1,240 @@
+use ansi_term::{Color, Style};
+use std::fmt;
+use std::str::FromStr;
+use tracing::Level;
+
+/// Environment variable overriding the colors of levels, see [`LevelColors`]
+pub(crate) const COLORS_ENV: &str = "PRETTY_LOG_COLORS";
+
+/// How the level of an event is labelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum LevelStyle {
+    /// Words like cargo and clap use, such as `error:` and `warning:`
+    #[default]
+    Words,
+    /// Single colored symbols, such as `✖` and `⚠`, which keep dense output narrow
+    ///
+    /// Falls back to ASCII characters when the locale doesn't indicate UTF-8 support.
+    Symbols,
+    /// Three-letter abbreviations of the same width, such as `ERR` and `WRN`
+    Short,
+    /// Uppercase names in brackets, such as `[ERROR]` and `[WARN]`, as many other loggers use
+    Bracketed,
+}
+
+impl LevelStyle {
+    /// The labels for `ERROR`, `WARN`, `INFO`, `DEBUG` and `TRACE`, in that order
+    pub(crate) fn labels(self) -> [&'static str; 5] {
+        match self {
+            LevelStyle::Words => ["error:", "warning:", "info:", "debug:", "trace:"],
+            LevelStyle::Symbols if unicode() => ["✖", "⚠", "ℹ", "·", "∘"],
+            LevelStyle::Symbols => ["x", "!", "i", "-", "."],
+            LevelStyle::Short => ["ERR", "WRN", "INF", "DBG", "TRC"],
+            LevelStyle::Bracketed => ["[ERROR]", "[WARN]", "[INFO]", "[DEBUG]", "[TRACE]"],
+        }
+    }
+}
+
+/// The parts of the context shown before events of a level outside of verbose mode, see
+/// [`Builder::level_context`](crate::Builder::level_context)
+///
+/// Verbose mode shows all of them, at every level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LevelContext {
+    /// The time the event happened
+    pub time: bool,
+    /// The module it was logged from, and its target if that differs
+    pub module: bool,
+    /// The path of spans it happened in
+    pub spans: bool,
+}
+
+impl LevelContext {
+    /// The whole context, as shown in verbose mode
+    pub const FULL: Self = Self {
+        time: true,
+        module: true,
+        spans: true,
+    };
+}
+
+/// Colors of level labels replacing the default palette, see
+/// [`Builder::level_colors`](crate::Builder::level_colors)
+///
+/// Parsed from comma-separated `level=color` pairs, as in the `PRETTY_LOG_COLORS` environment
+/// variable, such as `error=red,warn=93,info=cyan`. Colors are either the names `black`, `red`,
+/// `green`, `yellow`, `blue`, `purple` (or `magenta`), `cyan` and `white`, painted bold like the
+/// default palette, or SGR codes as in `LS_COLORS`, such as `93` for bright yellow, `1;4;31` for
+/// bold underlined red, or `38;5;208` for color 208 of the 256-color palette. Levels left out
+/// keep their color.
+///
+/// ```
+/// use pretty_tracing_subscriber::LevelColors;
+///
+/// let colors: LevelColors = "error=red,warn=93,info=38;5;39".parse().unwrap();
+/// assert!("error=scarlet".parse::<LevelColors>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LevelColors {
+    styles: [Option<Style>; 5],
+}
+
+impl LevelColors {
+    /// The default palette
+    pub(crate) fn palette() -> [Style; 5] {
+        [
+            Color::Red.bold(),
+            Color::Yellow.bold(),
+            Color::Green.bold(),
+            Color::Blue.bold(),
+            Color::Purple.bold(),
+        ]
+    }
+
+    /// Replaces the colors of `palette` with those set here
+    pub(crate) fn apply(&self, palette: &mut [Style; 5]) {
+        for (style, color) in palette.iter_mut().zip(self.styles) {
+            if let Some(color) = color {
+                *style = color;
+            }
+        }
+    }
+
+    /// The colors of the `PRETTY_LOG_COLORS` environment variable, if it is set
+    pub(crate) fn from_env() -> Option<Result<Self, ParseLevelColorsError>> {
+        let colors = std::env::var(COLORS_ENV).ok()?;
+        Some(colors.parse())
+    }
+}
+
+/// Error returned when [`LevelColors`] can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseLevelColorsError(String);
+
+impl fmt::Display for ParseLevelColorsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid level color `{}`, expected `level=color` with a color name or SGR codes",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseLevelColorsError {}
+
+impl FromStr for LevelColors {
+    type Err = ParseLevelColorsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut colors = Self::default();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let error = || ParseLevelColorsError(pair.to_owned());
+            let (level, color) = pair.split_once('=').ok_or_else(error)?;
+            let level: Level = level.trim().parse().map_err(|_| error())?;
+            let style = parse_color(color.trim()).ok_or_else(error)?;
+            colors.styles[index(level)] = Some(style);
+        }
+        Ok(colors)
+    }
+}
+
+/// Parses a color name, or SGR codes separated by `;`
+fn parse_color(color: &str) -> Option<Style> {
+    let named = match color.to_ascii_lowercase().as_str() {
+        "black" => Some(Color::Black),
+        "red" => Some(Color::Red),
+        "green" => Some(Color::Green),
+        "yellow" => Some(Color::Yellow),
+        "blue" => Some(Color::Blue),
+        "purple" | "magenta" => Some(Color::Purple),
+        "cyan" => Some(Color::Cyan),
+        "white" => Some(Color::White),
+        _ => None,
+    };
+    if let Some(color) = named {
+        return Some(color.bold());
+    }
+
+    let codes: Vec<u8> = color
+        .split(';')
+        .map(|code| code.parse().ok())
+        .collect::<Option<_>>()?;
+    let mut style = Style::new();
+    let mut codes = codes.into_iter();
+    while let Some(code) = codes.next() {
+        match code {
+            0 => style = Style::new(),
+            1 => style = style.bold(),
+            2 => style = style.dimmed(),
+            3 => style = style.italic(),
+            4 => style = style.underline(),
+            5 => style = style.blink(),
+            7 => style = style.reverse(),
+            8 => style = style.hidden(),
+            9 => style = style.strikethrough(),
+            30..=37 => style = style.fg(basic(code - 30)),
+            38 => style = style.fg(extended(&mut codes)?),
+            40..=47 => style = style.on(basic(code - 40)),
+            48 => style = style.on(extended(&mut codes)?),
+            90..=97 => style = style.fg(Color::Fixed(code - 90 + 8)),
+            100..=107 => style = style.on(Color::Fixed(code - 100 + 8)),
+            _ => return None,
+        }
+    }
+    Some(style)
+}
+
+/// One of the eight basic colors, by its offset in the SGR codes
+fn basic(offset: u8) -> Color {
+    [
+        Color::Black,
+        Color::Red,
+        Color::Green,
+        Color::Yellow,
+        Color::Blue,
+        Color::Purple,
+        Color::Cyan,
+        Color::White,
+    ][usize::from(offset)]
+}
+
+/// The color following SGR code 38 or 48, `5;n` from the 256-color palette or `2;r;g;b`
+fn extended(codes: &mut impl Iterator<Item = u8>) -> Option<Color> {
+    match codes.next()? {
+        5 => Some(Color::Fixed(codes.next()?)),
+        2 => Some(Color::RGB(codes.next()?, codes.next()?, codes.next()?)),
+        _ => None,
+    }
+}
+
+/// Position of a level in the label tables
+pub(crate) fn index(level: Level) -> usize {
+    match level {
+        Level::ERROR => 0,
+        Level::WARN => 1,
+        Level::INFO => 2,
+        Level::DEBUG => 3,
+        Level::TRACE => 4,
+    }
+}
+
+/// Whether the terminal can be expected to display non-ASCII symbols
+fn unicode() -> bool {
+    if cfg!(windows) {
+        // Windows Terminal and the VS Code terminal handle Unicode, the legacy console may not
+        return std::env::var_os("WT_SESSION").is_some()
+            || std::env::var("TERM_PROGRAM").is_ok_and(|program| program == "vscode");
+    }
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    locale.contains("utf-8") || locale.contains("utf8")
+}
@@ -1,191 +1,4493 @@
 use ansi_term::{ANSIGenericString, Color, Style};
-use chrono::format::{DelayedFormat, StrftimeItems};
-use chrono::Local;
 use std::fmt::Write;
-use std::path::MAIN_SEPARATOR;
-use std::{fmt, io, iter};
-use structopt::StructOpt;
-use tracing::{Event, Id, Level, Subscriber};
-use tracing_subscriber::filter::LevelFilter;
+use std::{cmp, fmt, fs};
+use tracing::callsite::Identifier;
+use tracing::level_filters::STATIC_MAX_LEVEL;
+use tracing::{Event, Id, Level, Metadata, Subscriber};
+use tracing_subscriber::filter::{filter_fn, LevelFilter, Targets};
 use tracing_subscriber::fmt::format::FmtSpan;
-use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields, MakeWriter};
+use tracing_subscriber::layer::{Identity, Layer, SubscriberExt};
+use tracing_subscriber::registry::{Extensions, LookupSpan, Registry, SpanRef};
 use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::EnvFilter;
-
-#[cfg(not(debug_assertions))]
-const DEFAULT_VERBOSITY: u8 = 2;
-#[cfg(debug_assertions)]
-const DEFAULT_VERBOSITY: u8 = 4;
-
-#[derive(Debug, Clone, StructOpt)]
-pub struct Verbosity {
-    /// Decreases logging verbosity. Can be specified multiple times
-    #[structopt(long = "quiet", short = "q", multiple = true, parse(from_occurrences))]
-    quiet: u8,
-    /// Increases logging verbosity. Can be specified multiple times
-    #[structopt(
-        long = "verbose",
-        short = "v",
-        multiple = true,
-        parse(from_occurrences)
-    )]
-    verbose: u8,
-    /// Logging filters in env_logger format
-    #[structopt(long = "log", short = "l", env = "SCROOGE_LOG")]
-    log_filters: Option<String>,
+
+mod audit;
+mod backlog;
+mod backtraces;
+mod banner;
+mod batch;
+mod binlog;
+mod boost;
+mod buffer;
+mod capture;
+mod child;
+mod config;
+#[cfg(feature = "config-file")]
+mod config_file;
+mod console;
+mod crash;
+mod debugger;
+mod dedup;
+mod delimited;
+mod escalate;
+mod exit;
+mod explain;
+mod fields;
+mod filter;
+mod gelf;
+mod github;
+mod group;
+mod health;
+mod highlight;
+mod histogram;
+mod html;
+mod http;
+mod interactive;
+mod journald;
+mod json;
+mod last_gasp;
+mod lazy;
+mod levels;
+mod locale;
+mod log_bridge;
+mod logcat;
+mod logfmt;
+mod loki;
+mod otel;
+mod otlp;
+mod overhead;
+mod panic;
+mod paths;
+pub mod prelude;
+mod presets;
+mod preview;
+mod progress;
+mod recorder;
+mod reload;
+mod rotate;
+mod route;
+mod run;
+mod sample;
+mod sentry;
+mod shutdown;
+mod span_ids;
+mod spantrace;
+mod status;
+mod stream;
+mod syslog;
+mod tags;
+mod teamcity;
+mod tee;
+mod template;
+mod timestamp;
+mod timing;
+mod verbosity;
+mod volume;
+mod workspace;
+mod wrap;
+
+#[cfg(feature = "audit")]
+pub use audit::{verify_audit_log, AuditError};
+pub use banner::{emit_git_sha, BuildInfo};
+pub use binlog::{
+    decode_binary_log, replay_binary_log, BinaryCallsite, BinaryDecoder, BinaryRecord, BinaryValue,
+};
+pub use boost::{BoostRule, ParseBoostRuleError};
+pub use capture::{Capture, CapturedEvent};
+pub use child::LoggedChild;
+pub use config::{LogConfig, LogSettings};
+#[cfg(feature = "config-file")]
+pub use config_file::{init_from_config, ConfigFileError, FileConfig};
+pub use delimited::Delimited;
+pub use escalate::{verbose_scope, VerboseScope};
+pub use exit::EventCounts;
+pub use fields::{FieldOrder, FieldStyle, SpanFields};
+pub use filter::{FieldDirective, ParseFieldDirectiveError};
+pub use health::SinkHealth;
+pub use histogram::{Histogram, SpanDurations};
+pub use json::JsonSpanFields;
+pub use last_gasp::{last_gasp, set_last_gasp_buffer};
+pub use lazy::{lazy, Lazy};
+pub use levels::{LevelColors, LevelContext, LevelStyle, ParseLevelColorsError};
+pub use locale::Locale;
+pub use loki::Loki;
+pub use overhead::Overhead;
+pub use paths::PathStyle;
+pub use presets::Preset;
+pub use progress::Progress;
+pub use route::{Destination, Discard, Output};
+pub use run::run_id;
+pub use shutdown::{LogGuard, ShutdownHandle};
+pub use status::StatusLine;
+#[cfg(feature = "cli")]
+pub use structopt;
+pub use timestamp::{TimePrecision, TimeSource};
+pub use tracing;
+#[cfg(feature = "argh")]
+pub use verbosity::LogArgs;
+pub use verbosity::{LogFormat, ParseLogFormatError, Verbosity, VerbosityMapping};
+pub use workspace::Workspace;
+pub use wrap::{strip_escapes, Continuation};
+
+use batch::Driver;
+use binlog::BinaryLog;
+use crash::{CrashReporter, CrashWriter};
+use dedup::{StormFilter, StormNote};
+use delimited::DelimitedFormatter;
+use exit::{Counter, Tally};
+use explain::Explainer;
+use fields::{EventFields, FieldFormatter, FieldLayer, SpanEvent};
+use filter::{EventFilter, Precheck, Regex};
+use gelf::Gelf;
+use histogram::HistogramLayer;
+use html::HtmlReport;
+use journald::Journald;
+use logcat::Logcat;
+use otel::TraceIds;
+use otlp::Otlp;
+use overhead::Stage;
+use paths::{Hyperlinks, PathPrefixes, SourceRoots};
+use progress::{ProgressEvent, ProgressLine};
+use recorder::{DumpOnError, Hidden, Recorder, RecorderWriter};
+use reload::{FilterHandle, Reloadable};
+use rotate::Rotation;
+use route::{Outputs, Router};
+use sample::SampleRule;
+use sentry::Sentry;
+use shutdown::Sink;
+use span_ids::SpanIdLayer;
+use status::Width;
+use std::any::TypeId;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write as _;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once, OnceLock, RwLock};
+use std::time::Duration;
+use stream::EventStream;
+use syslog::Syslog;
+use tags::{TagRoute, Tagged};
+use tee::{JsonTee, SharedFilter};
+use timestamp::{DisplayTime, TimeFormat};
+use timing::{DisplayDuration, TimingLayer};
+use volume::VolumeCounter;
+use wrap::Columns;
+
+/// Crates that log a lot at `INFO` and below, capped by [`Builder::quiet_dependencies`]
+const NOISY_DEPENDENCIES: &[&str] = &["hyper", "h2", "tokio", "mio", "want", "rustls"];
+
+/// 256-color palette entries that read well on both dark and light backgrounds, and that stay
+/// clear of the red and yellow of errors and warnings
+const TARGET_COLORS: [u8; 12] = [31, 32, 33, 35, 37, 61, 64, 67, 97, 128, 133, 166];
+
+/// Initialises [`tracing_subscriber`] with options from command-line arguments
+///
+/// `root_module` is the crate or module whose paths are shown relative to it, usually the name of
+/// the binary crate. Logging is never shut down, see [`init_with_guard`] to drain files and log
+/// collectors before the process exits.
+pub fn init(root_module: impl Into<String>, verbosity: Verbosity) {
+    Builder::new(root_module, verbosity).init()
+}
+
+/// Initialises [`tracing_subscriber`] like [`init`], shutting logging down when the returned
+/// guard is dropped
+pub fn init_with_guard(root_module: impl Into<String>, verbosity: Verbosity) -> LogGuard {
+    Builder::new(root_module, verbosity).init_with_guard()
+}
+
+/// Initialises [`tracing_subscriber`] like [`init`], inferring the root module from the name of
+/// the running executable
+///
+/// This matches the crate name for binaries built by Cargo, with dashes replaced by underscores.
+/// If the name can't be determined, every module path is shown in full.
+pub fn init_auto(verbosity: Verbosity) {
+    Builder::new(exe_crate_name().unwrap_or_default(), verbosity).init()
+}
+
+/// Captures the events logged on the current thread, formatted like [`init`] does, for tests
+/// that check what their code logs
+///
+/// Every level is captured, and the root module is inferred like [`init_auto`] does. See
+/// [`Builder::capture`] for other options.
+pub fn capture() -> Capture {
+    let verbosity = Verbosity::new(u64::MAX, 0);
+    Builder::new(exe_crate_name().unwrap_or_default(), verbosity).capture()
+}
+
+/// Installs a subscriber formatting events like [`init`] does for the tests of a crate, the
+/// first time it is called, so every test can call it
+///
+/// See [`Builder::init_for_tests`].
+pub fn init_for_tests() {
+    Builder::new(exe_crate_name().unwrap_or_default(), Verbosity::default()).init_for_tests()
+}
+
+/// Returns a layer that formats events like [`init`] does, for applications that assemble their
+/// own subscriber
+///
+/// See [`Builder::layer`] for what is and isn't included.
+pub fn pretty_layer<S>(root_module: impl Into<String>, verbosity: Verbosity) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Builder::new(root_module, verbosity).layer()
+}
+
+/// Guesses the crate name of the running binary from its file name
+fn exe_crate_name() -> Option<String> {
+    let exe = std::env::current_exe().ok()?;
+    let name = exe.file_stem()?.to_str()?;
+    Some(name.replace('-', "_"))
+}
+
+/// Configures the subscriber before installing it with [`Builder::init`]
+#[derive(Debug, Clone)]
+pub struct Builder {
+    roots: Vec<String>,
+    verbosity: Verbosity,
+    verbosity_mapping: VerbosityMapping,
+    verbose_format: bool,
+    slow_span_threshold: Option<Duration>,
+    slow_span_level: Option<Level>,
+    only_slow_spans: bool,
+    tree: bool,
+    multiline_fields: Option<usize>,
+    fields: FieldFormatter,
+    presets: Vec<Arc<dyn Preset>>,
+    enabled_presets: Vec<String>,
+    filter_presets: Vec<(String, LogSettings)>,
+    close_templates: HashMap<String, String>,
+    panic_record: bool,
+    panic_hook: bool,
+    crash_reports: Option<PathBuf>,
+    error_backtraces: bool,
+    field_filters: Vec<FieldDirective>,
+    boosts: Vec<BoostRule>,
+    message_matches: Vec<Regex>,
+    message_excludes: Vec<Regex>,
+    highlights: Vec<Regex>,
+    quiet_dependencies: bool,
+    muted: Vec<String>,
+    env_var: String,
+    closed_marker: bool,
+    path_style: PathStyle,
+    hyperlinks: Option<String>,
+    path_prefixes: PathPrefixes,
+    fail_on_errors: Option<usize>,
+    summary_at_exit: bool,
+    count_targets: bool,
+    metrics: bool,
+    aligned: bool,
+    pad_levels: bool,
+    error_storm_window: Option<Duration>,
+    collapse_repeats: bool,
+    span_histograms: bool,
+    span_summary: Option<Duration>,
+    recorder_capacity: Option<usize>,
+    samples: Vec<SampleRule>,
+    measure_overhead: bool,
+    slow_writer_threshold: Option<Duration>,
+    flush_interval: Option<Duration>,
+    discard: Option<Discard>,
+    wrap: bool,
+    continuation: Continuation,
+    default_width: Option<usize>,
+    level_style: LevelStyle,
+    level_labels: [Option<String>; 5],
+    level_context: [LevelContext; 5],
+    level_colors: LevelColors,
+    time_format: TimeFormat,
+    event_deltas: bool,
+    span_elapsed: bool,
+    color_targets: bool,
+    prefer_target: bool,
+    editor_locations: bool,
+    span_ids: bool,
+    correlation_fields: Vec<String>,
+    layout: Option<String>,
+    dim_fields: bool,
+    max_span_depth: Option<usize>,
+    grouped_spans: Vec<String>,
+    span_headers: bool,
+    format: LogFormat,
+    json_span_fields: JsonSpanFields,
+    github_annotations: Option<bool>,
+    teamcity_messages: Option<bool>,
+    debugger_output: bool,
+    stdout: bool,
+    color_per_stream: bool,
+    /// Strips the colors from stdout unless it is a terminal, see [`Builder::split_stdout`]
+    plain_piped_stdout: bool,
+    writer: Option<Arc<BoxMakeWriter>>,
+    rotation: Option<Rotation>,
+    #[cfg(feature = "gzip")]
+    compress_rotated: bool,
+    max_rotated_age: Option<Duration>,
+    min_free_disk: Option<u64>,
+    explain_window: Option<Duration>,
+    interactive: bool,
+    file_settings: LogSettings,
+    routes: [Option<Vec<Destination>>; 5],
+    json_tees: Vec<(Destination, String)>,
+    delimited_tees: Vec<(Destination, Delimited, String)>,
+    audit_logs: Vec<(PathBuf, String)>,
+    binary_logs: Vec<(PathBuf, String)>,
+    audit_key: Option<Vec<u8>>,
+    html_report: Option<(PathBuf, String)>,
+    banner: Option<BuildInfo>,
+    transcript: Option<Destination>,
+    tag_routes: Vec<(String, Vec<Destination>)>,
+    syslog: Option<String>,
+    gelf: Option<String>,
+    event_stream: Option<String>,
+    output_filters: HashMap<Output, String>,
+    loki: Option<Loki>,
+    tokio_batching: bool,
+    sentry_dsn: Option<String>,
+    otlp_url: Option<String>,
+    trace_ids: Option<TraceIds>,
+}
+
+impl Builder {
+    /// Starts from the defaults used by [`init`]
+    pub fn new(root_module: impl Into<String>, verbosity: Verbosity) -> Self {
+        Self {
+            roots: vec![root_module.into()],
+            verbosity,
+            verbosity_mapping: VerbosityMapping::default(),
+            verbose_format: cfg!(debug_assertions),
+            slow_span_threshold: None,
+            slow_span_level: None,
+            only_slow_spans: false,
+            tree: false,
+            multiline_fields: None,
+            fields: FieldFormatter::default(),
+            presets: presets::builtin(),
+            enabled_presets: Vec::new(),
+            filter_presets: Vec::new(),
+            close_templates: HashMap::new(),
+            panic_record: false,
+            panic_hook: false,
+            crash_reports: None,
+            error_backtraces: false,
+            field_filters: Vec::new(),
+            boosts: Vec::new(),
+            message_matches: Vec::new(),
+            message_excludes: Vec::new(),
+            highlights: Vec::new(),
+            quiet_dependencies: false,
+            muted: Vec::new(),
+            env_var: verbosity::LOG_ENV.to_owned(),
+            closed_marker: false,
+            path_style: PathStyle::default(),
+            hyperlinks: None,
+            path_prefixes: PathPrefixes::default(),
+            fail_on_errors: None,
+            summary_at_exit: false,
+            count_targets: false,
+            metrics: false,
+            aligned: false,
+            pad_levels: false,
+            error_storm_window: None,
+            collapse_repeats: false,
+            span_histograms: false,
+            span_summary: None,
+            recorder_capacity: None,
+            samples: Vec::new(),
+            measure_overhead: false,
+            slow_writer_threshold: None,
+            flush_interval: None,
+            discard: None,
+            wrap: false,
+            continuation: Continuation::default(),
+            default_width: None,
+            level_style: LevelStyle::default(),
+            level_labels: Default::default(),
+            level_context: Default::default(),
+            level_colors: LevelColors::default(),
+            time_format: TimeFormat::default(),
+            event_deltas: false,
+            span_elapsed: false,
+            color_targets: false,
+            prefer_target: false,
+            editor_locations: false,
+            span_ids: false,
+            correlation_fields: Vec::new(),
+            layout: None,
+            dim_fields: false,
+            max_span_depth: None,
+            grouped_spans: Vec::new(),
+            span_headers: false,
+            format: LogFormat::Pretty,
+            json_span_fields: JsonSpanFields::default(),
+            github_annotations: None,
+            teamcity_messages: None,
+            debugger_output: false,
+            stdout: false,
+            color_per_stream: false,
+            plain_piped_stdout: false,
+            writer: None,
+            rotation: None,
+            #[cfg(feature = "gzip")]
+            compress_rotated: false,
+            max_rotated_age: None,
+            min_free_disk: None,
+            explain_window: None,
+            interactive: false,
+            file_settings: LogSettings::default(),
+            routes: Default::default(),
+            json_tees: Vec::new(),
+            delimited_tees: Vec::new(),
+            audit_logs: Vec::new(),
+            binary_logs: Vec::new(),
+            audit_key: None,
+            html_report: None,
+            banner: None,
+            transcript: None,
+            tag_routes: Vec::new(),
+            syslog: None,
+            gelf: None,
+            event_stream: None,
+            output_filters: HashMap::new(),
+            loki: None,
+            tokio_batching: false,
+            sentry_dsn: None,
+            otlp_url: None,
+            trace_ids: None,
+        }
+    }
+
+    /// Replaces the table that maps `--verbose` and `--quiet` to a level
+    pub fn verbosity_mapping(mut self, mapping: VerbosityMapping) -> Self {
+        self.verbosity_mapping = mapping;
+        self
+    }
+
+    /// Disables logging entirely, like `--silent`, whatever the verbosity flags, the log
+    /// environment variable and the configuration file say
+    ///
+    /// Outputs with levels of their own, such as [`tee_json`](Self::tee_json), still get events.
+    pub fn silent(mut self) -> Self {
+        self.verbosity.silent = true;
+        self
+    }
+
+    /// Prints the filter in effect to stdout and exits when the subscriber is installed, like
+    /// `--log-print-filter`, such as to find out why a module's events are missing
+    ///
+    /// The filter combines the defaults, the configuration file, the log environment variable
+    /// and the command line, see [`LogConfig`]. `--log-print-config` shows what each of them
+    /// contributes instead, without exiting.
+    pub fn print_filter(mut self) -> Self {
+        self.verbosity.print_filter = true;
+        self
+    }
+
+    /// Sets the level shown when neither `--verbose` nor `--quiet` is given, in debug and
+    /// release builds, instead of `DEBUG` and `WARN`
+    ///
+    /// The flags step through the table of the [`VerbosityMapping`] from there, so set a custom
+    /// table first.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// use tracing_subscriber::filter::LevelFilter;
+    ///
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .default_level(LevelFilter::INFO, LevelFilter::WARN)
+    ///     .init();
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the level of the current build is not in the table.
+    pub fn default_level(mut self, debug: LevelFilter, release: LevelFilter) -> Self {
+        let level = if cfg!(debug_assertions) {
+            debug
+        } else {
+            release
+        };
+        self.verbosity_mapping = self.verbosity_mapping.with_default_level(level);
+        self
+    }
+
+    /// Sets whether the time, module and spans of events are shown when `--verbose` isn't
+    /// given, in debug and release builds, instead of only in debug builds
+    pub fn verbose_format(mut self, debug: bool, release: bool) -> Self {
+        self.verbose_format = if cfg!(debug_assertions) {
+            debug
+        } else {
+            release
+        };
+        self
+    }
+
+    /// Treats `module` as first-party code too, showing paths within it relative to it
+    ///
+    /// Module paths are shortened relative to the most specific root they belong to, and shown in
+    /// full if they don't belong to any.
+    pub fn root_module(mut self, module: impl Into<String>) -> Self {
+        self.roots.push(module.into());
+        self
+    }
+
+    /// Reads logging filters from the environment variable `name` instead of `SCROOGE_LOG`
+    ///
+    /// Filters passed with `--log` are added to those in the variable.
+    pub fn env_var(mut self, name: &str) -> Self {
+        self.env_var = name.to_owned();
+        self
+    }
+
+    /// Highlights the duration of spans that stay open for at least `threshold`
+    pub fn slow_span_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_span_threshold = Some(threshold);
+        self
+    }
+
+    /// Displays the close events of slow spans with `level` instead of the span's own level
+    pub fn slow_span_level(mut self, level: Level) -> Self {
+        self.slow_span_level = Some(level);
+        self
+    }
+
+    /// Suppresses span close events unless the span stayed open for at least `threshold`
+    ///
+    /// This also sets the [`slow_span_threshold`](Self::slow_span_threshold), so the close events
+    /// that do get through are highlighted.
+    pub fn only_slow_spans(mut self, threshold: Duration) -> Self {
+        self.slow_span_threshold = Some(threshold);
+        self.only_slow_spans = true;
+        self
+    }
+
+    /// Indents events by the depth of their span and draws tree guides around span lifetimes
+    ///
+    /// This replaces the colon-separated span path shown in verbose mode, and adds a line for
+    /// every span that is opened. In verbose mode, the line closing a span ends with how long it
+    /// was open, aligned on the right edge of the terminal, so where time went can be seen at a
+    /// glance.
+    pub fn tree(mut self) -> Self {
+        self.tree = true;
+        self
+    }
+
+    /// Holds back the events within spans named `name`, and writes them as one block when the
+    /// span closes, so the events of concurrent tasks don't interleave
+    ///
+    /// Each event is written with a single call whether or not it is grouped. Grouping goes
+    /// further, keeping the events of a whole request or job together, at the cost of showing
+    /// them only once it ends. Events of other threads in spans below the grouped one, such as
+    /// those of tasks it spawns, join the block too. When grouped spans are nested, the
+    /// outermost one holds the events. Can be called several times to group spans of several
+    /// names.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .group_spans("request")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn group_spans(mut self, name: &str) -> Self {
+        self.grouped_spans.push(name.to_owned());
+        self
+    }
+
+    /// Shows the spans of events in a dim header line whenever they differ from those of the
+    /// event before, instead of at the start of every line
+    ///
+    /// Long runs of events from the same operation then read as a section under its header:
+    ///
+    /// ```text
+    /// ── request:db
+    /// 09:14:03.210 db.rs:42 info: connected
+    /// 09:14:03.254 db.rs:57 info: fetched rows rows=3
+    /// ── request
+    /// 09:14:03.260 api.rs:18 info: responded status=200
+    /// ```
+    ///
+    /// Events outside of any span get a `── (no span)` header when they follow events in spans,
+    /// and span close events still name their span. Spans are only shown where the
+    /// [`level_context`](Self::level_context) shows them, and [`tree`](Self::tree) mode draws
+    /// them its own way. With events of several threads interleaving, headers come as often as
+    /// the spans change.
+    pub fn span_headers(mut self) -> Self {
+        self.span_headers = true;
+        self
+    }
+
+    /// Pads the module and `file:line` context and the level label into columns, so messages
+    /// start at the same column on every line
+    ///
+    /// The context column widens to fit the longest context seen so far.
+    pub fn aligned(mut self) -> Self {
+        self.aligned = true;
+        self
+    }
+
+    /// Sets the format of the time shown at the start of verbose lines, `%H:%M:%S%.3f` by default
+    ///
+    /// The format is a [`chrono` strftime format](https://docs.rs/chrono/0.4/chrono/format/strftime/index.html),
+    /// except that `%Z` is the abbreviated name of the time zone, such as `CEST`, where the
+    /// platform provides one. `%:z` shows the numeric offset, such as `+02:00`.
+    ///
+    /// Without the `chrono` feature, formats are written by the subscriber itself, which knows
+    /// the specifiers that don't depend on the locale, such as `%Y`, `%m`, `%d`, `%H`, `%M`,
+    /// `%S`, `%.3f`, `%z` and `%Z`, but not chrono's padding modifiers such as `%-d`.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .time_format("%Y-%m-%d %H:%M:%S%.3f %Z")
+    ///     .init();
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the format is invalid.
+    pub fn time_format(mut self, format: &str) -> Self {
+        self.time_format.set_format(format);
+        self
+    }
+
+    /// Shows times at a fixed offset of `minutes` east of UTC instead of in the local time zone,
+    /// such as `utc_offset(0)` for UTC or `utc_offset(5 * 60 + 30)` for India
+    ///
+    /// # Panics
+    ///
+    /// Panics if the offset is a day or more.
+    pub fn utc_offset(mut self, minutes: i32) -> Self {
+        self.time_format.set_offset(minutes);
+        self
+    }
+
+    /// Shows times to `precision`, such as [`TimePrecision::Micros`] to tell apart events logged
+    /// within the same millisecond
+    ///
+    /// Times are shown to the millisecond by default, and JSON and Bunyan records get them to the
+    /// microsecond. The precision applies to both, unless a [`time_format`](Self::time_format)
+    /// is given, whose own precision then applies to the formatted output.
+    pub fn time_precision(mut self, precision: TimePrecision) -> Self {
+        self.time_format.set_precision(precision);
+        self
+    }
+
+    /// Selects where the times shown come from, see [`TimeSource`]
+    ///
+    /// By default the wall clock is used unless it hasn't been set, as early in boot, in which
+    /// case times are shown as offsets from when logging was set up. JSON records and log
+    /// collectors always get wall clock times.
+    pub fn time_source(mut self, source: TimeSource) -> Self {
+        self.time_format.set_source(source);
+        self
+    }
+
+    /// Shows times and durations the way `locale` does, such as on a 12-hour clock and with
+    /// `%x` standing for `%m/%d/%Y` for `en_US`, or with durations such as `2,50мс` for `ru_RU`,
+    /// see [`Locale`]
+    ///
+    /// Times and durations are shown the same way everywhere by default, which suits logs read
+    /// by developers and tools, while tools shipped to people around the world may follow the
+    /// locale of the process. JSON records and log collectors aren't affected. The durations
+    /// shown by every subscriber of the process follow the last locale set.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Locale, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .locale(Locale::from_env())
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.time_format.set_locale(locale);
+        self
+    }
+
+    /// Shows how long it has been since the previous event of the same thread, such as
+    /// `+3.20ms` in dim text after the time, to spot where time goes in sequential code without
+    /// wrapping every step in a span
+    ///
+    /// The first event of each thread has none, and span close events count as events. The
+    /// transcript and crash reports leave the deltas out.
+    pub fn event_deltas(mut self) -> Self {
+        self.event_deltas = true;
+        self
+    }
+
+    /// Shows how long the span of each event has been open, such as `[@2.40s]` in dim text
+    /// after the time, to see where a long operation spends its time without waiting for the
+    /// line of its close event
+    ///
+    /// Events outside of any span, and the events of spans opening and closing, have none.
+    pub fn span_elapsed(mut self) -> Self {
+        self.span_elapsed = true;
+        self
+    }
+
+    /// Colors module names by their top-level module or crate, so lines from the same subsystem
+    /// share a color when many of them interleave
+    ///
+    /// Colors are derived from a hash of the name, so a subsystem keeps its color between runs.
+    pub fn color_targets(mut self) -> Self {
+        self.color_targets = true;
+        self
+    }
+
+    /// Shows the target of events in place of their module
+    ///
+    /// By default the target is shown dimmed in brackets after the module when they differ, as
+    /// for events logged with an explicit `target:`. Applications that use targets as
+    /// categories can show only those instead.
+    pub fn prefer_target(mut self) -> Self {
+        self.prefer_target = true;
+        self
+    }
+
+    /// Shows a short ID after the name of each span, such as `request#3fa2`, so that concurrent
+    /// spans of the same name can be told apart when their events interleave
+    ///
+    /// IDs are four hex digits derived from the order in which spans are created, so they are
+    /// not reused when a span closes, and only rarely shared by spans open at the same time.
+    pub fn span_ids(mut self) -> Self {
+        self.span_ids = true;
+        self
+    }
+
+    /// Shows the value of the field called `name` at the start of every line logged within a
+    /// span that has it, such as `request_id`, so a single request can be followed through
+    /// interleaved output
+    ///
+    /// The value comes right after the time, in brackets, colored after a hash of the value so
+    /// that lines of the same request share a color. When several fields are given, the
+    /// innermost span having any of them wins, and the first field given within that span. Can
+    /// be called several times.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .correlation_field("request_id")
+    ///     .correlation_field("job_id")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn correlation_field(mut self, name: impl Into<String>) -> Self {
+        self.correlation_fields.push(name.into());
+        self
+    }
+
+    /// Lays out each event line after `template`, in place of the usual layout
+    ///
+    /// The template holds placeholders in braces for the parts of the line: `{time}`,
+    /// `{level}`, `{module}`, `{target}`, `{file}`, `{line}`, `{location}` for `file:line`,
+    /// `{span}` for the span path, `{elapsed}` for how long the span has been open, as with
+    /// [`span_elapsed`](Self::span_elapsed), `{correlation}` for the value of
+    /// [`correlation_field`](Self::correlation_field), `{message}` and `{fields}`. A width
+    /// after a colon pads a part to that many columns, aligned left with `<` or right with `>`,
+    /// such as `{level:<8}`. Parts are shown whatever the verbosity, and `{{` and `}}` stand
+    /// for literal braces. Unknown placeholders are written unchanged.
+    ///
+    /// Tree mode, wrapping, presets and the other options arranging lines don't apply to
+    /// templated lines. The causes and backtraces of errors still follow them.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .layout("{time} {level:<8} {span} {location} {message} {fields}")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn layout(mut self, template: &str) -> Self {
+        self.layout = Some(template.to_owned());
+        self
+    }
+
+    /// Pads level labels to the width of the longest one, so that `info:` lines start their
+    /// message in the same column as `warning:` lines
+    ///
+    /// Implied by [`aligned`](Self::aligned). [`LevelStyle::Short`] avoids the padding instead.
+    pub fn pad_levels(mut self) -> Self {
+        self.pad_levels = true;
+        self
+    }
+
+    /// Wraps long messages to the width of the terminal, indenting continuation lines to where
+    /// the message starts, or by four spaces if that would leave them too narrow
+    ///
+    /// The width is read again when the terminal is resized, on Unix. When stderr is not a
+    /// terminal, nothing is wrapped unless a [`default_width`](Self::default_width) is set.
+    pub fn wrap(mut self) -> Self {
+        self.wrap = true;
+        self
+    }
+
+    /// Sets the width messages are wrapped to, and span durations aligned to in
+    /// [`tree`](Self::tree) mode, when that of the terminal is unknown, such as when stderr is
+    /// redirected to a file
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .wrap()
+    ///     .default_width(100)
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn default_width(mut self, columns: usize) -> Self {
+        self.default_width = Some(columns);
+        self
+    }
+
+    /// Sets how the lines after the first of messages with line breaks in them are written,
+    /// indented to where the message starts by default
+    ///
+    /// [`Continuation::Prefix`] repeats the time, context and level of the first line, dimmed,
+    /// so that each line can be searched on its own.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Continuation, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .continuation_lines(Continuation::Prefix)
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn continuation_lines(mut self, continuation: Continuation) -> Self {
+        self.continuation = continuation;
+        self
+    }
+
+    /// Selects how the level of events is labelled, see [`LevelStyle`]
+    pub fn level_style(mut self, style: LevelStyle) -> Self {
+        self.level_style = style;
+        self
+    }
+
+    /// Replaces the label of `level`, such as to localize it or to match existing conventions
+    ///
+    /// Takes precedence over the [`LevelStyle`]. Labels keep their color.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// # use tracing::Level;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .level_label(Level::ERROR, "Fehler:")
+    ///     .level_label(Level::WARN, "Warnung:")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn level_label(mut self, level: Level, label: impl Into<String>) -> Self {
+        self.level_labels[levels::index(level)] = Some(label.into());
+        self
+    }
+
+    /// Replaces the colors of level labels, such as for a colorblind-friendly palette, see
+    /// [`LevelColors`]
+    ///
+    /// The `PRETTY_LOG_COLORS` environment variable, such as `error=red,warn=93,info=cyan`,
+    /// overrides these, so users can fit the colors to their terminal theme.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, LevelColors, Verbosity};
+    /// # use structopt::StructOpt;
+    /// let colors: LevelColors = "error=38;5;208,warn=33".parse().unwrap();
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .level_colors(colors)
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn level_colors(mut self, colors: LevelColors) -> Self {
+        self.level_colors = colors;
+        self
+    }
+
+    /// Shows parts of the context before events at `level` even outside of verbose mode, such
+    /// as the time, module and spans of errors, which are what is needed to make sense of them
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, LevelContext, Verbosity};
+    /// # use structopt::StructOpt;
+    /// # use tracing::Level;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .level_context(Level::ERROR, LevelContext::FULL)
+    ///     .level_context(Level::WARN, LevelContext { module: true, ..Default::default() })
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn level_context(mut self, level: Level, context: LevelContext) -> Self {
+        self.level_context[levels::index(level)] = context;
+        self
+    }
+
+    /// Shows at most `depth` span names before events, the outermost span and the innermost
+    /// ones, with `…` in place of those in between
+    ///
+    /// Keeps lines readable in heavily instrumented code, such as `server:…:query:decode`
+    /// rather than every layer of middleware in between.
+    pub fn max_span_depth(mut self, depth: usize) -> Self {
+        self.max_span_depth = Some(depth.max(1));
+        self
+    }
+
+    /// Adds filter settings read from the application's configuration file, which the log
+    /// environment variable and the command line take precedence over, see [`LogConfig`]
+    pub fn file_settings(mut self, settings: LogSettings) -> Self {
+        self.file_settings = settings;
+        self
+    }
+
+    /// Returns the filter settings from each source, which [`LogConfig::resolve`] combines into
+    /// the filters in effect
+    pub fn log_config(&self) -> LogConfig {
+        let mapping = &self.verbosity_mapping;
+        let level = verbosity::compiled_in(mapping.level_filter(0, 0));
+        let mut defaults = LogSettings {
+            level: Some(level),
+            directives: Vec::new(),
+        };
+        if self.quiet_dependencies {
+            let verbosity = &self.verbosity;
+            let level = mapping.level_filter(verbosity.verbose, verbosity.quiet);
+            let cap = cmp::min(level, LevelFilter::WARN);
+            for dependency in NOISY_DEPENDENCIES {
+                defaults.directives.push(format!("{}={}", dependency, cap));
+            }
+        }
+        let muted = self.muted.iter().map(|target| format!("!{}", target));
+        defaults.directives.extend(muted);
+        let mut presets = LogSettings::default();
+        let enabled = self.enabled_presets.iter().chain(&self.verbosity.presets);
+        for settings in enabled.filter_map(|name| self.filter_preset_settings(name)) {
+            presets.level = settings.level.or(presets.level);
+            presets.directives.extend(settings.directives);
+        }
+        let env = std::env::var(&self.env_var).unwrap_or_default();
+        LogConfig {
+            defaults,
+            file: self.file_settings.clone(),
+            presets,
+            env: LogSettings::from_directives(&env),
+            cli: self.verbosity.cli_settings(mapping),
+            silent: self.verbosity.silent,
+        }
+    }
+
+    /// Dims the fields of events other than the message, so the message stands out while the
+    /// structured context stays available
+    pub fn dim_fields(mut self) -> Self {
+        self.dim_fields = true;
+        self
+    }
+
+    /// Prints each field of an event on its own line once it has at least `min_fields` of them
+    ///
+    /// The message stays on the first line and the remaining fields are listed below it,
+    /// indented and with their values aligned.
+    pub fn multiline_fields(mut self, min_fields: usize) -> Self {
+        self.multiline_fields = Some(min_fields);
+        self
+    }
+
+    /// Selects how the source file of events is shown, see [`PathStyle`]
+    pub fn path_style(mut self, style: PathStyle) -> Self {
+        self.path_style = style;
+        self
+    }
+
+    /// Writes the source location of events as a `path:line:column` token of its own, such as
+    /// `src/db/mod.rs:42:1`, which terminals and editors that detect file references let users
+    /// click
+    ///
+    /// The path is relative, as with [`PathStyle::Relative`], and is separated from the module
+    /// by a space rather than a colon. Only the line of an event is known, so the column is
+    /// always 1.
+    pub fn editor_locations(mut self) -> Self {
+        self.editor_locations = true;
+        self.path_style = PathStyle::Relative;
+        self
+    }
+
+    /// Makes the `file:line` of events a hyperlink built from `template` on terminals that
+    /// support them
+    ///
+    /// `{path}` is replaced with the absolute path of the source file and `{line}` with the line,
+    /// as in `vscode://file/{path}:{line}` or `file://{path}`. Setting `FORCE_HYPERLINK=0` in the
+    /// environment turns the links off, and `FORCE_HYPERLINK=1` forces them on.
+    pub fn hyperlinks(mut self, template: &str) -> Self {
+        self.hyperlinks = Some(template.to_owned());
+        self
+    }
+
+    /// Shows the source files whose path starts with `from` as if it started with `to`, so the
+    /// locations of binaries built in a container or on CI can be found outside of it
+    ///
+    /// `to` can be a directory, such as `~/work/my_app/src` for `/build/src`, or the URL of the
+    /// sources at the commit the binary was built from, whose paths then use slashes. The
+    /// longest `from` matching whole components of a path wins. The rewritten paths are shown
+    /// with [`PathStyle::Relative`] and in [`hyperlinks`](Self::hyperlinks), where `~` stands
+    /// for the home directory and relative paths are also matched once resolved against the
+    /// working directory.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, PathStyle, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .path_style(PathStyle::Relative)
+    ///     .path_prefix("/build", "https://github.com/me/my_app/blob/4f2a9c1")
+    ///     .hyperlinks("{path}#L{line}")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn path_prefix(mut self, from: &str, to: &str) -> Self {
+        self.path_prefixes.add(from.to_owned(), to.to_owned());
+        self
+    }
+
+    /// Selects how field values are rendered, see [`FieldStyle`]
+    pub fn field_style(mut self, style: FieldStyle) -> Self {
+        self.fields.style = style;
+        self
+    }
+
+    /// Selects the order in which fields other than the message are printed, see [`FieldOrder`]
+    pub fn field_order(mut self, order: FieldOrder) -> Self {
+        self.fields.order = order;
+        self
+    }
+
+    /// Truncates field values longer than `max_length` bytes
+    ///
+    /// Truncated values end with an ellipsis followed by their original length, so a single
+    /// large debug-printed value doesn't turn into a line that fills the terminal.
+    pub fn max_field_length(mut self, max_length: usize) -> Self {
+        self.fields.max_length = Some(max_length);
+        self
+    }
+
+    /// Replaces the value of every field whose name matches `pattern` with `[REDACTED]`
+    ///
+    /// The pattern may contain `*` wildcards, such as `*_secret`. Redaction applies to the fields
+    /// of both events and spans, in every output format.
+    pub fn redact(mut self, pattern: &str) -> Self {
+        self.fields.redaction.add(pattern);
+        self
+    }
+
+    /// Shows the values of the fields whose name matches `pattern` as durations, such as
+    /// `elapsed=1.52ms` rather than `elapsed=1523000`
+    ///
+    /// Values may be numbers of nanoseconds or `Duration`s. The pattern may contain `*`
+    /// wildcards, such as `*_ns`. Other values are shown as they are.
+    pub fn duration_fields(mut self, pattern: &str) -> Self {
+        self.fields.units.durations.push(pattern.to_owned());
+        self
+    }
+
+    /// Shows the values of the fields whose name matches `pattern` as sizes in binary units,
+    /// such as `bytes=1.0 MiB` rather than `bytes=1048576`
+    ///
+    /// The pattern may contain `*` wildcards, such as `*_bytes`. Values that aren't numbers are
+    /// shown as they are.
+    pub fn size_fields(mut self, pattern: &str) -> Self {
+        self.fields.units.sizes.push(pattern.to_owned());
+        self
+    }
+
+    /// Leaves out the fields whose name matches `pattern` from the formatted output
+    ///
+    /// The pattern may contain `*` wildcards, like those of [`redact`](Self::redact). The
+    /// `log.*` fields that records bridged from the `log` crate carry are hidden by default.
+    /// Events are still filtered on hidden fields, and other output formats are unaffected.
+    pub fn hide_fields(mut self, pattern: &str) -> Self {
+        self.fields.visibility.hide(pattern);
+        self
+    }
+
+    /// Shows the fields whose name matches `pattern` even if [`hide_fields`](Self::hide_fields)
+    /// hides them, such as `log.target` to show the target of `log` records
+    pub fn show_fields(mut self, pattern: &str) -> Self {
+        self.fields.visibility.show(pattern);
+        self
+    }
+
+    /// Only shows events where a field, or a field of an enclosing span, matches `directive`
+    ///
+    /// This is in addition to any `--log-field` arguments. When several directives are given,
+    /// events have to match all of them.
+    pub fn filter_field(mut self, directive: FieldDirective) -> Self {
+        self.field_filters.push(directive);
+        self
+    }
+
+    /// Raises the level of events meeting the condition of `rule`, in addition to any
+    /// `--log-boost`, so operationally important events get through conservative filters
+    /// without changing where they are logged
+    ///
+    /// The level filters let a raised event through if they would show it at its new level, and
+    /// the formatted output shows it at that level. When several rules apply, the most severe
+    /// level wins. Events are only ever raised, and only by their own fields, not those of their
+    /// spans. JSON tees, outputs with filters of their own and log collectors get the events at
+    /// their own level.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{BoostRule, Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// # use tracing::Level;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .boost_level(BoostRule::new("retry_count>3", Level::WARN)?)
+    ///     .boost_level("error=true:warn".parse()?)
+    ///     .init();
+    /// # }
+    /// # Ok::<(), pretty_tracing_subscriber::ParseBoostRuleError>(())
+    /// ```
+    pub fn boost_level(mut self, rule: BoostRule) -> Self {
+        self.boosts.push(rule);
+        self
+    }
+
+    /// Only shows events whose message matches `regex`, in addition to any `--log-match`, with
+    /// the `regex` feature
+    ///
+    /// An event is shown if its message matches any of the given expressions.
+    #[cfg(feature = "regex")]
+    pub fn filter_message(mut self, regex: Regex) -> Self {
+        self.message_matches.push(regex);
+        self
+    }
+
+    /// Hides events whose message matches `regex`, in addition to any `--log-exclude`, with the
+    /// `regex` feature
+    #[cfg(feature = "regex")]
+    pub fn exclude_message(mut self, regex: Regex) -> Self {
+        self.message_excludes.push(regex);
+        self
+    }
+
+    /// Paints the parts of messages that `regex` matches in inverse bold, in addition to any
+    /// `--highlight`, such as to spot a request ID in output scrolling by fast, with the `regex`
+    /// feature
+    ///
+    /// Nothing is hidden. Highlights are left out where colors are, such as in files.
+    #[cfg(feature = "regex")]
+    pub fn highlight(mut self, regex: Regex) -> Self {
+        self.highlights.push(regex);
+        self
+    }
+
+    /// Hides every event of `target` and the modules under it, in addition to any `--log-mute`,
+    /// whatever the level and the directives of any source say
+    ///
+    /// Unlike a `target=off` directive, which a more specific directive such as
+    /// `--log target::inner=trace` overrides, a muted target stays hidden, so a chatty module can
+    /// be kept out of the output while everything else is shown at `TRACE`.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .mute("my_app::poller")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn mute(mut self, target: impl Into<String>) -> Self {
+        self.muted.push(target.into());
+        self
+    }
+
+    /// Caps well-known chatty dependencies, such as hyper, h2, tokio and rustls, at `WARN`
+    ///
+    /// The rest of the output still follows `--verbose` and `--quiet`. A `--log` directive that
+    /// names one of these crates overrides its cap.
+    pub fn quiet_dependencies(mut self) -> Self {
+        self.quiet_dependencies = true;
+        self
+    }
+
+    /// Coalesces identical `ERROR` events emitted within `window` of each other
+    ///
+    /// The first occurrence of an error is shown, and repeats of it within the window are only
+    /// counted. The next occurrence after the window is shown with the number of repeats that
+    /// were suppressed, and a few of the spans they came from, so an error storm such as a
+    /// refused connection retried in a loop produces one line per window.
+    ///
+    /// When the error stops, its last repeats are reported by a line with the target
+    /// `pretty_tracing_subscriber::repeat`, with their number as `repeated`, as soon as another
+    /// event comes after the window, and on shutdown by the [`LogGuard`].
+    pub fn dedup_errors(mut self, window: Duration) -> Self {
+        self.error_storm_window = Some(window);
+        self
+    }
+
+    /// Keeps the last `capacity` `DEBUG` and `TRACE` events hidden by the filters, formatted,
+    /// and writes them out before the next error or panic, to give the context of a failure
+    /// without running at full verbosity
+    ///
+    /// Kept events are forgotten once written out. Every `DEBUG` and `TRACE` event is then
+    /// formatted, so this costs about as much as showing them, minus the writing.
+    pub fn flight_recorder(mut self, capacity: usize) -> Self {
+        self.recorder_capacity = Some(capacity);
+        self
+    }
+
+    /// Keeps a random fraction `rate`, from 0 to 1, of the events at `level` that pass the
+    /// filters, dropping the others before they are formatted
+    ///
+    /// This lets a busy service keep a trickle of `DEBUG` or `TRACE` events, which is still
+    /// useful statistically, without paying for formatting all of them. Each event is kept or
+    /// dropped independently, and JSON tees still get every event.
+    ///
+    /// ```no_run
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use tracing::Level;
+    /// Builder::new("my_app", Verbosity::new(2, 0))
+    ///     .sample(Level::TRACE, 0.01)
+    ///     .init();
+    /// ```
+    pub fn sample(mut self, level: Level, rate: f64) -> Self {
+        self.samples.push(SampleRule::new(None, level, rate));
+        self
+    }
+
+    /// Keeps a random fraction `rate` of the events at `level` in `target` and the modules under
+    /// it, like [`sample`](Self::sample), taking precedence over it and over the rates of
+    /// enclosing modules
+    pub fn sample_target(mut self, target: &str, level: Level, rate: f64) -> Self {
+        self.samples
+            .push(SampleRule::new(Some(target), level, rate));
+        self
+    }
+
+    /// Collapses consecutive identical events, from the same call site with the same fields,
+    /// into the first of them, followed by a `last message repeated 12 times` line when another
+    /// event comes, as syslog does
+    ///
+    /// This keeps the output of a retry loop readable without hiding when it ends. The line is
+    /// an `INFO` event with the target `pretty_tracing_subscriber::repeat` and the number of
+    /// repeats as `repeated`, which passes whatever the filters.
+    pub fn collapse_repeats(mut self) -> Self {
+        self.collapse_repeats = true;
+        self
+    }
+
+    /// Keeps a histogram of how long the spans that pass the filters live, by span name, which
+    /// [`LogGuard::span_durations`] returns
+    ///
+    /// This gives the latency of requests or jobs instrumented with spans, such as their 99th
+    /// percentile, without a tracing backend.
+    pub fn span_histograms(mut self) -> Self {
+        self.span_histograms = true;
+        self
+    }
+
+    /// Logs how long the spans closed in each `interval` lived, by span name, as with
+    /// [`span_histograms`](Self::span_histograms)
+    ///
+    /// Each span name gets a line such as `request: 120 spans, p50 3.2ms, p90 12ms, p99 40ms,
+    /// max 80ms`, logged as an `INFO` event with the target `pretty_tracing_subscriber::spans`.
+    /// The spans closed since the last summary are reported on shutdown.
+    pub fn span_summary(mut self, interval: Duration) -> Self {
+        self.span_histograms = true;
+        self.span_summary = Some(interval);
+        self
+    }
+
+    /// Makes a preset available to be enabled by name, replacing any preset with the same name
+    pub fn register_preset<P: Preset + 'static>(mut self, preset: P) -> Self {
+        self.presets.retain(|known| known.name() != preset.name());
+        self.presets.push(Arc::new(preset));
+        self
+    }
+
+    /// Enables a registered preset, in addition to those passed with `--log-preset`
+    ///
+    /// Presets are tried in the order they were enabled. Filter presets, see
+    /// [`filter_preset`](Self::filter_preset), are enabled the same way.
+    pub fn enable_preset(mut self, name: &str) -> Self {
+        self.enabled_presets.push(name.to_owned());
+        self
+    }
+
+    /// Makes comma-separated filter directives available to be enabled by name, such as with
+    /// `--log-preset debug-io`, replacing any filter preset with the same name
+    ///
+    /// This spares people running the application from composing filter directives: support
+    /// staff can be told to pass one flag. A bare level among the directives, such as `warn` in
+    /// `warn,my_app=info`, sets the level, while the other directives add to those of the other
+    /// sources, see [`LogConfig`]. Enabling several presets combines them, the later ones
+    /// winning for the same target.
+    ///
+    /// Two filter presets are known unless replaced: `quiet-deps` caps the chatty dependencies
+    /// of [`quiet_dependencies`](Self::quiet_dependencies) at `WARN`, and `trace-all-mine` shows
+    /// every event of the root modules.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .filter_preset("debug-io", "my_app::net=debug,my_app::storage=debug,hyper=info")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn filter_preset(mut self, name: &str, directives: &str) -> Self {
+        let mut settings = LogSettings::default();
+        for directive in config::split_directives(directives) {
+            match directive.parse::<LevelFilter>() {
+                Ok(level) => settings.level = Some(level),
+                Err(_) => settings.directives.push(directive),
+            }
+        }
+        self.filter_presets.retain(|(known, _)| known != name);
+        self.filter_presets.push((name.to_owned(), settings));
+        self
+    }
+
+    /// The settings of the filter preset called `name`, registered or built in
+    fn filter_preset_settings(&self, name: &str) -> Option<LogSettings> {
+        if let Some((_, settings)) = self.filter_presets.iter().find(|(known, _)| known == name) {
+            return Some(settings.clone());
+        }
+        let directives = match name {
+            "quiet-deps" => NOISY_DEPENDENCIES
+                .iter()
+                .map(|dependency| format!("{}=warn", dependency))
+                .collect(),
+            "trace-all-mine" => self
+                .roots
+                .iter()
+                .map(|root| format!("{}=trace", root))
+                .collect(),
+            _ => return None,
+        };
+        Some(LogSettings {
+            level: None,
+            directives,
+        })
+    }
+
+    /// Renders the close events of spans matched by the named preset from `template`
+    ///
+    /// Placeholders such as `{http.status_code}` are replaced with the span's fields, while
+    /// `{elapsed}`, `{busy}` and `{idle}` are replaced with its timings. Spans can also carry
+    /// their own template in a `log.close_msg` field, which takes precedence.
+    pub fn close_template(mut self, preset: &str, template: &str) -> Self {
+        self.close_templates
+            .insert(preset.to_owned(), template.to_owned());
+        self
+    }
+
+    /// Renders the close events of HTTP server spans as access-log lines
+    ///
+    /// Spans are recognised by an `http.method` field; `http.route`, `http.status_code` and
+    /// `latency` are shown when present. This is the `http` preset.
+    pub fn http_preset(self) -> Self {
+        self.enable_preset("http")
+    }
+
+    /// Renders the close events of gRPC spans as one line per call
+    ///
+    /// Spans are recognised by `rpc.system = "grpc"` or by `rpc.service` and `rpc.method` fields;
+    /// `rpc.grpc.status_code` is shown by name and `latency` is shown when present. This is the
+    /// `grpc` preset.
+    pub fn grpc_preset(self) -> Self {
+        self.enable_preset("grpc")
+    }
+
+    /// Renders the close events of database query spans as dim one-line summaries
+    ///
+    /// Spans are recognised by `db.system` or `db.statement` fields, as recorded by sqlx or
+    /// sea-orm instrumentation. Queries that take at least `slow_query` are highlighted. This is
+    /// the `db` preset, which otherwise uses a threshold of 100ms.
+    pub fn db_preset(self, slow_query: Duration) -> Self {
+        self.register_preset(presets::Db { slow_query })
+            .enable_preset("db")
+    }
+
+    /// Emits an `error` event with target `panic` whenever a thread panics
+    ///
+    /// The event carries the thread name, the location of the panic, the panic message, and a
+    /// backtrace when one is enabled with `RUST_BACKTRACE`. It is written before the default panic
+    /// message, so crashes show up in the log even when stderr is not being watched. With a
+    /// [`flight_recorder`](Self::flight_recorder) or [`crash_reports`](Self::crash_reports), it
+    /// also carries `recent_events`, the number of events they kept to write out.
+    ///
+    /// The files and log collectors are then drained, waiting at most 2 seconds, as the process
+    /// may exit before the [`LogGuard`] shuts logging down.
+    pub fn panic_record(mut self) -> Self {
+        self.panic_record = true;
+        self
+    }
+
+    /// Replaces the default panic message with the `panic` event of
+    /// [`panic_record`](Self::panic_record), formatted like other events
+    ///
+    /// The event is shown with a red `panic:` label, the location of the panic, the path of the
+    /// spans it happened in, and the message. The default message is still printed when the
+    /// filters would hide the event.
+    pub fn panic_hook(mut self) -> Self {
+        self.panic_hook = true;
+        self
+    }
+
+    /// Writes a report file to `dir` whenever a thread panics, and logs its path at `ERROR`
+    ///
+    /// The report, named like `my_app-crash-20210602T091403Z-4242.txt`, holds the panic message
+    /// and location, a backtrace whatever `RUST_BACKTRACE` says, the spans the panicking thread
+    /// was in with their fields, the details of the run, such as its ID, the version of
+    /// [`startup_banner`](Self::startup_banner), the process ID and the command line, and the
+    /// last 100 events shown, in the verbose format. Fatal errors that don't panic can be
+    /// reported the same way with [`LogGuard::crash_report`]. The directory is created if
+    /// needed.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .crash_reports("/var/log/my_app")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn crash_reports(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.crash_reports = Some(dir.into());
+        self
+    }
+
+    /// Shows a backtrace below every `ERROR` event, even when `RUST_BACKTRACE` doesn't enable them
+    ///
+    /// The frames start where the event was logged, and the frames of the root modules stand
+    /// out from those of dependencies and the standard library.
+    pub fn error_backtraces(mut self) -> Self {
+        self.error_backtraces = true;
+        self
+    }
+
+    /// Writes a final `log closed` line when the [`LogGuard`] is dropped
+    ///
+    /// A log that doesn't end with the marker was cut short, for example by a crash.
+    pub fn closed_marker(mut self) -> Self {
+        self.closed_marker = true;
+        self
+    }
+
+    /// Makes [`LogGuard::exit_code`] return 1 once at least `min_errors` `ERROR` events have been
+    /// logged
+    ///
+    /// Only events that pass the filters are counted. Scripts wrapping the program can then rely
+    /// on its exit status rather than scraping stderr for errors.
+    pub fn fail_on_errors(mut self, min_errors: usize) -> Self {
+        self.fail_on_errors = Some(min_errors);
+        self
+    }
+
+    /// Writes a line such as `3 errors, 12 warnings` to stderr when the [`LogGuard`] is dropped,
+    /// if any errors or warnings passed the filters
+    ///
+    /// The counts are also available from [`LogGuard::event_counts`].
+    pub fn summary_at_exit(mut self) -> Self {
+        self.summary_at_exit = true;
+        self
+    }
+
+    /// Counts events by target as well as by level, for [`LogGuard::event_counts`]
+    ///
+    /// Each event then takes a lock, which programs logging heavily from many threads may notice.
+    pub fn count_targets(mut self) -> Self {
+        self.count_targets = true;
+        self
+    }
+
+    /// Counts the events that pass the filters in the `log_events_total` counter of the
+    /// [`metrics`](https://docs.rs/metrics) facade, labelled with their `level` and `target`,
+    /// with the `metrics` feature
+    ///
+    /// The counter goes wherever the application's metrics recorder exports it, such as a
+    /// Prometheus endpoint, so dashboards can alert on a rising error rate without parsing logs.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(mut self) -> Self {
+        self.metrics = true;
+        self
+    }
+
+    /// Writes each event as a single line of JSON instead of formatting it for people
+    ///
+    /// The object holds the timestamp, level, target, source location, the fields of the event
+    /// and those of its spans. Filtering and redaction apply as usual, while the display options
+    /// have no effect.
+    pub fn json(mut self) -> Self {
+        self.format = LogFormat::Json;
+        self
+    }
+
+    /// Selects where the fields of spans go in JSON records, see [`JsonSpanFields`]
+    ///
+    /// Log collectors often can't query into the `spans` array of the default layout, while
+    /// fields at the top level of the record are indexed like any other. Span fields named after
+    /// a member of the record, such as `level`, get a trailing underscore. This applies to
+    /// [`json`](Self::json) and [`tee_json`](Self::tee_json), Bunyan records always prefix span
+    /// fields with the span name.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, JsonSpanFields, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .json()
+    ///     .json_span_fields(JsonSpanFields::Innermost)
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn json_span_fields(mut self, span_fields: JsonSpanFields) -> Self {
+        self.json_span_fields = span_fields;
+        self
+    }
+
+    /// Writes each event as a Bunyan record instead of formatting it for people, so the output
+    /// can be piped into the `bunyan` CLI or other Node tooling
+    ///
+    /// Records hold the core Bunyan fields, `v`, `name`, `hostname`, `pid`, `level` as a Bunyan
+    /// level number, `time` and `msg`, followed by the target, the source location as `src`, the
+    /// fields of the event and those of its spans, prefixed with the span name, such as
+    /// `request.id`. Event fields named after core fields get a trailing underscore. As with
+    /// [`json`](Self::json), the display options have no effect.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .bunyan()
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn bunyan(mut self) -> Self {
+        self.format = LogFormat::Bunyan;
+        self
+    }
+
+    /// Writes each event as a line of logfmt, `key=value` pairs separated by spaces, instead of
+    /// formatting it for people
+    ///
+    /// The line holds the time, level, target, message, the fields of the event and those of
+    /// its spans, prefixed with the span name, such as `request.id`. Values holding spaces,
+    /// quotes or `=` are quoted. As with [`json`](Self::json), the display options have no
+    /// effect.
+    pub fn logfmt(mut self) -> Self {
+        self.format = LogFormat::Logfmt;
+        self
+    }
+
+    /// Selects how events are written, see [`LogFormat`]
+    ///
+    /// `--log-format` and the log environment variable suffixed with `_FORMAT`, such as
+    /// `SCROOGE_LOG_FORMAT`, override this, so deployments can switch formats without changing
+    /// the code.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, LogFormat, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .log_format(LogFormat::Logfmt)
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn log_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Writes `WARN` and `ERROR` events as GitHub Actions workflow commands with `true`, so they
+    /// show up as annotations on pull requests rather than being buried in the job log, or never
+    /// with `false`
+    ///
+    /// By default they are when the `GITHUB_ACTIONS` environment variable is `true`, as it is in
+    /// GitHub Actions jobs. Annotations point at the call site of events logged from the
+    /// repository, and are titled with their target. Other events are formatted as usual.
+    pub fn github_annotations(mut self, enabled: bool) -> Self {
+        self.github_annotations = Some(enabled);
+        self
+    }
+
+    /// Writes `WARN` and `ERROR` events as TeamCity service messages with `true`, so build
+    /// problems surface in the build overview, or never with `false`
+    ///
+    /// By default they are when the `TEAMCITY_VERSION` environment variable is set, as it is in
+    /// TeamCity builds. The messages hold the target, call site and message of events, with the
+    /// `WARNING` or `ERROR` status. Other events are formatted as usual.
+    pub fn teamcity_messages(mut self, enabled: bool) -> Self {
+        self.teamcity_messages = Some(enabled);
+        self
+    }
+
+    /// Also writes the formatted output to the debugger with `OutputDebugStringW` on Windows, so
+    /// events show up in Visual Studio or DebugView when the process has no console
+    ///
+    /// The output is written without colors, and still goes to its usual destinations. This
+    /// does nothing on other platforms.
+    pub fn debugger_output(mut self) -> Self {
+        self.debugger_output = true;
+        self
+    }
+
+    /// Shows the OpenTelemetry trace and span IDs of the span each event happened in, shortened
+    /// and dimmed at the end of the line, and in full as `trace_id` and `span_id` in JSON
+    ///
+    /// `lookup` reads the IDs from the data the OpenTelemetry layer keeps in a span's
+    /// extensions, returning them as hex strings, and is tried on enclosing spans until it
+    /// finds them. With `tracing-opentelemetry`, that data is `OtelData`:
+    ///
+    /// ```ignore
+    /// use opentelemetry::trace::TraceContextExt;
+    /// use tracing_opentelemetry::OtelData;
+    ///
+    /// let _guard = Builder::new("my_app", Verbosity::from_args())
+    ///     .trace_ids(|extensions| {
+    ///         let otel = extensions.get::<OtelData>()?;
+    ///         let trace_id = otel.builder.trace_id.unwrap_or_else(|| {
+    ///             otel.parent_cx.span().span_context().trace_id()
+    ///         });
+    ///         Some((trace_id.to_string(), otel.builder.span_id?.to_string()))
+    ///     })
+    ///     .init_with_layer(tracing_opentelemetry::layer().with_tracer(tracer));
+    /// ```
+    pub fn trace_ids(
+        mut self,
+        lookup: impl Fn(&Extensions<'_>) -> Option<(String, String)> + Send + Sync + 'static,
+    ) -> Self {
+        self.trace_ids = Some(TraceIds::new(lookup));
+        self
+    }
+
+    /// Drops events rather than stalling the application when writing to stderr blocks for
+    /// longer than `threshold`, such as when it is piped to a consumer that stopped reading
+    ///
+    /// Events are then written from a separate thread. While the consumer is stalled and the
+    /// events waiting for it fill a queue, further events are dropped, and a line like
+    /// `warning: dropped 1200 debug events and 3 info events due to slow consumer` reports them
+    /// every few seconds.
+    pub fn drop_when_slow(mut self, threshold: Duration) -> Self {
+        self.slow_writer_threshold = Some(threshold);
+        self
+    }
+
+    /// Drops events as soon as the queue of events waiting to be written to stderr is full,
+    /// so that logging never holds up the application, like
+    /// [`drop_when_slow`](Self::drop_when_slow) without waiting for a write to block first
+    ///
+    /// The number of events dropped at each level is reported every few seconds.
+    pub fn drop_when_full(self) -> Self {
+        self.drop_when_slow(Duration::ZERO)
+    }
+
+    /// Buffers the output to stderr in memory, writing it out every `interval` and when 64 KiB
+    /// have piled up, so programs logging a lot don't pay for a write per event
+    ///
+    /// The buffer is written out when the [`LogGuard`] is dropped, and on Unix also when the
+    /// process exits without dropping it, such as with `std::process::exit`. Events may still be
+    /// lost if the process crashes.
+    pub fn buffer_output(mut self, interval: Duration) -> Self {
+        self.flush_interval = Some(interval);
+        self
+    }
+
+    /// Writes events of `level` to each of `destinations` instead of stderr
+    ///
+    /// Levels without a route keep going to stderr, and an empty list of destinations discards
+    /// the level. Files are opened when the subscriber is installed, with a warning for those
+    /// that can't be, and are written without colors. Routes take effect with the subscriber
+    /// installed by [`init`](Self::init), and are flushed on shutdown by the [`LogGuard`] of
+    /// [`init_with_guard`](Self::init_with_guard).
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Destination, Verbosity};
+    /// # use structopt::StructOpt;
+    /// # use tracing::Level;
+    /// let debug_log = Destination::File("debug.log".into());
+    /// let error_log = Destination::File("errors.log".into());
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .route(Level::TRACE, vec![debug_log.clone()])
+    ///     .route(Level::DEBUG, vec![debug_log])
+    ///     .route(Level::INFO, vec![Destination::Stdout])
+    ///     .route(Level::WARN, vec![Destination::Stderr, error_log.clone()])
+    ///     .route(Level::ERROR, vec![Destination::Stderr, error_log])
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn route(
+        mut self,
+        level: Level,
+        destinations: impl IntoIterator<Item = Destination>,
+    ) -> Self {
+        self.routes[levels::index(level)] = Some(destinations.into_iter().collect());
+        self
+    }
+
+    /// Writes the output to stdout instead of stderr, as `--log-stdout` does, for tools that keep
+    /// stderr for prompts and other messages to the user
+    ///
+    /// Levels routed to stderr with [`route`](Self::route) go to stdout instead. Diagnostics
+    /// about logging itself, and status lines, stay on stderr.
+    pub fn stdout(mut self) -> Self {
+        self.stdout = true;
+        self
+    }
+
+    /// Writes the formatted output with writers made by `make_writer` instead of to stderr, such
+    /// as a socket, a pipe or a buffer per connection or per test
+    ///
+    /// The writer takes the place of stderr in [`route`](Self::route)s too, for the subscriber
+    /// installed by [`init`](Self::init) and the one of [`layer`](Self::layer). Status lines and
+    /// diagnostics about logging itself stay on stderr, and colors are written as they would be
+    /// to stderr.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// use std::net::TcpStream;
+    ///
+    /// let socket = TcpStream::connect("127.0.0.1:5000").expect("log collector is down");
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .writer(move || socket.try_clone().expect("failed to clone the socket"))
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn writer<W>(mut self, make_writer: W) -> Self
+    where
+        W: MakeWriter + Send + Sync + 'static,
+        W::Writer: 'static,
+    {
+        self.writer = Some(Arc::new(BoxMakeWriter::new(make_writer)));
+        self
+    }
+
+    /// Writes colors to stdout and stderr only while each of them is a terminal, deciding for
+    /// the two streams separately
+    ///
+    /// By default the output is colored wherever it goes, which suits pagers such as `less -R`.
+    /// With this, redirecting one stream to a file leaves it plain while the other keeps its
+    /// colors on the terminal. Files are always written without colors.
+    pub fn color_per_stream(mut self) -> Self {
+        self.color_per_stream = true;
+        self
+    }
+
+    /// Writes warnings and errors to stderr and every other level to stdout, so shell users can
+    /// redirect the routine output while problems still show on the terminal
+    ///
+    /// A shorthand for [`route`](Self::route), which later calls can override per level. Whether
+    /// stdout is a terminal is checked apart from stderr, and the events redirected from it are
+    /// written without colors.
+    pub fn split_stdout(mut self) -> Self {
+        self.plain_piped_stdout = true;
+        self.route(Level::TRACE, vec![Destination::Stdout])
+            .route(Level::DEBUG, vec![Destination::Stdout])
+            .route(Level::INFO, vec![Destination::Stdout])
+            .route(Level::WARN, vec![Destination::Stderr])
+            .route(Level::ERROR, vec![Destination::Stderr])
+    }
+
+    /// Rotates the log files of `--log-file` and [`route`](Self::route)s once they grow past
+    /// `max_bytes`, keeping the last `keep` of them, so long-running processes don't fill their
+    /// disks
+    ///
+    /// The file being written is renamed with a `.1` suffix, the one with a `.1` suffix gets
+    /// `.2`, and so on, and the oldest is removed. With `keep` set to 0, the file is emptied
+    /// instead.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Destination, Verbosity};
+    /// # use structopt::StructOpt;
+    /// # use tracing::Level;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .route(Level::INFO, vec![Destination::File("/var/log/my_app.log".into())])
+    ///     .rotate_files(10 * 1024 * 1024, 5)
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn rotate_files(mut self, max_bytes: u64, keep: usize) -> Self {
+        self.rotation = Some(Rotation {
+            max_bytes,
+            keep,
+            #[cfg(feature = "gzip")]
+            compress: false,
+            max_age: None,
+            min_free_bytes: None,
+        });
+        self
+    }
+
+    /// Removes the files rotated by [`rotate_files`](Self::rotate_files) once they were last
+    /// written longer than `max_age` ago, on top of keeping at most the number given there
+    ///
+    /// Files are checked when logging starts and whenever a file is rotated.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// # use std::time::Duration;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .rotate_files(10 * 1024 * 1024, 20)
+    ///     .max_rotated_age(Duration::from_secs(7 * 24 * 3600))
+    ///     .min_free_disk(512 * 1024 * 1024)
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn max_rotated_age(mut self, max_age: Duration) -> Self {
+        self.max_rotated_age = Some(max_age);
+        self
+    }
+
+    /// Removes the oldest files rotated by [`rotate_files`](Self::rotate_files) while the disk
+    /// holding them has less than `bytes` free, on Unix
+    ///
+    /// Files are checked when logging starts and whenever a file is rotated. The file being
+    /// written is never removed, so this doesn't guarantee the space stays free.
+    pub fn min_free_disk(mut self, bytes: u64) -> Self {
+        self.min_free_disk = Some(bytes);
+        self
+    }
+
+    /// Compresses the files rotated by [`rotate_files`](Self::rotate_files) with gzip, in the
+    /// background, adding a `.gz` extension, with the `gzip` feature
+    ///
+    /// Does nothing unless files are rotated. Compression finishes before the [`LogGuard`]
+    /// returns from shutting down.
+    #[cfg(feature = "gzip")]
+    pub fn compress_rotated_files(mut self) -> Self {
+        self.compress_rotated = true;
+        self
+    }
+
+    /// Also writes the verbose rendering of each event shown, with its time, module and spans,
+    /// to `destination`, so the details are at hand without running again with `--verbose`
+    ///
+    /// The formatted output stays as concise as configured. The transcript gets the same events
+    /// after every filter, and is written without colors when `destination` is a file.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Destination, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .transcript(Destination::File("my_app.log".into()))
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn transcript(mut self, destination: Destination) -> Self {
+        self.transcript = Some(destination);
+        self
+    }
+
+    /// Also writes events tagged with `tag` to each of `destinations`, whatever their level and
+    /// the filters, such as to keep an audit log
+    ///
+    /// Tags are listed in the `tags` field of events, either as a comma-separated string or as
+    /// a list recorded with `Debug`. Tagged events are written with the same formatting as the
+    /// rest, and still go where their level is routed to if they pass the filters.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Destination, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .route_tag("audit", vec![Destination::File("audit.log".into())])
+    ///     .init();
+    ///
+    /// tracing::debug!(tags = "audit", user = "alice", "password changed");
+    /// tracing::info!(tags = ?["audit", "billing"], amount = 20, "refund issued");
+    /// # }
+    /// ```
+    pub fn route_tag(
+        mut self,
+        tag: impl Into<String>,
+        destinations: impl IntoIterator<Item = Destination>,
+    ) -> Self {
+        self.tag_routes
+            .push((tag.into(), destinations.into_iter().collect()));
+        self
+    }
+
+    /// Also sends the events shown to the syslog daemon at `address`, as RFC 5424 messages with
+    /// their fields and those of enclosing spans as structured data
+    ///
+    /// `address` is a socket path such as `/dev/log`, or a `host:port` to send UDP datagrams to,
+    /// and may be prefixed with `unix://` or `udp://`. Messages are sent with the `user`
+    /// facility, and the root module as the app name. The `--syslog` flag takes precedence.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .syslog("/dev/log")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn syslog(mut self, address: impl Into<String>) -> Self {
+        self.syslog = Some(address.into());
+        self
+    }
+
+    /// Also sends the events shown to Graylog at `address` as GELF messages, without an
+    /// external forwarder
+    ///
+    /// `address` is a `host:port` to send UDP datagrams to, chunking large messages, and may be
+    /// prefixed with `udp://`, or with `tcp://` to send over TCP instead. Datagrams are sent as
+    /// events happen, while messages sent over TCP are written in batches from the background
+    /// like [`loki`](Self::loki), dropped when Graylog can't keep up. The target, call site and
+    /// fields of the event and its spans go in additional fields. The `--gelf` flag takes
+    /// precedence.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .gelf("graylog.internal:12201")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn gelf(mut self, address: impl Into<String>) -> Self {
+        self.gelf = Some(address.into());
+        self
+    }
+
+    /// Also serves the events shown to log viewers connecting to `address`, so a terminal or
+    /// graphical viewer can attach to the running application and follow its events live
+    ///
+    /// `address` is the path of a Unix socket, which replaces any socket left there by an earlier
+    /// run, or a `host:port` to listen on over TCP when prefixed with `tcp://`. Each event is sent
+    /// to every viewer connected as a JSON object, as written by [`json`](Self::json), prefixed
+    /// with its length in bytes as a 4-byte big-endian integer. Events are dropped rather than
+    /// holding up the application when viewers can't keep up, and the other outputs are
+    /// unaffected. The `--log-stream` flag takes precedence.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .event_stream("/tmp/my_app.sock")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn event_stream(mut self, address: impl Into<String>) -> Self {
+        self.event_stream = Some(address.into());
+        self
+    }
+
+    /// Also pushes the events shown to Grafana Loki, without an agent such as Promtail
+    ///
+    /// Events are pushed in batches from a background thread, retrying while Loki is
+    /// unavailable. Events are dropped rather than slowing the application down when too many
+    /// are waiting, and those still waiting are pushed when logging shuts down.
+    pub fn loki(mut self, loki: Loki) -> Self {
+        self.loki = Some(loki);
+        self
+    }
+
+    /// Pushes the batches of [`loki`](Self::loki) and [`otlp`](Self::otlp) from tasks on the
+    /// Tokio runtime the subscriber is installed from, rather than from threads of their own,
+    /// with the `tokio` feature
+    ///
+    /// Requests are sent from the blocking pool of the runtime, and retries wait on its timers.
+    /// Shut logging down with [`LogGuard::shutdown_async`] to wait for the batches still being
+    /// pushed. Threads are used as usual when no runtime is running.
+    #[cfg(feature = "tokio")]
+    pub fn tokio_batching(mut self) -> Self {
+        self.tokio_batching = true;
+        self
+    }
+
+    /// Records the `INFO`, `WARN` and `ERROR` events shown as breadcrumbs, and reports `ERROR`
+    /// events to Sentry with their fields, spans and the breadcrumbs before them, with the
+    /// `sentry` feature
+    ///
+    /// The DSN is otherwise taken from the `SENTRY_DSN` environment variable, so [`init`] reports
+    /// errors as soon as it is set. Both `https://` DSNs and plain HTTP ones, such as that of a
+    /// Relay running alongside the application, are supported.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .sentry("https://public_key@o0.ingest.sentry.io/42")
+    ///     .init();
+    /// # }
+    /// ```
+    #[cfg(feature = "sentry")]
+    pub fn sentry(mut self, dsn: impl Into<String>) -> Self {
+        self.sentry_dsn = Some(dsn.into());
+        self
+    }
+
+    /// Also exports the events shown to an OpenTelemetry collector as log records, over OTLP/HTTP
+    /// with the JSON encoding, with the `otlp` feature
+    ///
+    /// Levels map to severity numbers, and the target, call site, fields and those of enclosing
+    /// spans to attributes. Records carry the trace and span IDs found with
+    /// [`trace_ids`](Self::trace_ids), linking them to traces. The `service.name` of the
+    /// resource is `OTEL_SERVICE_NAME`, or the name given to [`new`](Self::new).
+    ///
+    /// `url` is the collector's, such as `http://localhost:4318`, posted to at `/v1/logs` unless it
+    /// has a path. It is otherwise taken from the `OTEL_EXPORTER_OTLP_LOGS_ENDPOINT` or
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT` environment variables, so [`init`] exports events as soon as
+    /// one is set. Only plain HTTP is supported, and gRPC isn't. Events are exported in batches
+    /// from a background thread, as with [`loki`](Self::loki).
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .otlp("http://localhost:4318")
+    ///     .init();
+    /// # }
+    /// ```
+    #[cfg(feature = "otlp")]
+    pub fn otlp(mut self, url: impl Into<String>) -> Self {
+        self.otlp_url = Some(url.into());
+        self
+    }
+
+    /// Gives `output` a filter of its own, so it gets the events that pass `filter` whether or
+    /// not they are shown, such as to keep debug events in the log file and only send warnings
+    /// to Loki while the console shows info events
+    ///
+    /// `filter` holds comma-separated `target=level` directives and a default level, such as
+    /// `warn,my_app=info`, like the filter of [`tee_json`](Self::tee_json). The filters of the
+    /// formatted output, from `--verbose`, `--quiet` and `--log`, then don't apply to the output,
+    /// and nor do the field and message filters. Outputs without a filter of their own keep
+    /// getting the events shown. Takes effect with the subscriber installed by
+    /// [`init`](Self::init).
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Loki, Output, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .loki(Loki::new("http://loki:3100"))
+    ///     .output_filter(Output::Loki, "warn")
+    ///     .output_filter(Output::LogFile, "debug")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn output_filter(mut self, output: Output, filter: impl Into<String>) -> Self {
+        self.output_filters.insert(output, filter.into());
+        self
+    }
+
+    /// Also writes events as JSON to `destination`, keeping those that pass `filter` whether or
+    /// not they are shown, such as to ship complete logs while the console stays readable
+    ///
+    /// `filter` holds comma-separated `target=level` directives and a default level, such as
+    /// `info,my_app=debug`, but not the span and field directives `--log` accepts. The
+    /// filters of the formatted output, from `--verbose`, `--quiet` and `--log`, then only
+    /// apply to it and to the layer given to [`init_with_layer`](Self::init_with_layer), as do
+    /// the field and message filters. Can be called several times to write to several
+    /// destinations. Takes effect with the subscriber installed by [`init`](Self::init).
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Destination, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .tee_json(Destination::File("my_app.jsonl".into()), "info,my_app=debug")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn tee_json(mut self, destination: Destination, filter: impl Into<String>) -> Self {
+        self.json_tees.push((destination, filter.into()));
+        self
+    }
+
+    /// Also writes events as rows of comma- or tab-separated values to `destination`, keeping
+    /// those that pass `filter`, such as to load the events of a test run into a spreadsheet or
+    /// a data frame
+    ///
+    /// The columns are `timestamp`, `level`, `target`, `file`, `line`, `spans`, holding the
+    /// names of the event's spans from the root separated by `:`, `message`, and `fields`,
+    /// holding the other fields as a JSON object, with redaction applied. A header row naming
+    /// them comes first, unless appending to a file that isn't empty. `filter` is as for
+    /// [`tee_json`](Self::tee_json).
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Delimited, Destination, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .tee_delimited(Destination::File("events.csv".into()), Delimited::Csv, "debug")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn tee_delimited(
+        mut self,
+        destination: Destination,
+        format: Delimited,
+        filter: impl Into<String>,
+    ) -> Self {
+        self.delimited_tees
+            .push((destination, format, filter.into()));
+        self
+    }
+
+    /// Also appends the events that pass `filter` to a tamper-evident audit log at `path`, with
+    /// the `audit` feature
+    ///
+    /// Each line holds a JSON record, as written by [`tee_json`](Self::tee_json), after a
+    /// SHA-256 hash of the record chained to the hash of the line before it, so that auditors
+    /// can check with [`verify_audit_log`] that no line was changed, removed or inserted. The
+    /// chain carries on across runs. `filter` is as for `tee_json`, such as `audit=info` to keep
+    /// the events logged with the `audit` target.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .audit_log("/var/log/my_app/audit.log", "audit=info")
+    ///     .audit_key(std::env::var("AUDIT_KEY").expect("AUDIT_KEY is not set"))
+    ///     .init();
+    ///
+    /// tracing::info!(target: "audit", user = "alice", "granted admin rights");
+    /// # }
+    /// ```
+    #[cfg(feature = "audit")]
+    pub fn audit_log(mut self, path: impl Into<PathBuf>, filter: impl Into<String>) -> Self {
+        self.audit_logs.push((path.into(), filter.into()));
+        self
+    }
+
+    /// Also appends the events that pass `filter`, and the spans they are logged in, to a compact
+    /// binary log at `path`, for volumes of events that writing them as text can't keep up with
+    ///
+    /// Nothing is formatted but the values only known by their `Debug` rendering, such as
+    /// messages. The level, target, location and field names of each callsite are written once
+    /// per run, so an event takes little more than its field values, and records are written
+    /// through a buffer, which is flushed on shutdown and when an error is logged. Values of
+    /// [`redact`](Self::redact)ed fields are redacted, and each run is appended to the log.
+    /// `filter` is as for [`tee_json`](Self::tee_json).
+    ///
+    /// The records are read back with [`decode_binary_log`], or shown the way the formatted
+    /// output would have shown them with [`replay_binary_log`].
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .binary_log("/var/log/my_app/events.bin", "my_app=trace")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn binary_log(mut self, path: impl Into<PathBuf>, filter: impl Into<String>) -> Self {
+        self.binary_logs.push((path.into(), filter.into()));
+        self
+    }
+
+    /// Chains the lines of [`audit_log`](Self::audit_log)s with an HMAC-SHA256 keyed with `key`
+    /// instead of a plain hash, so that only holders of the key can write lines that verify, with
+    /// the `audit` feature
+    #[cfg(feature = "audit")]
+    pub fn audit_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.audit_key = Some(key.into());
+        self
+    }
+
+    /// Logs one event when the subscriber is installed, naming the binary and its version, the
+    /// git commit it was built from, the filter in effect and the command line arguments, so
+    /// that every log file tells what produced it
+    ///
+    /// The event has the `pretty_tracing_subscriber::startup` target and passes whatever the
+    /// filters, unless logging is [`silent`](Self::silent). [`build_info!`] fills in the
+    /// package of the application, and the git commit recorded by [`emit_git_sha`] in its build
+    /// script.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .startup_banner(pretty_tracing_subscriber::build_info!())
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn startup_banner(mut self, info: BuildInfo) -> Self {
+        self.banner = Some(info);
+        self
+    }
+
+    /// Also writes the spans and events that pass `filter` to a standalone HTML page at `path`
+    /// when logging shuts down, such as to attach a readable report of a run to the artifacts
+    /// of a CI job or to a bug report
+    ///
+    /// Spans are collapsible sections holding their events and the spans entered in them, with
+    /// how long they lived, and those holding warnings or errors start open. Levels are colored
+    /// as in the formatted output, and can be hidden with the checkboxes at the top of the page.
+    /// `filter` is as for [`tee_json`](Self::tee_json), such as `debug`. The page replaces any
+    /// report of an earlier run, and keeps the first 100,000 spans and events of the run.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// Builder::new("my_app", Verbosity::from_args())
+    ///     .html_report("target/my_app.html", "info,my_app=debug")
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn html_report(mut self, path: impl Into<PathBuf>, filter: impl Into<String>) -> Self {
+        self.html_report = Some((path.into(), filter.into()));
+        self
+    }
+
+    /// Explains why events are hidden, for `window` after the subscriber is installed, to help
+    /// find out why an expected message never appears
+    ///
+    /// The first time an event or span from a call site is filtered out, a line names the
+    /// filter responsible, such as
+    /// ``filter: hid DEBUG event at src/db.rs:42 in my_app::db: disabled by the verbosity level `info` ``.
+    /// At most 100 call sites are explained. Events below the verbosity level then cost
+    /// slightly more to skip for the rest of the run.
+    pub fn explain_filters(mut self, window: Duration) -> Self {
+        self.explain_window = Some(window);
+        self
+    }
+
+    /// Lets the filters be changed while the program runs by typing commands followed by Enter
+    /// in its terminal, such as to turn a long-running development server up to `TRACE` for a
+    /// moment without restarting it
+    ///
+    /// `+` shows one more level, `++` two, and `-` one less. A level, such as `debug`, or
+    /// directives, such as `my_app::db=trace`, replace the filters of the command line, and
+    /// `reset` restores them. `verbose` and `compact` switch the format as
+    /// [`LogGuard::set_verbose_format`] does. `help` lists the commands. Changes to the filters
+    /// are recorded like those of [`LogGuard::reload_file_settings`], with `changed_by` set to
+    /// `stdin`.
+    ///
+    /// Commands are read from stdin only when it is a terminal, so this is for programs that
+    /// don't read stdin themselves.
+    pub fn interactive_verbosity(mut self) -> Self {
+        self.interactive = true;
+        self
+    }
+
+    /// Measures the time spent filtering, formatting and writing events, see [`Overhead`]
+    pub fn measure_overhead(mut self) -> Self {
+        self.measure_overhead = true;
+        self
+    }
+
+    /// Throws the formatted output away instead of writing it, after formatting events or
+    /// instead of it, to benchmark the overhead of instrumentation without the cost of writing
+    /// to a terminal
+    ///
+    /// Events are filtered as usual either way, and still go to log collectors and tees. The
+    /// time spent at each stage can be measured with [`measure_overhead`](Self::measure_overhead).
+    pub fn discard_output(mut self, discard: Discard) -> Self {
+        self.discard = Some(discard);
+        self
+    }
+
+    /// Installs the configured subscriber as the global default
+    ///
+    /// Logging is never shut down, so the events still waiting to be written to files and log
+    /// collectors when the process exits are lost, see [`init_with_guard`](Self::init_with_guard).
+    pub fn init(self) {
+        // Keeps the sinks alive for the panic hook to drain
+        mem::forget(self.init_with_guard());
+    }
+
+    /// Installs the configured subscriber as the global default like [`init`](Self::init),
+    /// shutting logging down when the returned guard is dropped, so keep it alive until the end
+    /// of `main`
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// let guard = Builder::new("my_app", Verbosity::from_args())
+    ///     .closed_marker()
+    ///     .init_with_guard();
+    /// // ...
+    /// drop(guard);
+    /// # }
+    /// ```
+    pub fn init_with_guard(self) -> LogGuard {
+        self.init_with_layer(Identity::new())
+    }
+
+    /// Installs the configured subscriber as the global default like
+    /// [`init_with_guard`](Self::init_with_guard), with `layer` added to it, such as one
+    /// exporting spans to OpenTelemetry or Sentry
+    ///
+    /// The layer sees the events and spans that pass the level and target filters, including
+    /// those hidden by the field and message filters. Several layers can be combined with
+    /// [`Layer::and_then`].
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// # fn metrics_layer() -> tracing_subscriber::layer::Identity {
+    /// #     tracing_subscriber::layer::Identity::new()
+    /// # }
+    /// let _guard = Builder::new("my_app", Verbosity::from_args())
+    ///     .init_with_layer(metrics_layer());
+    /// # }
+    /// ```
+    pub fn init_with_layer<L>(self, layer: L) -> LogGuard
+    where
+        L: Layer<Registry> + Send + Sync + 'static,
+    {
+        let panic_record = self.panic_record;
+        let panic_hook = self.panic_hook;
+        let closed_marker = self.closed_marker;
+        if self.measure_overhead {
+            overhead::enable();
+        }
+        if self.verbosity.print_config {
+            eprintln!("{}", self.log_config());
+        }
+        if self.verbosity.print_filter {
+            println!("{}", self.log_config().resolve());
+            std::process::exit(0);
+        }
+        let startup = match &self.banner {
+            Some(info) if !self.verbosity.silent => {
+                Some((info.clone(), self.log_config().resolve().to_string()))
+            }
+            _ => None,
+        };
+        let verbosity = &self.verbosity;
+        let requested = (verbosity.verbose != 0).then(|| {
+            self.verbosity_mapping
+                .level_filter(verbosity.verbose, verbosity.quiet)
+        });
+        let fail_on_errors = self.fail_on_errors;
+        let summary = self.summary_at_exit;
+        let tally = Tally::new(self.count_targets);
+        let mut writer = match self.slow_writer_threshold {
+            Some(threshold) => status::Stderr::lossy(threshold),
+            None => status::Stderr::new(),
+        };
+        if let Some(interval) = self.flush_interval {
+            writer = writer.buffered(interval);
+        }
+        let mut outputs = self.outputs(&writer);
+        let files = mem::take(&mut outputs.files);
+        let unopened = mem::take(&mut outputs.errors);
+        let repeats = outputs.repeats.clone();
+        let storms = outputs.storms.clone();
+        let histograms = outputs.histograms.clone();
+        let crash = outputs.crash.clone();
+        let span_summary = self.span_summary;
+        let interactive = self.interactive;
+        let bad_format = self
+            .env_format()
+            .and_then(Result::err)
+            .map(|error| format!("{}_FORMAT: {}", self.env_var, error));
+        let bad_colors = LevelColors::from_env()
+            .and_then(Result::err)
+            .map(|error| format!("{}: {}", levels::COLORS_ENV, error));
+        let (subscriber, unknown_presets, filters) =
+            self.build(layer, true, outputs, Some(tally.counter()));
+        subscriber.init();
+        if let Some((info, filter)) = startup {
+            banner::log(&info, &filter);
+        }
+
+        if panic_hook {
+            panic::install_panic_hook();
+        } else if panic_record {
+            panic::install_panic_record();
+        }
+        // Installed last, so the report is written before the panic is shown
+        if let Some(reporter) = &crash {
+            reporter.install_hook();
+        }
+
+        for name in unknown_presets {
+            tracing::warn!("unknown log preset `{}`", name);
+        }
+        for error in unopened {
+            tracing::warn!("{}", error);
+        }
+        for error in bad_format.into_iter().chain(bad_colors) {
+            tracing::warn!("{}", error);
+        }
+        // Events compiled out can't be shown, however many `-v` are given
+        if let Some(requested) = requested.filter(|&level| level > STATIC_MAX_LEVEL) {
+            tracing::warn!(
+                "the verbosity flags ask for {} events, but the most verbose level compiled in is \
+                 {}, see the `max_level_*` features",
+                requested,
+                STATIC_MAX_LEVEL
+            );
+        }
+
+        // Repeats are reported before the output is drained
+        let mut sinks: Vec<Arc<dyn Sink>> = Vec::new();
+        sinks.extend(storms.map(|storms| storms as Arc<dyn Sink>));
+        sinks.extend(repeats.map(|repeats| repeats as Arc<dyn Sink>));
+        if let (Some(histograms), Some(interval)) = (&histograms, span_summary) {
+            histograms.report_every(interval);
+            sinks.push(histograms.clone());
+        }
+        sinks.push(Arc::new(writer));
+        sinks.extend(files);
+        let filters = Arc::new(filters);
+        if interactive {
+            interactive::start(filters.clone());
+        }
+        LogGuard::new(
+            sinks,
+            closed_marker,
+            tally,
+            fail_on_errors,
+            summary,
+            histograms,
+            filters,
+        )
+        .with_crash_reporter(crash)
+    }
+
+    /// Installs the configured subscriber as the default of the current thread, writing the
+    /// formatted output to memory instead of stderr, until the returned handle is dropped
+    ///
+    /// Tests can then check which events their code logs with
+    /// [`Capture::assert_logged`], or look them up by level and target. Routes, files and log
+    /// collectors are left out, while the filters apply as usual. Events logged on other
+    /// threads aren't captured, so tests running in parallel don't see each other's events.
+    pub fn capture(self) -> Capture {
+        let memory = Arc::new(capture::Memory::default());
+        let mut outputs = Outputs::stderr(status::Stderr::new());
+        outputs.console = Router::to_memory(memory.clone());
+        let (subscriber, _, _) = self.build(Identity::new(), true, outputs, None);
+        Capture::new(memory, tracing::subscriber::set_default(subscriber))
+    }
+
+    /// Installs the configured subscriber as the global default the first time it is called in
+    /// the process, doing nothing afterwards, so every test can call it
+    ///
+    /// The formatted output is written the way `eprintln!` does, so `cargo test` only shows the
+    /// output of the tests that fail, unless given `--nocapture`. Routes, files and log
+    /// collectors are left out. Nothing is installed if another global default was set already.
+    ///
+    /// ```ignore
+    /// #[test]
+    /// fn parses_the_config() {
+    ///     pretty_tracing_subscriber::init_for_tests();
+    ///     // ...
+    /// }
+    /// ```
+    pub fn init_for_tests(self) {
+        static INSTALLED: Once = Once::new();
+        INSTALLED.call_once(|| {
+            let mut outputs = Outputs::stderr(status::Stderr::new());
+            outputs.console = Router::printing();
+            let (subscriber, _, _) = self.build(Identity::new(), true, outputs, None);
+            let _ = subscriber.try_init();
+        });
+    }
+
+    /// Writes a set of sample events and spans with the configured formatting, without
+    /// installing the subscriber
+    ///
+    /// The samples cover every level, nested spans, long fields and errors, so applications can
+    /// offer a `--log-preview` option that shows the effect of formatting options and presets.
+    /// The level filters are not applied, but field and message filters are.
+    pub fn preview(self) {
+        let (subscriber, unknown_presets, _) = self.build(
+            Identity::new(),
+            false,
+            Outputs::stderr(status::Stderr::new()),
+            None,
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            for name in unknown_presets {
+                tracing::warn!("unknown log preset `{}`", name);
+            }
+            preview::samples();
+        });
+    }
+
+    /// Builds the event formatter alone, to plug into a `fmt::Layer` of your own
+    ///
+    /// This is for applications that compose their own subscriber, such as alongside an
+    /// OpenTelemetry layer. The filters configured here are not part of the formatter. Presets
+    /// and slow span detection rely on span data recorded by this crate's own layers, so they
+    /// only take effect with the subscriber installed by [`init`](Self::init).
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// use tracing_subscriber::fmt::format::FmtSpan;
+    /// use tracing_subscriber::prelude::*;
+    ///
+    /// let formatter = Builder::new("my_app", Verbosity::from_args()).formatter();
+    /// tracing_subscriber::registry()
+    ///     .with(
+    ///         tracing_subscriber::fmt::layer()
+    ///             .with_span_events(FmtSpan::CLOSE)
+    ///             .event_format(formatter),
+    ///     )
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn formatter(&self) -> EventFormatter {
+        self.configured_formatter().0
+    }
+
+    /// Returns a layer that formats and writes events as configured, without installing a
+    /// subscriber, for applications that manage their own `Registry`
+    ///
+    /// The layer includes the span events, the writer, and the field, message and error
+    /// deduplication filters, along with the span data that presets and slow span detection
+    /// rely on. Level and target filtering from `--verbose`, `--quiet` and `--log` is left to
+    /// the application, as filters apply to every layer of a subscriber, as are the options that
+    /// need a [`LogGuard`]. Unknown preset names are ignored.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// use tracing_subscriber::filter::LevelFilter;
+    /// use tracing_subscriber::prelude::*;
+    ///
+    /// let pretty = Builder::new("my_app", Verbosity::from_args()).layer();
+    /// tracing_subscriber::registry()
+    ///     .with(pretty)
+    ///     .with(LevelFilter::INFO)
+    ///     .init();
+    /// # }
+    /// ```
+    pub fn layer<S>(self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        self.layers(Outputs::stderr(status::Stderr::new()), None).0
+    }
+
+    /// The format read from the log environment variable suffixed with `_FORMAT`, if it is set
+    fn env_format(&self) -> Option<Result<LogFormat, ParseLogFormatError>> {
+        let format = std::env::var(format!("{}_FORMAT", self.env_var)).ok()?;
+        Some(format.parse())
+    }
+
+    /// The format in effect, from the command line, the environment or the code, in that order
+    /// of precedence
+    fn effective_format(&self) -> LogFormat {
+        let env = || self.env_format().and_then(Result::ok);
+        self.verbosity
+            .log_format
+            .or_else(env)
+            .unwrap_or(self.format)
+    }
+
+    /// Builds the event formatter, returning it with the names of enabled presets that aren't
+    /// registered
+    fn configured_formatter(&self) -> (EventFormatter, Vec<String>) {
+        let format = self.effective_format();
+        let verbose_format =
+            format != LogFormat::Compact && (self.verbose_format || self.verbosity.verbose != 0);
+        let mut roots = self.roots.iter().cloned();
+        let mut formatter = EventFormatter::new(roots.next().unwrap_or_default(), verbose_format);
+        formatter.roots.extend(roots);
+        formatter.slow_span_threshold = self.slow_span_threshold;
+        formatter.slow_span_level = self.slow_span_level;
+        formatter.only_slow_spans = self.only_slow_spans;
+        formatter.tree = self.tree;
+        formatter.multiline_fields = self.multiline_fields;
+        if self.aligned {
+            formatter.context_column = Some(AtomicUsize::new(0));
+        }
+        let labels = self.level_style.labels();
+        formatter.level_context = self.level_context;
+        self.level_colors.apply(&mut formatter.level_colors);
+        if let Some(Ok(colors)) = LevelColors::from_env() {
+            colors.apply(&mut formatter.level_colors);
+        }
+        let custom = &self.level_labels;
+        formatter.level_labels = [0, 1, 2, 3, 4].map(|i| match &custom[i] {
+            Some(label) => label.clone(),
+            None => labels[i].to_owned(),
+        });
+        formatter.json = matches!(format, LogFormat::Json | LogFormat::Bunyan);
+        formatter.bunyan = format == LogFormat::Bunyan;
+        formatter.logfmt = format == LogFormat::Logfmt;
+        formatter.highlights = self.highlights.clone();
+        formatter
+            .highlights
+            .extend(self.verbosity.highlights.iter().cloned());
+        formatter.json_span_fields = self.json_span_fields;
+        formatter.github_annotations = self.github_annotations.unwrap_or_else(github::detected);
+        formatter.teamcity_messages = self.teamcity_messages.unwrap_or_else(teamcity::detected);
+        formatter.time_format = self.time_format.clone();
+        formatter.event_deltas = self.event_deltas;
+        formatter.span_elapsed = self.span_elapsed;
+        formatter.color_targets = self.color_targets;
+        formatter.prefer_target = self.prefer_target;
+        formatter.editor_locations = self.editor_locations;
+        formatter.span_ids = self.span_ids;
+        formatter.correlation_fields = self.correlation_fields.clone();
+        formatter.layout = self.layout.clone();
+        formatter.dim_fields = self.dim_fields;
+        formatter.trace_ids = self.trace_ids.clone();
+        formatter.error_backtraces = self.error_backtraces;
+        formatter.max_span_depth = self.max_span_depth;
+        formatter.grouped_spans = self.grouped_spans.clone();
+        formatter.span_headers = self.span_headers;
+        formatter.pad_levels = self.aligned || self.pad_levels;
+        if self.wrap {
+            formatter.wrap_width = Width::detect(self.default_width);
+        }
+        formatter.continuation = self.continuation;
+        if self.tree {
+            formatter.terminal_width = Width::detect(self.default_width);
+        }
+        formatter.fields = self.fields.clone();
+        formatter.close_templates = self.close_templates.clone();
+        if self.path_style != PathStyle::FileName {
+            formatter.source_roots = SourceRoots::from_env();
+        }
+        formatter.path_style = self.path_style.clone();
+        formatter.hyperlinks = self
+            .hyperlinks
+            .clone()
+            .filter(|_| Hyperlinks::supported())
+            .map(|template| Hyperlinks::new(template, self.path_prefixes.clone()));
+        formatter.path_prefixes = self.path_prefixes.clone();
+        let mut unknown_presets = Vec::new();
+        let enabled = self.enabled_presets.iter().chain(&self.verbosity.presets);
+        for name in enabled {
+            match self.presets.iter().find(|preset| preset.name() == name) {
+                Some(preset) if !formatter.presets.iter().any(|p| Arc::ptr_eq(p, preset)) => {
+                    formatter.presets.push(preset.clone())
+                }
+                Some(_) => {}
+                None if self.filter_preset_settings(name).is_some() => {}
+                None => unknown_presets.push(name.clone()),
+            }
+        }
+        (formatter, unknown_presets)
+    }
+
+    /// Assembles the subscriber, returning it with the names of enabled presets that aren't
+    /// registered, and the handle to reload its filters
+    fn build<L>(
+        self,
+        extra: L,
+        filtered: bool,
+        mut outputs: Outputs,
+        counter: Option<Counter>,
+    ) -> (impl Subscriber + Send + Sync, Vec<String>, FilterHandle)
+    where
+        L: Layer<Registry> + Send + Sync + 'static,
+    {
+        let explainer = self
+            .explain_window
+            .map(|window| Arc::new(Explainer::new(window, outputs.console.stderr().clone())));
+        if let Some(locale) = self.time_format.locale() {
+            locale.apply_to_durations();
+        }
+
+        let config = self.log_config();
+        let settings = config.resolve();
+        let (level_filter, env_filter) = reload::filters(filtered.then_some(&settings), &explainer);
+        let handle = FilterHandle {
+            config: Mutex::new(config),
+            level: Reloadable::new(level_filter),
+            directives: Reloadable::new(env_filter),
+            explainer: explainer.clone(),
+            verbose_format: outputs.verbose_format.clone(),
+        };
+        let global = outputs.tees.is_empty()
+            && outputs.tagged.is_empty()
+            && outputs.html.is_none()
+            && outputs.binary_logs.is_empty()
+            && outputs.filters.is_empty()
+            && self.recorder_capacity.is_none();
+        let filters = SharedFilter::new(
+            handle.level.clone(),
+            handle.directives.clone(),
+            global,
+            self.boost_rules(),
+        );
+        let tees = self.tee_layers(
+            mem::take(&mut outputs.tees),
+            mem::take(&mut outputs.tagged),
+            outputs.html.take(),
+            mem::take(&mut outputs.binary_logs),
+        );
+        let filtered_outputs = self.filtered_outputs(&mut outputs);
+        let recorder = self
+            .recorder_capacity
+            .map(|capacity| Recorder::new(capacity, outputs.console.stderr().clone()));
+        let recording = recorder.clone().map(|recorder| {
+            let (formatter, _) = self.configured_formatter();
+            FieldLayer {
+                redaction: self.fields.redaction.clone(),
+            }
+            .and_then(
+                tracing_subscriber::fmt::layer()
+                    .fmt_fields(self.fields.clone())
+                    .with_writer(RecorderWriter(recorder))
+                    .event_format(formatter),
+            )
+            .with_filter(Hidden(filters.clone()))
+        });
+        let volume = self.metrics.then(VolumeCounter::new).flatten();
+        let histograms = outputs.histograms.clone().map(HistogramLayer);
+        let (layer, unknown_presets) = self.layers(outputs, explainer);
+        let formatted = extra
+            .and_then(recorder.map(DumpOnError))
+            .and_then(layer)
+            .and_then(histograms)
+            .and_then(counter)
+            .and_then(volume)
+            .with_filter(filters.clone());
+        let subscriber = tracing_subscriber::registry()
+            .with(formatted)
+            .with(tees)
+            .with(filtered_outputs)
+            .with(recording)
+            .with(filters)
+            .with(health::Reporter);
+        (subscriber, unknown_presets, handle)
+    }
+
+    /// Opens `--log-file` and the destinations of [`route`](Self::route),
+    /// [`transcript`](Self::transcript), [`tee_json`](Self::tee_json), [`syslog`](Self::syslog),
+    /// [`gelf`](Self::gelf) and [`loki`](Self::loki), and connects to Sentry and OpenTelemetry
+    fn outputs(&self, stderr: &status::Stderr) -> Outputs {
+        let rotation = self.rotation.map(|rotation| Rotation {
+            #[cfg(feature = "gzip")]
+            compress: self.compress_rotated,
+            max_age: self.max_rotated_age,
+            min_free_bytes: self.min_free_disk,
+            ..rotation
+        });
+        let structured = self.effective_format().structured();
+        let mut routes = self.routes.clone();
+        if self.stdout || self.verbosity.log_stdout {
+            for route in &mut routes {
+                let destinations = route.get_or_insert_with(|| vec![Destination::Stderr]);
+                for destination in destinations {
+                    if *destination == Destination::Stderr {
+                        *destination = Destination::Stdout;
+                    }
+                }
+            }
+        }
+        let mut filters = HashMap::new();
+        let mut filter_errors = Vec::new();
+        for (&output, filter) in &self.output_filters {
+            match filter.parse::<Targets>() {
+                Ok(parsed) => {
+                    filters.insert(output, parsed);
+                }
+                Err(e) => {
+                    filter_errors.push(format!("invalid filter `{}` for {}: {}", filter, output, e))
+                }
+            }
+        }
+        let mut log_file_error = None;
+        // A log file with a filter of its own is written by a layer of its own
+        let mut log_file = None;
+        if let Some(path) = &self.verbosity.log_file {
+            let path = match path.as_str() {
+                "auto" => route::default_log_file(&self.roots[0])
+                    .map_err(|e| format!("cannot set up the default log file: {}", e)),
+                path => Ok(PathBuf::from(path)),
+            };
+            match path {
+                Ok(path) if filters.contains_key(&Output::LogFile) => log_file = Some(path),
+                Ok(path) => {
+                    for route in &mut routes {
+                        route
+                            .get_or_insert_with(|| vec![Destination::Stderr])
+                            .push(Destination::File(path.clone()));
+                    }
+                }
+                Err(e) => log_file_error = Some(e),
+            }
+        }
+        let (console, mut files, mut errors) = match self.discard {
+            Some(_) => (Router::discarding(), Vec::new(), Vec::new()),
+            None => Router::with_routes(stderr.clone(), &routes, !structured, rotation),
+        };
+        errors.extend(log_file_error);
+        errors.extend(filter_errors);
+        let mut outputs = Outputs::stderr(stderr.clone());
+        outputs.console = console;
+        outputs.repeats = self.collapse_repeats.then(Arc::default);
+        outputs.storms = self
+            .error_storm_window
+            .map(|window| Arc::new(StormFilter::new(window)));
+        outputs.histograms = self.span_histograms.then(Arc::default);
+        last_gasp::use_colors(status::tty());
+        if self.color_per_stream {
+            outputs.console.strip_colors_when_piped(true);
+        } else if self.plain_piped_stdout {
+            outputs.console.strip_colors_when_piped(false);
+        }
+        if self.debugger_output {
+            outputs.console.mirror_to_debugger();
+        }
+        if let Some(path) = log_file {
+            let (writer, sinks, unopened) = Router::to(
+                stderr.clone(),
+                &[Destination::File(path)],
+                !structured,
+                rotation,
+            );
+            if unopened.is_empty() {
+                outputs.log_file = Some(writer);
+                files.extend(sinks);
+            } else {
+                errors.extend(unopened);
+            }
+        }
+        if let Some(destination) = &self.transcript {
+            let (writer, sinks, unopened) = Router::to(
+                stderr.clone(),
+                std::slice::from_ref(destination),
+                true,
+                rotation,
+            );
+            if unopened.is_empty() {
+                outputs.transcript = Some(writer);
+                files.extend(sinks);
+            } else {
+                errors.extend(unopened);
+            }
+        }
+        for (destination, filter) in &self.json_tees {
+            let filter: Targets = match filter.parse() {
+                Ok(filter) => filter,
+                Err(e) => {
+                    errors.push(format!(
+                        "invalid filter `{}` for JSON output: {}",
+                        filter, e
+                    ));
+                    continue;
+                }
+            };
+            let (writer, sinks, unopened) = Router::to(
+                stderr.clone(),
+                std::slice::from_ref(destination),
+                false,
+                rotation,
+            );
+            if unopened.is_empty() {
+                outputs.tees.push(JsonTee {
+                    writer,
+                    filter,
+                    delimited: None,
+                });
+                files.extend(sinks);
+            } else {
+                errors.extend(unopened);
+            }
+        }
+        for (destination, format, filter) in &self.delimited_tees {
+            let filter: Targets = match filter.parse() {
+                Ok(filter) => filter,
+                Err(e) => {
+                    errors.push(format!(
+                        "invalid filter `{}` for delimited output: {}",
+                        filter, e
+                    ));
+                    continue;
+                }
+            };
+            // Files appended to have their header row already
+            let header = match destination {
+                Destination::File(path) => fs::metadata(path).map_or(true, |file| file.len() == 0),
+                _ => true,
+            };
+            let (writer, sinks, unopened) = Router::to(
+                stderr.clone(),
+                std::slice::from_ref(destination),
+                false,
+                rotation,
+            );
+            if unopened.is_empty() {
+                let precision = self
+                    .time_format
+                    .precision()
+                    .unwrap_or(TimePrecision::Micros);
+                let formatter = DelimitedFormatter::new(
+                    *format,
+                    self.fields.redaction.clone(),
+                    precision,
+                    header,
+                );
+                outputs.tees.push(JsonTee {
+                    writer,
+                    filter,
+                    delimited: Some(formatter),
+                });
+                files.extend(sinks);
+            } else {
+                errors.extend(unopened);
+            }
+        }
+        for (path, filter) in &self.audit_logs {
+            let filter: Targets = match filter.parse() {
+                Ok(filter) => filter,
+                Err(e) => {
+                    errors.push(format!(
+                        "invalid filter `{}` for the audit log: {}",
+                        filter, e
+                    ));
+                    continue;
+                }
+            };
+            match audit::open(path, self.audit_key.clone(), stderr) {
+                Ok((writer, sink)) => {
+                    outputs.tees.push(JsonTee {
+                        writer,
+                        filter,
+                        delimited: None,
+                    });
+                    files.push(sink);
+                }
+                Err(e) => errors.push(format!("cannot open audit log {}: {}", path.display(), e)),
+            }
+        }
+        for (path, filter) in &self.binary_logs {
+            let filter: Targets = match filter.parse() {
+                Ok(filter) => filter,
+                Err(e) => {
+                    errors.push(format!(
+                        "invalid filter `{}` for the binary log: {}",
+                        filter, e
+                    ));
+                    continue;
+                }
+            };
+            match BinaryLog::open(path, self.fields.redaction.clone()) {
+                Ok(log) => {
+                    files.push(log.sink());
+                    outputs.binary_logs.push((log, filter));
+                }
+                Err(e) => errors.push(format!("cannot open binary log {}: {}", path.display(), e)),
+            }
+        }
+        if let Some((path, filter)) = &self.html_report {
+            match filter.parse::<Targets>() {
+                Ok(filter) => {
+                    let report = HtmlReport::new(
+                        path.clone(),
+                        self.roots[0].clone(),
+                        self.fields.redaction.clone(),
+                    );
+                    files.push(report.sink());
+                    outputs.html = Some((report, filter));
+                }
+                Err(e) => errors.push(format!(
+                    "invalid filter `{}` for the HTML report: {}",
+                    filter, e
+                )),
+            }
+        }
+        for (tag, destinations) in &self.tag_routes {
+            let (writer, sinks, unopened) =
+                Router::to(stderr.clone(), destinations, !structured, rotation);
+            if unopened.is_empty() {
+                outputs.tagged.push(TagRoute {
+                    tag: tag.clone(),
+                    writer,
+                });
+                files.extend(sinks);
+            } else {
+                errors.extend(unopened);
+            }
+        }
+        outputs.journald = Journald::connect(&self.roots[0], self.fields.redaction.clone());
+        outputs.logcat = Logcat::connect(self.fields.redaction.clone());
+        if let Some(address) = self.verbosity.syslog.as_ref().or(self.syslog.as_ref()) {
+            match Syslog::connect(address, &self.roots[0], self.fields.redaction.clone()) {
+                Ok(syslog) => outputs.syslog = Some(syslog),
+                Err(e) => errors.push(format!("cannot connect to syslog at {}: {}", address, e)),
+            }
+        }
+        let stream = self.verbosity.event_stream.as_ref();
+        if let Some(address) = stream.or(self.event_stream.as_ref()) {
+            match EventStream::listen(address) {
+                Ok(stream) => outputs.stream = Some(stream),
+                Err(e) => errors.push(format!(
+                    "cannot listen for log viewers at {}: {}",
+                    address, e
+                )),
+            }
+        }
+        if let Some(dir) = &self.crash_reports {
+            match CrashReporter::new(dir, &self.roots[0], self.banner.clone()) {
+                Ok(reporter) => outputs.crash = Some(reporter),
+                Err(e) => errors.push(format!(
+                    "cannot write crash reports to {}: {}",
+                    dir.display(),
+                    e
+                )),
+            }
+        }
+        let driver = self.batch_driver().unwrap_or_else(|e| {
+            errors.push(e.to_owned());
+            Driver::Thread
+        });
+        if let Some(address) = self.verbosity.gelf.as_ref().or(self.gelf.as_ref()) {
+            match Gelf::connect(address, self.fields.redaction.clone(), &driver) {
+                Ok((gelf, sink)) => {
+                    outputs.gelf = Some(gelf);
+                    if let Some(sink) = sink {
+                        files.push(Arc::new(sink));
+                    }
+                }
+                Err(e) => errors.push(format!("cannot connect to Graylog at {}: {}", address, e)),
+            }
+        }
+        if let Some(loki) = &self.loki {
+            match loki.clone().start(self.fields.redaction.clone(), &driver) {
+                Ok((layer, sink)) => {
+                    outputs.loki = Some(layer);
+                    files.push(Arc::new(sink));
+                }
+                Err(e) => errors.push(format!("cannot push to Loki at {}: {}", loki.url(), e)),
+            }
+        }
+        let sentry_dsn = self
+            .sentry_dsn
+            .clone()
+            .or_else(|| std::env::var("SENTRY_DSN").ok())
+            .filter(|dsn| cfg!(feature = "sentry") && !dsn.is_empty());
+        if let Some(dsn) = sentry_dsn {
+            match Sentry::connect(&dsn, self.fields.redaction.clone()) {
+                Ok((layer, sink)) => {
+                    outputs.sentry = Some(layer);
+                    files.push(Arc::new(sink));
+                }
+                Err(e) => errors.push(format!("cannot report errors to Sentry: {}", e)),
+            }
+        }
+        let env = |name| {
+            std::env::var(name)
+                .ok()
+                .filter(|url: &String| !url.is_empty())
+        };
+        let otlp_url = self
+            .otlp_url
+            .clone()
+            .or_else(|| env("OTEL_EXPORTER_OTLP_LOGS_ENDPOINT"))
+            .or_else(|| {
+                let url = env("OTEL_EXPORTER_OTLP_ENDPOINT")?;
+                Some(format!("{}/v1/logs", url.trim_end_matches('/')))
+            })
+            .filter(|_| cfg!(feature = "otlp"));
+        if let Some(url) = otlp_url {
+            let service_name = env("OTEL_SERVICE_NAME").unwrap_or_else(|| self.roots[0].clone());
+            let trace_ids = self.trace_ids.clone();
+            match Otlp::connect(
+                &url,
+                &service_name,
+                trace_ids,
+                self.fields.redaction.clone(),
+                &driver,
+            ) {
+                Ok((layer, sink)) => {
+                    outputs.otlp = Some(layer);
+                    files.push(Arc::new(sink));
+                }
+                Err(e) => errors.push(format!("cannot export to OpenTelemetry at {}: {}", url, e)),
+            }
+        }
+        filters.retain(|&output, _| outputs.has(output));
+        outputs.filters = filters;
+        outputs.files = files;
+        outputs.errors = errors;
+        outputs
+    }
+
+    /// What pushes the batches of log collectors, see [`tokio_batching`](Self::tokio_batching)
+    fn batch_driver(&self) -> Result<Driver, &'static str> {
+        if !self.tokio_batching {
+            return Ok(Driver::Thread);
+        }
+        #[cfg(feature = "tokio")]
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            return Ok(Driver::Tokio(runtime));
+        }
+        Err("no Tokio runtime is running, pushing to log collectors from threads")
+    }
+
+    /// Assembles the layers writing JSON for [`tee_json`](Self::tee_json), rows for
+    /// [`tee_delimited`](Self::tee_delimited), those writing
+    /// tagged events for [`route_tag`](Self::route_tag), the one recording the
+    /// [`html_report`](Self::html_report) and those writing [`binary_log`](Self::binary_log)s,
+    /// each with its own filter
+    fn tee_layers<S>(
+        &self,
+        tees: Vec<JsonTee>,
+        tagged: Vec<TagRoute>,
+        html: Option<(HtmlReport, Targets)>,
+        binary_logs: Vec<(BinaryLog, Targets)>,
+    ) -> Option<Box<dyn Layer<S> + Send + Sync>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    {
+        let mut layers: Option<Box<dyn Layer<S> + Send + Sync>> = None;
+        for TagRoute { tag, writer } in tagged {
+            let (formatter, _) = self.configured_formatter();
+            let layer = Tagged {
+                tag,
+                inner: FieldLayer {
+                    redaction: self.fields.redaction.clone(),
+                }
+                .and_then(
+                    tracing_subscriber::fmt::layer()
+                        .fmt_fields(self.fields.clone())
+                        .with_writer(writer)
+                        .event_format(formatter),
+                ),
+            }
+            .with_filter(filter_fn(|metadata| {
+                metadata.is_span() || tags::may_have_tags(metadata)
+            }));
+            layers = Some(match layers {
+                Some(layers) => Box::new(layers.and_then(layer)),
+                None => Box::new(layer),
+            });
+        }
+        for JsonTee {
+            writer,
+            filter,
+            delimited,
+        } in tees
+        {
+            if let Some(formatter) = delimited {
+                let layer = tracing_subscriber::fmt::layer()
+                    .with_writer(writer)
+                    .event_format(formatter)
+                    .with_filter(filter);
+                layers = Some(match layers {
+                    Some(layers) => Box::new(layers.and_then(layer)),
+                    None => Box::new(layer),
+                });
+                continue;
+            }
+            let layer = FieldLayer {
+                redaction: self.fields.redaction.clone(),
+            }
+            .and_then(
+                tracing_subscriber::fmt::layer()
+                    .fmt_fields(self.fields.clone())
+                    .with_writer(writer)
+                    .event_format(self.json_formatter()),
+            )
+            .with_filter(filter);
+            layers = Some(match layers {
+                Some(layers) => Box::new(layers.and_then(layer)),
+                None => Box::new(layer),
+            });
+        }
+        if let Some((report, filter)) = html {
+            let layer = report.with_filter(filter);
+            layers = Some(match layers {
+                Some(layers) => Box::new(layers.and_then(layer)),
+                None => Box::new(layer),
+            });
+        }
+        for (log, filter) in binary_logs {
+            let layer = log.with_filter(filter);
+            layers = Some(match layers {
+                Some(layers) => Box::new(layers.and_then(layer)),
+                None => Box::new(layer),
+            });
+        }
+        layers
+    }
+
+    /// Takes the outputs with a filter of their own out of `outputs`, returning them as layers
+    /// filtered by it, see [`output_filter`](Self::output_filter)
+    fn filtered_outputs<S>(&self, outputs: &mut Outputs) -> Option<Box<dyn Layer<S> + Send + Sync>>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+    {
+        let mut layers: Option<Box<dyn Layer<S> + Send + Sync>> = None;
+        for (output, filter) in mem::take(&mut outputs.filters) {
+            let layer: Box<dyn Layer<S> + Send + Sync> = match output {
+                Output::LogFile => match outputs.log_file.take() {
+                    Some(writer) => Box::new(
+                        tracing_subscriber::fmt::layer()
+                            .fmt_fields(self.fields.clone())
+                            .with_writer(writer)
+                            .event_format(self.configured_formatter().0),
+                    ),
+                    None => continue,
+                },
+                Output::Syslog => Box::new(outputs.syslog.take()),
+                Output::Gelf => Box::new(outputs.gelf.take()),
+                Output::Loki => Box::new(outputs.loki.take()),
+                Output::Sentry => Box::new(outputs.sentry.take()),
+                Output::Otlp => Box::new(outputs.otlp.take()),
+                Output::EventStream => Box::new(outputs.stream.take().map(|stream| {
+                    tracing_subscriber::fmt::layer()
+                        .fmt_fields(self.fields.clone())
+                        .with_writer(stream)
+                        .event_format(self.json_formatter())
+                })),
+            };
+            let layer = FieldLayer {
+                redaction: self.fields.redaction.clone(),
+            }
+            .and_then(layer)
+            .with_filter(filter);
+            layers = Some(match layers {
+                Some(layers) => Box::new(layers.and_then(layer)),
+                None => Box::new(layer),
+            });
+        }
+        layers
+    }
+
+    /// The rules of [`boost_level`](Self::boost_level) and `--log-boost`
+    fn boost_rules(&self) -> Arc<[BoostRule]> {
+        let rules = self.boosts.iter().chain(&self.verbosity.boosts);
+        rules.cloned().collect()
+    }
+
+    /// A formatter writing the events as JSON, for the outputs of JSON records
+    fn json_formatter(&self) -> EventFormatter {
+        let mut formatter = EventFormatter::new(self.roots[0].clone(), false);
+        formatter.json = true;
+        formatter.json_span_fields = self.json_span_fields;
+        formatter.fields = self.fields.clone();
+        formatter.trace_ids = self.trace_ids.clone();
+        formatter
+    }
+
+    /// The configured formatter, verbose whatever the flags, for the outputs read later
+    fn verbose_formatter(&self) -> EventFormatter {
+        let (mut verbose, _) = self.configured_formatter();
+        verbose.verbose = true;
+        verbose.wrap_width = None;
+        verbose.terminal_width = None;
+        verbose.github_annotations = false;
+        verbose.teamcity_messages = false;
+        verbose.hyperlinks = None;
+        verbose.progress = ProgressLine::new(false);
+        // The deltas of the formatted output would count each event twice
+        verbose.event_deltas = false;
+        verbose
+    }
+
+    /// Assembles the layers that format events along with those recording the span data they
+    /// rely on, returning them with the names of enabled presets that aren't registered
+    fn layers<S>(
+        self,
+        outputs: Outputs,
+        explainer: Option<Arc<Explainer>>,
+    ) -> (impl Layer<S>, Vec<String>)
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let (mut formatter, unknown_presets) = self.configured_formatter();
+        outputs
+            .verbose_format
+            .store(formatter.verbose, Ordering::Relaxed);
+        formatter.verbose_switch = Some(outputs.verbose_format);
+        let mut console = outputs.console;
+        if let Some(writer) = &self.writer {
+            console.replace_stderr(writer.clone());
+        }
+        // The journal takes the place of stderr, unless the writer already does, while the
+        // other destinations still get the formatted output
+        let mut journald = outputs.journald.filter(|_| self.writer.is_none());
+        if let Some(journald) = &mut journald {
+            journald.set_levels(console.take_stderr());
+        }
+        let unwritten = console.is_empty() && outputs.transcript.is_none();
+        if let Some(writer) = outputs.transcript {
+            formatter.transcript = Some(Box::new(Transcript {
+                formatter: self.verbose_formatter(),
+                writer,
+            }));
+        }
+        let span_events = if self.tree {
+            FmtSpan::NEW | FmtSpan::CLOSE
+        } else {
+            FmtSpan::CLOSE
+        };
+        let formatted = tracing_subscriber::fmt::layer()
+            .with_span_events(span_events)
+            .fmt_fields(self.fields.clone())
+            .with_writer(console)
+            .event_format(formatter);
+        let formatted =
+            (self.discard != Some(Discard::BeforeFormatting) && !unwritten).then_some(formatted);
+        let stream = outputs.stream.map(|stream| {
+            tracing_subscriber::fmt::layer()
+                .fmt_fields(self.fields.clone())
+                .with_writer(stream)
+                .event_format(self.json_formatter())
+        });
+        let crash = outputs.crash.map(|reporter| {
+            tracing_subscriber::fmt::layer()
+                .fmt_fields(self.fields.clone())
+                .with_writer(CrashWriter(reporter))
+                .event_format(self.verbose_formatter())
+        });
+        // The Android log takes the place of the formatted output
+        let (formatted, logcat) = match (&journald, outputs.logcat) {
+            (None, Some(logcat)) => (None, Some(logcat)),
+            _ => (formatted, None),
+        };
+        // Collectors go first, so the formatted output still finds the note of an error storm
+        let forwarded = Layer::<S>::and_then(outputs.syslog, outputs.gelf)
+            .and_then(outputs.loki)
+            .and_then(outputs.sentry)
+            .and_then(outputs.otlp);
+        let mut fmt_layer = EventFilter::new(Layer::<S>::and_then(
+            forwarded,
+            Layer::<S>::and_then(formatted, journald)
+                .and_then(logcat)
+                .and_then(stream)
+                .and_then(crash),
+        ));
+        fmt_layer.boosts = self.boost_rules();
+        fmt_layer.fields = self.field_filters;
+        fmt_layer.fields.extend(self.verbosity.field_filters);
+        fmt_layer.matches = self.message_matches;
+        fmt_layer.matches.extend(self.verbosity.message_matches);
+        fmt_layer.excludes = self.message_excludes;
+        fmt_layer.excludes.extend(self.verbosity.message_excludes);
+        // Without a guard to drain it, the last repeats of a storm aren't reported on shutdown
+        let window = self.error_storm_window;
+        fmt_layer.storms = outputs
+            .storms
+            .or_else(|| window.map(|window| Arc::new(StormFilter::new(window))));
+        fmt_layer.repeats = outputs.repeats;
+        fmt_layer.samples = self.samples;
+        fmt_layer.precheck =
+            Precheck::new(&fmt_layer.fields, &fmt_layer.matches, explainer.is_some());
+        fmt_layer.explainer = explainer;
+        let precheck = fmt_layer.precheck.clone();
+
+        let layer = TimingLayer
+            .and_then(self.span_ids.then_some(SpanIdLayer))
+            .and_then(spantrace::layer())
+            .and_then(FieldLayer {
+                redaction: self.fields.redaction,
+            })
+            .and_then(fmt_layer.with_filter(precheck));
+        (layer, unknown_presets)
+    }
+}
+
+/// Formats events the way the subscriber installed by [`Builder::init`] does, for use in a
+/// `fmt::Layer` of your own
+///
+/// Built from the formatting options of a [`Builder`] with [`Builder::formatter`], or with the
+/// defaults with [`EventFormatter::new`].
+#[derive(Debug)]
+pub struct EventFormatter {
+    roots: Vec<String>,
+    verbose: bool,
+    /// Overrides `verbose` once the subscriber is installed, see
+    /// [`LogGuard::set_verbose_format`]
+    verbose_switch: Option<Arc<AtomicBool>>,
+    slow_span_threshold: Option<Duration>,
+    slow_span_level: Option<Level>,
+    only_slow_spans: bool,
+    tree: bool,
+    multiline_fields: Option<usize>,
+    fields: FieldFormatter,
+    presets: Vec<Arc<dyn Preset>>,
+    close_templates: HashMap<String, String>,
+    progress: ProgressLine,
+    path_style: PathStyle,
+    source_roots: SourceRoots,
+    path_prefixes: PathPrefixes,
+    hyperlinks: Option<Hyperlinks>,
+    /// Width of the module and file column so far, when aligning columns
+    context_column: Option<AtomicUsize>,
+    wrap_width: Option<Width>,
+    continuation: Continuation,
+    /// Width of the terminal, which span durations are aligned to in tree mode
+    terminal_width: Option<Width>,
+    level_labels: [String; 5],
+    level_context: [LevelContext; 5],
+    level_colors: [Style; 5],
+    pad_levels: bool,
+    time_format: TimeFormat,
+    event_deltas: bool,
+    span_elapsed: bool,
+    color_targets: bool,
+    prefer_target: bool,
+    editor_locations: bool,
+    span_ids: bool,
+    correlation_fields: Vec<String>,
+    layout: Option<String>,
+    dim_fields: bool,
+    max_span_depth: Option<usize>,
+    grouped_spans: Vec<String>,
+    span_headers: bool,
+    json: bool,
+    bunyan: bool,
+    logfmt: bool,
+    /// Paints what these match in messages, see [`Builder::highlight`]
+    highlights: Vec<Regex>,
+    json_span_fields: JsonSpanFields,
+    /// Writes `WARN` and `ERROR` events as GitHub Actions workflow commands
+    github_annotations: bool,
+    /// Writes `WARN` and `ERROR` events as TeamCity service messages
+    teamcity_messages: bool,
+    transcript: Option<Box<Transcript>>,
+    trace_ids: Option<TraceIds>,
+    /// Captures backtraces for `ERROR` events without `RUST_BACKTRACE`
+    error_backtraces: bool,
+    /// The level labels, painted on first use
+    painted_levels: OnceLock<[String; 5]>,
+    /// The module, target and location of the events of each callsite, painted on first use
+    callsites: RwLock<HashMap<Identifier, Arc<Fragments>>>,
+    /// The span path of the last event formatted, see [`Builder::span_headers`]
+    last_span_path: Mutex<String>,
 }
 
-/// Initialises [`tracing_subscriber`] with options from command-line arguments
-pub fn init(root_module: &'static str, verbosity: Verbosity) {
-    let verbose_format = cfg!(debug_assertions) || verbosity.verbose != 0;
+/// The parts of the context of the events of a callsite, see [`EventFormatter::fragments`]
+#[derive(Debug)]
+struct Fragments {
+    module: Option<Painted>,
+    target: Option<Painted>,
+    location: Option<Painted>,
+}
+
+/// Text with escape sequences, and the columns it takes on screen
+#[derive(Debug)]
+struct Painted {
+    text: String,
+    width: usize,
+}
+
+/// The verbose rendering of events, written alongside the formatted output, see
+/// [`Builder::transcript`]
+#[derive(Debug)]
+struct Transcript {
+    formatter: EventFormatter,
+    writer: Router,
+}
+
+impl Transcript {
+    fn write<S, N>(&self, ctx: &FmtContext<'_, S, N>, e: &Event<'_>)
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        N: for<'a> FormatFields<'a> + 'static,
+    {
+        // The formatted output takes the note about repeated errors after this
+        let note = StormNote::take();
+        if let Some(note) = &note {
+            note.clone().restore();
+        }
+        let mut line = String::new();
+        if self.formatter.write_event(ctx, &mut line, e).is_ok() {
+            let _ = self
+                .writer
+                .make_writer_for(e.metadata())
+                .write_all(line.as_bytes());
+        }
+        if let Some(note) = note {
+            note.restore();
+        }
+    }
+}
+
+impl EventFormatter {
+    /// Creates a formatter with the default options, showing module paths relative to
+    /// `root_module`, and the time, module and spans of events if `verbose` is set
+    pub fn new(root_module: impl Into<String>, verbose: bool) -> Self {
+        Self {
+            roots: vec![root_module.into()],
+            verbose,
+            verbose_switch: None,
+            slow_span_threshold: None,
+            slow_span_level: None,
+            only_slow_spans: false,
+            tree: false,
+            multiline_fields: None,
+            fields: FieldFormatter::default(),
+            presets: Vec::new(),
+            close_templates: HashMap::new(),
+            progress: ProgressLine::new(status::tty()),
+            path_style: PathStyle::default(),
+            source_roots: SourceRoots::default(),
+            path_prefixes: PathPrefixes::default(),
+            hyperlinks: None,
+            context_column: None,
+            wrap_width: None,
+            continuation: Continuation::default(),
+            terminal_width: None,
+            level_labels: LevelStyle::default().labels().map(String::from),
+            level_context: Default::default(),
+            level_colors: LevelColors::palette(),
+            pad_levels: false,
+            time_format: TimeFormat::default(),
+            event_deltas: false,
+            span_elapsed: false,
+            color_targets: false,
+            prefer_target: false,
+            editor_locations: false,
+            span_ids: false,
+            correlation_fields: Vec::new(),
+            layout: None,
+            dim_fields: false,
+            max_span_depth: None,
+            grouped_spans: Vec::new(),
+            span_headers: false,
+            json: false,
+            bunyan: false,
+            logfmt: false,
+            highlights: Vec::new(),
+            json_span_fields: JsonSpanFields::default(),
+            github_annotations: false,
+            teamcity_messages: false,
+            transcript: None,
+            trace_ids: None,
+            error_backtraces: false,
+            painted_levels: OnceLock::new(),
+            callsites: RwLock::default(),
+            last_span_path: Mutex::default(),
+        }
+    }
+
+    /// Whether the time, module and spans of events are shown
+    fn verbose(&self) -> bool {
+        match &self.verbose_switch {
+            Some(switch) => switch.load(Ordering::Relaxed),
+            None => self.verbose,
+        }
+    }
+
+    /// Formats the time
+    fn time(&self) -> Option<DisplayTime<'_>> {
+        if self.verbose() {
+            self.time_format.now()
+        } else {
+            None
+        }
+    }
+
+    /// Colors the log level
+    fn level(&self, level: Level) -> Option<&str> {
+        let painted = self.painted_levels.get_or_init(|| {
+            let mut painted = self.level_labels.clone();
+            for (label, style) in painted.iter_mut().zip(self.level_colors) {
+                *label = style.paint(label.as_str()).to_string();
+            }
+            painted
+        });
+        Some(&painted[levels::index(level)])
+    }
+
+    /// Width of the widest level label, which labels are padded to when aligning columns
+    fn level_width(&self) -> usize {
+        let widths = self.level_labels.iter().map(|label| label.chars().count());
+        widths.max().unwrap_or(0)
+    }
+
+    /// Colors the module, or the target with [`Builder::prefer_target`], relative to the root
+    /// module it belongs to
+    fn module<'a>(&self, metadata: &Metadata<'a>) -> Option<ANSIGenericString<'a, str>> {
+        let module_path = match self.prefer_target {
+            true => metadata.target(),
+            false => metadata.module_path()?,
+        };
+        let style = |module: &str| {
+            if self.color_targets {
+                Self::target_color(module).bold()
+            } else {
+                Style::new().bold()
+            }
+        };
+        match self.relative(module_path) {
+            "" => None,
+            relative => Some(style(relative).paint(relative)),
+        }
+    }
+
+    /// Dims the target in brackets, when it isn't the module the event was logged in
+    fn target<'a>(&self, metadata: &Metadata<'a>) -> Option<ANSIGenericString<'a, str>> {
+        if self.prefer_target || metadata.module_path() == Some(metadata.target()) {
+            return None;
+        }
+        let target = format!("[{}]", self.relative(metadata.target()));
+        Some(Style::new().dimmed().paint(target))
+    }
+
+    /// Shortens a module path relative to the most specific root it belongs to, leaving it
+    /// whole if it doesn't belong to any
+    fn relative<'a>(&self, module_path: &'a str) -> &'a str {
+        self.roots
+            .iter()
+            .filter_map(|root| match module_path.strip_prefix(root.as_str())? {
+                "" => Some(""),
+                rest => rest.strip_prefix("::"),
+            })
+            .min_by_key(|relative| relative.len())
+            .unwrap_or(module_path)
+    }
+
+    /// Picks a stable color for the subsystem a module belongs to, its first path segment
+    fn target_color(module: &str) -> Color {
+        let subsystem = module.split("::").next().unwrap_or(module);
+        // FNV-1a, as the standard library's hashers may change between releases
+        let hash = subsystem
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        Color::Fixed(TARGET_COLORS[(hash % TARGET_COLORS.len() as u64) as usize])
+    }
+
+    /// Shortens the path of the source file, after replacing its prefix
+    fn file<'a>(&self, metadata: &Metadata<'a>) -> Option<Cow<'a, str>> {
+        Some(self.shorten(metadata.file()?))
+    }
+
+    /// Shortens `path` according to the path style, after replacing its prefix
+    fn shorten<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        match self.path_prefixes.rewrite(path) {
+            Cow::Borrowed(path) => Cow::Borrowed(self.source_roots.shorten(path, &self.path_style)),
+            Cow::Owned(path) => {
+                let shortened = self.source_roots.shorten(&path, &self.path_style);
+                Cow::Owned(shortened.to_owned())
+            }
+        }
+    }
+
+    /// Joins the file and line, adding the column with [`Builder::editor_locations`]
+    fn location(&self, file: &str, line: u32) -> String {
+        if self.editor_locations {
+            format!("{}:{}:1", file, line)
+        } else {
+            format!("{}:{}", file, line)
+        }
+    }
+
+    /// The parts of the context shown before events at `level`
+    fn context(&self, level: Level) -> LevelContext {
+        if self.verbose() {
+            LevelContext::FULL
+        } else {
+            self.level_context[levels::index(level)]
+        }
+    }
+
+    /// The painted module, target and location of the events of the callsite of `metadata`,
+    /// painted the first time one of them is formatted
+    fn fragments(&self, metadata: &Metadata) -> Arc<Fragments> {
+        let callsite = metadata.callsite();
+        let callsites = self.callsites.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(fragments) = callsites.get(&callsite) {
+            return fragments.clone();
+        }
+        drop(callsites);
+        let fragments = Arc::new(self.paint_fragments(metadata));
+        let mut callsites = self.callsites.write().unwrap_or_else(|e| e.into_inner());
+        callsites.insert(callsite, fragments.clone());
+        fragments
+    }
+
+    fn paint_fragments(&self, metadata: &Metadata) -> Fragments {
+        let painted = |part: ANSIGenericString<str>| Painted {
+            width: part.chars().count(),
+            text: part.to_string(),
+        };
+        let location = match (self.file(metadata), metadata.line()) {
+            (Some(file), Some(line)) => Some(self.location(&file, line)),
+            _ => None,
+        };
+        Fragments {
+            module: self.module(metadata).map(painted),
+            target: self.target(metadata).map(painted),
+            location: location.map(|location| Painted {
+                width: location.chars().count(),
+                text: location,
+            }),
+        }
+    }
+
+    /// Formats the context, removing any redundant parts, and leaving out the module and target
+    /// unless `module` is set
+    fn write_context(
+        &self,
+        f: &mut dyn Write,
+        fragments: &Fragments,
+        module: bool,
+        metadata: &Metadata,
+    ) -> fmt::Result {
+        let target = fragments.target.as_ref().filter(|_| module);
+        let module = fragments.module.as_ref().filter(|_| module);
+        let mut width = 0;
+
+        if let Some(module) = module {
+            f.write_str(&module.text)?;
+            width += module.width;
+        }
+        if let Some(target) = target {
+            if module.is_some() {
+                f.write_char(' ')?;
+                width += 1;
+            }
+            f.write_str(&target.text)?;
+            width += target.width;
+        }
+        if let Some(location) = &fragments.location {
+            if module.is_some() || target.is_some() {
+                f.write_char(if self.editor_locations { ' ' } else { ':' })?;
+                width += 1;
+            }
+            match (&self.hyperlinks, metadata.file(), metadata.line()) {
+                (Some(hyperlinks), Some(path), Some(line)) => {
+                    hyperlinks.write(f, path, line, &location.text)?
+                }
+                _ => f.write_str(&location.text)?,
+            }
+            width += location.width;
+        }
+
+        if let Some(ref column) = self.context_column {
+            // The column only ever grows, so lines stay aligned once the widest context is seen
+            let column = column.fetch_max(width, Ordering::Relaxed).max(width);
+            write!(f, "{:1$}", "", column - width + 1)?;
+        } else if width > 0 {
+            f.write_char(' ')?;
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the event's explicit parent, or the span the event was emitted in
+    fn current_span<'a, S, N>(
+        ctx: &'a FmtContext<'_, S, N>,
+        span: Option<&Id>,
+    ) -> Option<SpanRef<'a, S>>
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+        N: for<'writer> FormatFields<'writer> + 'static,
+    {
+        span.and_then(|id| ctx.span(id))
+            .or_else(|| ctx.lookup_current())
+    }
+
+    /// Draws guides that indent the event by the depth of its span
+    fn write_tree<S, N>(
+        &self,
+        f: &mut dyn Write,
+        ctx: &FmtContext<'_, S, N>,
+        event: &Event,
+        span_event: Option<SpanEvent>,
+    ) -> fmt::Result
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+        N: for<'writer> FormatFields<'writer> + 'static,
+    {
+        let depth = Self::current_span(ctx, event.parent()).map_or(0, |span| span.scope().count());
+        let (depth, corner) = match span_event {
+            Some(SpanEvent::New) => (depth.saturating_sub(1), "┌ "),
+            Some(SpanEvent::Close) => (depth.saturating_sub(1), "└ "),
+            None => (depth, ""),
+        };
+
+        let guides = "│ ".repeat(depth) + corner;
+        if !guides.is_empty() {
+            write!(f, "{}", Style::new().dimmed().paint(guides))?;
+        }
+        Ok(())
+    }
+
+    fn write_span<S, N>(
+        &self,
+        f: &mut dyn Write,
+        ctx: &FmtContext<'_, S, N>,
+        span: Option<&Id>,
+    ) -> fmt::Result
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+        N: for<'writer> FormatFields<'writer> + 'static,
+    {
+        let scope = Self::current_span(ctx, span)
+            .into_iter()
+            .flat_map(|span| span.scope().from_root());
+        let names = scope.map(|span| (span.metadata().name(), self.span_id(&span)));
+        let depth = match self.max_span_depth {
+            Some(depth) => depth,
+            None => return Self::write_span_names(f, names),
+        };
+        let mut names: Vec<_> = names.collect();
+        if names.len() > depth {
+            // Keep the outermost span and the innermost `depth - 1`, eliding the rest
+            let elided = names.len() - depth;
+            let first = usize::from(depth > 1);
+            names.splice(first..first + elided, Some(("…", None)));
+        }
+        Self::write_span_names(f, names)
+    }
+
+    /// Writes a dim line with the spans of `e` if they differ from those of the event formatted
+    /// before, see [`Builder::span_headers`]
+    fn write_span_header<S, N>(
+        &self,
+        f: &mut dyn Write,
+        ctx: &FmtContext<'_, S, N>,
+        e: &Event<'_>,
+    ) -> fmt::Result
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+        N: for<'writer> FormatFields<'writer> + 'static,
+    {
+        let mut path = String::new();
+        self.write_span(&mut path, ctx, e.parent())?;
+        let path = strip_escapes(path.trim_end());
+        let mut last = self
+            .last_span_path
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if *last == path {
+            return Ok(());
+        }
+        let header = match path.as_str() {
+            "" => "── (no span)".to_owned(),
+            path => format!("── {}", path),
+        };
+        *last = path;
+        writeln!(f, "{}", Style::new().dimmed().paint(header))
+    }
 
-    let registry = tracing_subscriber::registry().with(
-        tracing_subscriber::fmt::layer()
-            .with_span_events(FmtSpan::CLOSE)
-            .with_writer(io::stderr)
-            .event_format(EventFormatter::new(root_module, verbose_format)),
-    );
+    /// Writes the names of spans, from the outermost, separated with colons
+    fn write_span_names<'a>(
+        f: &mut dyn Write,
+        names: impl IntoIterator<Item = (&'a str, Option<ANSIGenericString<'static, str>>)>,
+    ) -> fmt::Result {
+        let bold = Style::new().bold();
+        let mut seen = false;
+        for (name, id) in names {
+            if seen {
+                f.write_char(':')?;
+            }
+            write!(f, "{}", bold.paint(name))?;
+            if let Some(id) = id {
+                write!(f, "{}", id)?;
+            }
+            seen = true;
+        }
 
-    if let Some(log_filter) = verbosity.log_filters {
-        registry.with(EnvFilter::from(log_filter)).init();
-    } else {
-        let level_filter: LevelFilter = verbosity.into();
-        registry.with(level_filter).init();
+        if seen {
+            f.write_char(' ')?;
+        }
+        Ok(())
     }
-}
 
-/// Combines the number of occurrences of `--quiet` and `--verbose` flags into a `LevelFilter`
-impl Into<LevelFilter> for Verbosity {
-    fn into(self) -> LevelFilter {
-        match self.verbose.checked_add(DEFAULT_VERBOSITY as u8) {
-            Some(v) => match v.checked_sub(self.quiet) {
-                Some(1) => LevelFilter::ERROR,
-                Some(2) => LevelFilter::WARN,
-                Some(3) => LevelFilter::INFO,
-                Some(4) => LevelFilter::DEBUG,
-                Some(5) => LevelFilter::TRACE,
-                Some(6..=std::u8::MAX) => LevelFilter::TRACE,
-                Some(0) | None => LevelFilter::OFF,
-            },
-            None => LevelFilter::TRACE,
+    /// Finds the value of a correlation field in the innermost span that has one, see
+    /// [`Builder::correlation_field`]
+    fn correlation<S, N>(&self, ctx: &FmtContext<'_, S, N>, event: &Event) -> Option<String>
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+        N: for<'writer> FormatFields<'writer> + 'static,
+    {
+        if self.correlation_fields.is_empty() {
+            return None;
         }
+        let span = Self::current_span(ctx, event.parent())?;
+        span.scope().find_map(|span| {
+            let extensions = span.extensions();
+            let fields = extensions.get::<SpanFields>()?;
+            self.correlation_fields
+                .iter()
+                .find_map(|name| fields.get(name))
+                .map(str::to_owned)
+        })
     }
-}
 
-struct EventFormatter {
-    root: &'static str,
-    verbose: bool,
-}
+    /// Dims the short ID of the span, with [`Builder::span_ids`]
+    fn span_id<S>(&self, span: &SpanRef<'_, S>) -> Option<ANSIGenericString<'static, str>>
+    where
+        S: for<'lookup> LookupSpan<'lookup>,
+    {
+        let id = span_ids::of(span).filter(|_| self.span_ids)?;
+        Some(Style::new().dimmed().paint(id.to_string()))
+    }
 
-impl EventFormatter {
-    pub fn new(root_module: &'static str, verbose: bool) -> Self {
-        Self {
-            root: root_module,
-            verbose,
+    /// Writes a `panic` event with the location of the panic rather than that of the hook, and
+    /// the backtrace, if any, on the following lines
+    fn write_panic<S, N>(
+        &self,
+        f: &mut dyn Write,
+        ctx: &FmtContext<'_, S, N>,
+        e: &Event<'_>,
+    ) -> fmt::Result
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+        N: for<'writer> FormatFields<'writer> + 'static,
+    {
+        let fields = SpanFields::record_selected(e, |_| true);
+        if let Some(time) = self.time() {
+            write!(f, "{} ", time)?;
+        }
+        // Shown like the locations of other events, without the column
+        let location = fields.get("location").and_then(|location| {
+            let (location, _column) = location.rsplit_once(':')?;
+            location.rsplit_once(':')
+        });
+        if let Some((file, line)) = location {
+            write!(f, "{}:{} ", self.shorten(file), line)?;
         }
+        self.write_span(f, ctx, e.parent())?;
+        write!(
+            f,
+            "{} {}",
+            Color::Red.bold().paint("panic:"),
+            fields.get("message").unwrap_or_default()
+        )?;
+        if let Some(thread) = fields.get("thread") {
+            let thread = format!("thread={}", thread);
+            write!(f, " {}", Style::new().dimmed().paint(thread))?;
+        }
+        writeln!(f)?;
+        if let Some(backtrace) = fields.get("backtrace") {
+            writeln!(f, "{}", Style::new().dimmed().paint(backtrace.trim_end()))?;
+        }
+        Ok(())
     }
 
-    /// Formats the time
-    fn time(&self) -> Option<DelayedFormat<StrftimeItems>> {
-        if self.verbose {
-            Some(Local::now().format("%H:%M:%S%.3f"))
-        } else {
-            None
+    /// Writes the fields a span was created with, ending the line
+    fn write_span_fields<S, N>(
+        &self,
+        f: &mut dyn Write,
+        ctx: &FmtContext<'_, S, N>,
+        event: &Event,
+    ) -> fmt::Result
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+        N: for<'writer> FormatFields<'writer> + 'static,
+    {
+        if let Some(span) = event.parent().and_then(|id| ctx.span(id)) {
+            if let Some(fields) = span.extensions().get::<FormattedFields<N>>() {
+                if !fields.is_empty() {
+                    write!(f, " {}", fields)?;
+                }
+            }
         }
+        writeln!(f)
     }
 
-    /// Colors the log level
-    fn level(&self, event: &Event) -> Option<ANSIGenericString<str>> {
-        Some(match *event.metadata().level() {
-            Level::ERROR => Color::Red.bold().paint("error:"),
-            Level::WARN => Color::Yellow.bold().paint("warning:"),
-            Level::INFO => Color::Green.bold().paint("info:"),
-            Level::DEBUG => Color::Blue.bold().paint("debug:"),
-            Level::TRACE => Color::Purple.bold().paint("trace:"),
-        })
+    /// Writes the event's fields, one per line if there are enough of them
+    fn write_fields<S, N>(
+        &self,
+        f: &mut dyn Write,
+        ctx: &FmtContext<'_, S, N>,
+        event: &Event,
+        continuation: &str,
+    ) -> fmt::Result
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+        N: for<'writer> FormatFields<'writer> + 'static,
+    {
+        let plain =
+            self.multiline_fields.is_none() && !self.dim_fields && self.highlights.is_empty();
+        // Fields formatted by a formatter other than ours go through it
+        let ours = TypeId::of::<N>() == TypeId::of::<FieldFormatter>();
+        if plain && ours && !event.metadata().is_span() {
+            if let Some(result) = self.fields.write_recorded(f, event, continuation) {
+                return result;
+            }
+        }
+        if event.metadata().is_span() || (plain && continuation.is_empty()) {
+            return ctx.format_fields(f, event);
+        }
+        let mut fields = EventFields::new(&self.fields, event);
+        if let Some(message) = &mut fields.message {
+            if !continuation.is_empty() && message.contains('\n') {
+                *message = message.replace('\n', &format!("\n{}", continuation));
+            }
+            if let Some(highlighted) = highlight::highlight(message, &self.highlights) {
+                *message = highlighted;
+            }
+        }
+        let dimmed = Style::new().dimmed();
+        let multiline = self.multiline_fields;
+        if multiline.is_none_or(|min_fields| fields.fields.len() < min_fields) {
+            if self.dim_fields {
+                return fields.write_styled(f, dimmed);
+            }
+            return fields.write(f);
+        }
+
+        if let Some(ref message) = fields.message {
+            f.write_str(message)?;
+        }
+        let width = fields.fields.iter().map(|(name, _)| name.len()).max();
+        for (name, value) in &fields.fields {
+            let name = format!(
+                "{:<width$}",
+                format!("{}:", name),
+                width = width.unwrap_or(0) + 1
+            );
+            if self.dim_fields {
+                write!(f, "\n    {}", dimmed.paint(format!("{} {}", name, value)))?;
+            } else {
+                write!(f, "\n    {} {}", dimmed.paint(name), value)?;
+            }
+        }
+        Ok(())
     }
 
-    /// Colors the module
-    fn module(&self, event: &Event) -> Option<ANSIGenericString<str>> {
-        let style = Style::new().bold();
-        if !self.verbose || event.metadata().module_path()? == self.root {
-            None
-        } else if event.metadata().module_path()?.starts_with(self.root) {
-            let module_path = event.metadata().module_path()?.get(self.root.len() + 2..)?;
-            Some(style.paint(module_path))
+    /// Renders a span close event from a close message template or with the first matching
+    /// preset, returning whether either applied
+    ///
+    /// A `log.close_msg` field on the span takes precedence over the template configured for the
+    /// matching preset, which in turn takes precedence over the preset's own rendering.
+    fn write_close<S, N>(
+        &self,
+        f: &mut dyn Write,
+        ctx: &FmtContext<'_, S, N>,
+        event: &Event,
+    ) -> Result<bool, fmt::Error>
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+        N: for<'writer> FormatFields<'writer> + 'static,
+    {
+        let span = match event.parent().and_then(|id| ctx.span(id)) {
+            Some(span) => span,
+            None => return Ok(false),
+        };
+        let elapsed = timing::elapsed(&span);
+        let extensions = span.extensions();
+        let fields = match extensions.get::<SpanFields>() {
+            Some(fields) => fields,
+            None => return Ok(false),
+        };
+
+        let preset = self.presets.iter().find(|preset| preset.matches(fields));
+        let template = fields.get("log.close_msg").or_else(|| {
+            let preset = preset?;
+            self.close_templates.get(preset.name()).map(String::as_str)
+        });
+
+        if let Some(template) = template {
+            // `fmt::Layer` records the busy and idle times on the close event itself
+            let mut timings = SpanFields::default();
+            event.record(&mut timings);
+            template::render(f, template, |name, f| match name {
+                "elapsed" => elapsed.map(|elapsed| write!(f, "{}", DisplayDuration(elapsed))),
+                "busy" => timings.get("time.busy").map(|busy| f.write_str(busy)),
+                "idle" => timings.get("time.idle").map(|idle| f.write_str(idle)),
+                name => fields.get(name).map(|value| f.write_str(value)),
+            })?;
+            return Ok(true);
+        }
+
+        match preset {
+            Some(preset) => {
+                preset.write_close(f, fields, elapsed)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the lifetime of the span closed by the event if it exceeded the threshold
+    fn slow_span<S, N>(&self, ctx: &FmtContext<'_, S, N>, event: &Event) -> Option<Duration>
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+        N: for<'writer> FormatFields<'writer> + 'static,
+    {
+        let elapsed = timing::elapsed(&ctx.span(event.parent()?)?)?;
+        if elapsed >= self.slow_span_threshold? {
+            Some(elapsed)
         } else {
-            Some(style.paint(event.metadata().module_path()?))
+            None
         }
     }
 
-    /// Extracts the last part of the filename
-    fn file(&self, event: &Event) -> Option<&str> {
-        Some(event.metadata().file()?.split(MAIN_SEPARATOR).last()?)
+    /// Returns how long the span of the event has been open, if it is in one
+    fn span_elapsed<S, N>(&self, ctx: &FmtContext<'_, S, N>, e: &Event) -> Option<Duration>
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+        N: for<'writer> FormatFields<'writer> + 'static,
+    {
+        let span = match e.parent() {
+            Some(id) => ctx.span(id),
+            None if e.is_contextual() => ctx.lookup_current(),
+            None => None,
+        };
+        timing::elapsed(&span?)
     }
 
-    /// Formats the context, removing any redundant parts.
-    fn write_context(
+    /// Formats an event, or writes nothing if it is suppressed
+    fn write_event<S, N>(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
         f: &mut dyn Write,
-        module: Option<ANSIGenericString<str>>,
-        file: Option<&str>,
-        line: Option<u32>,
-    ) -> fmt::Result {
-        let mut seen = false;
+        e: &Event<'_>,
+    ) -> fmt::Result
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        N: for<'a> FormatFields<'a> + 'static,
+    {
+        let span_event = SpanEvent::of(e);
+        let closed = span_event == Some(SpanEvent::Close);
+        let slow = if closed { self.slow_span(ctx, e) } else { None };
+        if self.only_slow_spans && closed && slow.is_none() {
+            return Ok(());
+        }
 
-        if let Some(ref module) = module {
-            write!(f, "{}", module)?;
-            seen = true;
+        // Records of the `log` crate carry where they were logged in fields
+        let normalized = log_bridge::normalize(e);
+        let metadata = normalized.as_ref().unwrap_or_else(|| e.metadata());
+
+        let ci = self.github_annotations || self.teamcity_messages;
+        if ci && *metadata.level() <= Level::WARN && span_event.is_none() {
+            let mut message = String::new();
+            self.write_message(&mut message, ctx, e, None, "")?;
+            let message = strip_escapes(&message);
+            if self.github_annotations {
+                return github::write_annotation(f, metadata, &message);
+            }
+            return teamcity::write_message(f, metadata, &message);
         }
-        if let (Some(file), Some(line)) = (file, line) {
-            if module.is_some() {
-                f.write_char(':')?;
+
+        if let Some(progress) = ProgressEvent::of(e) {
+            return self.progress.write(f, &progress, |f| {
+                if let Some(time) = self.time() {
+                    write!(f, "{} ", time)?;
+                }
+                if let Some(level) = self.level(*e.metadata().level()) {
+                    write!(f, "{} ", level)?;
+                }
+                Ok(())
+            });
+        }
+
+        if e.metadata().target() == dedup::REPEAT_TARGET {
+            if let Some(time) = self.time() {
+                write!(f, "{} ", time)?;
             }
-            write!(f, "{}:{}", file, line)?;
-            seen = true;
+            let fields = SpanFields::record_selected(e, |name| name == "message");
+            let message = fields.get("message").unwrap_or_default();
+            return writeln!(f, "{}", Style::new().dimmed().paint(message));
         }
 
-        if seen {
-            f.write_char(' ')?;
+        if e.metadata().target() == panic::TARGET && span_event.is_none() {
+            return self.write_panic(f, ctx, e);
+        }
+
+        if let Some(layout) = &self.layout {
+            self.write_layout(f, ctx, e, metadata, layout)?;
+            return self.write_details(f, e, *metadata.level(), span_event);
+        }
+
+        let context = self.context(boost::shown(*metadata.level()));
+        // The spans go in a header when they change rather than on every line
+        let headed = context.spans && self.span_headers && !self.tree && span_event.is_none();
+        if headed {
+            self.write_span_header(f, ctx, e)?;
+        }
+        let f = &mut match self.continuation {
+            Continuation::Prefix => Columns::recording(f),
+            Continuation::Indent | Continuation::Unchanged => Columns::new(f),
+        };
+        if let Some(time) = self.time_format.now().filter(|_| context.time) {
+            write!(f, "{} ", time)?;
+        }
+        if let Some(delta) = self.event_deltas.then(timing::since_last_event).flatten() {
+            let dimmed = Style::new().dimmed();
+            let delta = DisplayDuration(delta);
+            write!(f, "{}+{}{} ", dimmed.prefix(), delta, dimmed.suffix())?;
+        }
+        let elapsed = self.span_elapsed && span_event.is_none();
+        if let Some(elapsed) = elapsed.then(|| self.span_elapsed(ctx, e)).flatten() {
+            let dimmed = Style::new().dimmed();
+            let elapsed = DisplayDuration(elapsed);
+            write!(f, "{}[@{}]{} ", dimmed.prefix(), elapsed, dimmed.suffix())?;
+        }
+        if let Some(value) = self.correlation(ctx, e) {
+            let style = Self::target_color(&value).bold();
+            write!(f, "{} ", style.paint(format!("[{}]", value)))?;
+        }
+
+        if self.tree {
+            self.write_tree(f, ctx, e, span_event)?;
+        }
+
+        let fragments = match normalized {
+            // Records of the `log` crate share the callsite of the bridge
+            Some(_) => Arc::new(self.paint_fragments(metadata)),
+            None => self.fragments(metadata),
+        };
+        self.write_context(f, &fragments, context.module, metadata)?;
+
+        if self.tree {
+            if span_event.is_some() {
+                write!(f, "{}", Style::new().bold().paint(e.metadata().name()))?;
+                let span = e.parent().and_then(|id| ctx.span(id));
+                if let Some(id) = span.and_then(|span| self.span_id(&span)) {
+                    write!(f, "{}", id)?;
+                }
+            }
+            if span_event == Some(SpanEvent::New) {
+                return self.write_span_fields(f, ctx, e);
+            } else if span_event.is_some() {
+                f.write_char(' ')?;
+            }
+        } else if context.spans && !headed && self.context_column.is_none() {
+            self.write_span(f, ctx, e.parent())?;
+        }
+
+        let level = match slow {
+            Some(_) => self.slow_span_level.unwrap_or(*e.metadata().level()),
+            None => boost::shown(*e.metadata().level()),
+        };
+        if let Some(painted) = self.level(level) {
+            write!(f, "{} ", painted)?;
+            if self.pad_levels {
+                let width = self.level_labels[levels::index(level)].chars().count();
+                write!(f, "{:1$}", "", self.level_width().saturating_sub(width))?;
+            }
+        }
+
+        // Spans vary in length, so when aligning they go after the columns
+        if context.spans && !headed && !self.tree && self.context_column.is_some() {
+            self.write_span(f, ctx, e.parent())?;
         }
 
+        let continuation = match self.continuation {
+            Continuation::Indent => wrap::indent(f.column()),
+            Continuation::Prefix => {
+                let prefix = strip_escapes(f.line());
+                Cow::Owned(Style::new().dimmed().paint(prefix).to_string())
+            }
+            Continuation::Unchanged => Cow::Borrowed(""),
+        };
+        match self.wrap_width.as_ref().and_then(Width::get) {
+            Some(width) => {
+                let indent = f.column();
+                let mut message = String::new();
+                self.write_message(&mut message, ctx, e, slow, &continuation)?;
+                wrap::write_wrapped(f, &message, indent, width)?;
+            }
+            None => self.write_message(f, ctx, e, slow, &continuation)?,
+        }
+
+        if self.tree && self.verbose() && closed {
+            let span = e.parent().and_then(|id| ctx.span(id));
+            if let Some(elapsed) = span.and_then(|span| timing::elapsed(&span)) {
+                let duration = DisplayDuration(elapsed).to_string();
+                let width = duration.chars().count();
+                let padding = match self.terminal_width.as_ref().and_then(Width::get) {
+                    Some(columns) if f.column() + width < columns => columns - f.column() - width,
+                    _ => 1,
+                };
+                write!(
+                    f,
+                    "{:2$}{}",
+                    "",
+                    Style::new().dimmed().paint(duration),
+                    padding
+                )?;
+            }
+        }
+
+        writeln!(f)?;
+        self.write_details(f, e, level, span_event)
+    }
+
+    /// Writes the lines following an event at `level`: the causes of errors, their span traces
+    /// and the backtrace of `ERROR` events
+    fn write_details(
+        &self,
+        f: &mut dyn Write,
+        e: &Event<'_>,
+        level: Level,
+        span_event: Option<SpanEvent>,
+    ) -> fmt::Result {
+        if span_event.is_some() {
+            return Ok(());
+        }
+        fields::write_causes(f, e, &self.fields.redaction)?;
+        spantrace::write(f, e)?;
+        // The backtrace of a replayed event would be that of the replay
+        if level == Level::ERROR && !timestamp::replaying() {
+            if let Some(backtrace) = backtraces::capture(self.error_backtraces) {
+                backtraces::write(f, &backtrace, &self.roots)?;
+            }
+        }
         Ok(())
     }
 
-    fn write_span<S, N>(
+    /// Writes the line of an event after the template of [`Builder::layout`]
+    fn write_layout<S, N>(
         &self,
         f: &mut dyn Write,
         ctx: &FmtContext<'_, S, N>,
-        span: Option<&Id>,
+        e: &Event<'_>,
+        metadata: &Metadata<'_>,
+        layout: &str,
     ) -> fmt::Result
     where
-        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
-        N: for<'writer> FormatFields<'writer> + 'static,
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        N: for<'a> FormatFields<'a> + 'static,
     {
-        let bold = Style::new().bold();
-        let mut seen = false;
+        let fields = EventFields::new(&self.fields, e);
+        let note = StormNote::take();
+        template::render(f, layout, |placeholder, f| {
+            let (name, width) = match placeholder.split_once(':') {
+                Some((name, width)) => (name, Some(width)),
+                None => (placeholder, None),
+            };
+            let part = match name {
+                "time" => self.time_format.now().map(|time| time.to_string()),
+                "elapsed" => self
+                    .span_elapsed(ctx, e)
+                    .map(|elapsed| format!("@{}", DisplayDuration(elapsed))),
+                "level" => self.level(*metadata.level()).map(str::to_owned),
+                "module" => self.module(metadata).map(|module| module.to_string()),
+                "target" => Some(metadata.target().to_owned()),
+                "file" => self.file(metadata).map(Cow::into_owned),
+                "line" => metadata.line().map(|line| line.to_string()),
+                "location" => match (self.file(metadata), metadata.line()) {
+                    (Some(file), Some(line)) => Some(self.location(&file, line)),
+                    _ => None,
+                },
+                "span" => {
+                    let mut span = String::new();
+                    self.write_span(&mut span, ctx, e.parent())
+                        .expect("writing to a String can't fail");
+                    Some(span.trim_end().to_owned())
+                }
+                "correlation" => self.correlation(ctx, e).map(|value| {
+                    let style = Self::target_color(&value).bold();
+                    style.paint(value).to_string()
+                }),
+                "message" => fields.message.clone(),
+                "fields" => {
+                    let mut rendered = String::new();
+                    let others = EventFields {
+                        message: None,
+                        fields: fields.fields.clone(),
+                    };
+                    match self.dim_fields {
+                        true => others.write_styled(&mut rendered, Style::new().dimmed()),
+                        false => others.write(&mut rendered),
+                    }
+                    .expect("writing to a String can't fail");
+                    Some(rendered)
+                }
+                _ => return None,
+            };
+            Some(pad(f, part.as_deref().unwrap_or_default(), width))
+        })?;
+        if let Some(note) = note {
+            write!(f, " {}", Style::new().dimmed().paint(note.to_string()))?;
+        }
+        writeln!(f)
+    }
 
-        let span = span
-            .and_then(|id| ctx.span(id))
-            .or_else(|| ctx.lookup_current());
-        let scope = span
-            .into_iter()
-            .flat_map(|span| span.from_root().chain(iter::once(span)));
+    /// Writes what follows the level label: the message and fields, or the rendering of a span
+    /// close event, along with any notes
+    ///
+    /// Lines after the first of the message start with `continuation`.
+    fn write_message<S, N>(
+        &self,
+        f: &mut dyn Write,
+        ctx: &FmtContext<'_, S, N>,
+        e: &Event<'_>,
+        slow: Option<Duration>,
+        continuation: &str,
+    ) -> fmt::Result
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        N: for<'a> FormatFields<'a> + 'static,
+    {
+        let closed = SpanEvent::of(e) == Some(SpanEvent::Close);
+        if !(closed && self.write_close(f, ctx, e)?) {
+            self.write_fields(f, ctx, e, continuation)?;
+        }
 
-        for span in scope {
-            if seen {
-                f.write_char(':')?;
-            }
-            write!(f, "{}", bold.paint(span.metadata().name()))?;
-            seen = true;
+        if let Some(note) = StormNote::take() {
+            write!(f, " {}", Style::new().dimmed().paint(note.to_string()))?;
         }
 
-        if seen {
-            f.write_char(' ')?;
+        if let Some(elapsed) = slow {
+            let style = Color::Red.bold();
+            write!(
+                f,
+                " {}",
+                style.paint(format!("slow: {}", DisplayDuration(elapsed)))
+            )?;
+        }
+
+        if let Some((trace_id, span_id)) = self.trace_ids(ctx, e) {
+            let ids = format!(
+                "trace={} span={}",
+                otel::short(&trace_id),
+                otel::short(&span_id)
+            );
+            write!(f, " {}", Style::new().dimmed().paint(ids))?;
         }
         Ok(())
     }
+
+    /// The OpenTelemetry trace and span IDs of the span `e` happened in, if configured with
+    /// [`Builder::trace_ids`]
+    fn trace_ids<S, N>(&self, ctx: &FmtContext<'_, S, N>, e: &Event<'_>) -> Option<(String, String)>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        N: for<'a> FormatFields<'a> + 'static,
+    {
+        let trace_ids = self.trace_ids.as_ref()?;
+        let span = match e.parent() {
+            Some(id) => ctx.span(id),
+            None if e.is_contextual() => ctx.lookup_current(),
+            None => None,
+        };
+        trace_ids.of(span)
+    }
 }
 
 impl<S, N> FormatEvent<S, N> for EventFormatter
@@ -199,22 +4501,95 @@ where
         f: &mut dyn Write,
         e: &Event<'_>,
     ) -> fmt::Result {
-        if let Some(time) = self.time() {
-            write!(f, "{} ", time)?;
-        }
-
-        Self::write_context(f, self.module(e), self.file(e), e.metadata().line())?;
-
-        if self.verbose {
-            self.write_span(f, ctx, e.parent())?;
+        if self.grouped_spans.is_empty() {
+            return self.format_now(ctx, f, e);
         }
-
-        if let Some(level) = self.level(e) {
-            write!(f, "{} ", level)?;
+        // The outermost grouped span holds the event, until its own close event comes
+        let span = e.parent().and_then(|id| ctx.span(id));
+        let group = span.or_else(|| ctx.lookup_current()).and_then(|span| {
+            span.scope()
+                .from_root()
+                .find(|span| self.grouped_spans.iter().any(|name| name == span.name()))
+        });
+        match group {
+            Some(span) => {
+                let mut event = String::new();
+                self.format_now(ctx, &mut event, e)?;
+                let closing =
+                    SpanEvent::of(e) == Some(SpanEvent::Close) && e.parent() == Some(&span.id());
+                group::hold(&span, f, &event, closing)
+            }
+            None => self.format_now(ctx, f, e),
         }
+    }
+}
 
-        ctx.format_fields(f, e)?;
+impl EventFormatter {
+    /// Writes `e` in the configured format
+    fn format_now<S, N>(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        f: &mut dyn Write,
+        e: &Event<'_>,
+    ) -> fmt::Result
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        N: for<'a> FormatFields<'a> + 'static,
+    {
+        overhead::measure(Stage::Formatting, || {
+            if self.json {
+                let trace_ids = self.trace_ids(ctx, e);
+                let redaction = &self.fields.redaction;
+                let precision = self
+                    .time_format
+                    .precision()
+                    .unwrap_or(TimePrecision::Micros);
+                if self.bunyan {
+                    let name = &self.roots[0];
+                    return json::write_bunyan(f, ctx, e, name, redaction, trace_ids, precision);
+                }
+                let span_fields = self.json_span_fields;
+                return json::write_event(f, ctx, e, redaction, trace_ids, span_fields, precision);
+            }
+            if self.logfmt {
+                let trace_ids = self.trace_ids(ctx, e);
+                let precision = self
+                    .time_format
+                    .precision()
+                    .unwrap_or(TimePrecision::Micros);
+                return logfmt::write_event(
+                    f,
+                    ctx,
+                    e,
+                    &self.fields.redaction,
+                    trace_ids,
+                    precision,
+                );
+            }
+            if let Some(transcript) = &self.transcript {
+                transcript.write(ctx, e);
+            }
+            self.write_event(ctx, f, e)
+        })
+    }
+}
 
-        writeln!(f)
+/// Writes `part` padded to the width given after the colon of a placeholder, such as `<8`,
+/// counting the columns it takes on screen
+fn pad(f: &mut dyn Write, part: &str, width: Option<&str>) -> fmt::Result {
+    let (right, width) = match width {
+        Some(width) => match width.strip_prefix('>') {
+            Some(width) => (true, width),
+            None => (false, width.trim_start_matches('<')),
+        },
+        None => return f.write_str(part),
+    };
+    let padding = width
+        .parse::<usize>()
+        .unwrap_or(0)
+        .saturating_sub(strip_escapes(part).chars().count());
+    match right {
+        true => write!(f, "{:padding$}{}", "", part, padding = padding),
+        false => write!(f, "{}{:padding$}", part, "", padding = padding),
     }
 }
@@ -0,0 +1,169 @@
+use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Names of nanoseconds, microseconds, milliseconds and seconds
+type Units = [&'static str; 4];
+
+const SI: Units = ["ns", "µs", "ms", "s"];
+const CYRILLIC: Units = ["нс", "мкс", "мс", "с"];
+const CHINESE: Units = ["纳秒", "微秒", "毫秒", "秒"];
+const JAPANESE: Units = ["ナノ秒", "マイクロ秒", "ミリ秒", "秒"];
+
+/// The names of duration units, indexed by `Locale::units`
+const UNITS: [Units; 4] = [SI, CYRILLIC, CHINESE, JAPANESE];
+
+/// Languages writing a comma before the decimals
+const DECIMAL_COMMA: &[&str] = &[
+    "be", "bg", "cs", "da", "de", "el", "es", "et", "fi", "fr", "hr", "hu", "id", "it", "kk", "lt",
+    "lv", "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr", "uk", "vi",
+];
+
+/// How durations are shown by every formatter, set from the locale when the subscriber is
+/// built: the index of the units in [`UNITS`], times two, plus one for a decimal comma
+static DURATIONS: AtomicU8 = AtomicU8::new(0);
+
+/// The order of the day, month and year in dates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DateOrder {
+    DayMonthYear,
+    MonthDayYear,
+    YearMonthDay,
+}
+
+/// How times and durations are shown to the people reading the output, see
+/// [`Builder::locale`](crate::Builder::locale)
+///
+/// A locale is named by a tag such as `de_DE`, `en-US` or `ja_JP.UTF-8`, of which the language
+/// and region are looked at. They decide:
+///
+/// - whether times of day are shown on a 12-hour clock, as in `09:14:03.210 PM` for `en_US`, or
+///   a 24-hour clock
+/// - the date that `%x` stands for in [`Builder::time_format`](crate::Builder::time_format),
+///   such as `%d.%m.%Y` for `de_DE` or `%m/%d/%Y` for `en_US`, and the time that `%X` stands
+///   for
+/// - whether durations are written with a decimal comma, as in `2,50ms` for `fr_FR`, and the
+///   names of their units, as in `2,50мс` for `ru_RU` or `2.50毫秒` for `zh_CN`
+///
+/// Unknown languages, and the `C` and `POSIX` locales, get the defaults: a 24-hour clock, dates
+/// such as `2021-06-02`, and durations such as `2.50ms`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale {
+    tag: String,
+    twelve_hour: bool,
+    date_order: DateOrder,
+    date_separator: char,
+    decimal_comma: bool,
+    /// Index of the names of duration units in [`UNITS`]
+    units: u8,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self::new("C")
+    }
+}
+
+impl Locale {
+    /// The locale named by `tag`, such as `de_DE`, `en-US` or `ja_JP.UTF-8`
+    pub fn new(tag: &str) -> Self {
+        // Encoding and modifier, as in `de_DE.UTF-8@euro`, don't matter
+        let name = tag.split(['.', '@']).next().unwrap_or_default();
+        let mut parts = name.split(['_', '-']);
+        let language = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let region = parts.next().unwrap_or_default().to_ascii_uppercase();
+
+        let twelve_hour = match language.as_str() {
+            "en" => !matches!(region.as_str(), "GB" | "IE" | "ZA" | "NG"),
+            "hi" | "bn" | "ur" | "ar" | "fil" => true,
+            _ => false,
+        };
+        let date_order = match (language.as_str(), region.as_str()) {
+            ("en", "US" | "PH" | "") => DateOrder::MonthDayYear,
+            ("en", "CA") => DateOrder::YearMonthDay,
+            ("zh" | "ja" | "ko" | "hu" | "lt" | "sv" | "mn", _) => DateOrder::YearMonthDay,
+            ("c" | "posix" | "", _) => DateOrder::YearMonthDay,
+            _ => DateOrder::DayMonthYear,
+        };
+        let date_separator = match language.as_str() {
+            "de" | "ru" | "uk" | "be" | "pl" | "cs" | "sk" | "fi" | "tr" | "nb" | "no" | "nn"
+            | "da" | "ko" | "hu" | "ro" | "bg" | "hr" | "sr" | "sl" | "et" | "lv" | "kk" => '.',
+            "nl" | "sv" | "lt" | "c" | "posix" | "" => '-',
+            _ if language == "en" && region == "CA" => '-',
+            _ => '/',
+        };
+        let decimal_comma = DECIMAL_COMMA.contains(&language.as_str());
+        let units = match language.as_str() {
+            "ru" | "uk" | "be" | "bg" | "kk" => 1,
+            "zh" => 2,
+            "ja" => 3,
+            _ => 0,
+        };
+        Self {
+            tag: tag.to_owned(),
+            twelve_hour,
+            date_order,
+            date_separator,
+            decimal_comma,
+            units,
+        }
+    }
+
+    /// The locale of the process, named by the first of `LC_ALL`, `LC_TIME` and `LANG` that is
+    /// set, or the default one
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_TIME", "LANG"]
+            .iter()
+            .find_map(|name| env::var(name).ok().filter(|tag| !tag.is_empty()))
+            .map_or_else(Self::default, |tag| Self::new(&tag))
+    }
+
+    /// The tag the locale was named by
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Whether times of day are shown on a 12-hour clock
+    pub(crate) fn twelve_hour(&self) -> bool {
+        self.twelve_hour
+    }
+
+    /// The strftime format of the dates that `%x` stands for
+    pub(crate) fn date_format(&self) -> String {
+        let s = self.date_separator;
+        match self.date_order {
+            DateOrder::DayMonthYear => format!("%d{0}%m{0}%Y", s),
+            DateOrder::MonthDayYear => format!("%m{0}%d{0}%Y", s),
+            DateOrder::YearMonthDay => format!("%Y{0}%m{0}%d", s),
+        }
+    }
+
+    /// The strftime format of the times that `%X` stands for
+    pub(crate) fn time_format(&self) -> &'static str {
+        if self.twelve_hour {
+            "%I:%M:%S %p"
+        } else {
+            "%H:%M:%S"
+        }
+    }
+
+    /// Shows the durations of every formatter the way this locale does
+    pub(crate) fn apply_to_durations(&self) {
+        DURATIONS.store(
+            self.units * 2 + u8::from(self.decimal_comma),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.tag)
+    }
+}
+
+/// The names of duration units, and whether durations are written with a decimal comma
+pub(crate) fn durations() -> (&'static Units, bool) {
+    let durations = DURATIONS.load(Ordering::Relaxed);
+    (&UNITS[usize::from(durations / 2)], durations % 2 == 1)
+}
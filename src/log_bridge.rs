@@ -0,0 +1,29 @@
+//! Shows records of the `log` crate with the module, file and line they were logged at, with
+//! the `log` feature
+
+#[cfg(feature = "log")]
+pub(crate) use native::normalize;
+
+#[cfg(not(feature = "log"))]
+pub(crate) use unsupported::normalize;
+
+#[cfg(feature = "log")]
+mod native {
+    use tracing::{Event, Metadata};
+    use tracing_log::NormalizeEvent;
+
+    /// The metadata of the `log` record that `event` was converted from, read from its `log.*`
+    /// fields, or `None` if it was logged with `tracing`
+    pub(crate) fn normalize<'a>(event: &'a Event<'_>) -> Option<Metadata<'a>> {
+        event.normalized_metadata()
+    }
+}
+
+#[cfg(not(feature = "log"))]
+mod unsupported {
+    use tracing::{Event, Metadata};
+
+    pub(crate) fn normalize<'a>(_event: &'a Event<'_>) -> Option<Metadata<'a>> {
+        None
+    }
+}
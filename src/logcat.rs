@@ -0,0 +1,137 @@
+//! Writes events to the Android log, on Android
+
+#[cfg(target_os = "android")]
+pub(crate) use native::Logcat;
+
+#[cfg(not(target_os = "android"))]
+pub(crate) use unsupported::Logcat;
+
+#[cfg(target_os = "android")]
+mod native {
+    use crate::dedup::StormNote;
+    use crate::fields::{Redaction, SpanFields};
+    use std::ffi::CString;
+    use std::fmt::{self, Write as _};
+    use std::os::raw::{c_char, c_int};
+    use tracing::field::{Field, Visit};
+    use tracing::{Event, Level, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer};
+    use tracing_subscriber::registry::LookupSpan;
+
+    #[link(name = "log")]
+    extern "C" {
+        fn __android_log_write(prio: c_int, tag: *const c_char, text: *const c_char) -> c_int;
+    }
+
+    /// Writes each event to the Android log with the target as its tag
+    ///
+    /// The level maps to the priority, and fields follow the message as `name=value`, with the
+    /// fields of enclosing spans prefixed with the span name, such as `request.id=42`. Stderr
+    /// goes nowhere in Android apps, so this is where `adb logcat` finds events.
+    #[derive(Debug)]
+    pub(crate) struct Logcat {
+        redaction: Redaction,
+    }
+
+    impl Logcat {
+        pub(crate) fn connect(redaction: Redaction) -> Option<Self> {
+            Some(Self { redaction })
+        }
+    }
+
+    impl<S> Layer<S> for Logcat
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let metadata = event.metadata();
+            let mut visitor = FieldVisitor {
+                redaction: &self.redaction,
+                message: String::new(),
+                fields: String::new(),
+            };
+            if let Some(scope) = ctx.event_scope(event) {
+                for span in scope.from_root() {
+                    if let Some(fields) = span.extensions().get::<SpanFields>() {
+                        for (name, value) in fields.iter() {
+                            let _ = write!(visitor.fields, " {}.{}={}", span.name(), name, value);
+                        }
+                    }
+                }
+            }
+            event.record(&mut visitor);
+            let mut text = visitor.message;
+            text.push_str(&visitor.fields);
+            if let Some(note) = StormNote::take() {
+                let _ = write!(text, " {}", note);
+            }
+
+            // Interior nuls would cut the text short anyway
+            let tag = CString::new(metadata.target().replace('\0', ""));
+            let text = CString::new(text.replace('\0', ""));
+            if let (Ok(tag), Ok(text)) = (tag, text) {
+                // SAFETY: both strings are nul-terminated and outlive the call
+                unsafe {
+                    __android_log_write(priority(*metadata.level()), tag.as_ptr(), text.as_ptr());
+                }
+            }
+        }
+    }
+
+    /// The priority of a level, from `android/log.h`
+    fn priority(level: Level) -> c_int {
+        match level {
+            Level::TRACE => 2,
+            Level::DEBUG => 3,
+            Level::INFO => 4,
+            Level::WARN => 5,
+            Level::ERROR => 6,
+        }
+    }
+
+    struct FieldVisitor<'a> {
+        redaction: &'a Redaction,
+        message: String,
+        /// The fields following the message, each preceded by a space
+        fields: String,
+    }
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            let name = field.name();
+            if name == "message" {
+                self.message = value.to_owned();
+            } else if !name.starts_with("log.") {
+                let value = if self.redaction.matches(name) {
+                    Redaction::REDACTED
+                } else {
+                    value
+                };
+                let _ = write!(self.fields, " {}={}", name, value);
+            }
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.record_str(field, &format!("{:?}", value))
+        }
+    }
+}
+
+#[cfg(not(target_os = "android"))]
+mod unsupported {
+    use crate::fields::Redaction;
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::Layer;
+
+    /// Never constructed outside Android
+    #[derive(Debug)]
+    pub(crate) enum Logcat {}
+
+    impl Logcat {
+        pub(crate) fn connect(_redaction: Redaction) -> Option<Self> {
+            None
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Logcat {}
+}
@@ -0,0 +1,146 @@
+use crate::boost;
+use crate::dedup::StormNote;
+use crate::fields::{Redaction, SpanFields};
+use crate::json::MessageVisitor;
+use crate::run;
+use crate::timestamp::{self, TimePrecision};
+use std::fmt::{self, Write};
+use std::time::SystemTime;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::{FmtContext, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Writes an event as a line of logfmt, for log collectors and tools that parse `key=value`
+/// pairs
+///
+/// The time, level, run ID, target and message come first, followed by the fields of the event
+/// and those of its spans, prefixed with the span name, from the root span inwards:
+///
+/// ```text
+/// time=2021-06-02T09:14:03.210114Z level=info run_id=3f9c0a17b2e4 target=app::db msg="fetched rows" rows=3 request.id=42
+/// ```
+pub(crate) fn write_event<S, N>(
+    f: &mut dyn Write,
+    ctx: &FmtContext<'_, S, N>,
+    e: &Event<'_>,
+    redaction: &Redaction,
+    trace_ids: Option<(String, String)>,
+    precision: TimePrecision,
+) -> fmt::Result
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    let metadata = e.metadata();
+    f.write_str("time=")?;
+    timestamp::write_rfc3339(f, SystemTime::now(), precision)?;
+    let level = boost::shown(*metadata.level())
+        .as_str()
+        .to_ascii_lowercase();
+    write!(f, " level={} run_id={} target=", level, run::run_id())?;
+    write_value(f, metadata.target())?;
+
+    let mut message = MessageVisitor(None);
+    e.record(&mut message);
+    let note = StormNote::take();
+    let message = match (message.0, &note) {
+        (Some(message), Some(note)) => Some(format!("{} {}", message, note)),
+        (message, _) => message,
+    };
+    if let Some(message) = message {
+        f.write_str(" msg=")?;
+        write_value(f, &message)?;
+    }
+    if let Some(note) = note {
+        write!(f, " repeated={}", note.suppressed())?;
+    }
+    if let Some((trace_id, span_id)) = trace_ids {
+        f.write_str(" trace_id=")?;
+        write_value(f, &trace_id)?;
+        f.write_str(" span_id=")?;
+        write_value(f, &span_id)?;
+    }
+
+    let mut visitor = FieldVisitor {
+        f: &mut *f,
+        redaction,
+        result: Ok(()),
+    };
+    e.record(&mut visitor);
+    visitor.result?;
+
+    let span = e.parent().and_then(|id| ctx.span(id));
+    if let Some(scope) = span.or_else(|| ctx.lookup_current()) {
+        for span in scope.scope().from_root() {
+            if let Some(fields) = span.extensions().get::<SpanFields>() {
+                for (name, value) in fields.iter() {
+                    write!(f, " {}.{}=", span.name(), name)?;
+                    write_value(f, value)?;
+                }
+            }
+        }
+    }
+
+    f.write_char('\n')
+}
+
+/// Writes `value`, quoted if it is empty or holds spaces, quotes, `=` or control characters
+fn write_value(f: &mut dyn Write, value: &str) -> fmt::Result {
+    let quoted = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c == ' ' || c == '"' || c == '=' || c.is_control());
+    if !quoted {
+        return f.write_str(value);
+    }
+    f.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// Writes the fields of an event other than the message as `key=value` pairs
+struct FieldVisitor<'a> {
+    f: &'a mut dyn Write,
+    redaction: &'a Redaction,
+    result: fmt::Result,
+}
+
+impl FieldVisitor<'_> {
+    fn field(&mut self, field: &Field, value: &str) {
+        let name = field.name();
+        if self.result.is_err() || name == "message" || name.starts_with("log.") {
+            return;
+        }
+        let name = name.strip_prefix("r#").unwrap_or(name);
+        let value = match self.redaction.matches(name) {
+            true => Redaction::REDACTED,
+            false => value,
+        };
+        self.result = write!(self.f, " {}=", name).and_then(|()| write_value(self.f, value));
+    }
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.field(field, value)
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.field(field, &value.to_string())
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.field(field, &format!("{:?}", value))
+    }
+}
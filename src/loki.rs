@@ -0,0 +1,457 @@
+use crate::batch::{self, BatchSink, Driver};
+use crate::dedup::StormNote;
+use crate::fields::{Redaction, SpanFields};
+use crate::http::Endpoint;
+use crate::json;
+use crate::run;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Where and how [`Builder::loki`](crate::Builder::loki) pushes events to Grafana Loki
+///
+/// Events are pushed in batches by a background thread, in streams labelled with their level
+/// and target, along with the labels given with [`label`](Self::label). Each event is a line of
+/// logfmt, such as `msg="fetched rows" rows=3 request.id=42`, or of JSON with
+/// [`json`](Self::json).
+///
+/// ```no_run
+/// # #[cfg(feature = "cli")] {
+/// # use pretty_tracing_subscriber::{Builder, Loki, Verbosity};
+/// # use structopt::StructOpt;
+/// Builder::new("my_app", Verbosity::from_args())
+///     .loki(Loki::new("http://localhost:3100").label("service", "my_app"))
+///     .init();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Loki {
+    url: String,
+    labels: BTreeMap<String, String>,
+    json: bool,
+}
+
+impl Loki {
+    /// Pushes to the Loki server at `url`, such as `http://localhost:3100`, to the standard push
+    /// endpoint unless `url` has a path
+    ///
+    /// Only plain HTTP is supported, so Loki has to be reached through a local agent or proxy to
+    /// use TLS.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            labels: BTreeMap::new(),
+            json: false,
+        }
+    }
+
+    /// Adds a label to every stream, such as the name of the service or the environment
+    ///
+    /// Label names are made of letters, digits and underscores, others are replaced by
+    /// underscores.
+    pub fn label(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.insert(label_name(&name.into()), value.into());
+        self
+    }
+
+    /// Writes each event as a JSON object rather than logfmt
+    pub fn json(mut self) -> Self {
+        self.json = true;
+        self
+    }
+
+    /// The URL pushed to
+    pub(crate) fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Starts the thread pushing events, returning the layer that hands them to it, and the sink
+    /// that waits for it to push them all on shutdown
+    pub(crate) fn start(
+        self,
+        redaction: Redaction,
+        driver: &Driver,
+    ) -> io::Result<(LokiLayer, BatchSink<Entry>)> {
+        let endpoint = Endpoint::parse(&self.url, "/loki/api/v1/push")?;
+        let (sender, sink) = batch::start("Loki", driver, endpoint, encode)?;
+        let layer = LokiLayer {
+            sender,
+            labels: self.labels,
+            json: self.json,
+            redaction,
+        };
+        Ok((layer, sink))
+    }
+}
+
+/// Replaces the characters not allowed in label names by underscores
+fn label_name(name: &str) -> String {
+    let mut label: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !label.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        label.insert(0, '_');
+    }
+    label
+}
+
+pub(crate) struct Entry {
+    /// The labels of the stream, as the JSON object Loki expects
+    stream: String,
+    /// Nanoseconds since the Unix epoch
+    timestamp: u128,
+    line: String,
+}
+
+/// Hands events to the thread pushing them to Loki, see [`Loki`]
+#[derive(Debug)]
+pub(crate) struct LokiLayer {
+    sender: batch::Sender<Entry>,
+    labels: BTreeMap<String, String>,
+    json: bool,
+    redaction: Redaction,
+}
+
+impl<S> Layer<S> for LokiLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+
+        let mut labels = self.labels.clone();
+        labels.insert(
+            "level".to_owned(),
+            metadata.level().as_str().to_ascii_lowercase(),
+        );
+        labels.insert("target".to_owned(), metadata.target().to_owned());
+        let mut stream = String::from("{");
+        for (i, (name, value)) in labels.iter().enumerate() {
+            if i > 0 {
+                stream.push(',');
+            }
+            let _ = json::write_str(&mut stream, name);
+            stream.push(':');
+            let _ = json::write_str(&mut stream, value);
+        }
+        stream.push('}');
+
+        let mut visitor = FieldVisitor {
+            redaction: &self.redaction,
+            message: String::new(),
+            fields: vec![("run_id".to_owned(), Value::Str(run::run_id().to_owned()))],
+        };
+        event.record(&mut visitor);
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(fields) = span.extensions().get::<SpanFields>() {
+                    for (name, value) in fields.iter() {
+                        let name = format!("{}.{}", span.name(), name);
+                        visitor.fields.push((name, Value::Str(value.to_owned())));
+                    }
+                }
+            }
+        }
+        // The formatted output comes next and shows the note too
+        if let Some(note) = StormNote::take() {
+            let suppressed = Value::Raw(note.suppressed().to_string());
+            visitor.fields.push(("repeated".to_owned(), suppressed));
+            note.restore();
+        }
+        let line = if self.json {
+            visitor.json()
+        } else {
+            visitor.logfmt()
+        };
+
+        self.sender.send(Entry {
+            stream,
+            timestamp,
+            line,
+        });
+    }
+}
+
+/// Empties `batch` into the body of a push request
+fn encode(batch: &mut Vec<Entry>) -> String {
+    let mut streams: BTreeMap<&str, Vec<&Entry>> = BTreeMap::new();
+    for entry in batch.iter() {
+        streams.entry(&entry.stream).or_default().push(entry);
+    }
+    let mut body = String::from("{\"streams\":[");
+    for (i, (stream, entries)) in streams.iter().enumerate() {
+        if i > 0 {
+            body.push(',');
+        }
+        let _ = write!(body, "{{\"stream\":{},\"values\":[", stream);
+        for (i, entry) in entries.iter().enumerate() {
+            if i > 0 {
+                body.push(',');
+            }
+            let _ = write!(body, "[\"{}\",", entry.timestamp);
+            let _ = json::write_str(&mut body, &entry.line);
+            body.push(']');
+        }
+        body.push_str("]}");
+    }
+    body.push_str("]}");
+    batch.clear();
+    body
+}
+
+enum Value {
+    /// A number, written as is
+    Raw(String),
+    Str(String),
+}
+
+struct FieldVisitor<'a> {
+    redaction: &'a Redaction,
+    message: String,
+    fields: Vec<(String, Value)>,
+}
+
+impl FieldVisitor<'_> {
+    fn push(&mut self, field: &Field, value: Value) {
+        let name = field.name();
+        if name.starts_with("log.") {
+            return;
+        }
+        let name = name.strip_prefix("r#").unwrap_or(name);
+        let value = if self.redaction.matches(name) {
+            Value::Str(Redaction::REDACTED.to_owned())
+        } else {
+            value
+        };
+        self.fields.push((name.to_owned(), value));
+    }
+
+    fn logfmt(&self) -> String {
+        let mut line = String::new();
+        logfmt_pair(&mut line, "msg", &self.message);
+        for (name, value) in &self.fields {
+            line.push(' ');
+            match value {
+                Value::Raw(value) => {
+                    let _ = write!(line, "{}={}", name, value);
+                }
+                Value::Str(value) => logfmt_pair(&mut line, name, value),
+            }
+        }
+        line
+    }
+
+    fn json(&self) -> String {
+        let mut line = String::from("{\"msg\":");
+        let _ = json::write_str(&mut line, &self.message);
+        for (name, value) in &self.fields {
+            line.push(',');
+            let _ = json::write_str(&mut line, name);
+            line.push(':');
+            match value {
+                Value::Raw(value) => line.push_str(value),
+                Value::Str(value) => {
+                    let _ = json::write_str(&mut line, value);
+                }
+            }
+        }
+        line.push('}');
+        line
+    }
+}
+
+/// Writes `name=value`, quoting the value if it is empty or holds spaces, quotes or `=`
+fn logfmt_pair(line: &mut String, name: &str, value: &str) {
+    line.push_str(name);
+    line.push('=');
+    if !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || matches!(c, '"' | '=' | '\\'))
+    {
+        line.push_str(value);
+        return;
+    }
+    line.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, Value::Raw(value.to_string()))
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, Value::Raw(value.to_string()))
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if value.is_finite() {
+            self.push(field, Value::Raw(value.to_string()))
+        } else {
+            self.record_debug(field, &value)
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_owned();
+        } else {
+            self.push(field, Value::Str(value.to_owned()))
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch::Recorded;
+    use crate::fields::FieldLayer;
+    use crate::shutdown::Sink;
+    use tracing::level_filters::STATIC_MAX_LEVEL;
+    use tracing::Level;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    /// The bodies pushed for the events `log` logs, with the timestamps replaced by `T`
+    fn pushed(loki: Loki, log: impl FnOnce()) -> Vec<String> {
+        let recorded = Recorded::default();
+        let (sender, sink) =
+            batch::start("Loki", &Driver::Thread, recorded.clone(), encode).unwrap();
+        let mut redaction = Redaction::default();
+        redaction.add("password");
+        let layer = LokiLayer {
+            sender,
+            labels: loki.labels,
+            json: loki.json,
+            redaction,
+        };
+        let subscriber = Registry::default().with(FieldLayer::default()).with(layer);
+        tracing::subscriber::with_default(subscriber, log);
+        sink.drain().unwrap();
+        let bodies = recorded.0.lock().unwrap().clone();
+        bodies.iter().map(|body| without_timestamps(body)).collect()
+    }
+
+    fn without_timestamps(body: &str) -> String {
+        let mut parts = body.split("[\"");
+        let mut replaced = parts.next().unwrap_or_default().to_owned();
+        for part in parts {
+            replaced.push_str("[\"T");
+            replaced.push_str(part.trim_start_matches(|c: char| c.is_ascii_digit()));
+        }
+        replaced
+    }
+
+    fn pair(name: &str, value: &str) -> String {
+        let mut line = String::new();
+        logfmt_pair(&mut line, name, value);
+        line
+    }
+
+    #[test]
+    fn label_names_are_letters_digits_and_underscores() {
+        assert_eq!(label_name("service"), "service");
+        assert_eq!(label_name("service-name.v2"), "service_name_v2");
+        assert_eq!(label_name("2fa"), "_2fa");
+        assert_eq!(label_name("é"), "_");
+    }
+
+    #[test]
+    fn logfmt_values_are_quoted_when_needed() {
+        assert_eq!(pair("rows", "3"), "rows=3");
+        assert_eq!(pair("path", "/tmp/a.log"), "path=/tmp/a.log");
+        assert_eq!(pair("msg", ""), "msg=\"\"");
+        assert_eq!(pair("msg", "fetched rows"), "msg=\"fetched rows\"");
+        assert_eq!(pair("query", "a=b"), "query=\"a=b\"");
+        assert_eq!(
+            pair("quote", "say \"hi\"\\\n"),
+            "quote=\"say \\\"hi\\\"\\\\\\n\""
+        );
+    }
+
+    #[test]
+    fn entries_are_grouped_by_stream() {
+        let entry = |stream: &str, timestamp, line: &str| Entry {
+            stream: stream.to_owned(),
+            timestamp,
+            line: line.to_owned(),
+        };
+        let mut batch = vec![
+            entry("{\"level\":\"info\"}", 2, "b"),
+            entry("{\"level\":\"error\"}", 1, "a \"quoted\""),
+            entry("{\"level\":\"info\"}", 3, "c"),
+        ];
+        assert_eq!(
+            encode(&mut batch),
+            "{\"streams\":[\
+             {\"stream\":{\"level\":\"error\"},\"values\":[[\"1\",\"a \\\"quoted\\\"\"]]},\
+             {\"stream\":{\"level\":\"info\"},\"values\":[[\"2\",\"b\"],[\"3\",\"c\"]]}]}"
+        );
+        assert!(batch.is_empty());
+    }
+
+    #[test]
+    fn events_are_pushed_as_logfmt_lines() {
+        if STATIC_MAX_LEVEL < Level::INFO {
+            return;
+        }
+        let loki = Loki::new("http://localhost:3100").label("service-name", "my app");
+        let bodies = pushed(loki, || {
+            let span = tracing::info_span!("request", id = 42);
+            let _entered = span.enter();
+            tracing::info!(target: "app::db", rows = 3, password = "x", "fetched rows");
+        });
+        assert_eq!(
+            bodies,
+            [format!(
+                "{{\"streams\":[{{\"stream\":{{\"level\":\"info\",\"service_name\":\"my app\",\
+                 \"target\":\"app::db\"}},\"values\":[[\"T\",\"msg=\\\"fetched rows\\\" \
+                 run_id={} rows=3 password=[REDACTED] request.id=42\"]]}}]}}",
+                run::run_id()
+            )]
+        );
+    }
+
+    #[test]
+    fn events_can_be_pushed_as_json_lines() {
+        if STATIC_MAX_LEVEL < Level::WARN {
+            return;
+        }
+        let bodies = pushed(Loki::new("http://localhost:3100").json(), || {
+            tracing::warn!(target: "app", ratio = 0.5, path = "C:\\tmp", "disk \"full\"");
+        });
+        assert_eq!(
+            bodies,
+            [format!(
+                "{{\"streams\":[{{\"stream\":{{\"level\":\"warn\",\"target\":\"app\"}},\
+                 \"values\":[[\"T\",\"{{\\\"msg\\\":\\\"disk \\\\\\\"full\\\\\\\"\\\",\
+                 \\\"run_id\\\":\\\"{}\\\",\\\"ratio\\\":0.5,\
+                 \\\"path\\\":\\\"C:\\\\\\\\tmp\\\"}}\"]]}}]}}",
+                run::run_id()
+            )]
+        );
+    }
+}
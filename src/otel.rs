@@ -0,0 +1,41 @@
+use std::fmt;
+use std::sync::Arc;
+use tracing::Subscriber;
+use tracing_subscriber::registry::{Extensions, LookupSpan, SpanRef};
+
+/// How many hex digits of the IDs are shown in the formatted output, enough to find a trace
+const SHORT: usize = 8;
+
+type Lookup = dyn Fn(&Extensions<'_>) -> Option<(String, String)> + Send + Sync;
+
+/// Finds the OpenTelemetry trace and span IDs of spans, see
+/// [`Builder::trace_ids`](crate::Builder::trace_ids)
+#[derive(Clone)]
+pub(crate) struct TraceIds(Arc<Lookup>);
+
+impl TraceIds {
+    pub(crate) fn new(
+        lookup: impl Fn(&Extensions<'_>) -> Option<(String, String)> + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(lookup))
+    }
+
+    /// Returns the IDs of `span`, or of the innermost span enclosing it that has them
+    pub(crate) fn of<S>(&self, span: Option<SpanRef<'_, S>>) -> Option<(String, String)>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        span?.scope().find_map(|span| (self.0)(&span.extensions()))
+    }
+}
+
+impl fmt::Debug for TraceIds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TraceIds")
+    }
+}
+
+/// Shortens an ID for the formatted output
+pub(crate) fn short(id: &str) -> &str {
+    id.get(..SHORT).unwrap_or(id)
+}
@@ -0,0 +1,462 @@
+//! Exports events to an OpenTelemetry collector as OTLP log records, with the `otlp` feature
+
+#[cfg(feature = "otlp")]
+pub(crate) use native::Otlp;
+
+#[cfg(not(feature = "otlp"))]
+pub(crate) use unsupported::Otlp;
+
+#[cfg(feature = "otlp")]
+mod native {
+    use crate::batch::{self, BatchSink, Driver, Push};
+    use crate::dedup::StormNote;
+    use crate::fields::{Redaction, SpanFields};
+    use crate::http::Endpoint;
+    use crate::json;
+    use crate::otel::TraceIds;
+    use crate::run;
+    use crate::syslog;
+    use std::fmt::{self, Write as _};
+    use std::io;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use tracing::field::{Field, Visit};
+    use tracing::{Event, Level, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer};
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// Exports each event as a log record over OTLP/HTTP with the JSON encoding, in batches from
+    /// a background thread
+    ///
+    /// The level maps to the severity number of the OpenTelemetry log data model. The target,
+    /// call site, run ID and fields go in attributes, such as `code.lineno` and `user_id`, with
+    /// the fields of enclosing spans prefixed with the span name, such as `request.id`. Records
+    /// carry the trace and span IDs found with [`Builder::trace_ids`](crate::Builder::trace_ids).
+    #[derive(Debug)]
+    pub(crate) struct Otlp {
+        /// Log records, as JSON objects
+        sender: batch::Sender<String>,
+        redaction: Redaction,
+        trace_ids: Option<TraceIds>,
+    }
+
+    impl Otlp {
+        /// Starts the thread exporting events to the collector at `url`, to the standard logs
+        /// endpoint unless `url` has a path, returning the layer that hands events to it, and
+        /// the sink that waits for it to export them all on shutdown
+        ///
+        /// Only plain HTTP is supported, such as to a collector running alongside the
+        /// application.
+        pub(crate) fn connect(
+            url: &str,
+            service_name: &str,
+            trace_ids: Option<TraceIds>,
+            redaction: Redaction,
+            driver: &Driver,
+        ) -> io::Result<(Self, BatchSink<String>)> {
+            let endpoint = Endpoint::parse(url, "/v1/logs")?;
+            Self::start(endpoint, service_name, trace_ids, redaction, driver)
+        }
+
+        /// Starts the thread exporting events to `endpoint`, see [`connect`](Self::connect)
+        fn start(
+            endpoint: impl Push,
+            service_name: &str,
+            trace_ids: Option<TraceIds>,
+            redaction: Redaction,
+            driver: &Driver,
+        ) -> io::Result<(Self, BatchSink<String>)> {
+            let mut resource = String::from("{\"attributes\":[");
+            put_attribute(&mut resource, "service.name", &AnyValue::Str(service_name));
+            if let Some(host) = syslog::hostname() {
+                put_attribute(&mut resource, "host.name", &AnyValue::Str(&host));
+            }
+            resource.push_str("]}");
+            let scope = format!(
+                "{{\"name\":\"{}\",\"version\":\"{}\"}}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            );
+            let (sender, sink) = batch::start("OTLP", driver, endpoint, move |records| {
+                let body = format!(
+                    "{{\"resourceLogs\":[{{\"resource\":{},\"scopeLogs\":[{{\"scope\":{},\
+                     \"logRecords\":[{}]}}]}}]}}",
+                    resource,
+                    scope,
+                    records.join(",")
+                );
+                records.clear();
+                body
+            })?;
+            let otlp = Self {
+                sender,
+                redaction,
+                trace_ids,
+            };
+            Ok((otlp, sink))
+        }
+    }
+
+    impl<S> Layer<S> for Otlp
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let metadata = event.metadata();
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            let mut record = format!(
+                "{{\"timeUnixNano\":\"{0}\",\"observedTimeUnixNano\":\"{0}\",\
+                 \"severityNumber\":{1},\"severityText\":\"{2}\"",
+                timestamp,
+                severity(*metadata.level()),
+                metadata.level()
+            );
+
+            let mut visitor = FieldVisitor {
+                redaction: &self.redaction,
+                message: String::new(),
+                attributes: String::new(),
+            };
+            put_attribute(
+                &mut visitor.attributes,
+                "code.namespace",
+                &AnyValue::Str(metadata.target()),
+            );
+            if let Some(file) = metadata.file() {
+                put_attribute(
+                    &mut visitor.attributes,
+                    "code.filepath",
+                    &AnyValue::Str(file),
+                );
+            }
+            if let Some(line) = metadata.line() {
+                put_attribute(
+                    &mut visitor.attributes,
+                    "code.lineno",
+                    &AnyValue::Int(line.to_string()),
+                );
+            }
+            put_attribute(
+                &mut visitor.attributes,
+                "run_id",
+                &AnyValue::Str(run::run_id()),
+            );
+            if let Some(scope) = ctx.event_scope(event) {
+                for span in scope.from_root() {
+                    if let Some(fields) = span.extensions().get::<SpanFields>() {
+                        for (name, value) in fields.iter() {
+                            let name = format!("{}.{}", span.name(), name);
+                            put_attribute(&mut visitor.attributes, &name, &AnyValue::Str(value));
+                        }
+                    }
+                }
+            }
+            event.record(&mut visitor);
+            let mut message = visitor.message;
+            // The formatted output comes next and shows the note too
+            if let Some(note) = StormNote::take() {
+                let suppressed = AnyValue::Int(note.suppressed().to_string());
+                put_attribute(&mut visitor.attributes, "repeated", &suppressed);
+                let _ = write!(message, " {}", note);
+                note.restore();
+            }
+
+            record.push_str(",\"body\":{\"stringValue\":");
+            let _ = json::write_str(&mut record, &message);
+            let _ = write!(record, "}},\"attributes\":[{}]", visitor.attributes);
+            let span = ctx.event_span(event);
+            if let Some((trace_id, span_id)) = self.trace_ids.as_ref().and_then(|ids| ids.of(span))
+            {
+                record.push_str(",\"traceId\":");
+                let _ = json::write_str(&mut record, &trace_id);
+                record.push_str(",\"spanId\":");
+                let _ = json::write_str(&mut record, &span_id);
+            }
+            record.push('}');
+            self.sender.send(record);
+        }
+    }
+
+    /// The severity number of a level, the first of the range the log data model gives it
+    fn severity(level: Level) -> u8 {
+        match level {
+            Level::TRACE => 1,
+            Level::DEBUG => 5,
+            Level::INFO => 9,
+            Level::WARN => 13,
+            Level::ERROR => 17,
+        }
+    }
+
+    enum AnyValue<'a> {
+        Str(&'a str),
+        /// Written as a string, as 64-bit integers are in the JSON encoding
+        Int(String),
+        Double(f64),
+        Bool(bool),
+    }
+
+    /// Writes a key-value pair to a list of attributes, preceded by a comma unless it is the
+    /// first
+    fn put_attribute(attributes: &mut String, key: &str, value: &AnyValue<'_>) {
+        if !attributes.ends_with('[') && !attributes.is_empty() {
+            attributes.push(',');
+        }
+        attributes.push_str("{\"key\":");
+        let _ = json::write_str(attributes, key.strip_prefix("r#").unwrap_or(key));
+        attributes.push_str(",\"value\":{");
+        let _ = match value {
+            AnyValue::Str(value) => {
+                attributes.push_str("\"stringValue\":");
+                json::write_str(attributes, value)
+            }
+            AnyValue::Int(value) => write!(attributes, "\"intValue\":\"{}\"", value),
+            AnyValue::Double(value) => write!(attributes, "\"doubleValue\":{}", value),
+            AnyValue::Bool(value) => write!(attributes, "\"boolValue\":{}", value),
+        };
+        attributes.push_str("}}");
+    }
+
+    struct FieldVisitor<'a> {
+        redaction: &'a Redaction,
+        message: String,
+        /// The attributes of the record, as JSON objects separated by commas
+        attributes: String,
+    }
+
+    impl FieldVisitor<'_> {
+        fn put(&mut self, field: &Field, value: AnyValue<'_>) {
+            let name = field.name();
+            if name.starts_with("log.") {
+                return;
+            }
+            let value = if self.redaction.matches(name) {
+                AnyValue::Str(Redaction::REDACTED)
+            } else {
+                value
+            };
+            put_attribute(&mut self.attributes, name, &value);
+        }
+    }
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_i64(&mut self, field: &Field, value: i64) {
+            self.put(field, AnyValue::Int(value.to_string()))
+        }
+
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            // Beyond the range of intValue
+            if value > i64::MAX as u64 {
+                self.put(field, AnyValue::Str(&value.to_string()))
+            } else {
+                self.put(field, AnyValue::Int(value.to_string()))
+            }
+        }
+
+        fn record_f64(&mut self, field: &Field, value: f64) {
+            if value.is_finite() {
+                self.put(field, AnyValue::Double(value))
+            } else {
+                self.record_debug(field, &value)
+            }
+        }
+
+        fn record_bool(&mut self, field: &Field, value: bool) {
+            self.put(field, AnyValue::Bool(value))
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "message" {
+                self.message = value.to_owned();
+            } else {
+                self.put(field, AnyValue::Str(value))
+            }
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.record_str(field, &format!("{:?}", value))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::batch::Recorded;
+        use crate::fields::FieldLayer;
+        use crate::shutdown::Sink;
+        use tracing::level_filters::STATIC_MAX_LEVEL;
+        use tracing_subscriber::layer::SubscriberExt;
+        use tracing_subscriber::Registry;
+
+        /// The bodies exported for the events `log` logs, with the times replaced by `T`
+        fn exported(log: impl FnOnce()) -> Vec<String> {
+            let recorded = Recorded::default();
+            let mut redaction = Redaction::default();
+            redaction.add("password");
+            let (otlp, sink) =
+                Otlp::start(recorded.clone(), "my app", None, redaction, &Driver::Thread).unwrap();
+            let subscriber = Registry::default().with(FieldLayer::default()).with(otlp);
+            tracing::subscriber::with_default(subscriber, log);
+            sink.drain().unwrap();
+            let bodies = recorded.0.lock().unwrap().clone();
+            bodies.iter().map(|body| without_times(body)).collect()
+        }
+
+        fn without_times(body: &str) -> String {
+            let mut parts = body.split("UnixNano\":\"");
+            let mut replaced = parts.next().unwrap_or_default().to_owned();
+            for part in parts {
+                replaced.push_str("UnixNano\":\"T");
+                replaced.push_str(part.trim_start_matches(|c: char| c.is_ascii_digit()));
+            }
+            replaced
+        }
+
+        fn attributes(values: &[(&str, AnyValue<'_>)]) -> String {
+            let mut attributes = String::from("[");
+            for (key, value) in values {
+                put_attribute(&mut attributes, key, value);
+            }
+            attributes.push(']');
+            attributes
+        }
+
+        #[test]
+        fn attributes_are_typed_key_value_pairs() {
+            assert_eq!(
+                attributes(&[
+                    ("r#type", AnyValue::Str("a \"b\"\n")),
+                    ("rows", AnyValue::Int("3".to_owned())),
+                    ("ratio", AnyValue::Double(0.5)),
+                    ("ok", AnyValue::Bool(true)),
+                ]),
+                "[{\"key\":\"type\",\"value\":{\"stringValue\":\"a \\\"b\\\"\\n\"}},\
+                 {\"key\":\"rows\",\"value\":{\"intValue\":\"3\"}},\
+                 {\"key\":\"ratio\",\"value\":{\"doubleValue\":0.5}},\
+                 {\"key\":\"ok\",\"value\":{\"boolValue\":true}}]"
+            );
+            let mut attributes = String::new();
+            put_attribute(&mut attributes, "a", &AnyValue::Bool(false));
+            assert!(attributes.starts_with("{\"key\""));
+        }
+
+        #[test]
+        fn events_are_exported_as_log_records() {
+            if STATIC_MAX_LEVEL < Level::WARN {
+                return;
+            }
+            let mut line = 0;
+            let bodies = exported(|| {
+                let span = tracing::warn_span!("request", id = 42);
+                let _entered = span.enter();
+                line = line!() + 1;
+                tracing::warn!(
+                    target: "app::db",
+                    rows = 3,
+                    big = u64::MAX,
+                    nan = f64::NAN,
+                    password = "x",
+                    "slow \"query\""
+                );
+            });
+            let mut resource = String::from("{\"attributes\":[");
+            put_attribute(&mut resource, "service.name", &AnyValue::Str("my app"));
+            if let Some(host) = syslog::hostname() {
+                put_attribute(&mut resource, "host.name", &AnyValue::Str(&host));
+            }
+            resource.push_str("]}");
+            let mut file = String::new();
+            json::write_str(&mut file, file!()).unwrap();
+            assert_eq!(
+                bodies,
+                [format!(
+                    "{{\"resourceLogs\":[{{\"resource\":{},\"scopeLogs\":[{{\"scope\":\
+                     {{\"name\":\"{}\",\"version\":\"{}\"}},\"logRecords\":[\
+                     {{\"timeUnixNano\":\"T\",\"observedTimeUnixNano\":\"T\",\
+                     \"severityNumber\":13,\"severityText\":\"WARN\",\
+                     \"body\":{{\"stringValue\":\"slow \\\"query\\\"\"}},\"attributes\":[\
+                     {{\"key\":\"code.namespace\",\"value\":{{\"stringValue\":\"app::db\"}}}},\
+                     {{\"key\":\"code.filepath\",\"value\":{{\"stringValue\":{}}}}},\
+                     {{\"key\":\"code.lineno\",\"value\":{{\"intValue\":\"{}\"}}}},\
+                     {{\"key\":\"run_id\",\"value\":{{\"stringValue\":\"{}\"}}}},\
+                     {{\"key\":\"request.id\",\"value\":{{\"stringValue\":\"42\"}}}},\
+                     {{\"key\":\"rows\",\"value\":{{\"intValue\":\"3\"}}}},\
+                     {{\"key\":\"big\",\"value\":{{\"stringValue\":\"{}\"}}}},\
+                     {{\"key\":\"nan\",\"value\":{{\"stringValue\":\"NaN\"}}}},\
+                     {{\"key\":\"password\",\"value\":{{\"stringValue\":\"[REDACTED]\"}}}}]}}\
+                     ]}}]}}]}}",
+                    resource,
+                    env!("CARGO_PKG_NAME"),
+                    env!("CARGO_PKG_VERSION"),
+                    file,
+                    line,
+                    run::run_id(),
+                    u64::MAX
+                )]
+            );
+        }
+
+        #[test]
+        fn severities_start_the_ranges_of_the_data_model() {
+            let severities: Vec<_> = [
+                Level::TRACE,
+                Level::DEBUG,
+                Level::INFO,
+                Level::WARN,
+                Level::ERROR,
+            ]
+            .iter()
+            .map(|level| severity(*level))
+            .collect();
+            assert_eq!(severities, [1, 5, 9, 13, 17]);
+        }
+    }
+}
+
+#[cfg(not(feature = "otlp"))]
+mod unsupported {
+    use crate::batch::Driver;
+    use crate::fields::Redaction;
+    use crate::otel::TraceIds;
+    use crate::shutdown::Sink;
+    use std::io;
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::Layer;
+
+    /// Never constructed without the `otlp` feature
+    #[derive(Debug)]
+    pub(crate) enum Otlp {}
+
+    /// Never constructed without the `otlp` feature
+    #[derive(Debug)]
+    pub(crate) enum OtlpSink {}
+
+    impl Otlp {
+        pub(crate) fn connect(
+            _url: &str,
+            _service_name: &str,
+            _trace_ids: Option<TraceIds>,
+            _redaction: Redaction,
+            _driver: &Driver,
+        ) -> io::Result<(Self, OtlpSink)> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the `otlp` feature is not enabled",
+            ))
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Otlp {}
+
+    impl Sink for OtlpSink {
+        fn drain(&self) -> io::Result<()> {
+            match *self {}
+        }
+
+        fn close(&self, _marker: &str) -> io::Result<()> {
+            match *self {}
+        }
+    }
+}
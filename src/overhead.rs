@@ -0,0 +1,177 @@
+use crate::timing::DisplayDuration;
+use std::any::TypeId;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::span::{Attributes, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Id, Metadata, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context, Layer};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static EVENTS: AtomicU64 = AtomicU64::new(0);
+static NANOS: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+/// The parts of the logging pipeline whose cost is measured
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Stage {
+    Filtering,
+    Formatting,
+    Writing,
+}
+
+/// Starts measuring, see [`Builder::measure_overhead`](crate::Builder::measure_overhead)
+pub(crate) fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Runs `f`, adding the time it takes to `stage` when measuring
+pub(crate) fn measure<T>(stage: Stage, f: impl FnOnce() -> T) -> T {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return f();
+    }
+    let start = Instant::now();
+    let result = f();
+    let nanos = start.elapsed().as_nanos() as u64;
+    NANOS[stage as usize].fetch_add(nanos, Ordering::Relaxed);
+    if let Stage::Formatting = stage {
+        EVENTS.fetch_add(1, Ordering::Relaxed);
+    }
+    result
+}
+
+/// Time spent in the logging pipeline since measuring started, or since the last reset
+///
+/// Retrieved with [`Overhead::get`] once enabled with
+/// [`Builder::measure_overhead`](crate::Builder::measure_overhead). Displays as the cost per
+/// 10,000 events, such as `filtering 210µs, formatting 4.12ms, writing 9.87ms per 10k events`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Overhead {
+    /// Number of events formatted
+    pub events: u64,
+    /// Time spent deciding which events and spans are enabled and passing field and message
+    /// filters
+    pub filtering: Duration,
+    /// Time spent formatting events into lines
+    pub formatting: Duration,
+    /// Time spent writing lines to their destination
+    pub writing: Duration,
+}
+
+impl Overhead {
+    /// Returns the overhead measured so far
+    pub fn get() -> Self {
+        let nanos =
+            |stage: Stage| Duration::from_nanos(NANOS[stage as usize].load(Ordering::Relaxed));
+        Self {
+            events: EVENTS.load(Ordering::Relaxed),
+            filtering: nanos(Stage::Filtering),
+            formatting: nanos(Stage::Formatting),
+            writing: nanos(Stage::Writing),
+        }
+    }
+
+    /// Starts measuring from zero again, to compare workloads or configurations
+    pub fn reset() {
+        EVENTS.store(0, Ordering::Relaxed);
+        for nanos in &NANOS {
+            nanos.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Scales the durations to what `events` events would take on average
+    pub fn per_events(&self, events: u64) -> Self {
+        let scale = |total: Duration| match self.events {
+            0 => Duration::ZERO,
+            n => {
+                Duration::from_nanos((total.as_nanos() * u128::from(events) / u128::from(n)) as u64)
+            }
+        };
+        Self {
+            events,
+            filtering: scale(self.filtering),
+            formatting: scale(self.formatting),
+            writing: scale(self.writing),
+        }
+    }
+}
+
+impl fmt::Display for Overhead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let per_10k = self.per_events(10_000);
+        write!(
+            f,
+            "filtering {}, formatting {}, writing {} per 10k events",
+            DisplayDuration(per_10k.filtering),
+            DisplayDuration(per_10k.formatting),
+            DisplayDuration(per_10k.writing)
+        )
+    }
+}
+
+/// Wraps a filtering layer to measure the time spent in it
+pub(crate) struct Measured<L>(pub(crate) L);
+
+impl<S, L> Layer<S> for Measured<L>
+where
+    S: Subscriber,
+    L: Layer<S>,
+{
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.0.on_layer(subscriber)
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.0.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        measure(Stage::Filtering, || self.0.enabled(metadata, ctx))
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        self.0.new_span(attrs, id, ctx)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.0.max_level_hint()
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        self.0.on_record(span, values, ctx)
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.0.on_follows_from(span, follows, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        self.0.on_event(event, ctx)
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.0.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.0.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.0.on_close(id, ctx)
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.0.on_id_change(old, new, ctx)
+    }
+
+    #[doc(hidden)]
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const _ as *const ())
+        } else {
+            self.0.downcast_raw(id)
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use crate::shutdown::Sink;
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::io::{self, Write};
+use std::sync::{mpsc, Arc, Mutex, Weak};
+use std::time::Duration;
+use std::{panic, thread};
+use tracing::Level;
+
+/// The target of the events emitted for panics
+pub(crate) const TARGET: &str = "panic";
+
+#[allow(deprecated)] // `PanicHookInfo` requires Rust 1.81
+type Hook = Arc<dyn Fn(&panic::PanicInfo<'_>) + Send + Sync>;
+
+/// The hook that was installed before the first of ours, which shutting down puts back
+static REPLACED: Mutex<Option<Hook>> = Mutex::new(None);
+
+/// How long the hook waits for the sinks to be drained after recording a panic
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The sinks of the installed subscriber, drained after a panic is recorded, as the process may
+/// exit before the guard shuts logging down
+static SINKS: Mutex<Vec<Weak<dyn Sink>>> = Mutex::new(Vec::new());
+
+/// The buffers of recent events kept to be written out on a crash
+static RECENT: Mutex<Vec<Weak<dyn RecentEvents>>> = Mutex::new(Vec::new());
+
+/// A buffer of the last events logged, whose length the `panic` event records
+pub(crate) trait RecentEvents: Send + Sync {
+    /// How many events it holds
+    fn recent_events(&self) -> usize;
+}
+
+/// Records the length of `buffer` in the `panic` events, for as long as it is alive
+pub(crate) fn register_recent(buffer: Weak<dyn RecentEvents>) {
+    let mut recent = RECENT.lock().unwrap_or_else(|e| e.into_inner());
+    recent.retain(|buffer| buffer.strong_count() > 0);
+    recent.push(buffer);
+}
+
+/// Drains `sinks` after recording a panic, in place of those registered before
+pub(crate) fn register_sinks(sinks: &[Arc<dyn Sink>]) {
+    *SINKS.lock().unwrap_or_else(|e| e.into_inner()) = sinks.iter().map(Arc::downgrade).collect();
+}
+
+/// Takes the installed panic hook for one of ours to run, keeping the first it replaces to put
+/// it back with [`uninstall_hooks`]
+pub(crate) fn take_hook() -> Hook {
+    let previous: Hook = Arc::from(panic::take_hook());
+    REPLACED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(|| previous.clone());
+    previous
+}
+
+/// Puts back the panic hook that ours replaced, along with any installed after them
+///
+/// Hooks can't be changed while the thread unwinds, such as when the guard is dropped by a panic
+/// in `main`, so they are left in place then.
+pub(crate) fn uninstall_hooks() {
+    SINKS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    if thread::panicking() {
+        return;
+    }
+    let replaced = REPLACED.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(replaced) = replaced {
+        panic::set_hook(Box::new(move |info| replaced(info)));
+    }
+}
+
+/// Emits a structured `panic` event through the subscriber whenever a thread panics, before
+/// running the previously installed panic hook
+pub(crate) fn install_panic_record() {
+    let previous = take_hook();
+    panic::set_hook(Box::new(move |info| {
+        record(info);
+        previous(info);
+    }));
+}
+
+/// Replaces the panic hook with one emitting only the `panic` event, which the formatter shows in
+/// place of the default panic message, unless the subscriber would filter the event out
+pub(crate) fn install_panic_hook() {
+    let previous = take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if tracing::enabled!(target: TARGET, Level::ERROR) {
+            record(info);
+        } else {
+            previous(info);
+        }
+    }));
+}
+
+/// Extracts the message passed to `panic!`
+pub(crate) fn message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+#[allow(deprecated)] // `PanicHookInfo` requires Rust 1.81
+fn record(info: &panic::PanicInfo<'_>) {
+    let thread = thread::current();
+    let thread = thread.name().unwrap_or("<unnamed>");
+    let location = info.location().map(|location| {
+        format!(
+            "{}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        )
+    });
+    let message = message(info.payload());
+    let backtrace = Backtrace::capture();
+    // Read before any other hook writes the buffers out and empties them
+    let recent_events = RECENT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|buffer| buffer.recent_events())
+        .max();
+
+    if backtrace.status() == BacktraceStatus::Captured {
+        tracing::error!(
+            target: TARGET,
+            thread,
+            location = location.as_deref(),
+            recent_events,
+            backtrace = %backtrace,
+            "{}",
+            message
+        );
+    } else {
+        tracing::error!(
+            target: TARGET,
+            thread,
+            location = location.as_deref(),
+            recent_events,
+            "{}",
+            message
+        );
+    }
+
+    // The process may abort right after the hook, so don't leave anything in buffers
+    drain_sinks();
+    let _ = io::stderr().flush();
+}
+
+/// Drains the registered sinks on a thread of its own, giving up after `DRAIN_TIMEOUT`, as the
+/// panicking thread may hold the lock of one of them
+fn drain_sinks() {
+    let sinks: Vec<Arc<dyn Sink>> = SINKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect();
+    if sinks.is_empty() {
+        return;
+    }
+    let (done, drained) = mpsc::channel();
+    let draining = thread::Builder::new()
+        .name("log panic".to_owned())
+        .spawn(move || {
+            for sink in &sinks {
+                let _ = sink.drain();
+            }
+            let _ = done.send(());
+        });
+    if draining.is_ok() {
+        let _ = drained.recv_timeout(DRAIN_TIMEOUT);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{status, template};
+use std::borrow::Cow;
+use std::fmt::{self, Write};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR, MAIN_SEPARATOR_STR};
+
+/// How the source file of an event is shown
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum PathStyle {
+    /// Only the file name, such as `lib.rs`
+    #[default]
+    FileName,
+    /// The path relative to the crate or workspace it belongs to, such as `src/db/mod.rs`
+    ///
+    /// Dependencies are shown relative to the Cargo registry or git checkout they were built
+    /// from, such as `hyper-0.14.27/src/client/conn.rs`, and the standard library relative to
+    /// the Rust source tree.
+    Relative,
+}
+
+/// Directories that source paths are shown relative to, found once at startup
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SourceRoots(Vec<String>);
+
+impl SourceRoots {
+    /// Uses the manifest directory that `cargo run` exports, and the working directory
+    pub(crate) fn from_env() -> Self {
+        let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").ok();
+        let current_dir = std::env::current_dir()
+            .ok()
+            .and_then(|dir| dir.to_str().map(str::to_owned));
+        Self(manifest_dir.into_iter().chain(current_dir).collect())
+    }
+
+    /// Shortens `path` according to `style`
+    pub(crate) fn shorten<'a>(&self, path: &'a str, style: &PathStyle) -> &'a str {
+        match style {
+            PathStyle::FileName => path.rsplit(is_separator).next().unwrap_or(path),
+            PathStyle::Relative => self.relative(path),
+        }
+    }
+
+    fn relative<'a>(&self, path: &'a str) -> &'a str {
+        // Skip the directory named after the registry index or the git checkout's hash
+        let dependency = [("registry/src", 1), ("git/checkouts", 2), ("rustc", 1)]
+            .iter()
+            .find_map(|(marker, skip)| after_components(path, marker, *skip));
+        if let Some(relative) = dependency {
+            return relative;
+        }
+        self.0
+            .iter()
+            .filter_map(|root| path.strip_prefix(root.as_str()))
+            .map(|relative| relative.trim_start_matches(is_separator))
+            .min_by_key(|relative| relative.len())
+            .unwrap_or(path)
+    }
+}
+
+/// Prefixes of source paths replaced with others, so the locations of binaries built in a
+/// container or on CI point to where the sources are, see
+/// [`Builder::path_prefix`](crate::Builder::path_prefix)
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PathPrefixes(Vec<(String, String)>);
+
+impl PathPrefixes {
+    /// Replaces `from` with `to`, replacing any earlier rule for `from`
+    pub(crate) fn add(&mut self, from: String, to: String) {
+        let from = from.trim_end_matches(is_separator).to_owned();
+        self.0.retain(|(known, _)| *known != from);
+        self.0.push((from, to));
+    }
+
+    /// `path` with the longest prefix that has a replacement replaced, or unchanged
+    pub(crate) fn rewrite<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        let rule = self
+            .0
+            .iter()
+            .filter_map(|(from, to)| {
+                let rest = path.strip_prefix(from.as_str())?;
+                let whole = rest.is_empty() || rest.starts_with(is_separator);
+                whole.then_some((from, to, rest))
+            })
+            .max_by_key(|(from, _, _)| from.len());
+        let (to, rest) = match rule {
+            Some((_, to, rest)) => (to.trim_end_matches(is_separator), rest),
+            None => return Cow::Borrowed(path),
+        };
+        // URLs, such as those of a repository at a pinned commit, only know slashes
+        if to.contains("://") {
+            let rest = rest.chars().map(|c| if is_separator(c) { '/' } else { c });
+            return Cow::Owned(to.chars().chain(rest).collect());
+        }
+        Cow::Owned(format!("{}{}", to, rest))
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c == '/' || c == MAIN_SEPARATOR
+}
+
+/// Returns what follows `marker` in `path`, minus another `skip` components
+fn after_components<'a>(path: &'a str, marker: &str, skip: usize) -> Option<&'a str> {
+    let marker = marker.replace('/', MAIN_SEPARATOR_STR);
+    let start = path
+        .match_indices(marker.as_str())
+        .find(|(i, _)| *i == 0 || path[..*i].ends_with(is_separator))?
+        .0;
+    let mut rest = path[start + marker.len()..].trim_start_matches(is_separator);
+    for _ in 0..skip {
+        rest = rest.split_once(is_separator)?.1;
+    }
+    Some(rest)
+}
+
+/// Turns source locations into OSC 8 hyperlinks that terminals let users click
+#[derive(Debug, Clone)]
+pub(crate) struct Hyperlinks {
+    template: String,
+    /// Directory that relative source paths are resolved against
+    base: Option<PathBuf>,
+    prefixes: PathPrefixes,
+}
+
+impl Hyperlinks {
+    pub(crate) fn new(template: String, prefixes: PathPrefixes) -> Self {
+        Self {
+            template,
+            base: std::env::current_dir().ok(),
+            prefixes,
+        }
+    }
+
+    /// Whether stderr is a terminal that renders OSC 8 hyperlinks
+    ///
+    /// `FORCE_HYPERLINK=1` or `FORCE_HYPERLINK=0` overrides the detection.
+    pub(crate) fn supported() -> bool {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        match var("FORCE_HYPERLINK").as_str() {
+            "" => {}
+            "0" | "false" => return false,
+            _ => return true,
+        }
+        if !status::tty() || var("TERM") == "dumb" {
+            return false;
+        }
+        let term_program = var("TERM_PROGRAM");
+        ["iTerm.app", "vscode", "WezTerm", "Hyper", "ghostty"].contains(&term_program.as_str())
+            || var("VTE_VERSION").parse::<u32>().is_ok_and(|v| v >= 5000)
+            || !var("WT_SESSION").is_empty()
+            || !var("KONSOLE_VERSION").is_empty()
+            || ["xterm-kitty", "alacritty", "foot"].contains(&var("TERM").as_str())
+    }
+
+    /// Writes `text` as a hyperlink to `line` of `file`
+    pub(crate) fn write(
+        &self,
+        f: &mut dyn Write,
+        file: &str,
+        line: u32,
+        text: &dyn fmt::Display,
+    ) -> fmt::Result {
+        let path = self.resolve(file);
+        f.write_str("\x1b]8;;")?;
+        template::render(f, &self.template, |name, f| match name {
+            "path" => Some(f.write_str(&path)),
+            "line" => Some(write!(f, "{}", line)),
+            _ => None,
+        })?;
+        write!(f, "\x1b\\{}\x1b]8;;\x1b\\", text)
+    }
+
+    /// The absolute path of `file`, or where its prefix is replaced with
+    fn resolve(&self, file: &str) -> String {
+        let rewritten = self.prefixes.rewrite(file);
+        let path = match (&rewritten, &self.base) {
+            (Cow::Borrowed(_), Some(base)) if Path::new(file).is_relative() => {
+                let path = base.join(file).display().to_string();
+                self.prefixes.rewrite(&path).into_owned()
+            }
+            _ => rewritten.into_owned(),
+        };
+        // Links need the home directory spelled out
+        match (path.strip_prefix('~'), std::env::var("HOME")) {
+            (Some(rest), Ok(home)) if rest.starts_with(is_separator) => format!("{}{}", home, rest),
+            _ => path,
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Everything a binary needs to set up logging and log, with a single import
+//!
+//! Re-exports the `tracing` macros and the types of this crate used to configure it, along with
+//! `StructOpt` for parsing [`Verbosity`] from the command line, so binaries don't need to
+//! depend on `tracing` and `structopt` themselves, or keep their versions in step with this
+//! crate. The `#[instrument]` attribute is the exception, as it refers to the `tracing` crate by
+//! name.
+//!
+//! ```no_run
+//! # #[cfg(feature = "cli")] {
+//! use pretty_tracing_subscriber::prelude::*;
+//!
+//! init("my_app", Verbosity::from_args());
+//! info!(version = env!("CARGO_PKG_VERSION"), "started");
+//! let span = info_span!("connect", addr = "127.0.0.1:8080");
+//! span.in_scope(|| debug!("connecting"));
+//! # }
+//! ```
+
+pub use crate::{
+    init, init_auto, lazy, log_once, warn_once, Builder, LevelStyle, LogGuard, Progress,
+    StatusLine, Verbosity, Workspace,
+};
+#[cfg(feature = "cli")]
+pub use structopt::StructOpt;
+pub use tracing::{
+    self, debug, debug_span, error, error_span, event, info, info_span, span, trace, trace_span,
+    warn, warn_span, Instrument, Level,
+};
@@ -0,0 +1,253 @@
+use crate::fields::SpanFields;
+use crate::timing::DisplayDuration;
+use ansi_term::{Color, Style};
+use std::fmt::{self, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Renders the close events of spans following a well-known instrumentation convention
+///
+/// Presets are registered with [`Builder::register_preset`](crate::Builder::register_preset)
+/// and enabled by name, either from code or with `--log-preset`. The first enabled preset that
+/// matches a span renders its close event.
+pub trait Preset: Send + Sync {
+    /// The name used to enable the preset, such as `http`
+    fn name(&self) -> &str;
+
+    /// Whether the span is one that this preset knows how to render
+    fn matches(&self, fields: &SpanFields) -> bool;
+
+    /// Writes the summary line that replaces the generic `close` message
+    ///
+    /// `elapsed` is how long the span was open, when known.
+    fn write_close(
+        &self,
+        f: &mut dyn Write,
+        fields: &SpanFields,
+        elapsed: Option<Duration>,
+    ) -> fmt::Result;
+}
+
+impl fmt::Debug for dyn Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Preset").field(&self.name()).finish()
+    }
+}
+
+/// The presets that ship with this crate, with their default settings
+pub(crate) fn builtin() -> Vec<Arc<dyn Preset>> {
+    vec![
+        Arc::new(Http),
+        Arc::new(Grpc),
+        Arc::new(Db {
+            slow_query: Duration::from_millis(100),
+        }),
+    ]
+}
+
+/// Writes the latency reported by the instrumentation, or the time the span was open
+fn write_latency(
+    f: &mut dyn Write,
+    latency: Option<&str>,
+    elapsed: Option<Duration>,
+) -> fmt::Result {
+    match (latency, elapsed) {
+        (Some(latency), _) => write!(f, "{:>8}", latency),
+        (None, Some(elapsed)) => write!(f, "{:>8}", DisplayDuration(elapsed).to_string()),
+        (None, None) => Ok(()),
+    }
+}
+
+/// Access-log style lines for spans carrying `http.method`, `http.route` and `http.status_code`
+pub(crate) struct Http;
+
+impl Http {
+    /// Colors the status code by its class
+    fn status_style(status: &str) -> Style {
+        match status.as_bytes().first() {
+            Some(b'2') => Color::Green.bold(),
+            Some(b'3') => Color::Cyan.bold(),
+            Some(b'4') => Color::Yellow.bold(),
+            Some(b'5') => Color::Red.bold(),
+            _ => Style::new().dimmed(),
+        }
+    }
+}
+
+impl Preset for Http {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    fn matches(&self, fields: &SpanFields) -> bool {
+        fields.get("http.method").is_some()
+    }
+
+    fn write_close(
+        &self,
+        f: &mut dyn Write,
+        fields: &SpanFields,
+        elapsed: Option<Duration>,
+    ) -> fmt::Result {
+        let method = fields.get("http.method").unwrap_or_default();
+        let route = fields
+            .get("http.route")
+            .or_else(|| fields.get("http.target"))
+            .unwrap_or("-");
+        let status = fields.get("http.status_code").unwrap_or("---");
+
+        write!(f, "{:<7} {:<32} ", method, route)?;
+        write!(f, "{}", Self::status_style(status).paint(status))?;
+        f.write_char(' ')?;
+        write_latency(f, fields.get("latency"), elapsed)
+    }
+}
+
+/// Single-line summaries of gRPC calls, following the OpenTelemetry `rpc.*` conventions
+pub(crate) struct Grpc;
+
+impl Grpc {
+    /// Canonical names of the gRPC status codes, indexed by code
+    const STATUS_NAMES: [&'static str; 17] = [
+        "OK",
+        "CANCELLED",
+        "UNKNOWN",
+        "INVALID_ARGUMENT",
+        "DEADLINE_EXCEEDED",
+        "NOT_FOUND",
+        "ALREADY_EXISTS",
+        "PERMISSION_DENIED",
+        "RESOURCE_EXHAUSTED",
+        "FAILED_PRECONDITION",
+        "ABORTED",
+        "OUT_OF_RANGE",
+        "UNIMPLEMENTED",
+        "INTERNAL",
+        "UNAVAILABLE",
+        "DATA_LOSS",
+        "UNAUTHENTICATED",
+    ];
+
+    /// Colors the status by whether the caller or the server is at fault
+    fn status_style(code: Option<usize>) -> Style {
+        match code {
+            Some(0) => Color::Green.bold(),
+            Some(1) | Some(3) | Some(5) | Some(6) | Some(7) | Some(9) | Some(11) | Some(16) => {
+                Color::Yellow.bold()
+            }
+            Some(_) => Color::Red.bold(),
+            None => Style::new().dimmed(),
+        }
+    }
+}
+
+impl Preset for Grpc {
+    fn name(&self) -> &str {
+        "grpc"
+    }
+
+    fn matches(&self, fields: &SpanFields) -> bool {
+        fields.get("rpc.system") == Some("grpc")
+            || (fields.get("rpc.service").is_some() && fields.get("rpc.method").is_some())
+    }
+
+    fn write_close(
+        &self,
+        f: &mut dyn Write,
+        fields: &SpanFields,
+        elapsed: Option<Duration>,
+    ) -> fmt::Result {
+        let service = fields.get("rpc.service").unwrap_or("-");
+        let method = fields.get("rpc.method").unwrap_or("-");
+        let code = fields
+            .get("rpc.grpc.status_code")
+            .and_then(|code| code.parse::<usize>().ok());
+        let status = match code {
+            Some(code) => Self::STATUS_NAMES.get(code).copied().unwrap_or("UNKNOWN"),
+            None => "---",
+        };
+
+        write!(f, "{:<40} ", format!("{}/{}", service, method))?;
+        write!(
+            f,
+            "{}",
+            Self::status_style(code).paint(format!("{:<19}", status))
+        )?;
+        f.write_char(' ')?;
+        write_latency(f, fields.get("latency"), elapsed)
+    }
+}
+
+/// Dim one-line query summaries for spans carrying `db.system` and `db.statement`
+pub(crate) struct Db {
+    /// Queries that take at least this long are highlighted instead of dimmed
+    pub(crate) slow_query: Duration,
+}
+
+impl Db {
+    /// Longest statement shown before it's cut off
+    const MAX_STATEMENT: usize = 80;
+
+    /// Collapses whitespace so multi-line statements fit on one line, truncating long ones
+    fn statement(statement: &str) -> String {
+        let mut out = String::with_capacity(statement.len().min(Self::MAX_STATEMENT));
+        for (i, word) in statement.split_whitespace().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            out.push_str(word);
+        }
+        if let Some((cut, _)) = out.char_indices().nth(Self::MAX_STATEMENT) {
+            out.truncate(cut);
+            out.push('…');
+        }
+        out
+    }
+}
+
+impl Preset for Db {
+    fn name(&self) -> &str {
+        "db"
+    }
+
+    fn matches(&self, fields: &SpanFields) -> bool {
+        fields.get("db.system").is_some() || fields.get("db.statement").is_some()
+    }
+
+    fn write_close(
+        &self,
+        f: &mut dyn Write,
+        fields: &SpanFields,
+        elapsed: Option<Duration>,
+    ) -> fmt::Result {
+        let slow = elapsed.is_some_and(|elapsed| elapsed >= self.slow_query);
+        let style = if slow {
+            Style::new()
+        } else {
+            Style::new().dimmed()
+        };
+
+        let mut line = String::new();
+        if let Some(system) = fields.get("db.system") {
+            write!(line, "[{}] ", system)?;
+        }
+        line.push_str(&Self::statement(fields.get("db.statement").unwrap_or("")));
+        let rows = fields
+            .get("db.rows_affected")
+            .or_else(|| fields.get("rows_affected"))
+            .or_else(|| fields.get("rows_returned"))
+            .or_else(|| fields.get("rows"));
+        if let Some(rows) = rows {
+            write!(line, " ({} rows)", rows)?;
+        }
+        write!(f, "{} ", style.paint(line))?;
+
+        let mut latency = String::new();
+        write_latency(&mut latency, fields.get("latency"), elapsed)?;
+        if slow {
+            write!(f, "{}", Color::Red.bold().paint(latency))
+        } else {
+            write!(f, "{}", style.paint(latency))
+        }
+    }
+}
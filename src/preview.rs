@@ -0,0 +1,37 @@
+use std::io;
+use std::time::Duration;
+use tracing::field;
+
+/// Emits the sample events shown by [`Builder::preview`](crate::Builder::preview)
+pub(crate) fn samples() {
+    tracing::info!(addr = "127.0.0.1:8080", "server listening");
+
+    let request = tracing::info_span!(
+        "request",
+        http.method = "GET",
+        http.route = "/users/:id",
+        http.status_code = field::Empty,
+    );
+    request.in_scope(|| {
+        tracing::debug!(user_id = 42, "loading user");
+        let query = tracing::debug_span!(
+            "query",
+            db.system = "postgresql",
+            db.statement = "SELECT id, name, email FROM users WHERE id = $1",
+        );
+        query.in_scope(|| tracing::trace!(rows = 1, "fetched rows"));
+        request.record("http.status_code", 200);
+    });
+    drop(request);
+
+    let payload = "lorem ipsum dolor sit amet ".repeat(8);
+    tracing::debug!(
+        payload = payload.trim_end(),
+        bytes = payload.len(),
+        "received payload"
+    );
+    tracing::warn!(attempt = 2, retry_in = ?Duration::from_secs(5), "request timed out, retrying");
+
+    let error = io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused");
+    tracing::error!(error = %error, host = "db.internal", "failed to connect to the database");
+}
@@ -0,0 +1,211 @@
+use crate::status::StatusLine;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::Event;
+
+/// Target of the events emitted by [`Progress`]
+const TARGET: &str = "pretty_tracing_subscriber::progress";
+
+/// How often the progress of a transfer is printed when stderr is not a terminal
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// Reports the progress of a transfer, such as a download or an upload, as `info` events
+///
+/// On a terminal the updates are coalesced into a status line that is rewritten in place below
+/// the other events. Otherwise a line is printed for the first update, every
+/// few seconds after that, and when the transfer is finished.
+#[derive(Debug)]
+pub struct Progress {
+    task: String,
+    done: u64,
+    total: Option<u64>,
+}
+
+impl Progress {
+    /// Starts reporting the progress of `task`, which transfers `total` bytes if known
+    pub fn new(task: impl Into<String>, total: Option<u64>) -> Self {
+        let progress = Self {
+            task: task.into(),
+            done: 0,
+            total,
+        };
+        progress.emit(false);
+        progress
+    }
+
+    /// Records that `bytes` more bytes have been transferred
+    pub fn advance(&mut self, bytes: u64) {
+        self.done += bytes;
+        self.emit(false);
+    }
+
+    /// Records that `done` bytes have been transferred in total
+    pub fn set(&mut self, done: u64) {
+        self.done = done;
+        self.emit(false);
+    }
+
+    /// Records that the transfer is complete
+    pub fn finish(self) {
+        self.emit(true);
+    }
+
+    fn emit(&self, finished: bool) {
+        tracing::info!(
+            target: TARGET,
+            done = self.done,
+            total = self.total,
+            finished,
+            "{}",
+            self.task
+        );
+    }
+}
+
+/// The fields of an event emitted by [`Progress`]
+#[derive(Debug, Default)]
+pub(crate) struct ProgressEvent {
+    task: String,
+    done: u64,
+    total: Option<u64>,
+    finished: bool,
+}
+
+impl ProgressEvent {
+    pub(crate) fn of(event: &Event<'_>) -> Option<Self> {
+        if event.metadata().target() != TARGET {
+            return None;
+        }
+        let mut progress = Self::default();
+        event.record(&mut progress);
+        Some(progress)
+    }
+
+    fn write(&self, f: &mut dyn Write) -> fmt::Result {
+        write!(f, "{} {}", self.task, DisplayBytes(self.done))?;
+        if let Some(total) = self.total {
+            write!(f, " / {}", DisplayBytes(total))?;
+            if let Some(percent) = (self.done.min(total) * 100).checked_div(total) {
+                write!(f, " ({}%)", percent)?;
+            }
+        }
+        if self.finished {
+            f.write_str(" done")?;
+        }
+        Ok(())
+    }
+}
+
+impl Visit for ProgressEvent {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "done" => self.done = value,
+            "total" => self.total = Some(value),
+            _ => {}
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == "finished" {
+            self.finished = value;
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.task = format!("{:?}", value);
+        }
+    }
+}
+
+/// Decides how progress events are written
+///
+/// On a terminal each unfinished task is shown as a [`StatusLine`], and only the final update is
+/// written as an event. Otherwise updates for the same task are printed at most every few
+/// seconds.
+#[derive(Debug)]
+pub(crate) struct ProgressLine {
+    tty: bool,
+    state: Mutex<ProgressState>,
+}
+
+#[derive(Debug, Default)]
+struct ProgressState {
+    /// The status line of each unfinished task, on a terminal
+    lines: HashMap<String, StatusLine>,
+    /// When each unfinished task was last printed, when not on a terminal
+    printed: HashMap<String, Instant>,
+}
+
+impl ProgressLine {
+    pub(crate) fn new(tty: bool) -> Self {
+        Self {
+            tty,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Writes the update, after `prefix` has written the time and level, unless it is coalesced
+    pub(crate) fn write<P>(
+        &self,
+        f: &mut dyn Write,
+        event: &ProgressEvent,
+        prefix: P,
+    ) -> fmt::Result
+    where
+        P: FnOnce(&mut dyn Write) -> fmt::Result,
+    {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if self.tty && !event.finished {
+            let mut line = String::new();
+            prefix(&mut line)?;
+            event.write(&mut line)?;
+            match state.lines.get(&event.task) {
+                Some(status) => status.set(line),
+                None => {
+                    state
+                        .lines
+                        .insert(event.task.clone(), StatusLine::new(line));
+                }
+            }
+            return Ok(());
+        }
+
+        if event.finished {
+            state.lines.remove(&event.task);
+            state.printed.remove(&event.task);
+        } else {
+            let now = Instant::now();
+            match state.printed.get(&event.task) {
+                Some(last) if now.duration_since(*last) < INTERVAL => return Ok(()),
+                _ => {}
+            }
+            state.printed.insert(event.task.clone(), now);
+        }
+        prefix(f)?;
+        event.write(f)?;
+        writeln!(f)
+    }
+}
+
+/// Formats a number of bytes with a binary unit, such as `12.3 MiB`
+struct DisplayBytes(u64);
+
+impl fmt::Display for DisplayBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", value, UNITS[unit])
+    }
+}
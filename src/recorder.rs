@@ -0,0 +1,133 @@
+use crate::panic::RecentEvents;
+use crate::status::Stderr;
+use ansi_term::Style;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::panic;
+use std::sync::{Arc, Mutex, Weak};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Context, Filter, Layer};
+
+/// The last `DEBUG` and `TRACE` events hidden by the filters, formatted, which are written out
+/// when an error is logged or a thread panics, see
+/// [`Builder::flight_recorder`](crate::Builder::flight_recorder)
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    lines: Mutex<VecDeque<Vec<u8>>>,
+    capacity: usize,
+    stderr: Stderr,
+}
+
+impl Recorder {
+    /// Keeps up to `capacity` events, writing them to `stderr` when dumped
+    pub(crate) fn new(capacity: usize, stderr: Stderr) -> Arc<Self> {
+        let recorder = Arc::new(Self {
+            lines: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            stderr,
+        });
+        let weak = Arc::downgrade(&recorder);
+        crate::panic::register_recent(weak.clone());
+        let previous = crate::panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Some(recorder) = Weak::upgrade(&weak) {
+                recorder.dump("panic");
+            }
+            previous(info);
+        }));
+        recorder
+    }
+
+    fn push(&self, line: &[u8]) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line.to_vec());
+    }
+
+    /// Writes out the events kept so far, and forgets them, before the `what` that prompted it
+    fn dump(&self, what: &str) {
+        let lines = std::mem::take(&mut *self.lines.lock().unwrap_or_else(|e| e.into_inner()));
+        if lines.is_empty() {
+            return;
+        }
+        let heading = match lines.len() {
+            1 => format!("the hidden event before this {}:", what),
+            n => format!("the last {} hidden events before this {}:", n, what),
+        };
+        let mut out = format!("{}\n", Style::new().dimmed().paint(heading)).into_bytes();
+        for line in lines {
+            out.extend_from_slice(&line);
+        }
+        // A single write keeps the dump together
+        let _ = self.stderr.clone().write_all(&out);
+    }
+}
+
+impl RecentEvents for Recorder {
+    fn recent_events(&self) -> usize {
+        self.lines.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+/// Keeps each formatted event in the recorder
+#[derive(Debug, Clone)]
+pub(crate) struct RecorderWriter(pub(crate) Arc<Recorder>);
+
+impl MakeWriter for RecorderWriter {
+    type Writer = RecorderWriter;
+
+    fn make_writer(&self) -> RecorderWriter {
+        self.clone()
+    }
+}
+
+impl Write for RecorderWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.push(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Dumps the recorder before each error that passes the filters
+pub(crate) struct DumpOnError(pub(crate) Arc<Recorder>);
+
+impl<S: Subscriber> Layer<S> for DumpOnError {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if *event.metadata().level() == Level::ERROR {
+            self.0.dump("error");
+        }
+    }
+}
+
+/// Lets through the `DEBUG` and `TRACE` events and spans that `shown` hides
+pub(crate) struct Hidden<F>(pub(crate) F);
+
+impl<S, F: Filter<S>> Filter<S> for Hidden<F> {
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: &Context<'_, S>) -> bool {
+        *metadata.level() >= Level::DEBUG && !self.0.enabled(metadata, ctx)
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if *metadata.level() >= Level::DEBUG {
+            Interest::sometimes()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(LevelFilter::TRACE)
+    }
+}
@@ -0,0 +1,256 @@
+use crate::config::{self, LogConfig, LogSettings};
+use crate::explain::{Explained, Explainer};
+use crate::overhead::Measured;
+use crate::{banner, dedup, health};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::span::{Attributes, Record};
+use tracing::subscriber::Interest;
+use tracing::{Event, Id, Metadata, Subscriber};
+use tracing_subscriber::filter::Directive;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::EnvFilter;
+
+/// Target of the events recording changes to the filters, which pass whatever the filters
+const AUDIT_TARGET: &str = "pretty_tracing_subscriber::audit";
+
+/// The level of the formatted output when no directives are set
+pub(crate) type LevelPart = Explained<Measured<Option<LevelFilter>>>;
+
+/// The directives of the formatted output, when there are any
+pub(crate) type DirectivesPart = Explained<Measured<Option<EnvFilter>>>;
+
+/// Builds the filters for `settings`, or filters letting everything through without any
+///
+/// A plain level filter is used when there are no directives, as it is cheaper to check.
+pub(crate) fn filters(
+    settings: Option<&LogSettings>,
+    explainer: &Option<Arc<Explainer>>,
+) -> (LevelPart, DirectivesPart) {
+    let (level_filter, directives) = match settings {
+        None => (None, None),
+        Some(settings) if settings.directives.is_empty() => {
+            (Some(settings.level.unwrap_or(LevelFilter::OFF)), None)
+        }
+        Some(settings) => (None, Some(settings)),
+    };
+    let level_filter = Explained {
+        rule: format!(
+            "the verbosity level `{}`",
+            level_filter.unwrap_or(LevelFilter::OFF)
+        ),
+        inner: Measured(level_filter),
+        explainer: explainer.clone(),
+    };
+    let env_filter = Explained {
+        rule: format!(
+            "the filter `{}`",
+            directives.map(ToString::to_string).unwrap_or_default()
+        ),
+        inner: Measured(directives.map(env_filter)),
+        explainer: explainer.clone(),
+    };
+    (level_filter, env_filter)
+}
+
+/// Parses each directive of `settings` on its own, as `EnvFilter::new` would split span
+/// directives with several fields, such as `[request{method=POST,path=/}]`, at their commas
+///
+/// Those directives are ignored with a message, as `EnvFilter` keeps the commas between the
+/// fields in their values, so they would never match.
+///
+/// Muted targets are turned off, and the directives for targets under them are left out, so
+/// they can't show their events again by being more specific.
+fn env_filter(settings: &LogSettings) -> EnvFilter {
+    let muted: Vec<&str> = settings
+        .directives
+        .iter()
+        .filter_map(|directive| config::muted_target(directive))
+        .collect();
+    let level = settings.level.map(|level| level.to_string());
+    let directives = level.into_iter().chain(
+        settings
+            .directives
+            .iter()
+            .filter(|directive| config::muted_target(directive).is_none())
+            .filter(|directive| !muted.iter().any(|target| directive.starts_with(target)))
+            .cloned(),
+    );
+    let off = muted.iter().map(|target| format!("{}=off", target));
+    // Unlike the `ERROR` default of an empty `EnvFilter`, `off` hides the targets no directive
+    // matches, as `EnvFilter::new` does, and is replaced by any level given
+    directives
+        .chain(off)
+        .fold(EnvFilter::new("off"), |filter, directive| {
+            if directive.contains(',') {
+                eprintln!(
+                    "ignoring `{}`: span directives can only select one field, see \
+                     `--log-field` for more",
+                    directive
+                );
+                return filter;
+            }
+            match directive.parse::<Directive>() {
+                Ok(directive) => filter.add_directive(directive),
+                Err(e) => {
+                    eprintln!("ignoring `{}`: {}", directive, e);
+                    filter
+                }
+            }
+        })
+}
+
+/// Whether events at `metadata` pass whatever the filters, as the audit events of
+/// [`FilterHandle::reload_file`], the reports of repeated events and those of failing sinks do
+fn always_passes(metadata: &Metadata<'_>) -> bool {
+    [
+        AUDIT_TARGET,
+        dedup::REPEAT_TARGET,
+        banner::BANNER_TARGET,
+        health::HEALTH_TARGET,
+    ]
+    .contains(&metadata.target())
+}
+
+/// A filtering layer that can be replaced while the subscriber is installed
+///
+/// The events of [`always_passes`] pass.
+pub(crate) struct Reloadable<L>(Arc<RwLock<L>>);
+
+impl<L> Reloadable<L> {
+    pub(crate) fn new(inner: L) -> Self {
+        Self(Arc::new(RwLock::new(inner)))
+    }
+
+    fn set(&self, inner: L) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = inner;
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&L) -> T) -> T {
+        f(&self.0.read().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl<L> Clone for Reloadable<L> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<S, L> Layer<S> for Reloadable<L>
+where
+    S: Subscriber,
+    L: Layer<S>,
+{
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if always_passes(metadata) {
+            return Interest::always();
+        }
+        self.with(|inner| inner.register_callsite(metadata))
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        always_passes(metadata) || self.with(|inner| inner.enabled(metadata, ctx))
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        self.with(|inner| inner.new_span(attrs, id, ctx))
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        // Audit events and reports of repeats are skipped below the hint before reaching the
+        // filter
+        let hint = self.with(|inner| inner.max_level_hint());
+        hint.map(|hint| hint.max(LevelFilter::INFO))
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        self.with(|inner| inner.on_record(span, values, ctx))
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.with(|inner| inner.on_follows_from(span, follows, ctx))
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        self.with(|inner| inner.on_event(event, ctx))
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.with(|inner| inner.on_enter(id, ctx))
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.with(|inner| inner.on_exit(id, ctx))
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.with(|inner| inner.on_close(id, ctx))
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.with(|inner| inner.on_id_change(old, new, ctx))
+    }
+}
+
+/// Replaces the filters of the installed subscriber, see
+/// [`LogGuard::reload_file_settings`](crate::LogGuard::reload_file_settings), and switches its
+/// format between verbose and compact
+pub(crate) struct FilterHandle {
+    pub(crate) config: Mutex<LogConfig>,
+    pub(crate) level: Reloadable<LevelPart>,
+    pub(crate) directives: Reloadable<DirectivesPart>,
+    pub(crate) explainer: Option<Arc<Explainer>>,
+    pub(crate) verbose_format: Arc<AtomicBool>,
+}
+
+impl FilterHandle {
+    /// Replaces the settings of the configuration file, logging the change unless the filters
+    /// in effect stay the same
+    pub(crate) fn reload_file(&self, settings: LogSettings, changed_by: &str) {
+        self.update(|config| config.file = settings, changed_by);
+    }
+
+    /// Switches the formatted output between verbose and compact
+    pub(crate) fn set_verbose_format(&self, verbose: bool) {
+        self.verbose_format.store(verbose, Ordering::Relaxed);
+    }
+
+    /// Applies `change` to the settings of every source, logging the change unless the filters
+    /// in effect stay the same
+    pub(crate) fn update(&self, change: impl FnOnce(&mut LogConfig), changed_by: &str) {
+        let mut config = self.config.lock().unwrap_or_else(|e| e.into_inner());
+        let old = config.resolve();
+        change(&mut config);
+        let new = config.resolve();
+        drop(config);
+        if old == new {
+            return;
+        }
+
+        let (level, directives) = filters(Some(&new), &self.explainer);
+        self.level.set(level);
+        self.directives.set(directives);
+        tracing::callsite::rebuild_interest_cache();
+
+        let diff = |from: &LogSettings, to: &LogSettings| {
+            let directives: Vec<_> = to
+                .directives
+                .iter()
+                .filter(|directive| !from.directives.contains(directive))
+                .map(String::as_str)
+                .collect();
+            directives.join(",")
+        };
+        tracing::info!(
+            target: AUDIT_TARGET,
+            changed_by,
+            old = %old,
+            new = %new,
+            added = %diff(&old, &new),
+            removed = %diff(&new, &old),
+            "logging filters changed"
+        );
+    }
+}
@@ -0,0 +1,402 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "gzip")]
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+/// When log files are rotated, see [`Builder::rotate_files`](crate::Builder::rotate_files)
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rotation {
+    pub(crate) max_bytes: u64,
+    /// How many rotated segments are kept, the oldest being removed
+    pub(crate) keep: usize,
+    /// Whether rotated segments are compressed with gzip, only there with the `gzip` feature so
+    /// that it can't be asked for without it
+    #[cfg(feature = "gzip")]
+    pub(crate) compress: bool,
+    /// Segments last written longer ago than this are removed
+    pub(crate) max_age: Option<Duration>,
+    /// The oldest segments are removed while the disk has less space free than this
+    pub(crate) min_free_bytes: Option<u64>,
+}
+
+/// How far a log file is from being rotated
+#[derive(Debug)]
+pub(crate) struct Rotating {
+    rotation: Rotation,
+    path: PathBuf,
+    written: u64,
+    /// Compresses the segment rotated last
+    compressing: Option<JoinHandle<()>>,
+}
+
+impl Rotating {
+    /// Starts from the size of `file`, which events are appended to, removing the segments left
+    /// by earlier runs that are past retention
+    pub(crate) fn new(rotation: Rotation, path: &Path, file: &File) -> Self {
+        let rotating = Self {
+            rotation,
+            path: path.to_owned(),
+            written: file.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+            compressing: None,
+        };
+        rotating.prune();
+        rotating
+    }
+
+    /// Counts `len` more bytes written, returning whether the file is due for rotation
+    pub(crate) fn wrote(&mut self, len: usize) -> bool {
+        self.written += len as u64;
+        self.written >= self.rotation.max_bytes
+    }
+
+    /// Renames the file, which must be closed, to the first segment, shifting the older ones,
+    /// and starts compressing it
+    ///
+    /// A new file is then opened at the same path.
+    pub(crate) fn rotate(&mut self) -> io::Result<File> {
+        // Segments are renamed below, so the last one must be compressed first
+        self.finish();
+        let keep = self.rotation.keep;
+        for compressed in [false, true] {
+            remove(&self.segment(keep, compressed))?;
+        }
+        for n in (1..keep).rev() {
+            for compressed in [false, true] {
+                let from = self.segment(n, compressed);
+                if from.exists() {
+                    fs::rename(&from, self.segment(n + 1, compressed))?;
+                }
+            }
+        }
+        if keep == 0 {
+            remove(&self.path)?;
+        } else {
+            fs::rename(&self.path, self.segment(1, false))?;
+            self.prune();
+            #[cfg(feature = "gzip")]
+            if self.rotation.compress && self.segment(1, false).exists() {
+                let segment = self.segment(1, false);
+                self.compressing = Some(thread::spawn(move || {
+                    // The segment stays uncompressed if it can't be
+                    let _ = gzip::compress(&segment);
+                }));
+            }
+        }
+        self.written = 0;
+        self.reopen()
+    }
+
+    /// Opens the file at the path rotated, such as after a rotation that failed
+    pub(crate) fn reopen(&self) -> io::Result<File> {
+        crate::route::open_append(&self.path)
+    }
+
+    /// Waits for the last segment to be compressed
+    pub(crate) fn finish(&mut self) {
+        if let Some(compressing) = self.compressing.take() {
+            let _ = compressing.join();
+        }
+    }
+
+    /// Removes the segments older than the maximum age, then the oldest ones while the disk is
+    /// short of space
+    fn prune(&self) {
+        let mut segments: Vec<PathBuf> = (1..=self.rotation.keep)
+            .flat_map(|n| [self.segment(n, false), self.segment(n, true)])
+            .filter(|segment| segment.exists())
+            .collect();
+        if let Some(max_age) = self.rotation.max_age {
+            let expired = |segment: &PathBuf| {
+                let modified = fs::metadata(segment).and_then(|metadata| metadata.modified());
+                modified.is_ok_and(|modified| {
+                    SystemTime::now()
+                        .duration_since(modified)
+                        .is_ok_and(|age| age > max_age)
+                })
+            };
+            segments.retain(|segment| !(expired(segment) && remove(segment).is_ok()));
+        }
+        if let Some(min_free_bytes) = self.rotation.min_free_bytes {
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            while free_space(dir).is_some_and(|free| free < min_free_bytes) {
+                match segments.pop() {
+                    Some(oldest) => {
+                        let _ = remove(&oldest);
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// The path of the `n`th segment, such as `app.log.1` or `app.log.1.gz`
+    fn segment(&self, n: usize, compressed: bool) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        if compressed {
+            name.push(".gz");
+        }
+        PathBuf::from(name)
+    }
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The space available to the process on the file system holding `dir`
+#[cfg(unix)]
+fn free_space(dir: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    // SAFETY: `statvfs` only writes a `statvfs` to the pointer it is given
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(dir.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(feature = "gzip")]
+mod gzip {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::fs::{self, File};
+    use std::io;
+    use std::path::Path;
+
+    /// Replaces the file at `path` with a gzip-compressed copy with the `.gz` extension added
+    pub(super) fn compress(path: &Path) -> io::Result<()> {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".gz");
+        let mut encoder = GzEncoder::new(File::create(&name)?, Compression::default());
+        io::copy(&mut File::open(path)?, &mut encoder)?;
+        encoder.finish()?.sync_all()?;
+        fs::remove_file(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A fresh directory in the temporary directory, removed with its files when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("rotate_{}_{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn log(&self) -> PathBuf {
+            self.0.join("app.log")
+        }
+
+        /// The names of the files in the directory, with their contents
+        fn files(&self) -> Vec<(String, String)> {
+            let mut files: Vec<_> = fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| {
+                    let path = entry.unwrap().path();
+                    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                    (name, fs::read_to_string(&path).unwrap_or_default())
+                })
+                .collect();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn rotation(keep: usize) -> Rotation {
+        Rotation {
+            max_bytes: 10,
+            keep,
+            #[cfg(feature = "gzip")]
+            compress: false,
+            max_age: None,
+            min_free_bytes: None,
+        }
+    }
+
+    /// Writes each of `contents` to the log, rotating after each
+    fn rotate_after_each(dir: &TempDir, rotation: Rotation, contents: &[&str]) {
+        let mut file = crate::route::open_append(&dir.log()).unwrap();
+        let mut rotating = Rotating::new(rotation, &dir.log(), &file);
+        for content in contents {
+            file.write_all(content.as_bytes()).unwrap();
+            drop(file);
+            file = rotating.rotate().unwrap();
+        }
+        rotating.finish();
+    }
+
+    fn files(names: &[(&str, &str)]) -> Vec<(String, String)> {
+        names
+            .iter()
+            .map(|(name, content)| (name.to_string(), content.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn rotation_is_due_once_the_file_reaches_the_limit() {
+        let dir = TempDir::new("due");
+        fs::write(dir.log(), "123456").unwrap();
+        let file = crate::route::open_append(&dir.log()).unwrap();
+        let mut rotating = Rotating::new(rotation(1), &dir.log(), &file);
+        assert!(!rotating.wrote(3));
+        assert!(rotating.wrote(1));
+        drop(file);
+        rotating.rotate().unwrap();
+        assert!(!rotating.wrote(9));
+    }
+
+    #[test]
+    fn keeping_no_segments_truncates_the_file() {
+        let dir = TempDir::new("keep_0");
+        rotate_after_each(&dir, rotation(0), &["a", "b"]);
+        assert_eq!(dir.files(), files(&[("app.log", "")]));
+    }
+
+    #[test]
+    fn keeping_one_segment_replaces_it() {
+        let dir = TempDir::new("keep_1");
+        rotate_after_each(&dir, rotation(1), &["a", "b", "c"]);
+        assert_eq!(dir.files(), files(&[("app.log", ""), ("app.log.1", "c")]));
+    }
+
+    #[test]
+    fn segments_are_shifted_and_the_oldest_removed() {
+        let dir = TempDir::new("keep_n");
+        rotate_after_each(&dir, rotation(3), &["a", "b"]);
+        assert_eq!(
+            dir.files(),
+            files(&[("app.log", ""), ("app.log.1", "b"), ("app.log.2", "a")])
+        );
+        rotate_after_each(&dir, rotation(3), &["c", "d"]);
+        assert_eq!(
+            dir.files(),
+            files(&[
+                ("app.log", ""),
+                ("app.log.1", "d"),
+                ("app.log.2", "c"),
+                ("app.log.3", "b"),
+            ])
+        );
+    }
+
+    #[test]
+    fn compressed_segments_are_shifted_too() {
+        let dir = TempDir::new("shift_gz");
+        fs::write(dir.0.join("app.log.1.gz"), "a").unwrap();
+        fs::write(dir.0.join("app.log.2.gz"), "old").unwrap();
+        rotate_after_each(&dir, rotation(2), &["b"]);
+        assert_eq!(
+            dir.files(),
+            files(&[("app.log", ""), ("app.log.1", "b"), ("app.log.2.gz", "a")])
+        );
+    }
+
+    /// Makes the file at `path` look last written `age` ago
+    fn age(path: &Path, age: Duration) {
+        let file = File::options().append(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    #[test]
+    fn segments_past_the_maximum_age_are_removed_on_start() {
+        let dir = TempDir::new("age_start");
+        for (name, days) in &[("app.log.1", 1), ("app.log.2.gz", 3), ("app.log.3", 5)] {
+            let path = dir.0.join(name);
+            fs::write(&path, "x").unwrap();
+            age(&path, Duration::from_secs(days * 24 * 3600));
+        }
+        fs::write(dir.log(), "").unwrap();
+        age(&dir.log(), Duration::from_secs(30 * 24 * 3600));
+        let aging = Rotation {
+            max_age: Some(Duration::from_secs(2 * 24 * 3600)),
+            ..rotation(3)
+        };
+        let file = crate::route::open_append(&dir.log()).unwrap();
+        Rotating::new(aging, &dir.log(), &file);
+        // The file being written is never removed
+        assert_eq!(dir.files(), files(&[("app.log", ""), ("app.log.1", "x")]));
+    }
+
+    #[test]
+    fn segments_past_the_maximum_age_are_removed_on_rotation() {
+        let dir = TempDir::new("age_rotation");
+        let aging = Rotation {
+            max_age: Some(Duration::from_secs(3600)),
+            ..rotation(3)
+        };
+        rotate_after_each(&dir, aging, &["a", "b"]);
+        age(&dir.0.join("app.log.2"), Duration::from_secs(2 * 3600));
+        rotate_after_each(&dir, aging, &["c"]);
+        assert_eq!(
+            dir.files(),
+            files(&[("app.log", ""), ("app.log.1", "c"), ("app.log.2", "b")])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_oldest_segments_are_removed_while_the_disk_is_short_of_space() {
+        let dir = TempDir::new("free_space");
+        assert!(free_space(&dir.0).is_some());
+        rotate_after_each(&dir, rotation(3), &["a", "b", "c"]);
+        let short = Rotation {
+            min_free_bytes: Some(u64::MAX),
+            ..rotation(3)
+        };
+        rotate_after_each(&dir, short, &["d"]);
+        assert_eq!(dir.files(), files(&[("app.log", "")]));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn rotated_segments_are_compressed() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let dir = TempDir::new("gzip");
+        let compressing = Rotation {
+            compress: true,
+            ..rotation(2)
+        };
+        rotate_after_each(&dir, compressing, &["a", "b"]);
+        let names: Vec<_> = dir.files().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["app.log", "app.log.1.gz", "app.log.2.gz"]);
+        let mut decoded = String::new();
+        GzDecoder::new(File::open(dir.0.join("app.log.2.gz")).unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "a");
+    }
+}
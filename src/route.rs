@@ -0,0 +1,619 @@
+use crate::binlog::BinaryLog;
+use crate::capture::Memory;
+use crate::console;
+use crate::crash::CrashReporter;
+use crate::debugger;
+use crate::dedup::{RepeatFilter, StormFilter};
+use crate::gelf::Gelf;
+use crate::health::SinkStatus;
+use crate::histogram::SpanHistograms;
+use crate::html::HtmlReport;
+use crate::journald::Journald;
+use crate::levels;
+use crate::logcat::Logcat;
+use crate::loki::LokiLayer;
+use crate::otlp::Otlp;
+use crate::rotate::{Rotating, Rotation};
+use crate::run;
+use crate::sentry::Sentry;
+use crate::shutdown::Sink;
+use crate::status::{self, Stderr, Stdout};
+use crate::stream::EventStream;
+use crate::syslog::Syslog;
+use crate::tags::TagRoute;
+use crate::tee::JsonTee;
+use crate::wrap;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use std::{env, fmt, fs};
+use tracing::{Level, Metadata};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Where events of a level are written, see [`Builder::route`](crate::Builder::route)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Destination {
+    /// Standard error, above any status lines, where events go by default
+    Stderr,
+    /// Standard output
+    Stdout,
+    /// A file, which is created if needed and appended to, without colors
+    File(PathBuf),
+}
+
+/// An output that can be given a filter of its own, see
+/// [`Builder::output_filter`](crate::Builder::output_filter)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Output {
+    /// The file of `--log-file`
+    LogFile,
+    /// The syslog daemon, see [`Builder::syslog`](crate::Builder::syslog)
+    Syslog,
+    /// Graylog, see [`Builder::gelf`](crate::Builder::gelf)
+    Gelf,
+    /// Grafana Loki, see [`Builder::loki`](crate::Builder::loki)
+    Loki,
+    /// Sentry, with the `sentry` feature
+    Sentry,
+    /// An OpenTelemetry collector, with the `otlp` feature
+    Otlp,
+    /// The log viewers attached, see [`Builder::event_stream`](crate::Builder::event_stream)
+    EventStream,
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Output::LogFile => "the log file",
+            Output::Syslog => "syslog",
+            Output::Gelf => "Graylog",
+            Output::Loki => "Loki",
+            Output::Sentry => "Sentry",
+            Output::Otlp => "OpenTelemetry",
+            Output::EventStream => "the event stream",
+        })
+    }
+}
+
+/// How much of the work of logging is done before events are thrown away, see
+/// [`Builder::discard_output`](crate::Builder::discard_output)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Discard {
+    /// Events are filtered and formatted, and the text is thrown away
+    AfterFormatting,
+    /// Events are filtered, and those that pass are thrown away without being formatted
+    BeforeFormatting,
+}
+
+/// The conventional place for the log file of `app` on this platform, for `--log-file=auto`,
+/// creating its directory if needed
+///
+/// That is `$XDG_STATE_HOME/app/app.log`, defaulting to `~/.local/state`, on Linux and other
+/// Unix systems, `~/Library/Logs/app/app.log` on macOS, and `%LOCALAPPDATA%\app\logs\app.log`
+/// on Windows.
+pub(crate) fn default_log_file(app: &str) -> io::Result<PathBuf> {
+    let home = || {
+        env::var_os("HOME")
+            .filter(|home| !home.is_empty())
+            .map(PathBuf::from)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "$HOME is not set"))
+    };
+    let dir = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA")
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| PathBuf::from(dir).join(app).join("logs"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "%LOCALAPPDATA% is not set"))?
+    } else if cfg!(target_os = "macos") {
+        home()?.join("Library").join("Logs").join(app)
+    } else {
+        match env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => home()?.join(".local").join("state"),
+        }
+        .join(app)
+    };
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.log", app)))
+}
+
+/// Opens the file at `path` for appending, creating it if needed
+pub(crate) fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// A file events are appended to
+#[derive(Debug)]
+struct LogFile {
+    file: Mutex<Active>,
+    /// Whether the start and end of the run are marked, which JSON files go without
+    run_markers: bool,
+    health: Arc<SinkStatus>,
+}
+
+/// The file being appended to, and how far it is from being rotated
+#[derive(Debug)]
+struct Active {
+    /// Missing if it couldn't be opened again after a rotation, until a write opens it
+    file: Option<File>,
+    rotating: Option<Rotating>,
+}
+
+impl LogFile {
+    fn write(&self, buf: &[u8]) -> io::Result<()> {
+        let written = self.append(buf);
+        self.health.record(&written);
+        written
+    }
+
+    fn append(&self, buf: &[u8]) -> io::Result<()> {
+        let text = wrap::strip_escapes(&String::from_utf8_lossy(buf));
+        let mut active = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let Active { file, rotating } = &mut *active;
+        if let (None, Some(rotating)) = (&file, &rotating) {
+            *file = Some(rotating.reopen()?);
+        }
+        if let Some(file) = file {
+            file.write_all(text.as_bytes())?;
+        }
+        if let Some(rotating) = rotating {
+            if rotating.wrote(text.len()) {
+                // Closed first, as open files can't be renamed on Windows
+                *file = None;
+                *file = Some(rotating.rotate()?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sink for LogFile {
+    fn drain(&self) -> io::Result<()> {
+        let mut active = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(rotating) = &mut active.rotating {
+            rotating.finish();
+        }
+        match &mut active.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&self, marker: &str) -> io::Result<()> {
+        self.write(format!("{}\n", marker).as_bytes())
+    }
+
+    fn end_run(&self) -> io::Result<()> {
+        if self.run_markers {
+            self.write(format!("{}\n", run::ended_marker()).as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    Stderr(Stderr),
+    Stdout,
+    File(Arc<LogFile>),
+    /// The debugger output on Windows, without colors
+    Debugger,
+    /// Standard error through `eprint!`, which the test harness captures
+    Print,
+    /// Another target, without colors
+    Plain(Box<Target>),
+}
+
+/// Picks the destinations of each event by its level
+#[derive(Debug, Clone)]
+pub(crate) struct Router {
+    stderr: Stderr,
+    routes: [Arc<Vec<Target>>; 5],
+    /// Takes the place of every destination, see [`Builder::capture`](crate::Builder::capture)
+    memory: Option<Arc<Memory>>,
+    /// Takes the place of stderr, see [`Builder::writer`](crate::Builder::writer)
+    writer: Option<Arc<BoxMakeWriter>>,
+}
+
+impl Router {
+    /// Writes every level to `stderr`
+    pub(crate) fn new(stderr: Stderr) -> Self {
+        let all = Arc::new(vec![Target::Stderr(stderr.clone())]);
+        Self {
+            stderr,
+            routes: [(); 5].map(|()| all.clone()),
+            memory: None,
+            writer: None,
+        }
+    }
+
+    /// Writes nothing at any level
+    pub(crate) fn discarding() -> Self {
+        let mut router = Self::new(Stderr::new());
+        let nowhere = Arc::new(Vec::new());
+        router.routes = [(); 5].map(|()| nowhere.clone());
+        router
+    }
+
+    /// Writes every level to standard error through `eprint!`, so the output of each test is
+    /// only shown when it fails
+    pub(crate) fn printing() -> Self {
+        let mut router = Self::new(Stderr::new());
+        let print = Arc::new(vec![Target::Print]);
+        router.routes = [(); 5].map(|()| print.clone());
+        router
+    }
+
+    /// Writes every level to `memory` alone
+    pub(crate) fn to_memory(memory: Arc<Memory>) -> Self {
+        Self {
+            memory: Some(memory),
+            ..Self::new(Stderr::new())
+        }
+    }
+
+    /// Writes each level to the destinations listed for it, and levels without any to `stderr`
+    ///
+    /// Files get a line marking the start of the run when opened, and its end on shutdown, if
+    /// `run_markers` is set, and are rotated according to `rotation`. Returns the sinks to drain
+    /// on shutdown, after `stderr`, and a message for each file that couldn't be opened.
+    pub(crate) fn with_routes(
+        stderr: Stderr,
+        routes: &[Option<Vec<Destination>>; 5],
+        run_markers: bool,
+        rotation: Option<Rotation>,
+    ) -> (Self, Vec<Arc<dyn Sink>>, Vec<String>) {
+        let mut router = Self::new(stderr);
+        let mut sinks: Vec<Arc<dyn Sink>> = Vec::new();
+        let mut errors = Vec::new();
+        let mut files: HashMap<PathBuf, Option<Arc<LogFile>>> = HashMap::new();
+        let mut stdout = false;
+
+        for (index, destinations) in routes.iter().enumerate() {
+            let destinations = match destinations {
+                Some(destinations) => destinations,
+                None => continue,
+            };
+            let mut targets = Vec::new();
+            for destination in destinations {
+                match destination {
+                    Destination::Stderr => targets.push(Target::Stderr(router.stderr.clone())),
+                    Destination::Stdout => {
+                        if !stdout {
+                            stdout = true;
+                            sinks.push(Arc::new(Stdout));
+                        }
+                        targets.push(Target::Stdout);
+                    }
+                    Destination::File(path) => {
+                        let file =
+                            files
+                                .entry(path.clone())
+                                .or_insert_with(|| match open_append(path) {
+                                    Ok(file) => {
+                                        let rotating = rotation
+                                            .map(|rotation| Rotating::new(rotation, path, &file));
+                                        let file = Arc::new(LogFile {
+                                            file: Mutex::new(Active {
+                                                file: Some(file),
+                                                rotating,
+                                            }),
+                                            run_markers,
+                                            health: SinkStatus::register(format!(
+                                                "the log file {}",
+                                                path.display()
+                                            )),
+                                        });
+                                        if run_markers {
+                                            let marker = format!("{}\n", run::started_marker());
+                                            let _ = file.write(marker.as_bytes());
+                                        }
+                                        sinks.push(file.clone());
+                                        Some(file)
+                                    }
+                                    Err(e) => {
+                                        errors.push(format!(
+                                            "cannot open log file {}: {}",
+                                            path.display(),
+                                            e
+                                        ));
+                                        None
+                                    }
+                                });
+                        targets.extend(file.clone().map(Target::File));
+                    }
+                }
+            }
+            router.routes[index] = Arc::new(targets);
+        }
+        (router, sinks, errors)
+    }
+
+    /// Writes every level to `destinations`
+    pub(crate) fn to(
+        stderr: Stderr,
+        destinations: &[Destination],
+        run_markers: bool,
+        rotation: Option<Rotation>,
+    ) -> (Self, Vec<Arc<dyn Sink>>, Vec<String>) {
+        let routes = [(); 5].map(|()| Some(destinations.to_vec()));
+        Self::with_routes(stderr, &routes, run_markers, rotation)
+    }
+
+    /// Also writes every level to the debugger output, where it is supported
+    pub(crate) fn mirror_to_debugger(&mut self) {
+        if !debugger::SUPPORTED {
+            return;
+        }
+        for route in &mut self.routes {
+            let mut targets = route.to_vec();
+            targets.push(Target::Debugger);
+            *route = Arc::new(targets);
+        }
+    }
+
+    /// Strips the colors from what goes to stdout, or to stderr as well if `stderr`, unless that
+    /// stream is a terminal, deciding for each of them separately
+    pub(crate) fn strip_colors_when_piped(&mut self, stderr: bool) {
+        let plain = |target: &Target| match target {
+            Target::Stdout => !status::stdout_tty(),
+            Target::Stderr(_) => stderr && !status::tty(),
+            _ => false,
+        };
+        for route in &mut self.routes {
+            let targets = route
+                .iter()
+                .map(|target| {
+                    if plain(target) {
+                        Target::Plain(Box::new(target.clone()))
+                    } else {
+                        target.clone()
+                    }
+                })
+                .collect();
+            *route = Arc::new(targets);
+        }
+    }
+
+    /// Stops writing to stderr, returning at which levels it was written to, for the journal to
+    /// take its place
+    pub(crate) fn take_stderr(&mut self) -> [bool; 5] {
+        let stderr = |target: &Target| match target {
+            Target::Stderr(_) => true,
+            Target::Plain(target) => matches!(**target, Target::Stderr(_)),
+            _ => false,
+        };
+        let mut levels = [false; 5];
+        for (route, taken) in self.routes.iter_mut().zip(&mut levels) {
+            *taken = route.iter().any(stderr);
+            let targets = route.iter().filter(|target| !stderr(target)).cloned();
+            *route = Arc::new(targets.collect());
+        }
+        levels
+    }
+
+    /// Whether nothing is written at any level
+    pub(crate) fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.routes.iter().all(|route| route.is_empty())
+    }
+
+    /// Writes what goes to stderr with writers made by `writer` instead
+    pub(crate) fn replace_stderr(&mut self, writer: Arc<BoxMakeWriter>) {
+        self.writer = Some(writer);
+    }
+
+    /// The writer for diagnostics about logging itself
+    pub(crate) fn stderr(&self) -> &Stderr {
+        &self.stderr
+    }
+}
+
+impl MakeWriter for Router {
+    type Writer = Tee;
+
+    fn make_writer(&self) -> Tee {
+        Tee {
+            targets: Arc::new(vec![Target::Stderr(self.stderr.clone())]),
+            level: Level::INFO,
+            memory: None,
+            writer: self.writer.as_ref().map(|writer| writer.make_writer()),
+        }
+    }
+
+    fn make_writer_for(&self, meta: &Metadata<'_>) -> Tee {
+        match &self.memory {
+            Some(memory) => Tee {
+                targets: Arc::new(Vec::new()),
+                level: *meta.level(),
+                memory: Some((memory.clone(), meta.target().to_owned())),
+                writer: None,
+            },
+            None => Tee {
+                targets: self.routes[levels::index(*meta.level())].clone(),
+                level: *meta.level(),
+                memory: None,
+                writer: self
+                    .writer
+                    .as_ref()
+                    .map(|writer| writer.make_writer_for(meta)),
+            },
+        }
+    }
+}
+
+/// Where the subscriber writes, set up when it is installed
+pub(crate) struct Outputs {
+    /// Where the formatted output goes
+    pub(crate) console: Router,
+    /// Where the verbose rendering of the same events goes, see
+    /// [`Builder::transcript`](crate::Builder::transcript)
+    pub(crate) transcript: Option<Router>,
+    pub(crate) tees: Vec<JsonTee>,
+    pub(crate) tagged: Vec<TagRoute>,
+    /// Records the spans and events of the run, see
+    /// [`Builder::html_report`](crate::Builder::html_report)
+    pub(crate) html: Option<(HtmlReport, Targets)>,
+    /// Get the events that pass their filter, see
+    /// [`Builder::binary_log`](crate::Builder::binary_log)
+    pub(crate) binary_logs: Vec<(BinaryLog, Targets)>,
+    /// Takes the place of the formatted output when stderr goes to the systemd journal
+    pub(crate) journald: Option<Journald>,
+    /// Takes the place of the formatted output on Android
+    pub(crate) logcat: Option<Logcat>,
+    /// Gets the formatted events as well, see [`Builder::syslog`](crate::Builder::syslog)
+    pub(crate) syslog: Option<Syslog>,
+    /// Gets the formatted events as well, see [`Builder::gelf`](crate::Builder::gelf)
+    pub(crate) gelf: Option<Gelf>,
+    /// Gets the formatted events as well, see
+    /// [`Builder::event_stream`](crate::Builder::event_stream)
+    pub(crate) stream: Option<EventStream>,
+    /// Gets the formatted events as well, see [`Builder::loki`](crate::Builder::loki)
+    pub(crate) loki: Option<LokiLayer>,
+    /// Gets the formatted events as well, with the `sentry` feature
+    pub(crate) sentry: Option<Sentry>,
+    /// Gets the formatted events as well, with the `otlp` feature
+    pub(crate) otlp: Option<Otlp>,
+    /// Reports the repeats of the last event on shutdown, see
+    /// [`Builder::collapse_repeats`](crate::Builder::collapse_repeats)
+    pub(crate) repeats: Option<Arc<RepeatFilter>>,
+    /// Reports the repeats of the last errors on shutdown, see
+    /// [`Builder::dedup_errors`](crate::Builder::dedup_errors)
+    pub(crate) storms: Option<Arc<StormFilter>>,
+    /// Records how long spans live, see
+    /// [`Builder::span_histograms`](crate::Builder::span_histograms)
+    pub(crate) histograms: Option<Arc<SpanHistograms>>,
+    /// Keeps the last events for crash reports, see
+    /// [`Builder::crash_reports`](crate::Builder::crash_reports)
+    pub(crate) crash: Option<Arc<CrashReporter>>,
+    /// Switches the formatted output between verbose and compact, see
+    /// [`LogGuard::set_verbose_format`](crate::LogGuard::set_verbose_format)
+    pub(crate) verbose_format: Arc<AtomicBool>,
+    /// The file of `--log-file`, when it has a filter of its own
+    pub(crate) log_file: Option<Router>,
+    /// The filters of the outputs set up that have their own, see
+    /// [`Builder::output_filter`](crate::Builder::output_filter)
+    pub(crate) filters: HashMap<Output, Targets>,
+    /// The files written to, which have to be drained on shutdown
+    pub(crate) files: Vec<Arc<dyn Sink>>,
+    /// A message for each destination that couldn't be set up
+    pub(crate) errors: Vec<String>,
+}
+
+impl Outputs {
+    /// Whether `output` is set up
+    pub(crate) fn has(&self, output: Output) -> bool {
+        match output {
+            Output::LogFile => self.log_file.is_some(),
+            Output::Syslog => self.syslog.is_some(),
+            Output::Gelf => self.gelf.is_some(),
+            Output::Loki => self.loki.is_some(),
+            Output::Sentry => self.sentry.is_some(),
+            Output::Otlp => self.otlp.is_some(),
+            Output::EventStream => self.stream.is_some(),
+        }
+    }
+
+    /// Writes the formatted output to `stderr` alone
+    pub(crate) fn stderr(stderr: Stderr) -> Self {
+        Self {
+            console: Router::new(stderr),
+            transcript: None,
+            tees: Vec::new(),
+            tagged: Vec::new(),
+            html: None,
+            binary_logs: Vec::new(),
+            journald: None,
+            logcat: None,
+            syslog: None,
+            gelf: None,
+            stream: None,
+            loki: None,
+            sentry: None,
+            otlp: None,
+            repeats: None,
+            storms: None,
+            histograms: None,
+            crash: None,
+            verbose_format: Arc::new(AtomicBool::new(false)),
+            log_file: None,
+            filters: HashMap::new(),
+            files: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+/// Writes each event to every destination of its level
+pub(crate) struct Tee {
+    targets: Arc<Vec<Target>>,
+    /// The level of the event, which picks the method of the browser console
+    level: Level,
+    /// The buffer of [`Builder::capture`](crate::Builder::capture), with the target of the event
+    memory: Option<(Arc<Memory>, String)>,
+    /// Writes in place of stderr, see [`Builder::writer`](crate::Builder::writer)
+    writer: Option<Box<dyn Write>>,
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some((memory, target)) = &self.memory {
+            memory.write(self.level, target, buf);
+        }
+        let mut result = Ok(());
+        let targets = self.targets.clone();
+        for target in targets.iter() {
+            // Every destination gets the event even when an earlier one fails
+            result = result.and(self.write_to(target, buf));
+        }
+        result.map(|()| buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.writer {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Tee {
+    fn write_to(&mut self, target: &Target, buf: &[u8]) -> io::Result<()> {
+        if let (Target::Stderr(_), Some(writer)) = (target, &mut self.writer) {
+            return writer.write_all(buf);
+        }
+        match target {
+            Target::Stderr(_) if console::ENABLED => {
+                console::write(self.level, buf);
+                Ok(())
+            }
+            Target::Stderr(stderr) => stderr.write_event(self.level, buf),
+            Target::Stdout => Stdout.write_all(buf),
+            Target::File(file) => file.write(buf),
+            Target::Print if status::tty() => {
+                eprint!("{}", String::from_utf8_lossy(buf));
+                Ok(())
+            }
+            Target::Print => {
+                eprint!("{}", wrap::strip_escapes(&String::from_utf8_lossy(buf)));
+                Ok(())
+            }
+            Target::Debugger => {
+                debugger::write(&wrap::strip_escapes(&String::from_utf8_lossy(buf)));
+                Ok(())
+            }
+            Target::Plain(target) => {
+                let text = wrap::strip_escapes(&String::from_utf8_lossy(buf));
+                self.write_to(target, text.as_bytes())
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Returns the ID of this run of the process, 12 hexadecimal digits that differ between runs
+///
+/// It is included in JSON records and the events sent to log collectors, and log files get a
+/// `=== run <id> started ===` line when opened and a `=== run <id> ended ===` line on shutdown,
+/// so the logs of a service restarted over and over can be told apart.
+///
+/// ```no_run
+/// # use pretty_tracing_subscriber::run_id;
+/// println!("logs of this run are tagged with {}", run_id());
+/// ```
+pub fn run_id() -> &'static str {
+    static ID: OnceLock<String> = OnceLock::new();
+    ID.get_or_init(|| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        );
+        format!("{:012x}", hasher.finish() & 0xffff_ffff_ffff)
+    })
+}
+
+/// The line written to log files when they are opened
+pub(crate) fn started_marker() -> String {
+    format!("=== run {} started ===", run_id())
+}
+
+/// The line written to log files on shutdown
+pub(crate) fn ended_marker() -> String {
+    format!("=== run {} ended ===", run_id())
+}
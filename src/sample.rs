@@ -0,0 +1,88 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use tracing::{Level, Metadata};
+
+thread_local! {
+    /// State of the generator deciding which events are kept on this thread
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+/// Keeps a random fraction of the events at a level, in a target or everywhere, see
+/// [`Builder::sample`](crate::Builder::sample)
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SampleRule {
+    target: Option<String>,
+    level: Level,
+    rate: f64,
+}
+
+impl SampleRule {
+    pub(crate) fn new(target: Option<&str>, level: Level, rate: f64) -> Self {
+        Self {
+            target: target.map(str::to_owned),
+            level,
+            rate: rate.clamp(0.0, 1.0),
+        }
+    }
+
+    fn applies_to(&self, metadata: &Metadata<'_>) -> bool {
+        if *metadata.level() != self.level {
+            return false;
+        }
+        match &self.target {
+            Some(target) => metadata
+                .target()
+                .strip_prefix(target.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::")),
+            None => true,
+        }
+    }
+
+    /// Decides at random whether to keep an event
+    fn keeps(&self) -> bool {
+        random() < self.rate
+    }
+}
+
+impl fmt::Display for SampleRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}% of {} events", self.rate * 100.0, self.level)?;
+        if let Some(target) = &self.target {
+            write!(f, " in {}", target)?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the rule an event at `metadata` is sampled by, the one with the longest target that
+/// applies, and returns it if the event is dropped
+pub(crate) fn drops<'a>(
+    rules: &'a [SampleRule],
+    metadata: &Metadata<'_>,
+) -> Option<&'a SampleRule> {
+    let rule = rules
+        .iter()
+        .filter(|rule| rule.applies_to(metadata))
+        .max_by_key(|rule| rule.target.as_ref().map(|target| target.len() + 1))?;
+    Some(rule).filter(|rule| !rule.keeps())
+}
+
+/// A random seed, from the keys std draws for hash maps
+fn seed() -> u64 {
+    RandomState::new().build_hasher().finish() | 1
+}
+
+/// A number in `0.0..1.0` from a xorshift generator, which is plenty for sampling and much
+/// cheaper than formatting the events it saves
+fn random() -> f64 {
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
@@ -0,0 +1,459 @@
+#[cfg(feature = "tokio")]
+use crate::batch;
+use crate::config::LogSettings;
+use crate::crash::CrashReporter;
+use crate::exit::{EventCounts, Tally};
+use crate::health::{self, SinkHealth};
+use crate::histogram::{SpanDurations, SpanHistograms};
+use crate::reload::FilterHandle;
+use crate::status::{self, Stderr};
+use crate::{panic, wrap};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+/// Text of the line written to every sink when logging is shut down cleanly
+const CLOSED_MARKER: &str = "log closed";
+
+/// A destination for formatted events that has to be drained before the process exits
+pub(crate) trait Sink: Send + Sync {
+    /// Writes out every event accepted so far
+    fn drain(&self) -> io::Result<()>;
+
+    /// Writes the final line marking a clean shutdown, after every sink has been drained
+    fn close(&self, marker: &str) -> io::Result<()>;
+
+    /// Marks the end of the run in sinks that mark its start, after any closing line
+    fn end_run(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Shuts logging down when dropped, see
+/// [`Builder::init_with_guard`](crate::Builder::init_with_guard)
+///
+/// Shutting down drains every sink in the order they were registered, so events that were
+/// written to several sinks end up everywhere before the process exits. If enabled with
+/// [`Builder::closed_marker`](crate::Builder::closed_marker), a final `log closed` line is then
+/// written to each of them, which lets consumers tell a clean exit from a log cut short by a
+/// crash. The summary of [`Builder::summary_at_exit`](crate::Builder::summary_at_exit) is
+/// printed, and the panic hooks installed with the subscriber are
+/// replaced by the hook they replaced.
+///
+/// Logging is only shut down once, whether by dropping the guard, calling
+/// [`shutdown_timeout`](Self::shutdown_timeout), or through a [`ShutdownHandle`].
+#[must_use = "logging is shut down when the guard is dropped"]
+pub struct LogGuard {
+    shutdown: Arc<Shutdown>,
+    tally: Arc<Tally>,
+    fail_on_errors: Option<usize>,
+    histograms: Option<Arc<SpanHistograms>>,
+    filters: Arc<FilterHandle>,
+    crash: Option<Arc<CrashReporter>>,
+}
+
+/// What shutting down does, shared by the guard and its handles
+struct Shutdown {
+    sinks: Vec<Arc<dyn Sink>>,
+    closed_marker: bool,
+    summary: bool,
+    tally: Arc<Tally>,
+    done: AtomicBool,
+}
+
+impl LogGuard {
+    pub(crate) fn new(
+        sinks: Vec<Arc<dyn Sink>>,
+        closed_marker: bool,
+        tally: Arc<Tally>,
+        fail_on_errors: Option<usize>,
+        summary: bool,
+        histograms: Option<Arc<SpanHistograms>>,
+        filters: Arc<FilterHandle>,
+    ) -> Self {
+        panic::register_sinks(&sinks);
+        Self {
+            shutdown: Arc::new(Shutdown {
+                sinks,
+                closed_marker,
+                summary,
+                tally: tally.clone(),
+                done: AtomicBool::new(false),
+            }),
+            tally,
+            fail_on_errors,
+            histograms,
+            filters,
+            crash: None,
+        }
+    }
+
+    /// Writes the reports of [`crash_report`](Self::crash_report) with `crash`
+    pub(crate) fn with_crash_reporter(mut self, crash: Option<Arc<CrashReporter>>) -> Self {
+        self.crash = crash;
+        self
+    }
+
+    /// Returns the code the process should exit with: 1 if enough errors were logged to fail
+    /// the run, see [`Builder::fail_on_errors`](crate::Builder::fail_on_errors), and 0 otherwise
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// let guard = Builder::new("my_app", Verbosity::from_args())
+    ///     .fail_on_errors(1)
+    ///     .init_with_guard();
+    /// // ...
+    /// let code = guard.exit_code();
+    /// guard.shutdown();
+    /// std::process::exit(code);
+    /// # }
+    /// ```
+    pub fn exit_code(&self) -> i32 {
+        match self.fail_on_errors {
+            Some(min_errors) if self.event_counts().errors() >= min_errors as u64 => 1,
+            _ => 0,
+        }
+    }
+
+    /// Returns the number of events that passed the filters so far, by level, and by target
+    /// if enabled with [`Builder::count_targets`](crate::Builder::count_targets)
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// let guard = Builder::new("my_app", Verbosity::from_args()).init_with_guard();
+    /// // ...
+    /// if guard.event_counts().warnings() > 0 {
+    ///     eprintln!("finished with warnings");
+    /// }
+    /// # }
+    /// ```
+    pub fn event_counts(&self) -> EventCounts {
+        self.tally.counts()
+    }
+
+    /// Returns how long the spans closed so far lived, by span name, if enabled with
+    /// [`Builder::span_histograms`](crate::Builder::span_histograms)
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// let guard = Builder::new("my_app", Verbosity::from_args())
+    ///     .span_histograms()
+    ///     .init_with_guard();
+    /// // ...
+    /// if let Some(requests) = guard.span_durations().get("request") {
+    ///     println!("p99 {:?}", requests.quantile(0.99));
+    /// }
+    /// # }
+    /// ```
+    pub fn span_durations(&self) -> SpanDurations {
+        self.histograms
+            .as_ref()
+            .map_or_else(SpanDurations::default, |histograms| histograms.durations())
+    }
+
+    /// Returns whether the writes to each file, syslog daemon and log collector set up succeed,
+    /// with the number that failed and the last error
+    ///
+    /// Failures lose events, so they are also logged as `WARN` events with the target
+    /// `pretty_tracing_subscriber::health`, which pass whatever the filters, at most once a
+    /// minute for each sink while it keeps failing, and an `INFO` event once it recovers. The
+    /// console itself isn't listed.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// let guard = Builder::new("my_app", Verbosity::from_args()).init_with_guard();
+    /// // In a health check endpoint
+    /// let failing: Vec<String> = guard
+    ///     .sink_health()
+    ///     .iter()
+    ///     .filter(|sink| !sink.healthy())
+    ///     .map(|sink| sink.to_string())
+    ///     .collect();
+    /// # }
+    /// ```
+    pub fn sink_health(&self) -> Vec<SinkHealth> {
+        health::sinks()
+    }
+
+    /// Replaces the filter settings read from the application's configuration file, such as when
+    /// it is reloaded on `SIGHUP` or changed through an admin endpoint
+    ///
+    /// The new settings are combined with those of the environment and the command line as at
+    /// startup, see [`LogConfig`](crate::LogConfig). The change is recorded by an `INFO` event
+    /// with the target `pretty_tracing_subscriber::audit`, which passes whatever the filters,
+    /// with the filters in effect before and after as `old` and `new`, the directives `added`
+    /// and `removed`, and `changed_by`, such as `"SIGHUP"` or the user of an admin endpoint.
+    /// Nothing happens if the filters in effect stay the same.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, LogSettings, Verbosity};
+    /// # use structopt::StructOpt;
+    /// let guard = Builder::new("my_app", Verbosity::from_args()).init_with_guard();
+    /// // On SIGHUP
+    /// guard.reload_file_settings(LogSettings::from_directives("info,my_app::db=trace"), "SIGHUP");
+    /// # }
+    /// ```
+    pub fn reload_file_settings(&self, settings: LogSettings, changed_by: &str) {
+        self.filters.reload_file(settings, changed_by);
+    }
+
+    /// Switches the formatted output between verbose, showing the time, module and spans of
+    /// events, and compact, such as to see more while an incident is looked into
+    ///
+    /// This overrides [`Builder::verbose_format`](crate::Builder::verbose_format) and
+    /// `--verbose` until it is switched again. The transcript stays verbose, and routes and files
+    /// written as JSON aren't affected.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// let guard = Builder::new("my_app", Verbosity::from_args()).init_with_guard();
+    /// // On SIGUSR1
+    /// guard.set_verbose_format(true);
+    /// # }
+    /// ```
+    pub fn set_verbose_format(&self, verbose: bool) {
+        self.filters.set_verbose_format(verbose);
+    }
+
+    /// Whether the formatted output is verbose, see [`LogGuard::set_verbose_format`]
+    pub fn verbose_format(&self) -> bool {
+        self.filters.verbose_format.load(Ordering::Relaxed)
+    }
+
+    /// Writes a crash report for a fatal error that doesn't panic, and logs its path at `ERROR`,
+    /// see [`Builder::crash_reports`](crate::Builder::crash_reports)
+    ///
+    /// Returns the path of the report, or `None` if crash reports aren't enabled or the file
+    /// couldn't be written, which is logged too.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// # fn run() -> Result<(), std::io::Error> { Ok(()) }
+    /// let guard = Builder::new("my_app", Verbosity::from_args())
+    ///     .crash_reports("/var/log/my_app")
+    ///     .init_with_guard();
+    /// if let Err(e) = run() {
+    ///     guard.crash_report(&e.to_string());
+    ///     std::process::exit(1);
+    /// }
+    /// # }
+    /// ```
+    pub fn crash_report(&self, message: &str) -> Option<PathBuf> {
+        self.crash.as_ref()?.report(message).ok()
+    }
+
+    /// Shuts logging down now rather than when the guard goes out of scope
+    pub fn shutdown(self) {}
+
+    /// Shuts logging down now, waiting at most `timeout` for the sinks to be drained, such as
+    /// when a log collector is unreachable and the process has to exit promptly
+    ///
+    /// If they aren't drained in time, the events still waiting are lost, and the sinks aren't
+    /// marked closed, as their log was cut short.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Loki, Verbosity};
+    /// # use structopt::StructOpt;
+    /// # use std::time::Duration;
+    /// let guard = Builder::new("my_app", Verbosity::from_args())
+    ///     .loki(Loki::new("http://localhost:3100"))
+    ///     .init_with_guard();
+    /// // ...
+    /// guard.shutdown_timeout(Duration::from_secs(2));
+    /// # }
+    /// ```
+    pub fn shutdown_timeout(self, timeout: Duration) {
+        self.shutdown.run(Some(timeout));
+    }
+
+    /// Returns a handle that shuts logging down from anywhere, such as the thread handling
+    /// `SIGTERM`, where the guard, held by `main`, can't be dropped
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Verbosity};
+    /// # use structopt::StructOpt;
+    /// # use std::time::Duration;
+    /// # fn on_sigterm(_: impl FnOnce() + Send + 'static) {}
+    /// # fn serve() {}
+    /// let guard = Builder::new("my_app", Verbosity::from_args()).init_with_guard();
+    /// let handle = guard.shutdown_handle();
+    /// on_sigterm(move || {
+    ///     tracing::info!("terminating");
+    ///     handle.shutdown_timeout(Duration::from_secs(5));
+    ///     std::process::exit(143);
+    /// });
+    /// serve();
+    /// # }
+    /// ```
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(self.shutdown.clone())
+    }
+
+    /// Shuts logging down from an async context, waiting for the batches still being pushed to
+    /// log collectors by tasks on the Tokio runtime, see
+    /// [`Builder::tokio_batching`](crate::Builder::tokio_batching), with the `tokio` feature
+    ///
+    /// Dropping the guard on a thread of the runtime can't wait for those tasks, whose last
+    /// batches are then lost.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "cli")] {
+    /// # use pretty_tracing_subscriber::{Builder, Loki, Verbosity};
+    /// # use structopt::StructOpt;
+    /// # async fn run() {}
+    /// async fn serve() {
+    ///     let guard = Builder::new("my_app", Verbosity::from_args())
+    ///         .loki(Loki::new("http://localhost:3100"))
+    ///         .tokio_batching()
+    ///         .init_with_guard();
+    ///     run().await;
+    ///     guard.shutdown_async().await;
+    /// }
+    /// # }
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn shutdown_async(self) {
+        let _ = tokio::task::spawn_blocking(move || batch::off_runtime(|| drop(self))).await;
+    }
+}
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        self.shutdown.run(None);
+    }
+}
+
+impl Shutdown {
+    /// Shuts logging down unless it was already, giving up on draining the sinks after
+    /// `timeout`
+    fn run(&self, timeout: Option<Duration>) {
+        if self.done.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        panic::uninstall_hooks();
+        let drained = match timeout {
+            Some(timeout) => self.drain_within(timeout),
+            None => {
+                drain(&self.sinks);
+                true
+            }
+        };
+        if self.summary {
+            let summary = self.tally.counts().summary();
+            if !summary.is_empty() {
+                let summary = if status::tty() {
+                    summary
+                } else {
+                    wrap::strip_escapes(&summary)
+                };
+                let _ = writeln!(Stderr::new(), "{}", summary);
+            }
+        }
+        // The sinks left draining may still be stuck writing
+        if !drained {
+            return;
+        }
+        if self.closed_marker {
+            for sink in &self.sinks {
+                let _ = sink.close(CLOSED_MARKER);
+            }
+        }
+        for sink in &self.sinks {
+            let _ = sink.end_run();
+        }
+    }
+
+    /// Drains the sinks on a thread of its own, returning whether it was done within `timeout`
+    fn drain_within(&self, timeout: Duration) -> bool {
+        let sinks = self.sinks.clone();
+        let (done, drained) = mpsc::channel();
+        let draining = thread::Builder::new()
+            .name("log shutdown".to_owned())
+            .spawn(move || {
+                drain(&sinks);
+                let _ = done.send(());
+            });
+        match draining {
+            Ok(_) => drained.recv_timeout(timeout).is_ok(),
+            Err(_) => {
+                drain(&self.sinks);
+                true
+            }
+        }
+    }
+}
+
+fn drain(sinks: &[Arc<dyn Sink>]) {
+    for sink in sinks {
+        let _ = sink.drain();
+    }
+    // The last pushes may have failed while draining
+    if health::report() {
+        for sink in sinks {
+            let _ = sink.drain();
+        }
+    }
+}
+
+/// Shuts logging down from another thread than the one holding the [`LogGuard`], see
+/// [`LogGuard::shutdown_handle`]
+///
+/// The handle can be cloned and outlive the guard. Once logging is shut down, whether through a
+/// handle or the guard, shutting it down again does nothing.
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<Shutdown>);
+
+impl ShutdownHandle {
+    /// Shuts logging down now, waiting for the sinks to be drained as dropping the guard does
+    pub fn shutdown(&self) {
+        self.0.run(None);
+    }
+
+    /// Shuts logging down now, waiting at most `timeout` for the sinks to be drained, see
+    /// [`LogGuard::shutdown_timeout`]
+    pub fn shutdown_timeout(&self, timeout: Duration) {
+        self.0.run(Some(timeout));
+    }
+
+    /// Whether logging was shut down
+    pub fn is_shut_down(&self) -> bool {
+        self.0.done.load(Ordering::SeqCst)
+    }
+}
+
+impl std::fmt::Debug for ShutdownHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShutdownHandle")
+            .field("shut_down", &self.is_shut_down())
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for LogGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogGuard")
+            .field("sinks", &self.shutdown.sinks.len())
+            .field("closed_marker", &self.shutdown.closed_marker)
+            .field("fail_on_errors", &self.fail_on_errors)
+            .field("summary", &self.shutdown.summary)
+            .finish()
+    }
+}
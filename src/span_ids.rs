@@ -0,0 +1,46 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::span::Attributes;
+use tracing::{Id, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+/// A short ID telling apart the spans of the same name, stored in the span's extensions
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ShortId(u16);
+
+/// Displays as four hex digits, such as `#3fa2`
+impl fmt::Display for ShortId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:04x}", self.0)
+    }
+}
+
+/// Gives each span a [`ShortId`], see [`Builder::span_ids`](crate::Builder::span_ids)
+///
+/// The IDs of `tracing` are reused once spans close, so spans are numbered in the order they
+/// are created instead, and the numbers scrambled so that consecutive spans don't look alike.
+pub(crate) struct SpanIdLayer;
+
+impl<S> Layer<S> for SpanIdLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        let number = NEXT.fetch_add(1, Ordering::Relaxed);
+        // Fibonacci hashing spreads consecutive numbers over the whole range
+        let hashed = number.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 48;
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(ShortId(hashed as u16));
+        }
+    }
+}
+
+/// Returns the short ID of the span, if it was seen by [`SpanIdLayer`]
+pub(crate) fn of<S>(span: &SpanRef<'_, S>) -> Option<ShortId>
+where
+    S: for<'a> LookupSpan<'a>,
+{
+    span.extensions().get::<ShortId>().copied()
+}
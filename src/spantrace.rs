@@ -0,0 +1,99 @@
+//! Renders the span traces of errors instrumented with `tracing-error`, with the `span-trace`
+//! feature
+
+#[cfg(feature = "span-trace")]
+pub(crate) use native::{layer, write};
+
+#[cfg(not(feature = "span-trace"))]
+pub(crate) use unsupported::{layer, write};
+
+#[cfg(feature = "span-trace")]
+mod native {
+    use ansi_term::Style;
+    use std::error::Error;
+    use std::fmt::{self, Write};
+    use tracing::field::{Field, Visit};
+    use tracing::{Event, Subscriber};
+    use tracing_error::{ErrorLayer, ExtractSpanTrace, SpanTrace};
+    use tracing_subscriber::layer::Layer;
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// Keeps the fields of spans, which span traces are rendered from
+    pub(crate) fn layer<S>() -> impl Layer<S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        ErrorLayer::default()
+    }
+
+    /// Writes the span trace of each error among the fields of `event`, below the event line
+    pub(crate) fn write(f: &mut dyn Write, event: &Event<'_>) -> fmt::Result {
+        let mut visitor = TraceVisitor {
+            traces: String::new(),
+        };
+        event.record(&mut visitor);
+        f.write_str(&visitor.traces)
+    }
+
+    struct TraceVisitor {
+        traces: String,
+    }
+
+    impl Visit for TraceVisitor {
+        fn record_error(&mut self, field: &Field, value: &(dyn Error + 'static)) {
+            // The trace may come with an error that the logged one wraps
+            let mut error = Some(value);
+            while let Some(current) = error {
+                if let Some(trace) = current.span_trace() {
+                    let _ = write_trace(&mut self.traces, field.name(), trace);
+                    return;
+                }
+                error = current.source();
+            }
+        }
+
+        fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+    }
+
+    /// Writes the spans the error was created in, innermost first, the way backtraces are
+    fn write_trace(f: &mut String, field: &str, trace: &SpanTrace) -> fmt::Result {
+        let dimmed = Style::new().dimmed();
+        let heading = format!("spans where `{}` was created:", field);
+        writeln!(f, "    {}", dimmed.paint(heading))?;
+        let mut result = Ok(());
+        let mut i = 0;
+        trace.with_spans(|metadata, fields| {
+            result = (|| {
+                let name = Style::new().bold().paint(metadata.name());
+                write!(f, "    {} {}", dimmed.paint(format!("{:>2}:", i)), name)?;
+                if !fields.is_empty() {
+                    write!(f, " {}", fields)?;
+                }
+                writeln!(f)?;
+                if let (Some(file), Some(line)) = (metadata.file(), metadata.line()) {
+                    let location = format!("at {}:{}", file, line);
+                    writeln!(f, "        {}", dimmed.paint(location))?;
+                }
+                Ok(())
+            })();
+            i += 1;
+            result.is_ok()
+        });
+        result
+    }
+}
+
+#[cfg(not(feature = "span-trace"))]
+mod unsupported {
+    use std::fmt::{self, Write};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Identity, Layer};
+
+    pub(crate) fn layer<S: Subscriber>() -> impl Layer<S> {
+        Identity::new()
+    }
+
+    pub(crate) fn write(_f: &mut dyn Write, _event: &Event<'_>) -> fmt::Result {
+        Ok(())
+    }
+}
@@ -0,0 +1,361 @@
+use crate::backlog::Backlog;
+use crate::buffer::Buffer;
+use crate::overhead::{self, Stage};
+use crate::shutdown::Sink;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+use tracing::Level;
+
+/// The status lines currently shown at the bottom of the terminal
+static AREA: Mutex<StatusArea> = Mutex::new(StatusArea {
+    lines: Vec::new(),
+    drawn: 0,
+});
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A line at the bottom of the terminal that is rewritten in place, below the log output
+///
+/// Events are written above the status lines, which are redrawn after each of them, so the two
+/// never interleave. The line is removed when the `StatusLine` is dropped. When stderr is not a
+/// terminal, status lines are not shown at all.
+///
+/// Status lines should be shorter than the terminal is wide, as wrapped lines are not cleared
+/// properly.
+#[derive(Debug)]
+pub struct StatusLine {
+    id: u64,
+}
+
+impl StatusLine {
+    /// Shows a new status line below the existing ones
+    pub fn new(text: impl Into<String>) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        if tty() {
+            let mut area = lock();
+            area.lines.push((id, text.into()));
+            area.redraw();
+        }
+        Self { id }
+    }
+
+    /// Replaces the text of the status line
+    pub fn set(&self, text: impl Into<String>) {
+        if !tty() {
+            return;
+        }
+        let mut area = lock();
+        if let Some((_, line)) = area.lines.iter_mut().find(|(id, _)| *id == self.id) {
+            *line = text.into();
+            area.redraw();
+        }
+    }
+}
+
+impl Drop for StatusLine {
+    fn drop(&mut self) {
+        if !tty() {
+            return;
+        }
+        let mut area = lock();
+        area.lines.retain(|(id, _)| *id != self.id);
+        area.redraw();
+    }
+}
+
+#[derive(Debug)]
+struct StatusArea {
+    lines: Vec<(u64, String)>,
+    /// How many status lines are on screen above the cursor
+    drawn: usize,
+}
+
+impl StatusArea {
+    fn clear(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        for _ in 0..self.drawn {
+            out.write_all(b"\x1b[1A\x1b[2K")?;
+        }
+        self.drawn = 0;
+        Ok(())
+    }
+
+    fn draw(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        for (_, line) in &self.lines {
+            writeln!(out, "{}", line)?;
+        }
+        self.drawn = self.lines.len();
+        Ok(())
+    }
+
+    fn redraw(&mut self) {
+        let stderr = io::stderr();
+        let mut out = stderr.lock();
+        let _ = self.clear(&mut out).and_then(|()| self.draw(&mut out));
+        let _ = out.flush();
+    }
+}
+
+fn lock() -> MutexGuard<'static, StatusArea> {
+    AREA.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Whether stderr is a terminal, where status lines are shown
+pub(crate) fn tty() -> bool {
+    static TTY: OnceLock<bool> = OnceLock::new();
+    *TTY.get_or_init(|| io::stderr().is_terminal())
+}
+
+/// Whether stdout is a terminal
+pub(crate) fn stdout_tty() -> bool {
+    static TTY: OnceLock<bool> = OnceLock::new();
+    *TTY.get_or_init(|| io::stdout().is_terminal())
+}
+
+/// The width of the terminal last read, or 0 if it is unknown
+static WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Set when the terminal is resized, so that its width is read again
+static RESIZED: AtomicBool = AtomicBool::new(true);
+
+/// The width lines are fitted to, when wrapping messages or aligning span durations
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Width {
+    /// Whether to use the width of the terminal on stderr
+    terminal: bool,
+    /// The width used when that of the terminal is unknown, such as when stderr is redirected
+    fallback: Option<usize>,
+}
+
+impl Width {
+    /// The width of the terminal if stderr is one, which follows it as it is resized, or
+    /// `fallback`, or `None` if lines aren't fitted to any width
+    pub(crate) fn detect(fallback: Option<usize>) -> Option<Self> {
+        let terminal = tty();
+        if terminal {
+            watch_resizes();
+        }
+        (terminal || fallback.is_some()).then_some(Self { terminal, fallback })
+    }
+
+    pub(crate) fn get(&self) -> Option<usize> {
+        self.terminal.then(width).flatten().or(self.fallback)
+    }
+}
+
+/// Returns the width of the terminal on stderr, or the `COLUMNS` environment variable
+///
+/// The width is read again after the terminal is resized, once [`Width::detect`] has started
+/// watching for `SIGWINCH`.
+pub(crate) fn width() -> Option<usize> {
+    if RESIZED.swap(false, Ordering::Relaxed) {
+        WIDTH.store(read_width().unwrap_or(0), Ordering::Relaxed);
+    }
+    match WIDTH.load(Ordering::Relaxed) {
+        0 => None,
+        width => Some(width),
+    }
+}
+
+/// Marks the width as stale on `SIGWINCH`, unless the application handles the signal itself
+fn watch_resizes() {
+    #[cfg(unix)]
+    {
+        static WATCHING: OnceLock<()> = OnceLock::new();
+        WATCHING.get_or_init(|| {
+            extern "C" fn on_resize(_signal: libc::c_int) {
+                RESIZED.store(true, Ordering::Relaxed);
+            }
+            // SAFETY: the handler only stores to an atomic, which is async-signal-safe, and is
+            // only installed in place of the default disposition
+            unsafe {
+                let mut current: libc::sigaction = std::mem::zeroed();
+                if libc::sigaction(libc::SIGWINCH, std::ptr::null(), &mut current) != 0
+                    || current.sa_sigaction != libc::SIG_DFL
+                {
+                    return;
+                }
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = on_resize as extern "C" fn(libc::c_int) as usize;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(libc::SIGWINCH, &action, std::ptr::null_mut());
+            }
+        });
+    }
+}
+
+fn read_width() -> Option<usize> {
+    #[cfg(unix)]
+    {
+        // SAFETY: `TIOCGWINSZ` only writes a `winsize` to the pointer it is given
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        let result = unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) };
+        if result == 0 && size.ws_col > 0 {
+            return Some(usize::from(size.ws_col));
+        }
+    }
+    std::env::var("COLUMNS").ok()?.parse().ok()
+}
+
+/// Writes to stderr above the status lines
+///
+/// Used as the writer of the formatting layer, which writes each event with a single call.
+#[derive(Debug, Clone)]
+pub(crate) struct Stderr {
+    backlog: Option<Arc<Backlog>>,
+    buffer: Option<Arc<Buffer>>,
+}
+
+impl Stderr {
+    pub(crate) fn new() -> Self {
+        Self {
+            backlog: None,
+            buffer: None,
+        }
+    }
+
+    /// Writes from a separate thread, dropping events while writes block for longer than
+    /// `threshold`
+    pub(crate) fn lossy(threshold: Duration) -> Self {
+        let backlog = Backlog::start(threshold, |buf| Stderr::new().write_above(buf).map(drop));
+        Self {
+            backlog: Some(Arc::new(backlog)),
+            buffer: None,
+        }
+    }
+
+    /// Holds events in memory, writing them out every `interval`, when enough of them pile up,
+    /// and on shutdown
+    pub(crate) fn buffered(self, interval: Duration) -> Self {
+        let direct = self.clone();
+        Self {
+            buffer: Some(Buffer::start(interval, move |buf| {
+                direct.write_direct(None, buf)
+            })),
+            ..self
+        }
+    }
+
+    /// Writes an event at `level`, which is counted as such if it is dropped
+    pub(crate) fn write_event(&self, level: Level, buf: &[u8]) -> io::Result<()> {
+        overhead::measure(Stage::Writing, || match &self.buffer {
+            Some(buffer) => buffer.push(buf),
+            None => self.write_direct(Some(level), buf).map(drop),
+        })
+    }
+
+    fn write_direct(&self, level: Option<Level>, buf: &[u8]) -> io::Result<usize> {
+        match &self.backlog {
+            Some(backlog) => {
+                backlog.push(level, buf);
+                Ok(buf.len())
+            }
+            None => self.write_above(buf),
+        }
+    }
+}
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        overhead::measure(Stage::Writing, || match &self.buffer {
+            Some(buffer) => buffer.push(buf).map(|()| buf.len()),
+            None => self.write_direct(None, buf),
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(buffer) = &self.buffer {
+            buffer.flush()?;
+        }
+        io::stderr().flush()
+    }
+}
+
+impl Stderr {
+    fn write_above(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut area = lock();
+        let stderr = io::stderr();
+        let mut out = stderr.lock();
+        if area.drawn == 0 && area.lines.is_empty() {
+            out.write_all(buf)?;
+            return Ok(buf.len());
+        }
+        // The status lines are redrawn in the same write, so other processes writing to the
+        // terminal can't come between them and the event
+        let mut frame = Vec::with_capacity(buf.len() + 256);
+        area.clear(&mut frame)?;
+        frame.extend_from_slice(buf);
+        // Only a complete line leaves the cursor where the status lines belong
+        if buf.ends_with(b"\n") {
+            area.draw(&mut frame)?;
+        }
+        out.write_all(&frame)?;
+        Ok(buf.len())
+    }
+}
+
+/// Writes to stdout, clearing the status lines on stderr first in case both are the same terminal
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stdout;
+
+impl Write for Stdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        overhead::measure(Stage::Writing, || {
+            let mut area = lock();
+            let stderr = io::stderr();
+            let mut err = stderr.lock();
+            area.clear(&mut err)?;
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            out.write_all(buf)?;
+            out.flush()?;
+            if buf.ends_with(b"\n") {
+                area.draw(&mut err)?;
+            }
+            Ok(buf.len())
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+impl Sink for Stdout {
+    fn drain(&self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+
+    fn close(&self, marker: &str) -> io::Result<()> {
+        Stdout.write_all(format!("{}\n", marker).as_bytes())
+    }
+}
+
+impl Sink for Stderr {
+    fn drain(&self) -> io::Result<()> {
+        if let Some(buffer) = &self.buffer {
+            buffer.flush()?;
+        }
+        if let Some(backlog) = &self.backlog {
+            backlog.drain();
+        }
+        let mut area = lock();
+        let stderr = io::stderr();
+        let mut out = stderr.lock();
+        area.clear(&mut out)?;
+        out.flush()
+    }
+
+    fn close(&self, marker: &str) -> io::Result<()> {
+        self.clone().write_all(format!("{}\n", marker).as_bytes())?;
+        if let Some(buffer) = &self.buffer {
+            buffer.flush()?;
+        }
+        if let Some(backlog) = &self.backlog {
+            backlog.drain();
+        }
+        Ok(())
+    }
+}
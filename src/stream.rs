@@ -0,0 +1,163 @@
+//! Serves the events shown to external log viewers attached to a socket
+
+use std::io::{self, Write};
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// How many events wait to be sent before new ones are dropped, so a slow viewer never holds
+/// up the application
+const QUEUE: usize = 1024;
+
+/// How long a viewer has to take a frame before it is disconnected, so one that stopped
+/// reading doesn't hold up the others
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Sends each event as a JSON object prefixed with its length, as a 4-byte big-endian integer,
+/// to every viewer connected to the socket, see
+/// [`Builder::event_stream`](crate::Builder::event_stream)
+#[derive(Debug, Clone)]
+pub(crate) struct EventStream {
+    frames: SyncSender<Vec<u8>>,
+}
+
+/// The viewers connected since the last frame was sent, which the send thread takes over
+type Viewers = Arc<Mutex<Vec<Box<dyn Write + Send>>>>;
+
+impl EventStream {
+    /// Listens at `address`, a `host:port` prefixed with `tcp://` or the path of a Unix socket,
+    /// which replaces any socket left at that path by an earlier run
+    pub(crate) fn listen(address: &str) -> io::Result<Self> {
+        let viewers = Viewers::default();
+        let accepting = viewers.clone();
+        if let Some(address) = address.strip_prefix("tcp://") {
+            let listener = TcpListener::bind(address)?;
+            spawn("stream accept", move || {
+                for stream in listener.incoming().flatten() {
+                    let _ = stream.set_nodelay(true);
+                    if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_ok() {
+                        lock(&accepting).push(Box::new(stream));
+                    }
+                }
+            })?;
+        } else {
+            listen_unix(address, accepting)?;
+        }
+        let (frames, queued) = mpsc::sync_channel(QUEUE);
+        spawn("stream send", move || send(queued, viewers))?;
+        Ok(Self { frames })
+    }
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &str, viewers: Viewers) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    spawn("stream accept", move || {
+        for stream in listener.incoming().flatten() {
+            if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_ok() {
+                lock(&viewers).push(Box::new(stream));
+            }
+        }
+    })
+}
+
+#[cfg(not(unix))]
+fn listen_unix(_path: &str, _viewers: Viewers) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets aren't supported on this platform, use tcp://host:port",
+    ))
+}
+
+fn spawn(name: &str, f: impl FnOnce() + Send + 'static) -> io::Result<()> {
+    thread::Builder::new()
+        .name(name.to_owned())
+        .spawn(f)
+        .map(drop)
+}
+
+fn lock(viewers: &Viewers) -> std::sync::MutexGuard<'_, Vec<Box<dyn Write + Send>>> {
+    viewers.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Writes every frame queued to the viewers connected, forgetting those that went away or
+/// stopped reading
+///
+/// The viewers are written to without holding the lock, so the accept thread never waits on a
+/// slow viewer.
+fn send(queued: Receiver<Vec<u8>>, accepted: Viewers) {
+    let mut viewers = Vec::new();
+    for frame in queued {
+        viewers.append(&mut lock(&accepted));
+        viewers.retain_mut(|viewer| viewer.write_all(&frame).is_ok());
+    }
+}
+
+impl MakeWriter for EventStream {
+    type Writer = Self;
+
+    fn make_writer(&self) -> Self {
+        self.clone()
+    }
+}
+
+impl Write for EventStream {
+    /// Queues one formatted event, which the JSON formatter writes whole, line ending included
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let json = buf.strip_suffix(b"\n").unwrap_or(buf);
+        if !json.is_empty() {
+            let mut frame = Vec::with_capacity(4 + json.len());
+            frame.extend_from_slice(&(json.len() as u32).to_be_bytes());
+            frame.extend_from_slice(json);
+            let _ = self.frames.try_send(frame);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+    use std::time::Instant;
+
+    #[test]
+    fn viewer_that_stops_reading_is_dropped() {
+        let path = std::env::temp_dir().join(format!("stream_{}.sock", std::process::id()));
+        let mut stream = EventStream::listen(path.to_str().unwrap()).unwrap();
+        let _stalled = UnixStream::connect(&path).unwrap();
+        let mut reading = UnixStream::connect(&path).unwrap();
+        reading.set_read_timeout(Some(WRITE_TIMEOUT * 10)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        const FRAMES: usize = 64;
+        let event = format!("{{\"message\":\"{}\"}}\n", "x".repeat(64 * 1024));
+        let started = Instant::now();
+        for _ in 0..FRAMES {
+            stream.write_all(event.as_bytes()).unwrap();
+        }
+        for _ in 0..FRAMES {
+            let mut length = [0; 4];
+            reading.read_exact(&mut length).unwrap();
+            let mut json = vec![0; u32::from_be_bytes(length) as usize];
+            reading.read_exact(&mut json).unwrap();
+            assert_eq!(json, event.trim_end().as_bytes());
+        }
+        assert!(started.elapsed() < WRITE_TIMEOUT * 5);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,367 @@
+use crate::dedup::StormNote;
+use crate::fields::{Redaction, SpanFields};
+use crate::health::SinkStatus;
+use crate::run;
+use crate::timestamp::{self, TimePrecision};
+use std::fmt::{self, Write};
+use std::io;
+use std::net::UdpSocket;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// The `user` facility, for messages from applications
+const FACILITY: u8 = 1;
+
+/// ID of the structured data element holding the fields, qualified by the enterprise number
+/// that RFC 5424 reserves for examples, as the fields aren't registered with IANA
+const SD_ID: &str = "fields@32473";
+
+/// Longest name of a structured data parameter
+const MAX_PARAM_NAME: usize = 32;
+
+#[derive(Debug)]
+enum Transport {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+/// Sends each event to a syslog daemon as an RFC 5424 message, with its fields as structured
+/// data, see [`Builder::syslog`](crate::Builder::syslog)
+#[derive(Debug)]
+pub(crate) struct Syslog {
+    transport: Transport,
+    hostname: String,
+    app_name: String,
+    redaction: Redaction,
+    health: Arc<SinkStatus>,
+}
+
+impl Syslog {
+    /// Connects to `address`, a socket path such as `/dev/log`, or a `host:port` to send UDP
+    /// datagrams to, optionally prefixed with `unix://` or `udp://`
+    pub(crate) fn connect(address: &str, app_name: &str, redaction: Redaction) -> io::Result<Self> {
+        let transport = match address.strip_prefix("udp://") {
+            Some(address) => Self::udp(address)?,
+            None => match address.strip_prefix("unix://") {
+                Some(path) => Self::unix(path)?,
+                None if address.starts_with('/') => Self::unix(address)?,
+                None => Self::udp(address)?,
+            },
+        };
+        Ok(Self {
+            transport,
+            hostname: hostname().unwrap_or_else(|| "-".to_owned()),
+            app_name: header_field(app_name, 48),
+            redaction,
+            health: SinkStatus::register("syslog"),
+        })
+    }
+
+    fn udp(address: &str) -> io::Result<Transport> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(address)?;
+        Ok(Transport::Udp(socket))
+    }
+
+    #[cfg(unix)]
+    fn unix(path: &str) -> io::Result<Transport> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Transport::Unix(socket))
+    }
+
+    #[cfg(not(unix))]
+    fn unix(_path: &str) -> io::Result<Transport> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix sockets are not supported on this platform",
+        ))
+    }
+
+    fn send(&self, message: &str) {
+        let sent = match &self.transport {
+            Transport::Udp(socket) => socket.send(message.as_bytes()),
+            #[cfg(unix)]
+            Transport::Unix(socket) => socket.send(message.as_bytes()),
+        };
+        self.health.record(&sent);
+    }
+}
+
+impl<S> Layer<S> for Syslog
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut visitor = FieldVisitor {
+            redaction: &self.redaction,
+            message: String::new(),
+            params: String::new(),
+        };
+        visitor.param("run_id", run::run_id());
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(fields) = span.extensions().get::<SpanFields>() {
+                    for (name, value) in fields.iter() {
+                        visitor.param(&format!("{}.{}", span.name(), name), value);
+                    }
+                }
+            }
+        }
+        event.record(&mut visitor);
+        // The formatted output comes next and shows the note too
+        if let Some(note) = StormNote::take() {
+            let _ = write!(visitor.message, " {}", note);
+            note.restore();
+        }
+
+        let mut message = format!("<{}>1 ", FACILITY * 8 + severity(*metadata.level()));
+        let _ = timestamp::write_rfc3339(&mut message, SystemTime::now(), TimePrecision::Micros);
+        let _ = write!(
+            message,
+            " {} {} {} {} ",
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            header_field(metadata.target(), 32),
+        );
+        if visitor.params.is_empty() {
+            message.push('-');
+        } else {
+            let _ = write!(message, "[{}{}]", SD_ID, visitor.params);
+        }
+        message.push(' ');
+        message.push_str(&visitor.message);
+        self.send(&message);
+    }
+}
+
+/// The syslog severity of a level
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Returns the name of this host, as the syslog daemon may be on another one
+pub(crate) fn hostname() -> Option<String> {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        // SAFETY: `gethostname` writes at most `buf.len()` bytes to the buffer it is given
+        if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } == 0 {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            let name = String::from_utf8_lossy(&buf[..len]);
+            if !name.is_empty() {
+                return Some(header_field(&name, 255));
+            }
+        }
+    }
+    std::env::var("COMPUTERNAME")
+        .ok()
+        .map(|name| header_field(&name, 255))
+}
+
+/// Makes `value` fit a header field: printable ASCII without spaces, at most `max` characters
+fn header_field(value: &str, max: usize) -> String {
+    let field: String = value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max)
+        .collect();
+    if field.is_empty() {
+        "-".to_owned()
+    } else {
+        field
+    }
+}
+
+struct FieldVisitor<'a> {
+    redaction: &'a Redaction,
+    message: String,
+    /// The structured data parameters written so far, each preceded by a space
+    params: String,
+}
+
+impl FieldVisitor<'_> {
+    fn param(&mut self, name: &str, value: &str) {
+        let name: String = name
+            .chars()
+            .map(|c| match c {
+                '=' | ' ' | ']' | '"' => '_',
+                c if c.is_ascii_graphic() => c,
+                _ => '_',
+            })
+            .take(MAX_PARAM_NAME)
+            .collect();
+        let _ = write!(self.params, " {}=\"", name);
+        for c in value.chars() {
+            if matches!(c, '"' | '\\' | ']') {
+                self.params.push('\\');
+            }
+            self.params.push(c);
+        }
+        self.params.push('"');
+    }
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_owned();
+        } else if self.redaction.matches(field.name()) {
+            self.param(field.name(), Redaction::REDACTED);
+        } else {
+            self.param(field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields::FieldLayer;
+    use std::time::Duration;
+    use tracing::level_filters::STATIC_MAX_LEVEL;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    fn params(fields: &[(&str, &str)]) -> String {
+        let redaction = Redaction::default();
+        let mut visitor = FieldVisitor {
+            redaction: &redaction,
+            message: String::new(),
+            params: String::new(),
+        };
+        for (name, value) in fields {
+            visitor.param(name, value);
+        }
+        visitor.params
+    }
+
+    /// Logs what `log` logs through a sink connected to `address`, redacting passwords
+    fn messages(address: &str, log: impl FnOnce()) {
+        let mut redaction = Redaction::default();
+        redaction.add("password");
+        let syslog = Syslog::connect(address, "my app", redaction).unwrap();
+        let subscriber = Registry::default().with(FieldLayer::default()).with(syslog);
+        tracing::subscriber::with_default(subscriber, log);
+    }
+
+    /// Checks the header of `message` up to the timestamp, returning what follows it
+    fn after_timestamp(message: &str, priority: u8) -> &str {
+        let rest = message.strip_prefix(&format!("<{}>1 ", priority)).unwrap();
+        let (timestamp, rest) = rest.split_at(rest.find(' ').unwrap());
+        assert!(timestamp.ends_with('Z'), "{}", message);
+        assert_eq!(timestamp.len(), "2021-06-02T09:14:03.210114Z".len());
+        rest
+    }
+
+    #[test]
+    fn param_values_escape_quotes_backslashes_and_brackets() {
+        assert_eq!(
+            params(&[("path", r#"C:\dir "a" [b]"#)]),
+            r#" path="C:\\dir \"a\" [b\]""#
+        );
+        assert_eq!(params(&[("a", ""), ("b", "é\n")]), " a=\"\" b=\"é\n\"");
+    }
+
+    #[test]
+    fn param_names_are_printable_ascii_without_separators() {
+        assert_eq!(params(&[("a=b c]d\"e", "")]), " a_b_c_d_e=\"\"");
+        assert_eq!(params(&[("café", "")]), " caf_=\"\"");
+        let long = "x".repeat(MAX_PARAM_NAME + 5);
+        assert_eq!(
+            params(&[(&long, "")]),
+            format!(" {}=\"\"", "x".repeat(MAX_PARAM_NAME))
+        );
+    }
+
+    #[test]
+    fn header_fields_are_printable_ascii_without_spaces() {
+        assert_eq!(header_field("my app\n", 48), "my_app_");
+        assert_eq!(header_field("app::db", 4), "app:");
+        assert_eq!(header_field("", 48), "-");
+    }
+
+    #[test]
+    fn events_are_sent_as_rfc_5424_messages() {
+        if STATIC_MAX_LEVEL < Level::WARN {
+            return;
+        }
+        let server = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let address = format!("udp://{}", server.local_addr().unwrap());
+        messages(&address, || {
+            tracing::warn_span!("request", id = 42).in_scope(|| {
+                tracing::warn!(target: "app::db", password = "x", query = "a]b", "slow query");
+            });
+            tracing::error!(target: "app", "failed");
+        });
+
+        let mut datagram = [0; 2048];
+        let mut receive = || {
+            let len = server.recv(&mut datagram).unwrap();
+            String::from_utf8(datagram[..len].to_vec()).unwrap()
+        };
+        let host = hostname().unwrap_or_else(|| "-".to_owned());
+        let header = format!(" {} my_app {} ", host, std::process::id());
+        let warning = receive();
+        assert_eq!(
+            after_timestamp(&warning, 8 + 4),
+            format!(
+                "{}app::db [fields@32473 run_id=\"{}\" request.id=\"42\" \
+                 password=\"[REDACTED\\]\" query=\"a\\]b\"] slow query",
+                header,
+                run::run_id()
+            )
+        );
+        let error = receive();
+        assert_eq!(
+            after_timestamp(&error, 8 + 3),
+            format!(
+                "{}app [fields@32473 run_id=\"{}\"] failed",
+                header,
+                run::run_id()
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn messages_can_go_to_a_unix_socket() {
+        if STATIC_MAX_LEVEL < Level::INFO {
+            return;
+        }
+        let path = std::env::temp_dir().join(format!("syslog_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        messages(path.to_str().unwrap(), || tracing::info!("started"));
+        let _ = std::fs::remove_file(&path);
+
+        let mut datagram = [0; 2048];
+        let len = server.recv(&mut datagram).unwrap();
+        let message = String::from_utf8(datagram[..len].to_vec()).unwrap();
+        assert!(after_timestamp(&message, 8 + 6).ends_with("\"] started"));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::route::Router;
+use std::fmt;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Record};
+use tracing::{Event, Id, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Name of the field listing the tags of an event
+pub(crate) const TAGS_FIELD: &str = "tags";
+
+/// Where events with a tag are written, see [`Builder::route_tag`](crate::Builder::route_tag)
+pub(crate) struct TagRoute {
+    pub(crate) tag: String,
+    pub(crate) writer: Router,
+}
+
+/// Whether events from the call site described by `metadata` can have tags
+pub(crate) fn may_have_tags(metadata: &Metadata<'_>) -> bool {
+    metadata.fields().field(TAGS_FIELD).is_some()
+}
+
+/// Whether `event` lists `tag` in its `tags` field
+///
+/// The field holds either a comma-separated string, such as `tags = "audit,security"`, or a
+/// list recorded with `Debug`, such as `tags = ?["audit", "security"]`.
+pub(crate) fn has_tag(event: &Event<'_>, tag: &str) -> bool {
+    let mut visitor = TagsVisitor(None);
+    event.record(&mut visitor);
+    visitor.0.is_some_and(|tags| {
+        tags.trim_start_matches('[')
+            .trim_end_matches(']')
+            .split(',')
+            .any(|candidate| candidate.trim().trim_matches('"') == tag)
+    })
+}
+
+struct TagsVisitor(Option<String>);
+
+impl Visit for TagsVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == TAGS_FIELD {
+            self.0 = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == TAGS_FIELD {
+            self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// Passes the events with `tag` to `inner`, along with every span
+pub(crate) struct Tagged<L> {
+    pub(crate) tag: String,
+    pub(crate) inner: L,
+}
+
+impl<S, L> Layer<S> for Tagged<L>
+where
+    S: Subscriber,
+    L: Layer<S>,
+{
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber)
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        self.inner.new_span(attrs, id, ctx)
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        self.inner.on_record(span, values, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if has_tag(event, &self.tag) {
+            self.inner.on_event(event, ctx)
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.inner.on_close(id, ctx)
+    }
+}
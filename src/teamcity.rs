@@ -0,0 +1,54 @@
+//! Service messages that make TeamCity show events in the build overview
+
+use std::fmt::{self, Write};
+use tracing::{Level, Metadata};
+
+/// Whether the program runs in a TeamCity build
+pub(crate) fn detected() -> bool {
+    std::env::var_os("TEAMCITY_VERSION").is_some()
+}
+
+/// Writes a `message` service message with the `WARNING` or `ERROR` status for an event of
+/// `metadata` with `message`, or nothing for other levels
+///
+/// The target and call site go in the text, as service messages have no attributes for them.
+pub(crate) fn write_message(
+    f: &mut dyn Write,
+    metadata: &Metadata<'_>,
+    message: &str,
+) -> fmt::Result {
+    let status = match *metadata.level() {
+        Level::ERROR => "ERROR",
+        Level::WARN => "WARNING",
+        _ => return Ok(()),
+    };
+    f.write_str("##teamcity[message text='")?;
+    escape(f, metadata.target())?;
+    if let (Some(file), Some(line)) = (metadata.file(), metadata.line()) {
+        f.write_str(" (")?;
+        escape(f, file)?;
+        write!(f, ":{})", line)?;
+    }
+    f.write_str(": ")?;
+    escape(f, message)?;
+    writeln!(f, "' status='{}']", status)
+}
+
+/// Escapes `text` for a value of a service message
+fn escape(f: &mut dyn Write, text: &str) -> fmt::Result {
+    for c in text.chars() {
+        match c {
+            '|' => f.write_str("||")?,
+            '\'' => f.write_str("|'")?,
+            '\n' => f.write_str("|n")?,
+            '\r' => f.write_str("|r")?,
+            '[' => f.write_str("|[")?,
+            ']' => f.write_str("|]")?,
+            '\u{85}' => f.write_str("|x")?,
+            '\u{2028}' => f.write_str("|l")?,
+            '\u{2029}' => f.write_str("|p")?,
+            c => f.write_char(c)?,
+        }
+    }
+    Ok(())
+}
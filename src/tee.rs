@@ -0,0 +1,205 @@
+use crate::boost::{self, BoostRule};
+use crate::delimited::DelimitedFormatter;
+use crate::escalate;
+use crate::route::Router;
+use std::cmp;
+use std::sync::Arc;
+use tracing::span::{Attributes, Record};
+use tracing::subscriber::Interest;
+use tracing::{Id, Metadata, Subscriber};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::{Context, Filter, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// A destination of [`Builder::tee_json`](crate::Builder::tee_json), set up to be written to
+pub(crate) struct JsonTee {
+    pub(crate) writer: Router,
+    pub(crate) filter: Targets,
+    /// Writes rows instead of JSON, see
+    /// [`Builder::tee_delimited`](crate::Builder::tee_delimited)
+    pub(crate) delimited: Option<DelimitedFormatter>,
+}
+
+/// The level and target filters of the formatted output, which hide events from the whole
+/// subscriber unless other outputs with filters of their own are installed alongside it, see
+/// [`Builder::tee_json`](crate::Builder::tee_json)
+///
+/// Installed both as a layer, which keeps track of the spans that filters on span fields rely
+/// on, and as the filter of the formatting layers. Only one of the two hides events: the layer
+/// when `global` is set, so events are skipped as early as possible, and the filter otherwise,
+/// so the other outputs still get them. Events pass when both `A` and `B` enable them, or when
+/// they are emitted within a [`verbose_scope`](crate::verbose_scope). Events that a
+/// [`BoostRule`] may raise to a level they enable pass too, and are checked against the rules
+/// once their fields are known, see [`EventFilter`](crate::filter::EventFilter).
+pub(crate) struct SharedFilter<A, B> {
+    filters: Arc<(A, B)>,
+    global: bool,
+    boosts: Arc<[BoostRule]>,
+}
+
+impl<A, B> SharedFilter<A, B> {
+    pub(crate) fn new(first: A, second: B, global: bool, boosts: Arc<[BoostRule]>) -> Self {
+        Self {
+            filters: Arc::new((first, second)),
+            global,
+            boosts,
+        }
+    }
+
+    fn interest<S>(&self, metadata: &'static Metadata<'static>) -> Interest
+    where
+        S: Subscriber,
+        A: Layer<S>,
+        B: Layer<S>,
+    {
+        let (first, second) = &*self.filters;
+        let (first, second) = (
+            first.register_callsite(metadata),
+            second.register_callsite(metadata),
+        );
+        if first.is_never() || second.is_never() {
+            // Any callsite may be reached within a verbose scope, and events may be raised to a
+            // level that passes
+            let raised =
+                metadata.is_event() && boost::candidates(&self.boosts, metadata).next().is_some();
+            match escalate::active() || raised {
+                true => Interest::sometimes(),
+                false => Interest::never(),
+            }
+        } else if first.is_sometimes() || second.is_sometimes() {
+            Interest::sometimes()
+        } else {
+            Interest::always()
+        }
+    }
+
+    fn enables<S>(&self, metadata: &Metadata<'_>, ctx: &Context<'_, S>) -> bool
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        A: Layer<S>,
+        B: Layer<S>,
+    {
+        let (first, second) = &*self.filters;
+        let passes = |metadata: &Metadata<'_>| {
+            first.enabled(metadata, ctx.clone()) && second.enabled(metadata, ctx.clone())
+        };
+        if !metadata.is_event() || self.boosts.is_empty() {
+            return passes(metadata) || escalate::escalated(ctx);
+        }
+        if passes(metadata) || escalate::escalated(ctx) {
+            boost::set_pending(None);
+            return true;
+        }
+        let raised = boost::candidates(&self.boosts, metadata)
+            .filter(|&level| passes(&boost::at_level(metadata, level)))
+            .max();
+        boost::set_pending(raised);
+        raised.is_some()
+    }
+
+    fn level_hint<S>(&self) -> Option<LevelFilter>
+    where
+        S: Subscriber,
+        A: Layer<S>,
+        B: Layer<S>,
+    {
+        // Events of any level may be shown in a verbose scope or raised to a level shown
+        if escalate::active() || !self.boosts.is_empty() {
+            return Some(LevelFilter::TRACE);
+        }
+        let (first, second) = &*self.filters;
+        cmp::max(first.max_level_hint(), second.max_level_hint())
+    }
+}
+
+impl<A, B> Clone for SharedFilter<A, B> {
+    fn clone(&self) -> Self {
+        Self {
+            filters: self.filters.clone(),
+            global: self.global,
+            boosts: self.boosts.clone(),
+        }
+    }
+}
+
+impl<S, A, B> Layer<S> for SharedFilter<A, B>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    A: Layer<S>,
+    B: Layer<S>,
+{
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        let interest = self.interest(metadata);
+        if self.global {
+            interest
+        } else {
+            Interest::sometimes()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        !self.global || self.enables(metadata, &ctx)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        // The other outputs may want more verbose events, which only their own filters know of
+        if self.global {
+            self.level_hint()
+        } else {
+            None
+        }
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let (first, second) = &*self.filters;
+        first.new_span(attrs, id, ctx.clone());
+        second.new_span(attrs, id, ctx)
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let (first, second) = &*self.filters;
+        first.on_record(span, values, ctx.clone());
+        second.on_record(span, values, ctx)
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let (first, second) = &*self.filters;
+        first.on_enter(id, ctx.clone());
+        second.on_enter(id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let (first, second) = &*self.filters;
+        first.on_exit(id, ctx.clone());
+        second.on_exit(id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let (first, second) = &*self.filters;
+        first.on_close(id.clone(), ctx.clone());
+        second.on_close(id, ctx)
+    }
+}
+
+impl<S, A, B> Filter<S> for SharedFilter<A, B>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    A: Layer<S>,
+    B: Layer<S>,
+{
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: &Context<'_, S>) -> bool {
+        self.global || self.enables(metadata, ctx)
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if self.global {
+            Interest::always()
+        } else {
+            self.interest(metadata)
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.level_hint()
+    }
+}
@@ -0,0 +1,34 @@
+use std::fmt::{self, Write};
+
+/// Writes `template`, replacing each `{name}` placeholder with what `lookup` returns for it
+///
+/// `{{` and `}}` produce literal braces. Placeholders that `lookup` doesn't know are written
+/// unchanged, so mistakes in a template stay visible in the output.
+pub(crate) fn render<F>(f: &mut dyn Write, template: &str, mut lookup: F) -> fmt::Result
+where
+    F: FnMut(&str, &mut dyn Write) -> Option<fmt::Result>,
+{
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        f.write_str(&rest[..start])?;
+        rest = &rest[start..];
+
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            f.write_str(&rest[..1])?;
+            rest = &rest[2..];
+        } else if rest.starts_with('}') {
+            f.write_char('}')?;
+            rest = &rest[1..];
+        } else if let Some(end) = rest.find('}') {
+            let name = &rest[1..end];
+            match lookup(name, f) {
+                Some(result) => result?,
+                None => f.write_str(&rest[..=end])?,
+            }
+            rest = &rest[end + 1..];
+        } else {
+            break;
+        }
+    }
+    f.write_str(rest)
+}
@@ -0,0 +1,978 @@
+use crate::locale::Locale;
+#[cfg(feature = "chrono")]
+use chrono::format::{Item, StrftimeItems};
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, FixedOffset, Local, Timelike, Utc};
+#[cfg(feature = "chrono")]
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+thread_local! {
+    /// The time a record being replayed on this thread was written at, see [`set_replayed`]
+    static REPLAYED: Cell<Option<SystemTime>> = const { Cell::new(None) };
+}
+
+/// Wall clock readings before 2020 are taken for a clock that hasn't been set yet, as early in
+/// boot on devices without a battery-backed clock
+const TRUSTED_SINCE: Duration = Duration::from_secs(1_577_836_800);
+
+/// Where the times shown at the start of verbose lines come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum TimeSource {
+    /// The wall clock if it can be trusted, or else the monotonic clock
+    ///
+    /// The wall clock isn't trusted when it reads a time before 2020, as it does before it is
+    /// set. Where neither clock is available, as on `wasm32-unknown-unknown`, no time is shown.
+    /// The choice is made once, so times stay comparable if the clock is set later on.
+    #[default]
+    Auto,
+    /// The time of day, formatted as set with
+    /// [`Builder::time_format`](crate::Builder::time_format)
+    WallClock,
+    /// The time elapsed since logging was set up, such as `+0012.345`, which only ever
+    /// increases
+    Monotonic,
+}
+
+/// How finely times are shown, see [`Builder::time_precision`](crate::Builder::time_precision)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum TimePrecision {
+    /// Whole seconds
+    Seconds,
+    /// Milliseconds, as in `09:14:03.210`
+    Millis,
+    /// Microseconds, as in `09:14:03.210114`
+    Micros,
+    /// Nanoseconds, as in `09:14:03.210114587`
+    Nanos,
+}
+
+impl TimePrecision {
+    /// Writes the fraction of a second of `nanos`, with its leading dot
+    fn write_fraction(self, f: &mut dyn fmt::Write, nanos: u32) -> fmt::Result {
+        match self {
+            Self::Seconds => Ok(()),
+            Self::Millis => write!(f, ".{:03}", nanos / 1_000_000),
+            Self::Micros => write!(f, ".{:06}", nanos / 1_000),
+            Self::Nanos => write!(f, ".{:09}", nanos),
+        }
+    }
+
+    /// The strftime specifier of the fraction of a second
+    fn specifier(self) -> &'static str {
+        match self {
+            Self::Seconds => "",
+            Self::Millis => "%.3f",
+            Self::Micros => "%.6f",
+            Self::Nanos => "%.9f",
+        }
+    }
+}
+
+/// The source [`TimeSource::Auto`] settles on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resolved {
+    WallClock,
+    Monotonic,
+    Unavailable,
+}
+
+impl TimeSource {
+    fn resolve(self) -> Resolved {
+        match self {
+            Self::WallClock => Resolved::WallClock,
+            Self::Monotonic => Resolved::Monotonic,
+            Self::Auto => {
+                static AUTO: OnceLock<Resolved> = OnceLock::new();
+                *AUTO.get_or_init(|| {
+                    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+                        return Resolved::Unavailable;
+                    }
+                    match SystemTime::now().duration_since(UNIX_EPOCH) {
+                        Ok(now) if now >= TRUSTED_SINCE => Resolved::WallClock,
+                        _ => Resolved::Monotonic,
+                    }
+                })
+            }
+        }
+    }
+}
+
+/// Shows `time` as the time of the events formatted on this thread, or the current time again
+/// after `None`, see [`replay_binary_log`](crate::replay_binary_log)
+pub(crate) fn set_replayed(time: Option<SystemTime>) {
+    REPLAYED.with(|replayed| replayed.set(time));
+}
+
+/// Whether the event being formatted on this thread is replayed from a binary log
+pub(crate) fn replaying() -> bool {
+    REPLAYED.with(Cell::get).is_some()
+}
+
+/// When logging was set up, which monotonic times are offsets from
+fn start() -> Instant {
+    static START: OnceLock<Instant> = OnceLock::new();
+    *START.get_or_init(Instant::now)
+}
+
+/// How the time of events is shown
+///
+/// Without the `chrono` feature, custom formats are written by [`Specifiers`], and wall
+/// clock times are in the local time zone where the platform tells its offset, and in UTC
+/// elsewhere.
+#[derive(Debug, Clone)]
+pub(crate) struct TimeFormat {
+    /// A strftime format, in which `%Z` stands for the abbreviated zone name
+    format: String,
+    /// Whether the format was set rather than derived from the precision
+    custom: bool,
+    /// Set by the application, or else milliseconds here and microseconds in JSON records
+    precision: Option<TimePrecision>,
+    /// Shows times at this many seconds east of UTC instead of in the local time zone
+    offset: Option<i32>,
+    source: TimeSource,
+    /// Set by the application, see [`Builder::locale`](crate::Builder::locale)
+    locale: Option<Locale>,
+}
+
+impl Default for TimeFormat {
+    fn default() -> Self {
+        let mut format = Self {
+            format: "%H:%M:%S%.3f".to_owned(),
+            custom: false,
+            precision: None,
+            offset: None,
+            source: TimeSource::default(),
+            locale: None,
+        };
+        format.set_source(TimeSource::default());
+        format
+    }
+}
+
+impl TimeFormat {
+    /// Sets the strftime format, panicking if it is invalid
+    pub(crate) fn set_format(&mut self, format: &str) {
+        #[cfg(feature = "chrono")]
+        let valid = StrftimeItems::new(format).all(|item| item != Item::Error);
+        #[cfg(not(feature = "chrono"))]
+        let valid = Specifiers(format).all(|item| item != Specifier::Invalid);
+        assert!(valid, "invalid time format `{}`", format);
+        self.format = format.to_owned();
+        self.custom = true;
+    }
+
+    /// Shows times to `precision`, in JSON records too, unless a custom format says otherwise
+    pub(crate) fn set_precision(&mut self, precision: TimePrecision) {
+        self.precision = Some(precision);
+        if !self.custom {
+            self.format = format!("%H:%M:%S{}", precision.specifier());
+        }
+    }
+
+    /// The precision set by the application, if any
+    pub(crate) fn precision(&self) -> Option<TimePrecision> {
+        self.precision
+    }
+
+    /// Shows times at a fixed offset from UTC, panicking if it is a day or more
+    pub(crate) fn set_offset(&mut self, minutes: i32) {
+        let offset = minutes
+            .checked_mul(60)
+            .filter(|seconds| seconds.abs() < 86_400);
+        self.offset = Some(offset.expect("UTC offset out of range"));
+    }
+
+    /// Shows times the way `locale` does
+    pub(crate) fn set_locale(&mut self, locale: Locale) {
+        self.locale = Some(locale);
+    }
+
+    /// The locale set by the application, if any
+    pub(crate) fn locale(&self) -> Option<&Locale> {
+        self.locale.as_ref()
+    }
+
+    /// Selects where times come from, starting the monotonic clock if it may be used
+    pub(crate) fn set_source(&mut self, source: TimeSource) {
+        self.source = source;
+        if source.resolve() == Resolved::Monotonic {
+            start();
+        }
+    }
+
+    /// Returns the current time, ready to be displayed, unless no clock is available
+    pub(crate) fn now(&self) -> Option<DisplayTime<'_>> {
+        // Replayed records show the time of day they were written at, whatever the source
+        let replayed = REPLAYED.with(Cell::get);
+        let now = match (replayed, self.source.resolve()) {
+            (Some(time), _) => self.wall_clock(time),
+            (None, Resolved::WallClock) => self.wall_clock(SystemTime::now()),
+            (None, Resolved::Monotonic) => Now::Monotonic(start().elapsed()),
+            (None, Resolved::Unavailable) => return None,
+        };
+        Some(DisplayTime { format: self, now })
+    }
+
+    #[cfg(feature = "chrono")]
+    fn wall_clock(&self, time: SystemTime) -> Now {
+        // The offset was checked to be less than a day by `set_offset`
+        let fixed = self.offset.and_then(FixedOffset::east_opt);
+        Now::WallClock(match fixed {
+            Some(offset) => DateTime::<Utc>::from(time).with_timezone(&offset),
+            None => DateTime::<Local>::from(time).fixed_offset(),
+        })
+    }
+
+    #[cfg(not(feature = "chrono"))]
+    fn wall_clock(&self, time: SystemTime) -> Now {
+        let (seconds, nanos) = unix_time(time);
+        let offset = match self.offset {
+            Some(offset) => i64::from(offset),
+            None => with_local_zone(seconds, |zone| zone.offset).unwrap_or(0),
+        };
+        Now::WallClock {
+            local: seconds + offset,
+            nanos,
+            offset,
+        }
+    }
+
+    /// The abbreviated name of the time zone at `timestamp`, seconds since the Unix epoch, or
+    /// its offset when it has no name
+    fn zone_name(&self, timestamp: i64, offset: i64) -> String {
+        let mut numeric = String::new();
+        let _ = write_offset(&mut numeric, offset, true);
+        match self.offset {
+            Some(0) => "UTC".to_owned(),
+            Some(_) => numeric,
+            None => with_local_zone(timestamp, |zone| zone.name.clone())
+                .flatten()
+                .unwrap_or(numeric),
+        }
+    }
+}
+
+enum Now {
+    #[cfg(feature = "chrono")]
+    WallClock(DateTime<FixedOffset>),
+    #[cfg(not(feature = "chrono"))]
+    WallClock {
+        /// Seconds since the Unix epoch, shifted by `offset`
+        local: i64,
+        nanos: u32,
+        /// Seconds east of UTC
+        offset: i64,
+    },
+    /// Since [`start`]
+    Monotonic(Duration),
+}
+
+/// The time of an event, see [`TimeFormat::now`]
+pub(crate) struct DisplayTime<'a> {
+    format: &'a TimeFormat,
+    now: Now,
+}
+
+impl DisplayTime<'_> {
+    fn precision(&self) -> TimePrecision {
+        self.format.precision.unwrap_or(TimePrecision::Millis)
+    }
+
+    /// Writes a time of day to the precision, on a 12-hour clock if the locale uses one
+    fn write_time_of_day(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        hour: u32,
+        minute: u32,
+        second: u32,
+        nanos: u32,
+    ) -> fmt::Result {
+        let twelve_hour = self.format.locale.as_ref().is_some_and(Locale::twelve_hour);
+        let shown = if twelve_hour {
+            (hour + 11) % 12 + 1
+        } else {
+            hour
+        };
+        write!(f, "{:02}:{:02}:{:02}", shown, minute, second)?;
+        self.precision().write_fraction(f, nanos)?;
+        if twelve_hour {
+            f.write_str(if hour < 12 { " AM" } else { " PM" })?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DisplayTime<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.now {
+            #[cfg(feature = "chrono")]
+            Now::WallClock(now) => {
+                // The format derived from the precision is written without chrono, which formats
+                // through intermediate strings
+                if !self.format.custom {
+                    let nanos = now.nanosecond();
+                    // A leap second is counted as more than a billion nanoseconds into the 59th
+                    let second = now.second() + nanos / 1_000_000_000;
+                    let nanos = nanos % 1_000_000_000;
+                    return self.write_time_of_day(f, now.hour(), now.minute(), second, nanos);
+                }
+                let mut format = Cow::Borrowed(self.format.format.as_str());
+                // chrono's `%x` and `%X` are always those of the United States
+                if let Some(locale) = &self.format.locale {
+                    if format.contains("%x") || format.contains("%X") {
+                        format = Cow::Owned(
+                            format
+                                .replace("%x", &locale.date_format())
+                                .replace("%X", locale.time_format()),
+                        );
+                    }
+                }
+                // chrono only knows the offset of the local time zone, not its name
+                if format.contains("%Z") {
+                    let offset = i64::from(now.offset().local_minus_utc());
+                    let name = self.format.zone_name(now.timestamp(), offset);
+                    format = Cow::Owned(format.replace("%Z", &name));
+                }
+                write!(f, "{}", now.format(&format))
+            }
+            #[cfg(not(feature = "chrono"))]
+            Now::WallClock {
+                local,
+                nanos,
+                offset,
+            } => {
+                let time = Civil::new(*local, *nanos, *offset);
+                if self.format.custom {
+                    return self.write_format(f, &self.format.format, &time);
+                }
+                self.write_time_of_day(f, time.hour, time.minute, time.second, time.nanos)
+            }
+            Now::Monotonic(elapsed) => {
+                write!(f, "+{:04}", elapsed.as_secs())?;
+                self.precision().write_fraction(f, elapsed.subsec_nanos())
+            }
+        }
+    }
+}
+
+/// Month names, for `%b` and `%B`
+#[cfg(not(feature = "chrono"))]
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Weekday names from Sunday, for `%a` and `%A`
+#[cfg(not(feature = "chrono"))]
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// Days in the months before each month of a common year
+#[cfg(not(feature = "chrono"))]
+const DAYS_BEFORE_MONTH: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+/// The specifiers written without `chrono`: those of chrono's that don't depend on the
+/// locale, without its padding modifiers such as `%-d`
+#[cfg(not(feature = "chrono"))]
+const SPECIFIERS: &[&str] = &[
+    "%", "Y", "C", "y", "m", "b", "h", "B", "d", "e", "a", "A", "w", "u", "j", "H", "I", "M", "S",
+    "p", "P", "f", "3f", "6f", "9f", ".f", ".3f", ".6f", ".9f", "z", ":z", "Z", "s", "n", "t", "F",
+    "T", "R", "D", "x", "X",
+];
+
+/// A piece of a strftime format
+#[cfg(not(feature = "chrono"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Specifier<'a> {
+    Literal(&'a str),
+    /// What follows the `%`, such as `Y`, `:z` or `.3f`
+    Known(&'a str),
+    /// The rest of the format, from a `%` not followed by a known specifier
+    Invalid,
+}
+
+/// Splits a strftime format into literal text and the specifiers written without `chrono`
+#[cfg(not(feature = "chrono"))]
+struct Specifiers<'a>(&'a str);
+
+#[cfg(not(feature = "chrono"))]
+impl<'a> Iterator for Specifiers<'a> {
+    type Item = Specifier<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let format = self.0;
+        match format.find('%') {
+            _ if format.is_empty() => return None,
+            Some(0) => {}
+            Some(at) => {
+                self.0 = &format[at..];
+                return Some(Specifier::Literal(&format[..at]));
+            }
+            None => {
+                self.0 = "";
+                return Some(Specifier::Literal(format));
+            }
+        }
+        let rest = &format[1..];
+        let known = SPECIFIERS
+            .iter()
+            .filter(|specifier| rest.starts_with(**specifier))
+            .max_by_key(|specifier| specifier.len());
+        match known {
+            Some(specifier) => {
+                let (specifier, rest) = rest.split_at(specifier.len());
+                self.0 = rest;
+                Some(Specifier::Known(specifier))
+            }
+            None => {
+                self.0 = "";
+                Some(Specifier::Invalid)
+            }
+        }
+    }
+}
+
+/// A wall clock time broken down into what the specifiers stand for
+#[cfg(not(feature = "chrono"))]
+struct Civil {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    nanos: u32,
+    /// Days since Sunday
+    weekday: u32,
+    /// Days since the 1st of January
+    day_of_year: u32,
+    /// Seconds since the Unix epoch
+    timestamp: i64,
+    /// Seconds east of UTC
+    offset: i64,
+}
+
+#[cfg(not(feature = "chrono"))]
+impl Civil {
+    /// Breaks down `local`, the seconds since the Unix epoch shifted by `offset`, and the
+    /// `nanos` after them
+    fn new(local: i64, nanos: u32, offset: i64) -> Self {
+        let days = local.div_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let day_of_year =
+            DAYS_BEFORE_MONTH[month as usize - 1] + day - 1 + u32::from(leap && month > 2);
+        let time_of_day = local.rem_euclid(86_400) as u32;
+        Self {
+            year,
+            month,
+            day,
+            hour: time_of_day / 3600,
+            minute: time_of_day / 60 % 60,
+            second: time_of_day % 60,
+            nanos,
+            // The Unix epoch was a Thursday
+            weekday: (days + 4).rem_euclid(7) as u32,
+            day_of_year,
+            timestamp: local - offset,
+            offset,
+        }
+    }
+}
+
+#[cfg(not(feature = "chrono"))]
+impl DisplayTime<'_> {
+    /// Writes `time` in a strftime format checked by [`TimeFormat::set_format`]
+    fn write_format(&self, f: &mut fmt::Formatter<'_>, format: &str, time: &Civil) -> fmt::Result {
+        for specifier in Specifiers(format) {
+            let specifier = match specifier {
+                Specifier::Literal(text) => {
+                    f.write_str(text)?;
+                    continue;
+                }
+                Specifier::Known(specifier) => specifier,
+                Specifier::Invalid => return Err(fmt::Error),
+            };
+            let month = MONTHS[time.month as usize - 1];
+            let weekday = WEEKDAYS[time.weekday as usize];
+            let locale = self.format.locale.as_ref();
+            match specifier {
+                "%" => f.write_str("%"),
+                "Y" => write!(f, "{:04}", time.year),
+                "C" => write!(f, "{:02}", time.year.div_euclid(100)),
+                "y" => write!(f, "{:02}", time.year.rem_euclid(100)),
+                "m" => write!(f, "{:02}", time.month),
+                "b" | "h" => f.write_str(&month[..3]),
+                "B" => f.write_str(month),
+                "d" => write!(f, "{:02}", time.day),
+                "e" => write!(f, "{:>2}", time.day),
+                "a" => f.write_str(&weekday[..3]),
+                "A" => f.write_str(weekday),
+                "w" => write!(f, "{}", time.weekday),
+                "u" => write!(f, "{}", (time.weekday + 6) % 7 + 1),
+                "j" => write!(f, "{:03}", time.day_of_year + 1),
+                "H" => write!(f, "{:02}", time.hour),
+                "I" => write!(f, "{:02}", (time.hour + 11) % 12 + 1),
+                "M" => write!(f, "{:02}", time.minute),
+                "S" => write!(f, "{:02}", time.second),
+                "p" => f.write_str(if time.hour < 12 { "AM" } else { "PM" }),
+                "P" => f.write_str(if time.hour < 12 { "am" } else { "pm" }),
+                "f" | "9f" => write!(f, "{:09}", time.nanos),
+                "3f" => write!(f, "{:03}", time.nanos / 1_000_000),
+                "6f" => write!(f, "{:06}", time.nanos / 1_000),
+                ".3f" => TimePrecision::Millis.write_fraction(f, time.nanos),
+                ".6f" => TimePrecision::Micros.write_fraction(f, time.nanos),
+                ".9f" => TimePrecision::Nanos.write_fraction(f, time.nanos),
+                // As many digits as the fraction needs, if any
+                ".f" => {
+                    let precision = match time.nanos {
+                        0 => TimePrecision::Seconds,
+                        nanos if nanos % 1_000_000 == 0 => TimePrecision::Millis,
+                        nanos if nanos % 1_000 == 0 => TimePrecision::Micros,
+                        _ => TimePrecision::Nanos,
+                    };
+                    precision.write_fraction(f, time.nanos)
+                }
+                "z" => write_offset(f, time.offset, false),
+                ":z" => write_offset(f, time.offset, true),
+                "Z" => f.write_str(&self.format.zone_name(time.timestamp, time.offset)),
+                "s" => write!(f, "{}", time.timestamp),
+                "n" => f.write_str("\n"),
+                "t" => f.write_str("\t"),
+                "F" => self.write_format(f, "%Y-%m-%d", time),
+                "T" => self.write_format(f, "%H:%M:%S", time),
+                "R" => self.write_format(f, "%H:%M", time),
+                "D" => self.write_format(f, "%m/%d/%y", time),
+                "x" => match locale {
+                    Some(locale) => self.write_format(f, &locale.date_format(), time),
+                    None => self.write_format(f, "%m/%d/%y", time),
+                },
+                "X" => match locale {
+                    Some(locale) => self.write_format(f, locale.time_format(), time),
+                    None => self.write_format(f, "%H:%M:%S", time),
+                },
+                _ => unreachable!("unknown specifier `%{}`", specifier),
+            }?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes an offset of seconds east of UTC, such as `+0200`, or `+02:00` with `colon`
+fn write_offset(f: &mut dyn fmt::Write, offset: i64, colon: bool) -> fmt::Result {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+    let separator = if colon { ":" } else { "" };
+    write!(
+        f,
+        "{}{:02}{}{:02}",
+        sign,
+        minutes / 60,
+        separator,
+        minutes % 60
+    )
+}
+
+/// Writes `time` as an RFC 3339 timestamp in UTC to `precision`, such as
+/// `2021-06-02T09:14:03.210114Z` with microseconds
+///
+/// Written field by field without `chrono`, so records can be written in minimal builds and
+/// nothing is allocated.
+pub(crate) fn write_rfc3339(
+    f: &mut dyn fmt::Write,
+    time: SystemTime,
+    precision: TimePrecision,
+) -> fmt::Result {
+    let (seconds, nanos) = unix_time(time);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let seconds = seconds.rem_euclid(86_400);
+    write!(
+        f,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+    )?;
+    precision.write_fraction(f, nanos)?;
+    f.write_char('Z')
+}
+
+/// The seconds since the Unix epoch, negative before it, and the nanoseconds after them
+fn unix_time(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+        Err(before) => {
+            let before = before.duration();
+            let seconds = -(before.as_secs() as i64);
+            match before.subsec_nanos() {
+                0 => (seconds, 0),
+                nanos => (seconds - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+
+/// Converts days since the Unix epoch to a year, month and day of the proleptic Gregorian
+/// calendar, following Howard Hinnant's `civil_from_days`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The local time zone at some point in time
+struct LocalZone {
+    /// Seconds east of UTC
+    #[cfg(not(feature = "chrono"))]
+    offset: i64,
+    /// The abbreviated name, such as `CEST`, for zones that have one
+    name: Option<String>,
+}
+
+/// Calls `f` with the local time zone at a Unix timestamp, if it is known
+///
+/// The zone is looked up again only when the hour changes, as zones change their offset on the
+/// hour, rather than for every event.
+fn with_local_zone<T>(timestamp: i64, f: impl FnOnce(&LocalZone) -> T) -> Option<T> {
+    static CACHED: Mutex<Option<(i64, Option<LocalZone>)>> = Mutex::new(None);
+    let hour = timestamp.div_euclid(3600);
+    let mut cached = CACHED.lock().unwrap_or_else(|e| e.into_inner());
+    if !matches!(&*cached, Some((cached_hour, _)) if *cached_hour == hour) {
+        *cached = Some((hour, local_zone(timestamp)));
+    }
+    cached.as_ref().and_then(|(_, zone)| zone.as_ref()).map(f)
+}
+
+/// Looks up the local time zone at a Unix timestamp
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+fn local_zone(timestamp: i64) -> Option<LocalZone> {
+    use std::ffi::CStr;
+
+    let time = timestamp as libc::time_t;
+    // SAFETY: `localtime_r` only writes to the `tm` it is given, whose `tm_zone` then points to
+    // a static, nul terminated string if it isn't null
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return None;
+        }
+        Some(LocalZone {
+            #[cfg(not(feature = "chrono"))]
+            offset: tm.tm_gmtoff as i64,
+            name: if tm.tm_zone.is_null() {
+                None
+            } else {
+                CStr::from_ptr(tm.tm_zone)
+                    .to_str()
+                    .ok()
+                    // Zones without an abbreviation are named after their offset, like `+0530`
+                    .filter(|name| name.chars().all(|c| c.is_ascii_alphabetic()))
+                    .map(str::to_owned)
+            },
+        })
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+fn local_zone(_timestamp: i64) -> Option<LocalZone> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shows `timestamp`, seconds since the Unix epoch, and `nanos` after it in `format`, at
+    /// `minutes` east of UTC
+    fn shown(format: &str, minutes: i32, timestamp: i64, nanos: u32) -> String {
+        let mut time_format = TimeFormat::default();
+        time_format.set_format(format);
+        time_format.set_offset(minutes);
+        let seconds = Duration::from_secs(timestamp.unsigned_abs());
+        let time = if timestamp < 0 {
+            UNIX_EPOCH - seconds
+        } else {
+            UNIX_EPOCH + seconds
+        };
+        let now = time_format.wall_clock(time + Duration::from_nanos(u64::from(nanos)));
+        DisplayTime {
+            format: &time_format,
+            now,
+        }
+        .to_string()
+    }
+
+    fn rfc3339(time: SystemTime, precision: TimePrecision) -> String {
+        let mut text = String::new();
+        write_rfc3339(&mut text, time, precision).unwrap();
+        text
+    }
+
+    fn offset(seconds: i64, colon: bool) -> String {
+        let mut text = String::new();
+        write_offset(&mut text, seconds, colon).unwrap();
+        text
+    }
+
+    #[test]
+    fn leap_days_are_counted() {
+        assert_eq!(
+            shown("%F %j %A", 0, 1_709_208_000, 0),
+            "2024-02-29 060 Thursday"
+        );
+        assert_eq!(shown("%F %j", 0, 1_709_251_200, 0), "2024-03-01 061");
+        assert_eq!(shown("%F %j", 0, 1_735_603_200, 0), "2024-12-31 366");
+        // Centuries are only leap years when divisible by 400
+        assert_eq!(shown("%F %j", 0, 951_782_400, 0), "2000-02-29 060");
+        assert_eq!(shown("%F %j", 0, 4_107_542_399, 0), "2100-02-28 059");
+        assert_eq!(shown("%F %j", 0, 4_107_542_400, 0), "2100-03-01 060");
+    }
+
+    #[test]
+    fn years_and_centuries_roll_over() {
+        assert_eq!(
+            shown("%F %T %j %C %y", 0, 1_704_067_199, 0),
+            "2023-12-31 23:59:59 365 20 23"
+        );
+        assert_eq!(
+            shown("%F %T %j %C %y", 0, 1_704_067_200, 0),
+            "2024-01-01 00:00:00 001 20 24"
+        );
+        assert_eq!(
+            shown("%F %T %C %y", 0, 946_684_799, 0),
+            "1999-12-31 23:59:59 19 99"
+        );
+        assert_eq!(
+            shown("%F %T %C %y", 0, 946_684_800, 0),
+            "2000-01-01 00:00:00 20 00"
+        );
+        // The new year comes earlier east of UTC
+        assert_eq!(
+            shown("%F %T %z", 60, 1_704_067_199, 0),
+            "2024-01-01 00:59:59 +0100"
+        );
+    }
+
+    #[test]
+    fn weekdays_count_from_sunday_or_monday() {
+        // 1 January 2023 was a Sunday
+        assert_eq!(shown("%a %u %w", 0, 1_672_531_200, 0), "Sun 7 0");
+        assert_eq!(shown("%a %u %w", 0, 1_672_531_200 + 86_400, 0), "Mon 1 1");
+        assert_eq!(
+            shown("%a %u %w", 0, 1_672_531_200 + 6 * 86_400, 0),
+            "Sat 6 6"
+        );
+    }
+
+    #[test]
+    fn twelve_hour_clock_starts_at_twelve() {
+        assert_eq!(shown("%I:%M %p", 0, 1_704_069_000, 0), "12:30 AM");
+        assert_eq!(shown("%I:%M %p", 0, 1_704_110_400, 0), "12:00 PM");
+        assert_eq!(shown("%I:%M %P", 0, 1_704_114_300, 0), "01:05 pm");
+        assert_eq!(shown("%I:%M %p", 0, 1_704_110_399, 0), "11:59 AM");
+    }
+
+    #[test]
+    fn offsets_can_be_negative_or_fractional_hours() {
+        assert_eq!(shown("%H:%M %z", -330, 1_704_067_200, 0), "18:30 -0530");
+        assert_eq!(shown("%H:%M %:z", -210, 1_704_067_200, 0), "20:30 -03:30");
+        assert_eq!(shown("%H:%M %z", 345, 1_704_067_200, 0), "05:45 +0545");
+        assert_eq!(
+            shown("%H:%M %:z %Z", 0, 1_704_067_200, 0),
+            "00:00 +00:00 UTC"
+        );
+        // The timestamp doesn't depend on the offset
+        assert_eq!(shown("%s", -330, 1_704_067_200, 0), "1704067200");
+    }
+
+    #[test]
+    fn times_before_1970_are_shown() {
+        assert_eq!(
+            shown("%F %T %j %A", 0, -14_182_940, 0),
+            "1969-07-20 20:17:40 201 Sunday"
+        );
+        assert_eq!(
+            shown("%F %T%.3f", 0, -1, 500_000_000),
+            "1969-12-31 23:59:59.500"
+        );
+        assert_eq!(shown("%F %T %s", -300, 0, 0), "1969-12-31 19:00:00 0");
+        // 1900 was not a leap year
+        assert_eq!(
+            shown("%F %j %A", 0, -2_203_891_200, 0),
+            "1900-03-01 060 Thursday"
+        );
+    }
+
+    #[test]
+    fn rfc3339_is_in_utc() {
+        let time = UNIX_EPOCH + Duration::new(1_709_208_000, 123_456_789);
+        assert_eq!(
+            rfc3339(time, TimePrecision::Micros),
+            "2024-02-29T12:00:00.123456Z"
+        );
+        assert_eq!(
+            rfc3339(time, TimePrecision::Seconds),
+            "2024-02-29T12:00:00Z"
+        );
+        let before = UNIX_EPOCH - Duration::from_millis(500);
+        assert_eq!(
+            rfc3339(before, TimePrecision::Millis),
+            "1969-12-31T23:59:59.500Z"
+        );
+    }
+
+    #[test]
+    fn unix_time_rounds_down_before_the_epoch() {
+        assert_eq!(unix_time(UNIX_EPOCH), (0, 0));
+        assert_eq!(unix_time(UNIX_EPOCH - Duration::from_secs(1)), (-1, 0));
+        assert_eq!(
+            unix_time(UNIX_EPOCH - Duration::from_millis(1_250)),
+            (-2, 750_000_000)
+        );
+    }
+
+    #[test]
+    fn civil_from_days_follows_the_gregorian_calendar() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-25_567), (1900, 1, 1));
+        assert_eq!(civil_from_days(-135_081), (1600, 2, 29));
+        assert_eq!(civil_from_days(-719_468), (0, 3, 1));
+    }
+
+    #[test]
+    fn write_offset_pads_hours_and_minutes() {
+        assert_eq!(offset(0, false), "+0000");
+        assert_eq!(offset(0, true), "+00:00");
+        assert_eq!(offset(-19_800, false), "-0530");
+        assert_eq!(offset(20_700, true), "+05:45");
+        assert_eq!(offset(-43_200, true), "-12:00");
+        assert_eq!(offset(50_400, false), "+1400");
+    }
+
+    #[cfg(not(feature = "chrono"))]
+    #[test]
+    fn specifiers_split_literals_from_the_longest_specifier() {
+        use Specifier::{Invalid, Known, Literal};
+
+        let pieces: Vec<_> = Specifiers("at %H:%M:%S%.3f %:z%%").collect();
+        assert_eq!(
+            pieces,
+            [
+                Literal("at "),
+                Known("H"),
+                Literal(":"),
+                Known("M"),
+                Literal(":"),
+                Known("S"),
+                Known(".3f"),
+                Literal(" "),
+                Known(":z"),
+                Known("%"),
+            ]
+        );
+        let pieces: Vec<_> = Specifiers("%Y %Q %d").collect();
+        assert_eq!(pieces, [Known("Y"), Literal(" "), Invalid]);
+        let pieces: Vec<_> = Specifiers("%d%").collect();
+        assert_eq!(pieces, [Known("d"), Invalid]);
+        assert_eq!(Specifiers("").next(), None);
+    }
+
+    #[cfg(not(feature = "chrono"))]
+    #[test]
+    fn civil_breaks_down_local_times() {
+        let time = Civil::new(-1, 5, 0);
+        assert_eq!((time.year, time.month, time.day), (1969, 12, 31));
+        assert_eq!(
+            (time.hour, time.minute, time.second, time.nanos),
+            (23, 59, 59, 5)
+        );
+        assert_eq!((time.weekday, time.day_of_year), (3, 364));
+
+        // Shifted an hour east, so the timestamp is an hour earlier
+        let time = Civil::new(1_709_208_000 + 3600, 0, 3600);
+        assert_eq!(
+            (time.year, time.month, time.day, time.hour),
+            (2024, 2, 29, 13)
+        );
+        assert_eq!((time.weekday, time.day_of_year), (4, 59));
+        assert_eq!(time.timestamp, 1_709_208_000);
+    }
+
+    #[test]
+    fn cached_zones_follow_the_hour() {
+        // Midwinter, an hour later, and midsummer, when zones with daylight saving time differ
+        for timestamp in [1_736_942_400, 1_736_946_000, 1_752_580_800, 1_736_942_400] {
+            let cached = with_local_zone(timestamp, |zone| zone.name.clone());
+            assert_eq!(cached, local_zone(timestamp).map(|zone| zone.name));
+            #[cfg(not(feature = "chrono"))]
+            assert_eq!(
+                with_local_zone(timestamp, |zone| zone.offset),
+                local_zone(timestamp).map(|zone| zone.offset)
+            );
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::locale;
+use std::cell::Cell;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tracing::span::Attributes;
+use tracing::{Id, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+thread_local! {
+    /// When the last event was formatted on this thread, see [`since_last_event`]
+    static LAST_EVENT: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// The instant a span was created, stored in the span's extensions
+struct Opened(Instant);
+
+/// Records when each span is opened so that the formatter can tell how long it lived
+pub(crate) struct TimingLayer;
+
+impl<S> Layer<S> for TimingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Opened(Instant::now()));
+        }
+    }
+}
+
+/// Returns how long ago the span was opened, if it was seen by [`TimingLayer`]
+pub(crate) fn elapsed<S>(span: &SpanRef<'_, S>) -> Option<Duration>
+where
+    S: for<'a> LookupSpan<'a>,
+{
+    Some(span.extensions().get::<Opened>()?.0.elapsed())
+}
+
+/// Returns how long it has been since the last event formatted on this thread, if any, and
+/// notes that one is being formatted now
+pub(crate) fn since_last_event() -> Option<Duration> {
+    let now = Instant::now();
+    let last = LAST_EVENT.with(|last| last.replace(Some(now)));
+    last.map(|last| now - last)
+}
+
+/// Displays a duration with three significant digits, the same way `tracing_subscriber` does,
+/// with the units and decimal separator of the locale, see
+/// [`Builder::locale`](crate::Builder::locale)
+pub(crate) struct DisplayDuration(pub Duration);
+
+impl fmt::Display for DisplayDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (units, decimal_comma) = locale::durations();
+        let write = |f: &mut fmt::Formatter<'_>, decimals: usize, t: f64, unit: &str| {
+            if decimal_comma && decimals > 0 {
+                let number = format!("{:.*}", decimals, t).replace('.', ",");
+                write!(f, "{}{}", number, unit)
+            } else {
+                write!(f, "{:.*}{}", decimals, t, unit)
+            }
+        };
+        let mut t = self.0.as_nanos() as f64;
+        for unit in units.iter() {
+            if t < 10.0 {
+                return write(f, 2, t, unit);
+            } else if t < 100.0 {
+                return write(f, 1, t, unit);
+            } else if t < 1000.0 {
+                return write(f, 0, t, unit);
+            }
+            t /= 1000.0;
+        }
+        write(f, 0, t * 1000.0, units[3])
+    }
+}
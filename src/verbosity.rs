@@ -0,0 +1,597 @@
+use crate::boost::BoostRule;
+use crate::config::{self, LogSettings};
+use crate::filter::{FieldDirective, Regex};
+use std::str::FromStr;
+use std::{cmp, fmt};
+#[cfg(feature = "cli")]
+use structopt::StructOpt;
+use tracing::level_filters::STATIC_MAX_LEVEL;
+use tracing_subscriber::filter::LevelFilter;
+
+#[cfg(not(debug_assertions))]
+const DEFAULT_VERBOSITY: u8 = 2;
+#[cfg(debug_assertions)]
+const DEFAULT_VERBOSITY: u8 = 4;
+
+/// Environment variable holding logging filters, which `--log` adds to, unless configured with
+/// [`Builder::env_var`](crate::Builder::env_var)
+pub(crate) const LOG_ENV: &str = "SCROOGE_LOG";
+
+// No doc comment, as `StructOpt` would show it in the help of applications flattening this
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "cli", derive(StructOpt))]
+pub struct Verbosity {
+    /// Decreases logging verbosity. Can be specified multiple times
+    #[cfg_attr(
+        feature = "cli",
+        structopt(long = "quiet", short = "q", multiple = true, parse(from_occurrences))
+    )]
+    pub(crate) quiet: u64,
+    /// Increases logging verbosity. Can be specified multiple times
+    #[cfg_attr(
+        feature = "cli",
+        structopt(
+            long = "verbose",
+            short = "v",
+            multiple = true,
+            parse(from_occurrences)
+        )
+    )]
+    pub(crate) verbose: u64,
+    /// Disables logging entirely, whatever the other flags and the log environment variable say
+    #[cfg_attr(feature = "cli", structopt(long = "silent"))]
+    pub(crate) silent: bool,
+    /// Logging filters in env_logger format. Can be specified multiple times, and is combined
+    /// with the filters in the log environment variable, SCROOGE_LOG by default. Directives such
+    /// as [request{method=POST}]=trace select the events inside matching spans
+    #[cfg_attr(
+        feature = "cli",
+        structopt(long = "log", short = "l", number_of_values = 1)
+    )]
+    pub(crate) log_filters: Vec<String>,
+    /// Comma-separated presets to enable, of filters such as `quiet-deps` or of formatting such
+    /// as `http,db`
+    #[cfg_attr(
+        feature = "cli",
+        structopt(long = "log-preset", use_delimiter = true, number_of_values = 1)
+    )]
+    pub(crate) presets: Vec<String>,
+    /// How events are written: pretty, compact, json, bunyan or logfmt. Also read from the log
+    /// environment variable suffixed with _FORMAT, such as SCROOGE_LOG_FORMAT
+    #[cfg_attr(feature = "cli", structopt(long = "log-format", value_name = "format"))]
+    pub(crate) log_format: Option<LogFormat>,
+    /// Only shows events where a field, or a field of an enclosing span, has this value, such as
+    /// `tenant_id=42`. Can be specified multiple times
+    #[cfg_attr(feature = "cli", structopt(long = "log-field", number_of_values = 1))]
+    pub(crate) field_filters: Vec<FieldDirective>,
+    /// Raises the level of events meeting a condition on one of their fields, so they get
+    /// through the filters, such as `retry_count>3:warn`. Can be specified multiple times
+    #[cfg_attr(
+        feature = "cli",
+        structopt(long = "log-boost", value_name = "rule", number_of_values = 1)
+    )]
+    pub(crate) boosts: Vec<BoostRule>,
+    /// Only shows events whose message matches this regular expression. Can be specified
+    /// multiple times
+    #[cfg_attr(
+        all(feature = "cli", feature = "regex"),
+        structopt(long = "log-match", number_of_values = 1)
+    )]
+    #[cfg_attr(all(feature = "cli", not(feature = "regex")), structopt(skip))]
+    pub(crate) message_matches: Vec<Regex>,
+    /// Hides events whose message matches this regular expression. Can be specified multiple
+    /// times
+    #[cfg_attr(
+        all(feature = "cli", feature = "regex"),
+        structopt(long = "log-exclude", number_of_values = 1)
+    )]
+    #[cfg_attr(all(feature = "cli", not(feature = "regex")), structopt(skip))]
+    pub(crate) message_excludes: Vec<Regex>,
+    /// Paints the parts of messages that this regular expression matches, without hiding
+    /// anything. Can be specified multiple times
+    #[cfg_attr(
+        all(feature = "cli", feature = "regex"),
+        structopt(long = "highlight", value_name = "pattern", number_of_values = 1)
+    )]
+    #[cfg_attr(all(feature = "cli", not(feature = "regex")), structopt(skip))]
+    pub(crate) highlights: Vec<Regex>,
+    /// Hides every event of this target and the modules under it, whatever the level and other
+    /// filters. Can be specified multiple times
+    #[cfg_attr(
+        feature = "cli",
+        structopt(
+            long = "log-mute",
+            value_name = "target",
+            use_delimiter = true,
+            number_of_values = 1
+        )
+    )]
+    pub(crate) muted: Vec<String>,
+    /// Prints the logging filters from each source, and the filters in effect, at startup
+    #[cfg_attr(feature = "cli", structopt(long = "log-print-config"))]
+    pub(crate) print_config: bool,
+    /// Prints the logging filter in effect, combining the defaults, the configuration file, the
+    /// log environment variable and the command line, and exits
+    #[cfg_attr(feature = "cli", structopt(long = "log-print-filter"))]
+    pub(crate) print_filter: bool,
+    /// Also writes the output to this file, without colors, or to the conventional place for
+    /// logs of the platform with `auto`
+    #[cfg_attr(feature = "cli", structopt(long = "log-file", value_name = "path"))]
+    pub(crate) log_file: Option<String>,
+    /// Writes the output to stdout instead of stderr
+    #[cfg_attr(feature = "cli", structopt(long = "log-stdout"))]
+    pub(crate) log_stdout: bool,
+    /// Also sends events to the syslog daemon at this address, a socket path such as `/dev/log`
+    /// or a `host:port` to send UDP datagrams to
+    #[cfg_attr(feature = "cli", structopt(long = "syslog", value_name = "address"))]
+    pub(crate) syslog: Option<String>,
+    /// Also sends events to Graylog at this address, a `host:port` to send GELF messages to
+    /// over UDP, or over TCP when prefixed with `tcp://`
+    #[cfg_attr(feature = "cli", structopt(long = "gelf", value_name = "address"))]
+    pub(crate) gelf: Option<String>,
+    /// Also serves events to log viewers connecting to this address, the path of a Unix socket
+    /// or a `host:port` to listen on when prefixed with `tcp://`
+    #[cfg_attr(
+        feature = "cli",
+        structopt(long = "log-stream", value_name = "address")
+    )]
+    pub(crate) event_stream: Option<String>,
+}
+
+impl Verbosity {
+    /// As if `--verbose` and `--quiet` were given `verbose` and `quiet` times, and no other
+    /// logging flags, for applications parsing their arguments without the `cli` feature
+    pub fn new(verbose: u64, quiet: u64) -> Self {
+        Self {
+            verbose,
+            quiet,
+            ..Self::default()
+        }
+    }
+
+    /// As if `--verbose` and `--quiet` were given `verbose` and `quiet` times, and `--log` was
+    /// given `log`, for applications parsing their arguments with another library, such as
+    /// argh or pico-args
+    pub fn from_flags(verbose: u8, quiet: u8, log: Option<String>) -> Self {
+        Self {
+            log_filters: log.into_iter().collect(),
+            ..Self::new(verbose.into(), quiet.into())
+        }
+    }
+
+    /// Reads the equivalents of the logging flags from environment variables starting with
+    /// `prefix`, for processes configured through their environment rather than arguments, such
+    /// as workers and serverless functions
+    ///
+    /// `{prefix}_VERBOSITY` is a number of `--verbose` flags, or of `--quiet` flags when negative,
+    /// so `2` is like `-vv` and `-1` like `-q`. `{prefix}_LOG_PRESET`, `{prefix}_LOG_FORMAT` and
+    /// `{prefix}_LOG_FILE` are like `--log-preset`, `--log-format` and `--log-file`. Filters are
+    /// read from the log environment variable as usual. Unset variables, and values that aren't
+    /// numbers, leave the defaults.
+    ///
+    /// ```no_run
+    /// use pretty_tracing_subscriber::Verbosity;
+    ///
+    /// // With MY_APP_VERBOSITY=1 in the environment
+    /// pretty_tracing_subscriber::init("my_app", Verbosity::from_env("MY_APP"));
+    /// ```
+    pub fn from_env(prefix: &str) -> Self {
+        let var = |name: &str| std::env::var(format!("{}_{}", prefix, name)).ok();
+        let verbosity: i64 = var("VERBOSITY")
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(0);
+        Self {
+            log_format: var("LOG_FORMAT").and_then(|format| format.parse().ok()),
+            presets: var("LOG_PRESET")
+                .iter()
+                .flat_map(|presets| presets.split(','))
+                .map(str::trim)
+                .filter(|preset| !preset.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            log_file: var("LOG_FILE"),
+            ..Self::new(verbosity.max(0) as u64, verbosity.min(0).unsigned_abs())
+        }
+    }
+
+    /// Combines the number of occurrences of `--quiet` and `--verbose` flags into a `LevelFilter`
+    /// using the default [`VerbosityMapping`]
+    pub fn level_filter(&self) -> LevelFilter {
+        VerbosityMapping::default().level_filter(self.verbose, self.quiet)
+    }
+
+    /// The settings given on the command line, where `--log` filters replace the level set by
+    /// `--verbose` and `--quiet`
+    pub(crate) fn cli_settings(&self, mapping: &VerbosityMapping) -> LogSettings {
+        let directives: Vec<_> = self
+            .log_filters
+            .iter()
+            .flat_map(|filters| config::split_directives(filters))
+            .collect();
+        let flagged = self.verbose != 0 || self.quiet != 0;
+        let level = Some(compiled_in(mapping.level_filter(self.verbose, self.quiet)))
+            .filter(|_| flagged && directives.is_empty());
+        let muted = self.muted.iter().map(|target| format!("!{}", target));
+        LogSettings {
+            level,
+            directives: directives.into_iter().chain(muted).collect(),
+        }
+    }
+}
+
+/// How events are written, chosen with `--log-format`, the log environment variable suffixed
+/// with `_FORMAT`, [`Builder::log_format`](crate::Builder::log_format) or the `format` of a
+/// configuration file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config-file",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum LogFormat {
+    /// Formatted for people, the default
+    Pretty,
+    /// Formatted for people, without the time, module and spans of events even with `--verbose`
+    Compact,
+    /// A JSON object per line, see [`Builder::json`](crate::Builder::json)
+    Json,
+    /// Bunyan records, see [`Builder::bunyan`](crate::Builder::bunyan)
+    Bunyan,
+    /// A line of `key=value` pairs per event, see [`Builder::logfmt`](crate::Builder::logfmt)
+    Logfmt,
+}
+
+impl LogFormat {
+    const NAMES: [(&'static str, LogFormat); 5] = [
+        ("pretty", LogFormat::Pretty),
+        ("compact", LogFormat::Compact),
+        ("json", LogFormat::Json),
+        ("bunyan", LogFormat::Bunyan),
+        ("logfmt", LogFormat::Logfmt),
+    ];
+
+    /// Whether events are written for programs rather than people, without colors or run
+    /// markers
+    pub(crate) fn structured(self) -> bool {
+        matches!(
+            self,
+            LogFormat::Json | LogFormat::Bunyan | LogFormat::Logfmt
+        )
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = Self::NAMES
+            .iter()
+            .find(|(_, format)| format == self)
+            .expect("every format is named");
+        f.write_str(name)
+    }
+}
+
+/// Error returned when a [`LogFormat`] can't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseLogFormatError(String);
+
+impl fmt::Display for ParseLogFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unknown log format `{}`, expected pretty, compact, json, bunyan or logfmt",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseLogFormatError {}
+
+impl FromStr for LogFormat {
+    type Err = ParseLogFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_ascii_lowercase();
+        Self::NAMES
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|&(_, format)| format)
+            .ok_or_else(|| ParseLogFormatError(s.to_owned()))
+    }
+}
+
+/// Caps `level` at the most verbose level compiled in, see the `max_level_*` features
+pub(crate) fn compiled_in(level: LevelFilter) -> LevelFilter {
+    cmp::min(level, STATIC_MAX_LEVEL)
+}
+
+/// The `--verbose`, `--quiet` and `--log` flags for applications parsing their arguments with
+/// argh, with the `argh` feature
+///
+/// argh can't flatten one struct into another, so this only parses the arguments of tools that
+/// take no other flags. Others declare the same fields in their own arguments and pass them to
+/// [`Verbosity::from_flags`]:
+///
+/// ```ignore
+/// #[derive(argh::FromArgs)]
+/// /// Copies files
+/// struct Args {
+///     /// increases logging verbosity, can be given multiple times
+///     #[argh(switch, short = 'v')]
+///     verbose: u8,
+///     /// decreases logging verbosity, can be given multiple times
+///     #[argh(switch, short = 'q')]
+///     quiet: u8,
+///     /// logging filters in env_logger format
+///     #[argh(option, short = 'l')]
+///     log: Option<String>,
+///     #[argh(positional)]
+///     paths: Vec<String>,
+/// }
+///
+/// let args: Args = argh::from_env();
+/// init("my_app", Verbosity::from_flags(args.verbose, args.quiet, args.log));
+/// ```
+#[cfg(feature = "argh")]
+#[derive(Debug, Clone, argh::FromArgs)]
+#[argh(description = "Logging options")]
+pub struct LogArgs {
+    /// increases logging verbosity, can be given multiple times
+    #[argh(switch, short = 'v')]
+    pub verbose: u8,
+    /// decreases logging verbosity, can be given multiple times
+    #[argh(switch, short = 'q')]
+    pub quiet: u8,
+    /// logging filters in env_logger format, combined with those of the log environment variable
+    #[argh(option, short = 'l')]
+    pub log: Option<String>,
+}
+
+#[cfg(feature = "argh")]
+impl From<LogArgs> for Verbosity {
+    fn from(args: LogArgs) -> Self {
+        Self::from_flags(args.verbose, args.quiet, args.log)
+    }
+}
+
+impl From<Verbosity> for LevelFilter {
+    fn from(verbosity: Verbosity) -> Self {
+        verbosity.level_filter()
+    }
+}
+
+/// Maps the number of `--verbose` and `--quiet` flags to a `LevelFilter`
+///
+/// The mapping is a table of levels ordered from least to most verbose, and the position in that
+/// table used when neither flag is given. Each `-v` moves one step up the table and each `-q`
+/// one step down, saturating at both ends, so any number of flags is well-defined.
+///
+/// The default table is `OFF, ERROR, WARN, INFO, DEBUG, TRACE`, starting at `WARN` in release
+/// builds and at `DEBUG` in debug builds:
+///
+/// ```
+/// use pretty_tracing_subscriber::VerbosityMapping;
+/// use tracing_subscriber::filter::LevelFilter;
+///
+/// let mapping = VerbosityMapping::new(
+///     vec![
+///         LevelFilter::OFF,
+///         LevelFilter::ERROR,
+///         LevelFilter::WARN,
+///         LevelFilter::INFO,
+///         LevelFilter::DEBUG,
+///         LevelFilter::TRACE,
+///     ],
+///     2,
+/// );
+///
+/// let table = [
+///     // (verbose, quiet, level)
+///     (0, 0, LevelFilter::WARN),
+///     (1, 0, LevelFilter::INFO),
+///     (2, 0, LevelFilter::DEBUG),
+///     (3, 0, LevelFilter::TRACE),
+///     (1000, 0, LevelFilter::TRACE),
+///     (u64::MAX, 0, LevelFilter::TRACE),
+///     (0, 1, LevelFilter::ERROR),
+///     (0, 2, LevelFilter::OFF),
+///     (0, u64::MAX, LevelFilter::OFF),
+///     (3, 3, LevelFilter::WARN),
+///     (u64::MAX, u64::MAX, LevelFilter::WARN),
+/// ];
+/// for &(verbose, quiet, level) in &table {
+///     assert_eq!(mapping.level_filter(verbose, quiet), level, "-v × {}, -q × {}", verbose, quiet);
+/// }
+/// ```
+///
+/// Applications can use their own table, for example to make a single `-v` enable everything:
+///
+/// ```
+/// use pretty_tracing_subscriber::VerbosityMapping;
+/// use tracing_subscriber::filter::LevelFilter;
+///
+/// let mapping = VerbosityMapping::new(
+///     vec![LevelFilter::OFF, LevelFilter::ERROR, LevelFilter::INFO, LevelFilter::TRACE],
+///     2,
+/// );
+/// assert_eq!(mapping.level_filter(0, 0), LevelFilter::INFO);
+/// assert_eq!(mapping.level_filter(1, 0), LevelFilter::TRACE);
+/// assert_eq!(mapping.level_filter(0, 1), LevelFilter::ERROR);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerbosityMapping {
+    levels: Vec<LevelFilter>,
+    default: usize,
+}
+
+impl VerbosityMapping {
+    /// Creates a mapping from `levels`, ordered from least to most verbose
+    ///
+    /// # Panics
+    ///
+    /// Panics if `levels` is empty or `default` is not a position in it.
+    pub fn new(levels: Vec<LevelFilter>, default: usize) -> Self {
+        assert!(
+            default < levels.len(),
+            "default verbosity {} is out of range for {} levels",
+            default,
+            levels.len()
+        );
+        Self { levels, default }
+    }
+
+    /// Starts from `level` when neither flag is given
+    ///
+    /// # Panics
+    ///
+    /// Panics if `level` is not in the table.
+    pub fn with_default_level(mut self, level: LevelFilter) -> Self {
+        self.default = self
+            .levels
+            .iter()
+            .position(|&l| l == level)
+            .unwrap_or_else(|| panic!("default level {} is not in the verbosity table", level));
+        self
+    }
+
+    /// Returns the level reached after `verbose` steps up and `quiet` steps down from the default
+    pub fn level_filter(&self, verbose: u64, quiet: u64) -> LevelFilter {
+        let default = self.default as i128;
+        let max = self.levels.len() as i128 - 1;
+        let position = (default + verbose as i128 - quiet as i128).clamp(0, max);
+        self.levels[position as usize]
+    }
+}
+
+impl Default for VerbosityMapping {
+    fn default() -> Self {
+        Self::new(
+            vec![
+                LevelFilter::OFF,
+                LevelFilter::ERROR,
+                LevelFilter::WARN,
+                LevelFilter::INFO,
+                LevelFilter::DEBUG,
+                LevelFilter::TRACE,
+            ],
+            DEFAULT_VERBOSITY as usize,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVELS: [LevelFilter; 6] = [
+        LevelFilter::OFF,
+        LevelFilter::ERROR,
+        LevelFilter::WARN,
+        LevelFilter::INFO,
+        LevelFilter::DEBUG,
+        LevelFilter::TRACE,
+    ];
+
+    #[test]
+    fn default_mapping_reaches_every_level() {
+        let mapping = VerbosityMapping::default();
+        let default = u64::from(DEFAULT_VERBOSITY);
+        for (position, &level) in LEVELS.iter().enumerate() {
+            let position = position as u64;
+            let (verbose, quiet) = if position >= default {
+                (position - default, 0)
+            } else {
+                (0, default - position)
+            };
+            assert_eq!(
+                mapping.level_filter(verbose, quiet),
+                level,
+                "-v × {}, -q × {}",
+                verbose,
+                quiet
+            );
+        }
+    }
+
+    #[test]
+    fn default_mapping_starts_at_the_profile_level() {
+        let expected = if cfg!(debug_assertions) {
+            LevelFilter::DEBUG
+        } else {
+            LevelFilter::WARN
+        };
+        assert_eq!(VerbosityMapping::default().level_filter(0, 0), expected);
+        assert_eq!(Verbosity::new(0, 0).level_filter(), expected);
+    }
+
+    #[test]
+    fn flags_saturate_at_both_ends() {
+        let mapping = VerbosityMapping::new(LEVELS.to_vec(), 2);
+        assert_eq!(mapping.level_filter(3, 0), LevelFilter::TRACE);
+        assert_eq!(mapping.level_filter(4, 0), LevelFilter::TRACE);
+        assert_eq!(mapping.level_filter(u64::MAX, 0), LevelFilter::TRACE);
+        assert_eq!(mapping.level_filter(0, 2), LevelFilter::OFF);
+        assert_eq!(mapping.level_filter(0, 3), LevelFilter::OFF);
+        assert_eq!(mapping.level_filter(0, u64::MAX), LevelFilter::OFF);
+    }
+
+    #[test]
+    fn verbose_and_quiet_cancel_out() {
+        let mapping = VerbosityMapping::new(LEVELS.to_vec(), 2);
+        assert_eq!(mapping.level_filter(3, 3), LevelFilter::WARN);
+        assert_eq!(mapping.level_filter(5, 1), LevelFilter::TRACE);
+        assert_eq!(mapping.level_filter(1, 5), LevelFilter::OFF);
+        assert_eq!(mapping.level_filter(u64::MAX, u64::MAX), LevelFilter::WARN);
+    }
+
+    #[test]
+    fn custom_mapping_jumps_to_trace() {
+        let mapping = VerbosityMapping::new(
+            vec![
+                LevelFilter::OFF,
+                LevelFilter::ERROR,
+                LevelFilter::INFO,
+                LevelFilter::TRACE,
+            ],
+            2,
+        );
+        assert_eq!(mapping.level_filter(0, 0), LevelFilter::INFO);
+        assert_eq!(mapping.level_filter(1, 0), LevelFilter::TRACE);
+        assert_eq!(mapping.level_filter(0, 1), LevelFilter::ERROR);
+        assert_eq!(mapping.level_filter(0, 2), LevelFilter::OFF);
+    }
+
+    #[test]
+    fn single_level_mapping_ignores_flags() {
+        let mapping = VerbosityMapping::new(vec![LevelFilter::INFO], 0);
+        assert_eq!(mapping.level_filter(0, 0), LevelFilter::INFO);
+        assert_eq!(mapping.level_filter(7, 0), LevelFilter::INFO);
+        assert_eq!(mapping.level_filter(0, 7), LevelFilter::INFO);
+    }
+
+    #[test]
+    fn with_default_level_moves_the_start() {
+        let mapping = VerbosityMapping::default().with_default_level(LevelFilter::ERROR);
+        assert_eq!(mapping.level_filter(0, 0), LevelFilter::ERROR);
+        assert_eq!(mapping.level_filter(1, 0), LevelFilter::WARN);
+        assert_eq!(mapping.level_filter(0, 1), LevelFilter::OFF);
+    }
+
+    #[test]
+    #[should_panic(expected = "not in the verbosity table")]
+    fn with_default_level_rejects_levels_outside_the_table() {
+        let mapping = VerbosityMapping::new(vec![LevelFilter::OFF, LevelFilter::INFO], 1);
+        let _ = mapping.with_default_level(LevelFilter::TRACE);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn new_rejects_a_default_outside_the_table() {
+        let _ = VerbosityMapping::new(LEVELS.to_vec(), LEVELS.len());
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn new_rejects_an_empty_table() {
+        let _ = VerbosityMapping::new(Vec::new(), 0);
+    }
+}
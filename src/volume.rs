@@ -0,0 +1,57 @@
+//! Counts the events logged through the `metrics` facade, with the `metrics` feature
+
+#[cfg(feature = "metrics")]
+pub(crate) use native::VolumeCounter;
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) use unsupported::VolumeCounter;
+
+#[cfg(feature = "metrics")]
+mod native {
+    use tracing::{Event, Level, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer};
+
+    /// Increments the `log_events_total` counter for each event that passes the filters, with
+    /// the level and target as the `level` and `target` labels
+    #[derive(Debug)]
+    pub(crate) struct VolumeCounter;
+
+    impl VolumeCounter {
+        pub(crate) fn new() -> Option<Self> {
+            Some(Self)
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for VolumeCounter {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            let metadata = event.metadata();
+            let level = match *metadata.level() {
+                Level::ERROR => "error",
+                Level::WARN => "warn",
+                Level::INFO => "info",
+                Level::DEBUG => "debug",
+                Level::TRACE => "trace",
+            };
+            metrics::counter!("log_events_total", "level" => level, "target" => metadata.target())
+                .increment(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod unsupported {
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::Layer;
+
+    /// Never constructed without the `metrics` feature
+    #[derive(Debug)]
+    pub(crate) enum VolumeCounter {}
+
+    impl VolumeCounter {
+        pub(crate) fn new() -> Option<Self> {
+            None
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for VolumeCounter {}
+}
@@ -0,0 +1,86 @@
+use crate::{Builder, LogGuard, Verbosity};
+
+/// Logging defaults shared by the binaries of a workspace
+///
+/// Define the defaults once, in a crate that every binary depends on, and initialise logging
+/// from them so the binaries don't drift apart:
+///
+/// ```no_run
+/// # #[cfg(feature = "cli")] {
+/// use pretty_tracing_subscriber::{Builder, FieldStyle, Verbosity, Workspace};
+/// use structopt::StructOpt;
+///
+/// pub static LOGGING: Workspace = Workspace::new("my_app")
+///     .root_modules(&["my_app_core", "my_app_db"])
+///     .env_var("MY_APP_LOG")
+///     .configure(|builder| builder.field_style(FieldStyle::KeyValue).http_preset());
+///
+/// // In each binary
+/// LOGGING.init(Verbosity::from_args());
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Workspace {
+    root_module: &'static str,
+    extra_roots: &'static [&'static str],
+    env_var: Option<&'static str>,
+    configure: Option<fn(Builder) -> Builder>,
+}
+
+impl Workspace {
+    /// Starts from the defaults used by [`init`](crate::init)
+    pub const fn new(root_module: &'static str) -> Self {
+        Self {
+            root_module,
+            extra_roots: &[],
+            env_var: None,
+            configure: None,
+        }
+    }
+
+    /// Treats these modules as first-party code too, see [`Builder::root_module`]
+    pub const fn root_modules(mut self, modules: &'static [&'static str]) -> Self {
+        self.extra_roots = modules;
+        self
+    }
+
+    /// Reads logging filters from the environment variable `name`, see [`Builder::env_var`]
+    pub const fn env_var(mut self, name: &'static str) -> Self {
+        self.env_var = Some(name);
+        self
+    }
+
+    /// Applies further options to the [`Builder`] of every binary, such as presets or field
+    /// styles
+    pub const fn configure(mut self, configure: fn(Builder) -> Builder) -> Self {
+        self.configure = Some(configure);
+        self
+    }
+
+    /// Returns a [`Builder`] with the shared defaults applied, which a binary can customise
+    /// further before calling [`Builder::init`]
+    pub fn builder(&self, verbosity: Verbosity) -> Builder {
+        let mut builder = Builder::new(self.root_module, verbosity);
+        for module in self.extra_roots {
+            builder = builder.root_module(*module);
+        }
+        if let Some(env_var) = self.env_var {
+            builder = builder.env_var(env_var);
+        }
+        match self.configure {
+            Some(configure) => configure(builder),
+            None => builder,
+        }
+    }
+
+    /// Initialises [`tracing_subscriber`] with the shared defaults
+    pub fn init(&self, verbosity: Verbosity) {
+        self.builder(verbosity).init()
+    }
+
+    /// Initialises [`tracing_subscriber`] with the shared defaults, shutting logging down when
+    /// the returned guard is dropped
+    pub fn init_with_guard(&self, verbosity: Verbosity) -> LogGuard {
+        self.builder(verbosity).init_with_guard()
+    }
+}
@@ -0,0 +1,214 @@
+use std::borrow::Cow;
+use std::fmt::{self, Write};
+
+/// Where a writer is within an escape sequence, which takes up no columns on screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After `ESC`
+    Start,
+    /// In a control sequence such as a color, `ESC [ ... m`
+    Csi,
+    /// In an operating system command such as a hyperlink, `ESC ] ... ESC \`
+    Osc,
+    /// After `ESC` in an operating system command
+    OscEnd,
+}
+
+impl Escape {
+    /// Advances past `c`, returning whether it is displayed
+    fn next(&mut self, c: char) -> bool {
+        let (state, visible) = match (*self, c) {
+            (Escape::None, '\x1b') => (Escape::Start, false),
+            (Escape::None, _) => (Escape::None, true),
+            (Escape::Start, '[') => (Escape::Csi, false),
+            (Escape::Start, ']') => (Escape::Osc, false),
+            (Escape::Start, _) => (Escape::None, false),
+            (Escape::Csi, c) if ('\x40'..='\x7e').contains(&c) => (Escape::None, false),
+            (Escape::Csi, _) => (Escape::Csi, false),
+            (Escape::Osc, '\x1b') => (Escape::OscEnd, false),
+            (Escape::Osc, '\x07') => (Escape::None, false),
+            (Escape::Osc, _) => (Escape::Osc, false),
+            (Escape::OscEnd, _) => (Escape::None, false),
+        };
+        *self = state;
+        visible
+    }
+}
+
+/// Counts the columns taken up by `text` on screen, ignoring escape sequences
+pub(crate) fn visible_width(text: &str) -> usize {
+    let mut escape = Escape::None;
+    text.chars().filter(|c| escape.next(*c)).count()
+}
+
+/// Removes the escape sequences this crate writes from `text`, leaving what is shown on screen
+///
+/// Colors and other styles are removed, as are hyperlinks to source files, keeping the text they
+/// link, and the sequences clearing status lines. This is what files get written, and lets tools
+/// and tests compare captured output without caring about styling.
+///
+/// ```
+/// # use pretty_tracing_subscriber::strip_escapes;
+/// let line = "\x1b[1;31merror:\x1b[0m failed at \x1b]8;;file:///src/main.rs\x1b\\main.rs:3\x1b]8;;\x1b\\";
+/// assert_eq!(strip_escapes(line), "error: failed at main.rs:3");
+/// ```
+pub fn strip_escapes(text: &str) -> String {
+    let mut escape = Escape::None;
+    text.chars().filter(|c| escape.next(*c)).collect()
+}
+
+/// How the lines after the first of a message with line breaks in it are written, see
+/// [`Builder::continuation_lines`](crate::Builder::continuation_lines)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Continuation {
+    /// Indented to the column the message starts in
+    #[default]
+    Indent,
+    /// Starting with the time, context and level of the first line, dimmed
+    Prefix,
+    /// Written as they are, starting in the first column
+    Unchanged,
+}
+
+/// Passes text through while keeping track of the column the cursor ends up in
+pub(crate) struct Columns<'a> {
+    inner: &'a mut dyn Write,
+    column: usize,
+    escape: Escape,
+    /// The text of the current line so far, when recording
+    line: Option<String>,
+}
+
+impl<'a> Columns<'a> {
+    pub(crate) fn new(inner: &'a mut dyn Write) -> Self {
+        Self {
+            inner,
+            column: 0,
+            escape: Escape::None,
+            line: None,
+        }
+    }
+
+    /// Also keeps the text of the current line, see [`line`](Self::line)
+    pub(crate) fn recording(inner: &'a mut dyn Write) -> Self {
+        Self {
+            line: Some(String::new()),
+            ..Self::new(inner)
+        }
+    }
+
+    pub(crate) fn column(&self) -> usize {
+        self.column
+    }
+
+    /// The text written since the last line break, escape sequences included, if recording
+    pub(crate) fn line(&self) -> &str {
+        self.line.as_deref().unwrap_or_default()
+    }
+}
+
+impl Write for Columns<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if c == '\n' {
+                self.column = 0;
+            } else if self.escape.next(c) {
+                self.column += 1;
+            }
+        }
+        if let Some(line) = &mut self.line {
+            match s.rfind('\n') {
+                Some(i) => {
+                    line.clear();
+                    line.push_str(&s[i + 1..]);
+                }
+                None => line.push_str(s),
+            }
+        }
+        self.inner.write_str(s)
+    }
+}
+
+/// Passes text through, starting each line after the first with `continuation`
+pub(crate) struct Continued<'a> {
+    pub(crate) inner: &'a mut dyn Write,
+    pub(crate) continuation: &'a str,
+}
+
+impl Write for Continued<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut lines = s.split('\n');
+        if let Some(first) = lines.next() {
+            self.inner.write_str(first)?;
+        }
+        for line in lines {
+            self.inner.write_char('\n')?;
+            self.inner.write_str(self.continuation)?;
+            self.inner.write_str(line)?;
+        }
+        Ok(())
+    }
+}
+
+/// `width` spaces, borrowed unless the indent is unusually deep
+pub(crate) fn indent(width: usize) -> Cow<'static, str> {
+    const SPACES: &str = match std::str::from_utf8(&[b' '; 128]) {
+        Ok(spaces) => spaces,
+        Err(_) => panic!("spaces are valid UTF-8"),
+    };
+    match SPACES.get(..width) {
+        Some(spaces) => Cow::Borrowed(spaces),
+        None => Cow::Owned(" ".repeat(width)),
+    }
+}
+
+/// Narrowest space for continuation lines before they are indented less than the message
+const MIN_MESSAGE_WIDTH: usize = 40;
+
+/// Indentation of continuation lines when the message starts too far right for a hanging indent
+const SHORT_INDENT: usize = 4;
+
+/// Writes `text`, starting at column `indent`, breaking its first line at spaces so it fits in
+/// `width` columns and indenting the continuation lines to `indent`
+///
+/// When that would leave less than 40 columns for the continuation lines, they are indented by
+/// four spaces instead. Words that don't fit on a line of their own are left whole. Any further
+/// lines of `text` are written unchanged.
+pub(crate) fn write_wrapped(
+    f: &mut dyn Write,
+    text: &str,
+    indent: usize,
+    width: usize,
+) -> fmt::Result {
+    let (first, rest) = match text.split_once('\n') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (text, None),
+    };
+    let hanging = if width.saturating_sub(indent) >= MIN_MESSAGE_WIDTH {
+        indent
+    } else {
+        SHORT_INDENT
+    };
+
+    let mut column = indent;
+    let mut line_start = true;
+    for word in first.split(' ') {
+        let word_width = visible_width(word);
+        if !line_start && column + 1 + word_width > width {
+            write!(f, "\n{:1$}", "", hanging)?;
+            column = hanging;
+        } else if !line_start {
+            f.write_char(' ')?;
+            column += 1;
+        }
+        f.write_str(word)?;
+        column += word_width;
+        line_start = false;
+    }
+
+    if let Some(rest) = rest {
+        write!(f, "\n{}", rest)?;
+    }
+    Ok(())
+}